@@ -1,9 +1,6 @@
 use std::sync::Arc;
 
-use circular_queue::CircularQueue;
-
 use crate::audiodevice::AudioChunk;
-use crate::biquad::{Biquad, BiquadCoefficients};
 use crate::config;
 use crate::filters::Filter;
 
@@ -18,11 +15,37 @@ pub struct Gain {
     pub gain: PrcFmt,
 }
 
+// Changes of delay are crossfaded over this time.
+const DELAY_CROSSFADE_MS: PrcFmt = 50.0;
+const MAX_INTERPOLATION_ORDER: usize = 10;
+const MAX_SINC_ORDER: usize = 256;
+
 pub struct Delay {
     pub name: String,
     samplerate: usize,
-    queue: CircularQueue<PrcFmt>,
-    biquad: Option<Biquad>,
+    delay: PrcFmt,
+    interpolation: Option<config::DelayInterpolation>,
+    order: usize,
+    buffer: Vec<PrcFmt>,
+    index: usize,
+    tap: DelayTap,
+    fading_tap: Option<DelayTap>,
+    pending_tap: Option<DelayTap>,
+    fade_length: usize,
+    fade_position: usize,
+}
+
+// A read position in the delay buffer, with an optional filter for the fractional part of the delay.
+struct DelayTap {
+    samples: usize,
+    fraction: Option<FractionalDelay>,
+}
+
+// A FIR or IIR filter for a fractional delay, in transposed direct form II.
+struct FractionalDelay {
+    b: Vec<PrcFmt>,
+    a: Vec<PrcFmt>,
+    state: Vec<PrcFmt>,
 }
 
 pub struct Volume {
@@ -321,53 +344,246 @@ impl Filter for Gain {
     }
 }
 
+impl FractionalDelay {
+    fn new(mut b: Vec<PrcFmt>, mut a: Vec<PrcFmt>) -> Self {
+        let len = b.len().max(a.len());
+        b.resize(len, 0.0);
+        a.resize(len, 0.0);
+        let state = vec![0.0; len - 1];
+        FractionalDelay { b, a, state }
+    }
+
+    fn process_single(&mut self, input: PrcFmt) -> PrcFmt {
+        let output = self.b[0] * input + self.state.first().copied().unwrap_or_default();
+        let order = self.state.len();
+        for n in 0..order {
+            let next = if n + 1 < order {
+                self.state[n + 1]
+            } else {
+                0.0
+            };
+            self.state[n] = self.b[n + 1] * input - self.a[n + 1] * output + next;
+        }
+        output
+    }
+}
+
+impl DelayTap {
+    /// Split a delay in samples into an integer delay and a filter for the remaining fraction.
+    fn new(delay: PrcFmt, interpolation: Option<config::DelayInterpolation>, order: usize) -> Self {
+        let whole = delay.floor();
+        let pi = std::f64::consts::PI as PrcFmt;
+        match interpolation {
+            None => DelayTap {
+                // for super-small delays, store at least a single sample
+                samples: (delay.round() as usize).max(1),
+                fraction: None,
+            },
+            Some(config::DelayInterpolation::Allpass) => {
+                let fraction = delay - whole;
+                DelayTap {
+                    samples: (whole as usize).max(1),
+                    fraction: Some(FractionalDelay::new(
+                        vec![1.0 - fraction, 1.0],
+                        vec![1.0, 1.0 - fraction],
+                    )),
+                }
+            }
+            Some(config::DelayInterpolation::Lagrange) => {
+                // The interpolator is most accurate when the fractional delay is close to order/2.
+                // Short delays use a lower order to keep the fractional delay centered.
+                let order = order.min(2 * whole as usize + 2);
+                let (samples, fraction) = centered_fraction(delay, (order - 1) / 2);
+                let coeffs = (0..=order)
+                    .map(|k| {
+                        (0..=order)
+                            .filter(|m| *m != k)
+                            .map(|m| (fraction - m as PrcFmt) / (k as PrcFmt - m as PrcFmt))
+                            .product()
+                    })
+                    .collect();
+                DelayTap {
+                    samples,
+                    fraction: Some(FractionalDelay::new(coeffs, vec![1.0])),
+                }
+            }
+            Some(config::DelayInterpolation::Thiran) => {
+                // The allpass is stable for a fractional delay larger than order-1,
+                // and most accurate for delays close to the order.
+                let rounded = delay.round();
+                let order = order.min(rounded as usize);
+                if order == 0 {
+                    return DelayTap {
+                        samples: rounded as usize,
+                        fraction: None,
+                    };
+                }
+                let fraction = order as PrcFmt + delay - rounded;
+                let mut binomial = 1.0;
+                let a: Vec<PrcFmt> = (0..=order)
+                    .map(|k| {
+                        if k > 0 {
+                            binomial *= (order - k + 1) as PrcFmt / k as PrcFmt;
+                        }
+                        let sign = if k % 2 == 0 { 1.0 } else { -1.0 };
+                        let product: PrcFmt = (0..=order)
+                            .map(|n| {
+                                (fraction - order as PrcFmt + n as PrcFmt)
+                                    / (fraction - order as PrcFmt + (k + n) as PrcFmt)
+                            })
+                            .product();
+                        sign * binomial * product
+                    })
+                    .collect();
+                let b = a.iter().rev().copied().collect();
+                DelayTap {
+                    samples: rounded as usize - order,
+                    fraction: Some(FractionalDelay::new(b, a)),
+                }
+            }
+            Some(config::DelayInterpolation::Sinc) => {
+                // Blackman windowed sinc with 2*order taps, shortened for short delays.
+                let order = order.min(whole as usize + 1);
+                let (samples, fraction) = centered_fraction(delay, order - 1);
+                let half = order as PrcFmt;
+                let mut coeffs: Vec<PrcFmt> = (0..2 * order)
+                    .map(|k| {
+                        let x = k as PrcFmt - fraction;
+                        if x.abs() >= half {
+                            return 0.0;
+                        }
+                        let sinc = if x.abs() < 1.0e-9 {
+                            1.0
+                        } else {
+                            (pi * x).sin() / (pi * x)
+                        };
+                        let window =
+                            0.42 + 0.5 * (pi * x / half).cos() + 0.08 * (2.0 * pi * x / half).cos();
+                        sinc * window
+                    })
+                    .collect();
+                let sum: PrcFmt = coeffs.iter().sum();
+                for coeff in coeffs.iter_mut() {
+                    *coeff /= sum;
+                }
+                DelayTap {
+                    samples,
+                    fraction: Some(FractionalDelay::new(coeffs, vec![1.0])),
+                }
+            }
+        }
+    }
+
+    fn read(&mut self, buffer: &[PrcFmt], index: usize) -> PrcFmt {
+        let value = buffer[(index + buffer.len() - self.samples) % buffer.len()];
+        match &mut self.fraction {
+            Some(filter) => filter.process_single(value),
+            None => value,
+        }
+    }
+}
+
+// Split a delay into an integer number of samples, and a fractional delay at the center of an interpolator.
+fn centered_fraction(delay: PrcFmt, center: usize) -> (usize, PrcFmt) {
+    let whole = delay.floor() as usize;
+    (whole - center, center as PrcFmt + delay - whole as PrcFmt)
+}
+
 impl Delay {
     /// Creates a delay filter with delay in samples
     pub fn new(name: &str, samplerate: usize, delay: PrcFmt, subsample: bool) -> Self {
-        let name = name.to_string();
-
-        let (integerdelay, biquad) = if subsample {
-            let samples = delay.floor();
-            let fraction = delay - samples;
-            let bqcoeffs = BiquadCoefficients::new(1.0 - fraction, 0.0, 1.0 - fraction, 1.0, 0.0);
-            let bq = Biquad::new("subsample", samplerate, bqcoeffs);
-            debug!(
-                "Building delay filter '{}' with delay {} + {} samples",
-                name, samples, fraction
-            );
-            (samples as usize, Some(bq))
-        } else {
-            let samples = delay.round() as usize;
-            debug!(
-                "Building delay filter '{}' with delay {} samples",
-                name, samples
-            );
-            (samples, None)
-        };
+        let interpolation = subsample.then_some(config::DelayInterpolation::Allpass);
+        Self::with_interpolation(name, samplerate, delay, interpolation, 1)
+    }
 
-        // for super-small delays, store at least a single sample
-        let integerdelay = integerdelay.max(1);
-        let mut queue = CircularQueue::with_capacity(integerdelay);
-        for _ in 0..integerdelay {
-            queue.push(0.0);
-        }
+    /// Creates a delay filter with delay in samples, using the given interpolation for the fractional part.
+    pub fn with_interpolation(
+        name: &str,
+        samplerate: usize,
+        delay: PrcFmt,
+        interpolation: Option<config::DelayInterpolation>,
+        order: usize,
+    ) -> Self {
+        let name = name.to_string();
+        let tap = DelayTap::new(delay, interpolation, order);
+        debug!(
+            "Building delay filter '{}' with delay {} samples, using {} samples and {:?} interpolation",
+            name, delay, tap.samples, interpolation
+        );
+        let buffer = vec![0.0; tap.samples + 1];
 
         Self {
             name,
             samplerate,
-            queue,
-            biquad,
+            delay,
+            interpolation,
+            order,
+            buffer,
+            index: 0,
+            tap,
+            fading_tap: None,
+            pending_tap: None,
+            fade_length: (DELAY_CROSSFADE_MS / 1000.0 * samplerate as PrcFmt) as usize,
+            fade_position: 0,
         }
     }
 
-    pub fn from_config(name: &str, samplerate: usize, conf: config::DelayParameters) -> Self {
-        let delay_samples = match conf.unit() {
+    fn delay_in_samples(samplerate: usize, conf: &config::DelayParameters) -> PrcFmt {
+        match conf.unit() {
             config::TimeUnit::Milliseconds => conf.delay / 1000.0 * (samplerate as PrcFmt),
             config::TimeUnit::Millimetres => conf.delay / 1000.0 * (samplerate as PrcFmt) / 343.0,
             config::TimeUnit::Samples => conf.delay,
-        };
+        }
+    }
 
-        Self::new(name, samplerate, delay_samples, conf.subsample())
+    pub fn from_config(name: &str, samplerate: usize, conf: config::DelayParameters) -> Self {
+        let delay_samples = Self::delay_in_samples(samplerate, &conf);
+        Self::with_interpolation(
+            name,
+            samplerate,
+            delay_samples,
+            conf.interpolation(),
+            conf.order(),
+        )
+    }
+
+    /// Change the delay, crossfading from the old to the new delay to avoid clicks.
+    /// A change during a running crossfade is started when that crossfade is done.
+    fn set_delay(
+        &mut self,
+        delay: PrcFmt,
+        interpolation: Option<config::DelayInterpolation>,
+        order: usize,
+    ) {
+        if delay == self.delay && interpolation == self.interpolation && order == self.order {
+            return;
+        }
+        debug!(
+            "Changing delay filter '{}' from {} to {} samples",
+            self.name, self.delay, delay
+        );
+        let tap = DelayTap::new(delay, interpolation, order);
+        if tap.samples >= self.buffer.len() {
+            // Grow the buffer, keeping the history in order with the newest value last.
+            let len = self.buffer.len();
+            let mut buffer = vec![0.0; tap.samples + 1];
+            for (n, value) in buffer.iter_mut().take(len).enumerate() {
+                *value = self.buffer[(self.index + n) % len];
+            }
+            self.buffer = buffer;
+            self.index = len;
+        }
+        if self.fading_tap.is_some() {
+            // Only the latest change is kept.
+            self.pending_tap = Some(tap);
+        } else {
+            let old_tap = std::mem::replace(&mut self.tap, tap);
+            self.fading_tap = Some(old_tap);
+            self.fade_position = 0;
+        }
+        self.delay = delay;
+        self.interpolation = interpolation;
+        self.order = order;
     }
 }
 
@@ -377,19 +593,34 @@ impl Filter for Delay {
     }
 
     fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        let pi = std::f64::consts::PI as PrcFmt;
         for item in waveform.iter_mut() {
-            // this returns the item that was popped while pushing
-            *item = self.queue.push(*item).unwrap();
-        }
-        if let Some(bq) = &mut self.biquad {
-            bq.process_waveform(waveform)?;
+            self.buffer[self.index] = *item;
+            let mut value = self.tap.read(&self.buffer, self.index);
+            if let Some(old_tap) = &mut self.fading_tap {
+                let old_value = old_tap.read(&self.buffer, self.index);
+                let gain = 0.5
+                    - 0.5 * (pi * self.fade_position as PrcFmt / self.fade_length as PrcFmt).cos();
+                value = gain * value + (1.0 - gain) * old_value;
+                self.fade_position += 1;
+                if self.fade_position >= self.fade_length {
+                    self.fading_tap = None;
+                    if let Some(tap) = self.pending_tap.take() {
+                        self.fading_tap = Some(std::mem::replace(&mut self.tap, tap));
+                        self.fade_position = 0;
+                    }
+                }
+            }
+            *item = value;
+            self.index = (self.index + 1) % self.buffer.len();
         }
         Ok(())
     }
 
     fn update_parameters(&mut self, conf: config::Filter) {
         if let config::Filter::Delay { parameters, .. } = conf {
-            *self = Self::from_config(&self.name, self.samplerate, parameters);
+            let delay = Self::delay_in_samples(self.samplerate, &parameters);
+            self.set_delay(delay, parameters.interpolation(), parameters.order());
         } else {
             // This should never happen unless there is a bug somewhere else
             unreachable!("Invalid config change!");
@@ -400,7 +631,24 @@ impl Filter for Delay {
 /// Validate a Loudness config.
 pub fn validate_delay_config(conf: &config::DelayParameters) -> Res<()> {
     if conf.delay < 0.0 {
-        return Err(config::ConfigError::new("Delay cannot be negative").into());
+        return Err(config::ConfigError::for_parameter("delay", "Delay cannot be negative").into());
+    }
+    let (min_order, max_order) = match conf.interpolation() {
+        None => return Ok(()),
+        Some(config::DelayInterpolation::Allpass) => (1, 1),
+        Some(config::DelayInterpolation::Lagrange) | Some(config::DelayInterpolation::Thiran) => {
+            (1, MAX_INTERPOLATION_ORDER)
+        }
+        Some(config::DelayInterpolation::Sinc) => (2, MAX_SINC_ORDER),
+    };
+    if conf.order() < min_order || conf.order() > max_order {
+        let msg = format!(
+            "Order of {:?} interpolation must be in the range {} to {}",
+            conf.interpolation().unwrap(),
+            min_order,
+            max_order
+        );
+        return Err(config::ConfigError::for_parameter("order", &msg).into());
     }
     Ok(())
 }
@@ -408,7 +656,11 @@ pub fn validate_delay_config(conf: &config::DelayParameters) -> Res<()> {
 /// Validate a Volume config.
 pub fn validate_volume_config(conf: &config::VolumeParameters) -> Res<()> {
     if conf.ramp_time() < 0.0 {
-        return Err(config::ConfigError::new("Ramp time cannot be negative").into());
+        return Err(config::ConfigError::for_parameter(
+            "ramp_time",
+            "Ramp time cannot be negative",
+        )
+        .into());
     }
     Ok(())
 }
@@ -417,21 +669,38 @@ pub fn validate_volume_config(conf: &config::VolumeParameters) -> Res<()> {
 pub fn validate_gain_config(conf: &config::GainParameters) -> Res<()> {
     if conf.scale() == config::GainScale::Decibel {
         if conf.gain < -150.0 {
-            return Err(config::ConfigError::new("Gain must be larger than -150 dB").into());
+            return Err(config::ConfigError::for_parameter(
+                "gain",
+                "Gain must be larger than -150 dB",
+            )
+            .into());
         } else if conf.gain > 150.0 {
-            return Err(config::ConfigError::new("Gain must be less than +150 dB").into());
+            return Err(config::ConfigError::for_parameter(
+                "gain",
+                "Gain must be less than +150 dB",
+            )
+            .into());
         }
     } else if conf.gain < -10.0 {
-        return Err(config::ConfigError::new("Linear gain must be larger than -10.0").into());
+        return Err(config::ConfigError::for_parameter(
+            "gain",
+            "Linear gain must be larger than -10.0",
+        )
+        .into());
     } else if conf.gain > 10.0 {
-        return Err(config::ConfigError::new("Linear gain must be less than +10.0").into());
+        return Err(config::ConfigError::for_parameter(
+            "gain",
+            "Linear gain must be less than +10.0",
+        )
+        .into());
     }
     Ok(())
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::basicfilters::{Delay, Gain};
+    use crate::basicfilters::{validate_delay_config, Delay, Gain};
+    use crate::config;
     use crate::filters::Filter;
     use crate::PrcFmt;
 
@@ -516,4 +785,133 @@ mod tests {
         delay.process_waveform(&mut waveform).unwrap();
         assert!(compare_waveforms(waveform, waveform_delayed, 1.0e-6));
     }
+
+    fn sine(freq: PrcFmt, delay: PrcFmt, len: usize) -> Vec<PrcFmt> {
+        let w = 2.0 * std::f64::consts::PI as PrcFmt * freq / 48000.0;
+        (0..len)
+            .map(|n| (w * (n as PrcFmt - delay)).sin())
+            .collect()
+    }
+
+    #[test]
+    fn delay_interpolated() {
+        for (interpolation, order) in [
+            (config::DelayInterpolation::Lagrange, 3),
+            (config::DelayInterpolation::Thiran, 3),
+            (config::DelayInterpolation::Sinc, 16),
+        ] {
+            let mut delay =
+                Delay::with_interpolation("test", 48000, 10.37, Some(interpolation), order);
+            let mut waveform = sine(1000.0, 0.0, 1024);
+            delay.process_waveform(&mut waveform).unwrap();
+            let expected = sine(1000.0, 10.37, 1024);
+            assert!(compare_waveforms(
+                waveform[512..].to_vec(),
+                expected[512..].to_vec(),
+                1.0e-3
+            ));
+        }
+    }
+
+    #[test]
+    fn delay_integer_lagrange() {
+        let mut waveform = vec![0.0, -0.5, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0];
+        let waveform_delayed = vec![0.0, 0.0, 0.0, 0.0, -0.5, 1.0, 0.0, 0.0];
+        let mut delay = Delay::with_interpolation(
+            "test",
+            44100,
+            3.0,
+            Some(config::DelayInterpolation::Lagrange),
+            3,
+        );
+        delay.process_waveform(&mut waveform).unwrap();
+        assert!(compare_waveforms(waveform, waveform_delayed, 1.0e-9));
+    }
+
+    #[test]
+    fn delay_change_is_smooth() {
+        let mut delay = Delay::new("test", 48000, 2.0, false);
+        let mut waveform = sine(100.0, 0.0, 4800);
+        delay.process_waveform(&mut waveform).unwrap();
+        let params = config::DelayParameters {
+            delay: 50.0,
+            unit: Some(config::TimeUnit::Samples),
+            subsample: None,
+            interpolation: None,
+            order: None,
+        };
+        delay.update_parameters(config::Filter::Delay {
+            description: None,
+            parameters: params,
+        });
+        let mut waveform = sine(100.0, -4800.0, 4800);
+        delay.process_waveform(&mut waveform).unwrap();
+        // No steps larger than the slope of the sine while fading.
+        let max_step = 2.0 * std::f64::consts::PI as PrcFmt * 100.0 / 48000.0;
+        for pair in waveform.windows(2) {
+            assert!((pair[1] - pair[0]).abs() < 1.1 * max_step);
+        }
+        // After the crossfade, the new delay is used.
+        let expected = sine(100.0, 50.0 - 4800.0, 4800);
+        assert!(compare_waveforms(
+            waveform[2400..].to_vec(),
+            expected[2400..].to_vec(),
+            1.0e-9
+        ));
+
+        // Two changes 10 ms apart, the second one starts when the first crossfade is done.
+        let mut waveform = sine(100.0, -9600.0, 480);
+        delay.process_waveform(&mut waveform).unwrap();
+        let mut output = waveform;
+        let params = config::DelayParameters {
+            delay: 10.0,
+            unit: Some(config::TimeUnit::Samples),
+            subsample: None,
+            interpolation: None,
+            order: None,
+        };
+        delay.update_parameters(config::Filter::Delay {
+            description: None,
+            parameters: params.clone(),
+        });
+        let mut waveform = sine(100.0, -10080.0, 480);
+        delay.process_waveform(&mut waveform).unwrap();
+        output.append(&mut waveform);
+        delay.update_parameters(config::Filter::Delay {
+            description: None,
+            parameters: config::DelayParameters {
+                delay: 100.0,
+                ..params
+            },
+        });
+        let mut waveform = sine(100.0, -10560.0, 8640);
+        delay.process_waveform(&mut waveform).unwrap();
+        output.append(&mut waveform);
+        for pair in output.windows(2) {
+            assert!((pair[1] - pair[0]).abs() < 1.1 * max_step);
+        }
+        let expected = sine(100.0, 100.0 - 9600.0, 9600);
+        assert!(compare_waveforms(
+            output[6000..].to_vec(),
+            expected[6000..].to_vec(),
+            1.0e-9
+        ));
+    }
+
+    #[test]
+    fn check_delay_order() {
+        let mut params = config::DelayParameters {
+            delay: 1.5,
+            unit: None,
+            subsample: None,
+            interpolation: Some(config::DelayInterpolation::Thiran),
+            order: Some(5),
+        };
+        assert!(validate_delay_config(&params).is_ok());
+        params.order = Some(0);
+        assert!(validate_delay_config(&params).is_err());
+        params.interpolation = Some(config::DelayInterpolation::Sinc);
+        params.order = Some(1000);
+        assert!(validate_delay_config(&params).is_err());
+    }
 }
@@ -0,0 +1,339 @@
+use crate::audiodevice::AudioChunk;
+use crate::biquadcombo::BiquadCombo;
+use crate::config;
+use crate::filters::{Filter, Processor};
+use crate::mixer;
+use crate::PrcFmt;
+use crate::Res;
+
+/// Bass management for a speaker layout.
+///
+/// The small speakers are highpass filtered, and their bass is lowpass filtered
+/// and summed into the subwoofers together with the LFE channel.
+/// The filters are Linkwitz-Riley, so that the sum of the highpass and lowpass outputs is flat.
+/// The summing is done with a mixer, that takes the original channels
+/// followed by the bass of each small speaker as inputs.
+pub struct BassManagement {
+    pub name: String,
+    pub channels: usize,
+    samplerate: usize,
+    chunksize: usize,
+    small: Vec<usize>,
+    highpass: Vec<BiquadCombo>,
+    lowpass: Vec<BiquadCombo>,
+    mixer: mixer::Mixer,
+    bass: Vec<Vec<PrcFmt>>,
+}
+
+/// Generate the mixer config that routes the bass and the LFE to the subwoofers.
+/// With several subwoofers, the bass is split evenly between them,
+/// so that their combined output has the same level as a single subwoofer.
+pub fn summing_mixer(config: &config::BassManagementParameters) -> config::Mixer {
+    let small = config.channels_of_type(config::SpeakerType::Small);
+    let subwoofers = config
+        .channels_of_type(config::SpeakerType::Subwoofer)
+        .len();
+    let split_gain = -20.0 * (subwoofers.max(1) as PrcFmt).log10();
+    let mut mapping = Vec::new();
+    for (dest, speaker) in config.speakers.iter().enumerate() {
+        let sources = match speaker {
+            config::SpeakerType::Large | config::SpeakerType::Small => {
+                vec![mixer_source(dest, 0.0)]
+            }
+            config::SpeakerType::Subwoofer => {
+                let mut sources: Vec<config::MixerSource> = (0..small.len())
+                    .map(|n| mixer_source(config.channels + n, split_gain))
+                    .collect();
+                if let Some(lfe) = config.lfe_channel {
+                    sources.push(mixer_source(lfe, config.lfe_gain() + split_gain));
+                }
+                sources
+            }
+            config::SpeakerType::Unused => continue,
+        };
+        mapping.push(config::MixerMapping {
+            dest,
+            sources,
+            mute: None,
+        });
+    }
+    config::Mixer {
+        description: None,
+        channels: config::MixerChannels {
+            r#in: config.channels + small.len(),
+            out: config.channels,
+        },
+        mapping,
+        labels: None,
+    }
+}
+
+fn mixer_source(channel: usize, gain: PrcFmt) -> config::MixerSource {
+    config::MixerSource {
+        channel,
+        gain: Some(gain),
+        inverted: None,
+        mute: None,
+        scale: None,
+    }
+}
+
+impl BassManagement {
+    /// Creates a BassManagement from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::BassManagementParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let small = config.channels_of_type(config::SpeakerType::Small);
+        let freq = config.crossover_freq;
+        let order = config.order();
+        let highpass = small
+            .iter()
+            .map(|_| {
+                BiquadCombo::from_config(
+                    name,
+                    samplerate,
+                    config::BiquadComboParameters::LinkwitzRileyHighpass { freq, order },
+                )
+            })
+            .collect();
+        let lowpass = small
+            .iter()
+            .map(|_| {
+                BiquadCombo::from_config(
+                    name,
+                    samplerate,
+                    config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order },
+                )
+            })
+            .collect();
+        let mixer = mixer::Mixer::from_config(name.to_string(), summing_mixer(&config));
+        debug!(
+            "Creating bass management '{}', small speakers: {:?}, subwoofers: {:?}, lfe channel: {:?}, crossover: {} Hz, order: {}, lfe gain: {}",
+            name,
+            small,
+            config.channels_of_type(config::SpeakerType::Subwoofer),
+            config.lfe_channel,
+            freq,
+            order,
+            config.lfe_gain()
+        );
+        BassManagement {
+            name: name.to_string(),
+            channels: config.channels,
+            samplerate,
+            chunksize,
+            bass: vec![Vec::with_capacity(chunksize); small.len()],
+            small,
+            highpass,
+            lowpass,
+            mixer,
+        }
+    }
+}
+
+impl Processor for BassManagement {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply a BassManagement to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        for (n, channel) in self.small.iter().enumerate() {
+            let waveform = &mut input.waveforms[*channel];
+            let bass = &mut self.bass[n];
+            bass.clear();
+            if waveform.is_empty() {
+                bass.resize(input.frames, 0.0);
+                continue;
+            }
+            bass.extend_from_slice(waveform);
+            self.lowpass[n].process_waveform(bass)?;
+            self.highpass[n].process_waveform(waveform)?;
+        }
+        // Append the bass signals as extra channels, and let the mixer sum them into the subwoofers.
+        let mut waveforms = std::mem::take(&mut input.waveforms);
+        waveforms.append(&mut self.bass);
+        let extended = AudioChunk::from(input, waveforms);
+        let mixed = self.mixer.process_chunk(&extended);
+        let mut waveforms = extended.waveforms;
+        self.bass = waveforms.split_off(self.channels);
+        input.waveforms = mixed.waveforms;
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::BassManagement {
+            parameters: config, ..
+        } = config
+        {
+            // Keep the filter state of the channels that stay small, to avoid clicks.
+            let mut updated =
+                BassManagement::from_config(&self.name, config, self.samplerate, self.chunksize);
+            for (n, channel) in updated.small.iter().enumerate() {
+                if let Some(old) = self.small.iter().position(|ch| ch == channel) {
+                    updated.highpass[n].copy_state(&self.highpass[old]);
+                    updated.lowpass[n].copy_state(&self.lowpass[old]);
+                }
+            }
+            *self = updated;
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the bass management config, to give a helpful message intead of a panic.
+pub fn validate_bass_management(
+    samplerate: usize,
+    config: &config::BassManagementParameters,
+) -> Res<()> {
+    if config.speakers.len() != config.channels {
+        let msg = format!(
+            "The speaker layout must have one entry per channel, expected {}, found {}.",
+            config.channels,
+            config.speakers.len()
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    let subwoofers = config.channels_of_type(config::SpeakerType::Subwoofer);
+    if subwoofers.is_empty() {
+        let msg = "At least one subwoofer channel must be given.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if let Some(lfe) = config.lfe_channel {
+        match config.speakers.get(lfe) {
+            Some(config::SpeakerType::Subwoofer) | Some(config::SpeakerType::Unused) => {}
+            Some(_) => {
+                let msg = format!(
+                    "The LFE channel {lfe} can not be a main speaker, it must be a subwoofer or unused."
+                );
+                return Err(config::ConfigError::new(&msg).into());
+            }
+            None => {
+                let msg = format!(
+                    "Invalid LFE channel: {}, max is: {}.",
+                    lfe,
+                    config.channels - 1
+                );
+                return Err(config::ConfigError::new(&msg).into());
+            }
+        }
+    }
+    let freq = config.crossover_freq;
+    let order = config.order();
+    crate::biquadcombo::validate_config(
+        samplerate,
+        &config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order },
+    )?;
+    mixer::validate_mixer(&summing_mixer(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::bassmanagement::{summing_mixer, validate_bass_management, BassManagement};
+    use crate::config::{BassManagementParameters, SpeakerType};
+    use crate::filters::Processor;
+    use crate::PrcFmt;
+
+    fn make_params() -> BassManagementParameters {
+        BassManagementParameters {
+            channels: 6,
+            speakers: vec![
+                SpeakerType::Small,
+                SpeakerType::Small,
+                SpeakerType::Large,
+                SpeakerType::Subwoofer,
+                SpeakerType::Small,
+                SpeakerType::Unused,
+            ],
+            lfe_channel: Some(3),
+            crossover_freq: 80.0,
+            order: None,
+            lfe_gain: None,
+        }
+    }
+
+    #[test]
+    fn routes_bass_to_sub() {
+        let mut bm = BassManagement::from_config("test", make_params(), 48000, 1000);
+        let mut chunk = AudioChunk::new(vec![], 1.0, -1.0, 1000, 1000);
+        for _ in 0..50 {
+            let waveforms = vec![
+                vec![0.1; 1000],
+                vec![0.2; 1000],
+                vec![0.3; 1000],
+                vec![0.01; 1000],
+                vec![],
+                vec![0.5; 1000],
+            ];
+            chunk = AudioChunk::new(waveforms, 1.0, -1.0, 1000, 1000);
+            bm.process_chunk(&mut chunk).unwrap();
+        }
+        let last: Vec<PrcFmt> = chunk.waveforms.iter().map(|w| w[999]).collect();
+        // DC is removed from the small speakers.
+        assert!(last[0].abs() < 1.0e-3);
+        assert!(last[1].abs() < 1.0e-3);
+        // The large speaker is untouched, and the unused channel is silent.
+        assert!((last[2] - 0.3).abs() < 1.0e-6);
+        assert!(last[5].abs() < 1.0e-9);
+        // The sub gets the bass of the small speakers plus the LFE at +10 dB.
+        let expected = 0.1 + 0.2 + 0.01 * (10.0 as PrcFmt).powf(0.5);
+        assert!((last[3] - expected).abs() < 1.0e-3, "{}", last[3]);
+    }
+
+    #[test]
+    fn splits_bass_between_subs() {
+        let mut conf = make_params();
+        conf.speakers[5] = SpeakerType::Subwoofer;
+        let mixer = summing_mixer(&conf);
+        let split = -20.0 * (2.0 as PrcFmt).log10();
+        for dest in [3, 5] {
+            let mapping = mixer.mapping.iter().find(|m| m.dest == dest).unwrap();
+            assert!((mapping.sources[0].gain() - split).abs() < 1.0e-9);
+            assert!((mapping.sources.last().unwrap().gain() - 10.0 - split).abs() < 1.0e-9);
+        }
+    }
+
+    fn make_small_chunk() -> AudioChunk {
+        let mut waveforms = vec![vec![0.5; 1000]; 6];
+        waveforms[2] = vec![0.0; 1000];
+        waveforms[3] = vec![0.0; 1000];
+        AudioChunk::new(waveforms, 1.0, -1.0, 1000, 1000)
+    }
+
+    #[test]
+    fn update_keeps_state() {
+        let mut bm = BassManagement::from_config("test", make_params(), 48000, 1000);
+        let mut chunk = make_small_chunk();
+        bm.process_chunk(&mut chunk).unwrap();
+        let mut conf = make_params();
+        conf.crossover_freq = 81.0;
+        bm.update_parameters(crate::config::Processor::BassManagement {
+            description: None,
+            parameters: conf,
+        });
+        // The lowpass filters keep their state, so the sub continues from its previous level.
+        let mut chunk = make_small_chunk();
+        bm.process_chunk(&mut chunk).unwrap();
+        assert!(chunk.waveforms[3][0] > 0.5, "{}", chunk.waveforms[3][0]);
+    }
+
+    #[test]
+    fn check_config() {
+        let mut conf = make_params();
+        assert!(validate_bass_management(48000, &conf).is_ok());
+        conf.lfe_channel = Some(0);
+        assert!(validate_bass_management(48000, &conf).is_err());
+        let mut conf = make_params();
+        conf.order = Some(3);
+        assert!(validate_bass_management(48000, &conf).is_err());
+        let mut conf = make_params();
+        conf.speakers.pop();
+        assert!(validate_bass_management(48000, &conf).is_err());
+    }
+}
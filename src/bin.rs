@@ -48,6 +48,9 @@ use camillalib::Res;
 use camillalib::audiodevice;
 use camillalib::config;
 use camillalib::countertimer;
+use camillalib::diagnostics;
+use camillalib::gainstaging;
+use camillalib::graph;
 use camillalib::processing;
 #[cfg(feature = "websocket")]
 use camillalib::socketserver;
@@ -59,7 +62,7 @@ use std::net::IpAddr;
 use camillalib::{
     list_supported_devices, CaptureStatus, CommandMessage, ExitState, PlaybackStatus,
     ProcessingParameters, ProcessingState, ProcessingStatus, SharedConfigs, StatusMessage,
-    StatusStructs, StopReason,
+    StatusStructs, StopReason, ToneControl,
 };
 
 const EXIT_BAD_CONFIG: i32 = 101; // Error in config file
@@ -229,6 +232,17 @@ fn run(
                             }
                         };
                     },
+                    Ok(ControllerMessage::FilterParametersChanged(new_conf, filters)) => {
+                        debug!("Parameters changed for filters: {:?}", filters);
+                        let comp = config::ConfigChange::FilterParameters {
+                            filters,
+                            mixers: Vec::new(),
+                            processors: Vec::new(),
+                        };
+                        tx_pipeconf.send((comp, *new_conf.clone())).unwrap();
+                        active_config = *new_conf;
+                        *shared_configs.active.lock() = Some(active_config.clone());
+                    },
                     Ok(ControllerMessage::Stop) => {
                         debug!("Stop requested...");
                         if tx_command_cap.send(CommandMessage::Exit).is_err() {
@@ -495,6 +509,15 @@ fn main_process() -> i32 {
                 .requires("configfile")
                 .action(ArgAction::SetTrue),
         )
+        .arg(
+            Arg::new("graph")
+                .help("Write a graph of the pipeline and exit")
+                .long("graph")
+                .value_name("FORMAT")
+                .requires("configfile")
+                .action(ArgAction::Set)
+                .value_parser(["dot", "svg", "json"]),
+        )
         .arg(
             Arg::new("verbosity")
                 .help("Increase message verbosity")
@@ -887,17 +910,60 @@ fn main_process() -> i32 {
         initial_mutes[4] = true;
     }
 
+    let initial_tone_controls = if let Some(s) = &state {
+        debug!("Using statefile for initial tone controls");
+        [s.bass, s.treble, s.balance]
+    } else {
+        [0.0, 0.0, 0.0]
+    };
+
+    let initial_eq_gains = state
+        .as_ref()
+        .map(|s| s.eq_gains.clone())
+        .unwrap_or_default();
+
     debug!("Initial mute: {initial_mutes:?}");
     debug!("Initial volume: {initial_volumes:?}");
 
     debug!("Read config file {:?}", configname);
 
     if matches.get_flag("check") {
-        match config::load_validate_config(&configname.unwrap()) {
-            Ok(_) => {
+        match diagnostics::check_config_file(&configname.unwrap()) {
+            Ok(conf) => {
                 println!("Config is valid");
+                match gainstaging::analyze_config(&conf) {
+                    Ok(analyses) => {
+                        for analysis in analyses {
+                            print!("{analysis}");
+                        }
+                    }
+                    Err(err) => println!("Unable to analyze the gain staging: {err}"),
+                }
                 return EXIT_OK;
             }
+            Err(problems) => {
+                println!("Config is not valid");
+                for problem in problems {
+                    println!("{problem}");
+                }
+                return EXIT_BAD_CONFIG;
+            }
+        }
+    }
+
+    if let Some(format) = matches.get_one::<String>("graph") {
+        let format = graph::GraphFormat::from_name(format).unwrap();
+        match config::load_validate_config(&configname.unwrap()) {
+            Ok(conf) => match graph::PipelineGraph::from_config(&conf).render(format) {
+                Ok(output) => {
+                    print!("{output}");
+                    return EXIT_OK;
+                }
+                Err(err) => {
+                    println!("Unable to write the graph: {err}");
+                    return EXIT_BAD_CONFIG;
+                }
+            },
             Err(err) => {
                 println!("Config is not valid");
                 println!("{err}");
@@ -918,6 +984,10 @@ fn main_process() -> i32 {
             config_path: configname.clone(),
             volume: initial_volumes,
             mute: initial_mutes,
+            bass: initial_tone_controls[0],
+            treble: initial_tone_controls[1],
+            balance: initial_tone_controls[2],
+            eq_gains: initial_eq_gains.clone(),
         };
         if state.is_none() || state.map(|s| s != state_to_save).unwrap_or(false) {
             statefile::save_state_to_file(fname, &state_to_save);
@@ -926,11 +996,25 @@ fn main_process() -> i32 {
         }
     }
 
+    let processing_params = Arc::new(ProcessingParameters::new(&initial_volumes, &initial_mutes));
+    processing_params.set_tone_control(ToneControl::Bass, initial_tone_controls[0]);
+    processing_params.set_tone_control(ToneControl::Treble, initial_tone_controls[1]);
+    processing_params.set_tone_control(ToneControl::Balance, initial_tone_controls[2]);
+    processing_params.set_all_eq_gains(initial_eq_gains);
+
     let (tx_command, rx_command) = crossbeam_channel::bounded(10);
     if let Some(path) = &configname {
         match config::load_validate_config(path) {
-            Ok(conf) => {
+            Ok(mut conf) => {
                 debug!("Config is valid");
+                let kept = config::apply_eq_gains(&mut conf, &processing_params.eq_gains());
+                if processing_params.set_all_eq_gains(kept) {
+                    if let Some(fname) = &statefilename {
+                        let state =
+                            statefile::current_state(configname.clone(), &processing_params);
+                        statefile::save_state_to_file(fname, &state);
+                    }
+                }
                 tx_command
                     .send(ControllerMessage::ConfigChanged(Box::new(conf)))
                     .unwrap();
@@ -949,6 +1033,10 @@ fn main_process() -> i32 {
 
     #[cfg(not(windows))]
     let active_path_thread = active_config_path.clone();
+    #[cfg(not(windows))]
+    let processing_params_thread = processing_params.clone();
+    #[cfg(not(windows))]
+    let statefilename_thread = statefilename.clone();
 
     #[cfg(not(windows))]
     thread::spawn(move || {
@@ -963,8 +1051,21 @@ fn main_process() -> i32 {
                     let path = (*active_path_thread.lock()).clone();
                     if let Some(path) = path {
                         match config::load_validate_config(path.as_str()) {
-                            Ok(conf) => {
+                            Ok(mut conf) => {
                                 debug!("Config is valid");
+                                let kept = config::apply_eq_gains(
+                                    &mut conf,
+                                    &processing_params_thread.eq_gains(),
+                                );
+                                if processing_params_thread.set_all_eq_gains(kept) {
+                                    if let Some(fname) = &statefilename_thread {
+                                        let state = statefile::current_state(
+                                            Some(path.clone()),
+                                            &processing_params_thread,
+                                        );
+                                        statefile::save_state_to_file(fname, &state);
+                                    }
+                                }
                                 if let Err(e) = tx_command_thread
                                     .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
                                 {
@@ -1044,7 +1145,6 @@ fn main_process() -> i32 {
         signal_rms: countertimer::ValueHistory::new(1024, 2),
         signal_peak: countertimer::ValueHistory::new(1024, 2),
     }));
-    let processing_params = Arc::new(ProcessingParameters::new(&initial_volumes, &initial_mutes));
     let processing_status = Arc::new(RwLock::new(ProcessingStatus {
         stop_reason: StopReason::None,
     }));
@@ -1141,6 +1241,10 @@ fn main_process() -> i32 {
                     debug!("Config change command received");
                     *active_config.lock() = Some(*new_conf);
                 }
+                Ok(ControllerMessage::FilterParametersChanged(new_conf, _)) => {
+                    debug!("Filter parameters change command received");
+                    *active_config.lock() = Some(*new_conf);
+                }
                 Ok(ControllerMessage::Stop) => {
                     debug!("Stop command received");
                     *active_config.lock() = None;
@@ -532,9 +532,15 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         | config::BiquadParameters::Allpass(config::NotchWidth::Bandwidth { freq, .. })
         | config::BiquadParameters::AllpassFO { freq, .. } => {
             if *freq <= 0.0 {
-                return Err(config::ConfigError::new("Frequency must be > 0").into());
+                return Err(
+                    config::ConfigError::for_parameter("freq", "Frequency must be > 0").into(),
+                );
             } else if *freq >= maxfreq {
-                return Err(config::ConfigError::new("Frequency must be < samplerate/2").into());
+                return Err(config::ConfigError::for_parameter(
+                    "freq",
+                    "Frequency must be < samplerate/2",
+                )
+                .into());
             }
         }
         _ => {}
@@ -551,7 +557,11 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         | config::BiquadParameters::Lowshelf(config::ShelfSteepness::Q { q, .. })
         | config::BiquadParameters::GeneralNotch(config::GeneralNotchParams { q_p: q, .. }) => {
             if *q <= 0.0 {
-                return Err(config::ConfigError::new("Q must be > 0").into());
+                let parameter = match parameters {
+                    config::BiquadParameters::GeneralNotch(_) => "q_p",
+                    _ => "q",
+                };
+                return Err(config::ConfigError::for_parameter(parameter, "Q must be > 0").into());
             }
         }
         _ => {}
@@ -565,7 +575,11 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         | config::BiquadParameters::Bandpass(config::NotchWidth::Bandwidth { bandwidth, .. })
         | config::BiquadParameters::Allpass(config::NotchWidth::Bandwidth { bandwidth, .. }) => {
             if *bandwidth <= 0.0 {
-                return Err(config::ConfigError::new("Bandwidth must be > 0").into());
+                return Err(config::ConfigError::for_parameter(
+                    "bandwidth",
+                    "Bandwidth must be > 0",
+                )
+                .into());
             }
         }
         _ => {}
@@ -575,9 +589,13 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope { slope, .. })
         | config::BiquadParameters::Lowshelf(config::ShelfSteepness::Slope { slope, .. }) => {
             if *slope <= 0.0 {
-                return Err(config::ConfigError::new("Slope must be > 0").into());
+                return Err(
+                    config::ConfigError::for_parameter("slope", "Slope must be > 0").into(),
+                );
             } else if *slope > 12.0 {
-                return Err(config::ConfigError::new("Slope must be <= 12.0").into());
+                return Err(
+                    config::ConfigError::for_parameter("slope", "Slope must be <= 12.0").into(),
+                );
             }
         }
         _ => {}
@@ -590,24 +608,41 @@ pub fn validate_config(samplerate: usize, parameters: &config::BiquadParameters)
         q_target,
     } = parameters
     {
-        if *freq_act <= 0.0 || *freq_target <= 0.0 {
-            return Err(config::ConfigError::new("Frequency must be > 0").into());
-        } else if *freq_act >= maxfreq || *freq_target >= maxfreq {
-            return Err(config::ConfigError::new("Frequency must be < samplerate/2").into());
+        for (parameter, freq) in [("freq_act", freq_act), ("freq_target", freq_target)] {
+            if *freq <= 0.0 {
+                return Err(
+                    config::ConfigError::for_parameter(parameter, "Frequency must be > 0").into(),
+                );
+            } else if *freq >= maxfreq {
+                return Err(config::ConfigError::for_parameter(
+                    parameter,
+                    "Frequency must be < samplerate/2",
+                )
+                .into());
+            }
         }
-        if *q_act <= 0.0 || *q_target <= 0.0 {
-            return Err(config::ConfigError::new("Q must be > 0").into());
+        for (parameter, q) in [("q_act", q_act), ("q_target", q_target)] {
+            if *q <= 0.0 {
+                return Err(config::ConfigError::for_parameter(parameter, "Q must be > 0").into());
+            }
         }
     }
     // Check GeneralNotch frequencies
     if let config::BiquadParameters::GeneralNotch(params) = parameters {
-        if params.freq_p <= 0.0 || params.freq_z <= 0.0 {
-            return Err(config::ConfigError::new("Pole and zero frequencies must be > 0").into());
-        } else if params.freq_p >= maxfreq || params.freq_z >= maxfreq {
-            return Err(config::ConfigError::new(
-                "Pole and zero frequencies must be < samplerate/2",
-            )
-            .into());
+        for (parameter, freq) in [("freq_p", params.freq_p), ("freq_z", params.freq_z)] {
+            if freq <= 0.0 {
+                return Err(config::ConfigError::for_parameter(
+                    parameter,
+                    "Pole and zero frequencies must be > 0",
+                )
+                .into());
+            } else if freq >= maxfreq {
+                return Err(config::ConfigError::for_parameter(
+                    parameter,
+                    "Pole and zero frequencies must be < samplerate/2",
+                )
+                .into());
+            }
         }
     }
     let coeffs = BiquadCoefficients::from_config(samplerate, parameters.clone());
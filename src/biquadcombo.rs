@@ -5,18 +5,159 @@
 
 use crate::biquad;
 use crate::config;
+use crate::eqfile;
 use crate::filters::Filter;
+use num_complex::Complex;
 
 // Sample format
 //type SmpFmt = i16;
 use crate::PrcFmt;
 use crate::Res;
 
+// An analog first order section, given as (c0, c1, d0, d1) for H(s) = (c0 + c1*s) / (d0 + d1*s).
+type AnalogSection = (f64, f64, f64, f64);
+
+// Number of samples between updates of the graphic equalizer coefficients while ramping.
+const SUBBLOCK: usize = 16;
+// Largest change of a graphic equalizer band gain in dB for each coefficient update.
+const MAX_GAIN_STEP: PrcFmt = 0.1;
+/// Largest allowed band gain of a graphic equalizer, in dB.
+pub const MAX_GRAPHIC_EQ_GAIN: f32 = 40.0;
+
+// Bands with a gain below this are left out.
+const MIN_BAND_GAIN: PrcFmt = 0.001;
+
+#[derive(Clone, Debug)]
+struct GraphicBand {
+    freq: PrcFmt,
+    bandwidth: PrcFmt,
+    gain: PrcFmt,
+    target: PrcFmt,
+    filter: Option<biquad::Biquad>,
+}
+
+impl GraphicBand {
+    fn is_ramping(&self) -> bool {
+        self.gain != self.target
+    }
+
+    /// Move the gain towards the target in small steps, and update the coefficients.
+    fn update_gain(&mut self, samplerate: usize) {
+        let step = (self.target - self.gain).clamp(-MAX_GAIN_STEP, MAX_GAIN_STEP);
+        if (self.target - self.gain - step).abs() < 1.0e-6 {
+            self.gain = self.target;
+        } else {
+            self.gain += step;
+        }
+        self.set_gain(samplerate, self.gain);
+    }
+
+    fn set_gain(&mut self, samplerate: usize, gain: PrcFmt) {
+        self.gain = gain;
+        if gain.abs() <= MIN_BAND_GAIN && self.target.abs() <= MIN_BAND_GAIN {
+            self.filter = None;
+            return;
+        }
+        let parameters = config::BiquadParameters::Peaking(config::PeakingWidth::Bandwidth {
+            freq: self.freq,
+            bandwidth: self.bandwidth,
+            gain,
+        });
+        match &mut self.filter {
+            Some(filter) => filter.update_parameters(config::Filter::Biquad {
+                description: None,
+                parameters,
+            }),
+            None => {
+                let coeffs = biquad::BiquadCoefficients::from_config(samplerate, parameters);
+                self.filter = Some(biquad::Biquad::new("", samplerate, coeffs));
+            }
+        }
+    }
+}
+
+/// The bands of a graphic equalizer.
+/// The band gains can be changed at runtime, and are then ramped to the new values.
+#[derive(Clone, Debug)]
+struct GraphicEqualizer {
+    freq_min: f32,
+    freq_max: f32,
+    bands: Vec<GraphicBand>,
+}
+
+impl GraphicEqualizer {
+    fn new(samplerate: usize, freq_min: f32, freq_max: f32, gains: &[f32]) -> Self {
+        let nbr_bands = gains.len();
+        let f_min_log = freq_min.log2();
+        let f_max_log = freq_max.log2();
+        let bw = (f_max_log - f_min_log) / nbr_bands as f32;
+        let bands = gains
+            .iter()
+            .enumerate()
+            .map(|(band, gain)| {
+                let freq_log = f_min_log + (band as f32 + 0.5) * bw;
+                let mut band = GraphicBand {
+                    freq: 2.0_f32.powf(freq_log) as PrcFmt,
+                    bandwidth: bw as PrcFmt,
+                    gain: *gain as PrcFmt,
+                    target: *gain as PrcFmt,
+                    filter: None,
+                };
+                band.set_gain(samplerate, band.gain);
+                band
+            })
+            .collect();
+        GraphicEqualizer {
+            freq_min,
+            freq_max,
+            bands,
+        }
+    }
+
+    /// Check if new parameters only change the band gains.
+    fn is_compatible(&self, params: &config::GraphicEqualizerParameters) -> bool {
+        self.freq_min == params.freq_min()
+            && self.freq_max == params.freq_max()
+            && self.bands.len() == params.gains.len()
+    }
+
+    fn set_targets(&mut self, gains: &[f32]) {
+        for (band, gain) in self.bands.iter_mut().zip(gains.iter()) {
+            band.target = *gain as PrcFmt;
+        }
+    }
+
+    fn process_waveform(&mut self, samplerate: usize, waveform: &mut [PrcFmt]) -> Res<()> {
+        if self.bands.iter().any(|band| band.is_ramping()) {
+            for chunk in waveform.chunks_mut(SUBBLOCK) {
+                for band in self.bands.iter_mut() {
+                    if band.is_ramping() {
+                        band.update_gain(samplerate);
+                    }
+                    if let Some(filter) = &mut band.filter {
+                        filter.process_waveform(chunk)?;
+                    }
+                }
+            }
+        } else {
+            for filter in self
+                .bands
+                .iter_mut()
+                .filter_map(|band| band.filter.as_mut())
+            {
+                filter.process_waveform(waveform)?;
+            }
+        }
+        Ok(())
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct BiquadCombo {
     samplerate: usize,
     pub name: String,
     filters: Vec<biquad::Biquad>,
+    graphic: Option<GraphicEqualizer>,
 }
 
 impl BiquadCombo {
@@ -80,6 +221,150 @@ impl BiquadCombo {
         qvalues
     }
 
+    // Get the poles of a Bessel lowpass prototype.
+    // These are the roots of the reverse Bessel polynomial, that gives a group delay of 1 s at DC.
+    // The roots are found with the Durand-Kerner method.
+    fn bessel_poles(
+        order: usize,
+        normalization: config::BesselNormalization,
+    ) -> Vec<Complex<PrcFmt>> {
+        let factorial = |n: usize| (1..=n).fold(1.0, |acc, k| acc * k as f64);
+        let coeffs: Vec<f64> = (0..=order)
+            .map(|k| {
+                factorial(2 * order - k)
+                    / (2.0_f64.powi((order - k) as i32) * factorial(k) * factorial(order - k))
+            })
+            .collect();
+        let eval = |s: Complex<f64>| {
+            coeffs
+                .iter()
+                .rev()
+                .fold(Complex::new(0.0, 0.0), |acc, c| acc * s + c)
+        };
+        let a0 = coeffs[0];
+        let radius = a0.powf(1.0 / order as f64);
+        let mut poles: Vec<Complex<f64>> = (0..order)
+            .map(|n| {
+                Complex::from_polar(
+                    radius,
+                    2.0 * std::f64::consts::PI * n as f64 / order as f64 + 0.4,
+                )
+            })
+            .collect();
+        for _ in 0..500 {
+            for n in 0..order {
+                let pole = poles[n];
+                let denom = poles
+                    .iter()
+                    .enumerate()
+                    .filter(|(m, _)| *m != n)
+                    .fold(Complex::new(1.0, 0.0), |acc, (_, p)| acc * (pole - p));
+                poles[n] = pole - eval(pole) / denom;
+            }
+        }
+        let scale = match normalization {
+            config::BesselNormalization::Delay => 1.0,
+            config::BesselNormalization::Phase => radius,
+            config::BesselNormalization::Magnitude => {
+                // Find the -3 dB frequency by bisection, the magnitude decreases monotonically.
+                let magn_sqr = |w: f64| {
+                    poles.iter().fold(1.0, |acc, p| {
+                        acc * a0.powf(2.0 / order as f64) / (Complex::new(0.0, w) - p).norm_sqr()
+                    })
+                };
+                let (mut low, mut high) = (0.0, 100.0);
+                for _ in 0..100 {
+                    let mid = 0.5 * (low + high);
+                    if magn_sqr(mid) > 0.5 {
+                        low = mid;
+                    } else {
+                        high = mid;
+                    }
+                }
+                0.5 * (low + high)
+            }
+        };
+        poles
+            .iter()
+            .map(|p| Complex::new((p.re / scale) as PrcFmt, (p.im / scale) as PrcFmt))
+            .collect()
+    }
+
+    // Get the poles of a Chebyshev type I lowpass prototype, with the passband edge at 1 rad/s.
+    fn chebyshev_poles(order: usize, ripple: PrcFmt) -> Vec<Complex<PrcFmt>> {
+        let eps = ((10.0 as PrcFmt).powf(ripple / 10.0) - 1.0).sqrt();
+        let mu = (1.0 / eps).asinh() / order as PrcFmt;
+        (1..=order)
+            .map(|k| {
+                let theta =
+                    std::f64::consts::PI as PrcFmt * (2 * k - 1) as PrcFmt / (2 * order) as PrcFmt;
+                Complex::new(-mu.sinh() * theta.sin(), mu.cosh() * theta.cos())
+            })
+            .collect()
+    }
+
+    // Get the passband gain of a Chebyshev type I filter.
+    // Even orders start at the bottom of the ripple, odd orders at the top.
+    fn chebyshev_gain(order: usize, ripple: PrcFmt) -> PrcFmt {
+        if order % 2 == 0 {
+            (10.0 as PrcFmt).powf(-ripple / 20.0)
+        } else {
+            1.0
+        }
+    }
+
+    // Convert prototype poles to second order sections.
+    // Each section is given as the frequency scale factor and the Q-value,
+    // where a negative Q-value means a first order section.
+    fn pole_sections(poles: &[Complex<PrcFmt>]) -> Vec<(PrcFmt, PrcFmt)> {
+        let mut sections = Vec::with_capacity(poles.len() / 2 + 1);
+        for pole in poles.iter() {
+            if pole.im > 1.0e-6 * pole.norm() {
+                sections.push((pole.norm(), pole.norm() / (-2.0 * pole.re)));
+            }
+        }
+        for pole in poles.iter() {
+            if pole.im.abs() <= 1.0e-6 * pole.norm() {
+                sections.push((pole.norm(), -1.0));
+            }
+        }
+        sections
+    }
+
+    // Make a highpass or lowpass filter from second order sections.
+    // The highpass is made from the lowpass prototype by the s -> 1/s transform,
+    // which inverts the frequencies while keeping the Q-values.
+    fn make_sections(
+        fs: usize,
+        freq: PrcFmt,
+        sections: &[(PrcFmt, PrcFmt)],
+        highpass: bool,
+        gain: PrcFmt,
+    ) -> Vec<biquad::Biquad> {
+        let mut filters = Vec::with_capacity(sections.len() + 1);
+        for (scale, q) in sections.iter() {
+            let filtconf = match (highpass, *q >= 0.0) {
+                (true, true) => config::BiquadParameters::Highpass {
+                    freq: freq / scale,
+                    q: *q,
+                },
+                (true, false) => config::BiquadParameters::HighpassFO { freq: freq / scale },
+                (false, true) => config::BiquadParameters::Lowpass {
+                    freq: freq * scale,
+                    q: *q,
+                },
+                (false, false) => config::BiquadParameters::LowpassFO { freq: freq * scale },
+            };
+            let coeffs = biquad::BiquadCoefficients::from_config(fs, filtconf);
+            filters.push(biquad::Biquad::new("", fs, coeffs));
+        }
+        if gain != 1.0 {
+            let coeffs = biquad::BiquadCoefficients::new(0.0, 0.0, gain, 0.0, 0.0);
+            filters.push(biquad::Biquad::new("", fs, coeffs));
+        }
+        filters
+    }
+
     fn make_tilt(fs: usize, gain: PrcFmt) -> Vec<biquad::Biquad> {
         let gain_low = -gain / 2.0;
         let gain_high = gain / 2.0;
@@ -137,35 +422,215 @@ impl BiquadCombo {
         filters
     }
 
-    fn make_graphic(
-        samplerate: usize,
-        freq_min: f32,
-        freq_max: f32,
-        gains: &[f32],
-    ) -> Vec<biquad::Biquad> {
-        let bands = gains.len();
-        let mut filters = Vec::with_capacity(bands);
+    // Get the analog first order sections of an emphasis curve, and the frequency where it has unity gain.
+    // The time constants are in seconds.
+    // The curves are the de-emphasis or playback curves, a frequency of None means unity gain at DC.
+    fn emphasis_sections(curve: config::EmphasisCurve) -> (Vec<AnalogSection>, Option<f64>) {
+        match curve {
+            config::EmphasisCurve::Riaa => (
+                vec![(1.0, 318.0e-6, 1.0, 3180.0e-6), (1.0, 0.0, 1.0, 75.0e-6)],
+                Some(1000.0),
+            ),
+            config::EmphasisCurve::Cd => (vec![(1.0, 15.0e-6, 1.0, 50.0e-6)], None),
+            config::EmphasisCurve::Fm50 => (vec![(1.0, 0.0, 1.0, 50.0e-6)], None),
+            config::EmphasisCurve::Fm75 => (vec![(1.0, 0.0, 1.0, 75.0e-6)], None),
+            config::EmphasisCurve::Nab => (vec![(1.0, 50.0e-6, 1.0, 3180.0e-6)], Some(1000.0)),
+            config::EmphasisCurve::IecType1 => {
+                (vec![(1.0, 120.0e-6, 1.0, 3180.0e-6)], Some(1000.0))
+            }
+            config::EmphasisCurve::IecType2 => (vec![(1.0, 70.0e-6, 1.0, 3180.0e-6)], Some(1000.0)),
+        }
+    }
 
-        let f_min_log = freq_min.log2();
-        let f_max_log = freq_max.log2();
-        let bw = (f_max_log - f_min_log) / bands as f32;
-        for (band, gain) in gains.iter().enumerate() {
-            if gain.abs() > 0.001 {
-                let freq_log = f_min_log + (band as f32 + 0.5) * bw;
-                let freq = 2.0_f32.powf(freq_log);
-                let filtconf = config::BiquadParameters::Peaking(config::PeakingWidth::Bandwidth {
-                    freq: freq as PrcFmt,
-                    bandwidth: bw as PrcFmt,
-                    gain: *gain as PrcFmt,
-                });
-                let coeffs = biquad::BiquadCoefficients::from_config(samplerate, filtconf);
-                let filt = biquad::Biquad::new("", samplerate, coeffs);
-                filters.push(filt);
+    // Get the corner frequencies of an analog section.
+    fn section_corners(section: &AnalogSection) -> Vec<f64> {
+        let (c0, c1, d0, d1) = *section;
+        [(c0, c1), (d0, d1)]
+            .iter()
+            .filter(|(k0, k1)| *k0 > 0.0 && *k1 > 0.0)
+            .map(|(k0, k1)| k0 / (2.0 * std::f64::consts::PI * k1))
+            .collect()
+    }
+
+    fn analog_gain(section: &AnalogSection, freq: f64) -> f64 {
+        let w = 2.0 * std::f64::consts::PI * freq;
+        let (c0, c1, d0, d1) = *section;
+        ((c0 * c0 + c1 * c1 * w * w) / (d0 * d0 + d1 * d1 * w * w)).sqrt()
+    }
+
+    // Squared magnitude of the numerator and denominator of a biquad given as [b0, b1, b2, a1, a2],
+    // at the normalized angular frequency w.
+    fn digital_gain_squared(coeffs: &[f64; 5], w: f64) -> (f64, f64) {
+        let [b0, b1, b2, a1, a2] = *coeffs;
+        let num = b0 * b0
+            + b1 * b1
+            + b2 * b2
+            + 2.0 * (b0 * b1 + b1 * b2) * w.cos()
+            + 2.0 * b0 * b2 * (2.0 * w).cos();
+        let den =
+            1.0 + a1 * a1 + a2 * a2 + 2.0 * (a1 + a1 * a2) * w.cos() + 2.0 * a2 * (2.0 * w).cos();
+        (num, den)
+    }
+
+    // Design a digital first order filter, that matches the magnitude of an analog section
+    // exactly at DC, at the Nyquist frequency, and at the geometric mean of the corner frequencies.
+    // Unlike the bilinear transform, this does not compress the response near the Nyquist frequency.
+    fn matched_first_order(fs: usize, section: &AnalogSection) -> [f64; 5] {
+        let corners = BiquadCombo::section_corners(section);
+        let freq_m = corners
+            .iter()
+            .product::<f64>()
+            .powf(1.0 / corners.len() as f64);
+        let gain_0 = BiquadCombo::analog_gain(section, 0.0);
+        let gain_pi = BiquadCombo::analog_gain(section, fs as f64 / 2.0);
+        let gain_m = BiquadCombo::analog_gain(section, freq_m);
+        if (gain_pi - gain_0).abs() < 1.0e-9 * gain_0 {
+            return [gain_0, 0.0, 0.0, 0.0, 0.0];
+        }
+        let cos_m = (2.0 * std::f64::consts::PI * freq_m / fs as f64).cos();
+        let ratio = ((1.0 - cos_m) * (gain_pi * gain_pi - gain_m * gain_m))
+            / ((1.0 + cos_m) * (gain_m * gain_m - gain_0 * gain_0));
+        let a1 = (ratio.sqrt() - 1.0) / (ratio.sqrt() + 1.0);
+        let sum = gain_0 * (1.0 + a1);
+        let diff = gain_pi * (1.0 - a1);
+        [0.5 * (sum + diff), 0.5 * (sum - diff), 0.0, a1, 0.0]
+    }
+
+    // Refine the matched first order filter to a biquad, by a Gauss-Newton least squares fit
+    // of the log magnitude from 10 Hz to 95% of the Nyquist frequency.
+    // This brings the error down to a few hundredths of a dB over the whole range.
+    // The matched filter is kept if the fit does not give a stable minimum phase result.
+    fn fit_section(fs: usize, section: &AnalogSection) -> [f64; 5] {
+        let npoints = 64;
+        let iterations = 30;
+        let matched = BiquadCombo::matched_first_order(fs, section);
+        let freq_max = 0.95 * fs as f64 / 2.0;
+        let points: Vec<(f64, f64)> = (0..npoints)
+            .map(|n| {
+                let freq = 10.0 * (freq_max / 10.0).powf(n as f64 / (npoints - 1) as f64);
+                let target = BiquadCombo::analog_gain(section, freq).powi(2).ln();
+                (2.0 * std::f64::consts::PI * freq / fs as f64, target)
+            })
+            .collect();
+        let mut coeffs = matched;
+        for _ in 0..iterations {
+            let mut jtj = [[0.0; 5]; 5];
+            let mut jtr = [0.0; 5];
+            for (w, target) in points.iter() {
+                let [b0, b1, b2, a1, a2] = coeffs;
+                let (num, den) = BiquadCombo::digital_gain_squared(&coeffs, *w);
+                let (cos1, cos2) = (w.cos(), (2.0 * w).cos());
+                let jac = [
+                    (2.0 * b0 + 2.0 * b1 * cos1 + 2.0 * b2 * cos2) / num,
+                    (2.0 * b1 + 2.0 * (b0 + b2) * cos1) / num,
+                    (2.0 * b2 + 2.0 * b1 * cos1 + 2.0 * b0 * cos2) / num,
+                    -(2.0 * a1 + 2.0 * (1.0 + a2) * cos1) / den,
+                    -(2.0 * a2 + 2.0 * a1 * cos1 + 2.0 * cos2) / den,
+                ];
+                let residual = num.ln() - den.ln() - target;
+                for row in 0..5 {
+                    jtr[row] -= jac[row] * residual;
+                    for col in 0..5 {
+                        jtj[row][col] += jac[row] * jac[col];
+                    }
+                }
+            }
+            for (row, values) in jtj.iter_mut().enumerate() {
+                values[row] += 1.0e-12;
             }
+            match solve_linear(jtj, jtr) {
+                Some(step) => {
+                    for (value, delta) in coeffs.iter_mut().zip(step.iter()) {
+                        *value += delta;
+                    }
+                }
+                None => return matched,
+            }
+        }
+        let [b0, b1, b2, a1, a2] = coeffs;
+        let stable = is_inside_unit_circle(1.0, a1, a2);
+        let minimum_phase = b0 > 0.0 && is_inside_unit_circle(b0, b1, b2);
+        if coeffs.iter().all(|c| c.is_finite()) && stable && minimum_phase {
+            coeffs
+        } else {
+            matched
+        }
+    }
+
+    fn make_emphasis(
+        fs: usize,
+        curve: config::EmphasisCurve,
+        inverse: bool,
+    ) -> Vec<biquad::Biquad> {
+        let (sections, norm_freq) = BiquadCombo::emphasis_sections(curve);
+        let mut designs: Vec<[f64; 5]> = sections
+            .iter()
+            .map(|section| BiquadCombo::fit_section(fs, section))
+            .collect();
+        if let Some(freq) = norm_freq {
+            let w = 2.0 * std::f64::consts::PI * freq / fs as f64;
+            let gain: f64 = designs
+                .iter()
+                .map(|c| {
+                    let (num, den) = BiquadCombo::digital_gain_squared(c, w);
+                    (num / den).sqrt()
+                })
+                .product();
+            for value in designs[0].iter_mut().take(3) {
+                *value /= gain;
+            }
+        }
+        designs
+            .into_iter()
+            .map(|[b0, b1, b2, a1, a2]| {
+                // The designs are minimum phase, the inverse is made by swapping the numerator and denominator.
+                let coeffs = if inverse {
+                    biquad::BiquadCoefficients::new(
+                        (b1 / b0) as PrcFmt,
+                        (b2 / b0) as PrcFmt,
+                        (1.0 / b0) as PrcFmt,
+                        (a1 / b0) as PrcFmt,
+                        (a2 / b0) as PrcFmt,
+                    )
+                } else {
+                    biquad::BiquadCoefficients::new(
+                        a1 as PrcFmt,
+                        a2 as PrcFmt,
+                        b0 as PrcFmt,
+                        b1 as PrcFmt,
+                        b2 as PrcFmt,
+                    )
+                };
+                biquad::Biquad::new("", fs, coeffs)
+            })
+            .collect()
+    }
+
+    fn make_eqfile(samplerate: usize, eq: eqfile::EqFile) -> Vec<biquad::Biquad> {
+        let mut filters = Vec::with_capacity(eq.filters.len() + 1);
+        if eq.preamp != 0.0 {
+            let gain = (10.0 as PrcFmt).powf(eq.preamp / 20.0);
+            let coeffs = biquad::BiquadCoefficients::new(0.0, 0.0, gain, 0.0, 0.0);
+            filters.push(biquad::Biquad::new("", samplerate, coeffs));
+        }
+        for filtconf in eq.filters {
+            let coeffs = biquad::BiquadCoefficients::from_config(samplerate, filtconf);
+            filters.push(biquad::Biquad::new("", samplerate, coeffs));
         }
         filters
     }
 
+    /// Take over the state of another filter with the same number of sections.
+    /// This allows changing the parameters of a running filter without a click.
+    pub fn copy_state(&mut self, other: &BiquadCombo) {
+        if self.filters.len() == other.filters.len() {
+            for (filter, old) in self.filters.iter_mut().zip(other.filters.iter()) {
+                filter.s1 = old.s1;
+                filter.s2 = old.s2;
+            }
+        }
+    }
+
     pub fn from_config(
         name: &str,
         samplerate: usize,
@@ -180,6 +645,7 @@ impl BiquadCombo {
                     samplerate,
                     name,
                     filters,
+                    graphic: None,
                 }
             }
             config::BiquadComboParameters::LinkwitzRileyLowpass { order, freq } => {
@@ -189,6 +655,7 @@ impl BiquadCombo {
                     samplerate,
                     name,
                     filters,
+                    graphic: None,
                 }
             }
             config::BiquadComboParameters::ButterworthHighpass { order, freq } => {
@@ -198,6 +665,7 @@ impl BiquadCombo {
                     samplerate,
                     name,
                     filters,
+                    graphic: None,
                 }
             }
             config::BiquadComboParameters::ButterworthLowpass { order, freq } => {
@@ -207,6 +675,75 @@ impl BiquadCombo {
                     samplerate,
                     name,
                     filters,
+                    graphic: None,
+                }
+            }
+            config::BiquadComboParameters::BesselHighpass {
+                order,
+                freq,
+                normalization,
+            } => {
+                let poles = BiquadCombo::bessel_poles(
+                    order,
+                    normalization.unwrap_or(config::BesselNormalization::Phase),
+                );
+                let sections = BiquadCombo::pole_sections(&poles);
+                let filters = BiquadCombo::make_sections(samplerate, freq, &sections, true, 1.0);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                    graphic: None,
+                }
+            }
+            config::BiquadComboParameters::BesselLowpass {
+                order,
+                freq,
+                normalization,
+            } => {
+                let poles = BiquadCombo::bessel_poles(
+                    order,
+                    normalization.unwrap_or(config::BesselNormalization::Phase),
+                );
+                let sections = BiquadCombo::pole_sections(&poles);
+                let filters = BiquadCombo::make_sections(samplerate, freq, &sections, false, 1.0);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                    graphic: None,
+                }
+            }
+            config::BiquadComboParameters::ChebyshevHighpass {
+                order,
+                freq,
+                ripple,
+            } => {
+                let poles = BiquadCombo::chebyshev_poles(order, ripple);
+                let sections = BiquadCombo::pole_sections(&poles);
+                let gain = BiquadCombo::chebyshev_gain(order, ripple);
+                let filters = BiquadCombo::make_sections(samplerate, freq, &sections, true, gain);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                    graphic: None,
+                }
+            }
+            config::BiquadComboParameters::ChebyshevLowpass {
+                order,
+                freq,
+                ripple,
+            } => {
+                let poles = BiquadCombo::chebyshev_poles(order, ripple);
+                let sections = BiquadCombo::pole_sections(&poles);
+                let gain = BiquadCombo::chebyshev_gain(order, ripple);
+                let filters = BiquadCombo::make_sections(samplerate, freq, &sections, false, gain);
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                    graphic: None,
                 }
             }
             config::BiquadComboParameters::Tilt { gain } => {
@@ -215,6 +752,7 @@ impl BiquadCombo {
                     samplerate,
                     name,
                     filters,
+                    graphic: None,
                 }
             }
             config::BiquadComboParameters::FivePointPeq {
@@ -244,10 +782,36 @@ impl BiquadCombo {
                     samplerate,
                     name,
                     filters,
+                    graphic: None,
+                }
+            }
+            config::BiquadComboParameters::EqFile(params) => {
+                let filters = match eqfile::read_eq_file(&params.filename, params.channel()) {
+                    Ok(eq) => BiquadCombo::make_eqfile(samplerate, eq),
+                    Err(err) => {
+                        error!("Could not load equalizer file, filter is disabled. {}", err);
+                        Vec::new()
+                    }
+                };
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                    graphic: None,
+                }
+            }
+            config::BiquadComboParameters::Emphasis { curve, inverse } => {
+                let filters =
+                    BiquadCombo::make_emphasis(samplerate, curve, inverse.unwrap_or_default());
+                BiquadCombo {
+                    samplerate,
+                    name,
+                    filters,
+                    graphic: None,
                 }
             }
             config::BiquadComboParameters::GraphicEqualizer(params) => {
-                let filters = BiquadCombo::make_graphic(
+                let graphic = GraphicEqualizer::new(
                     samplerate,
                     params.freq_min(),
                     params.freq_max(),
@@ -256,7 +820,8 @@ impl BiquadCombo {
                 BiquadCombo {
                     samplerate,
                     name,
-                    filters,
+                    filters: Vec::new(),
+                    graphic: Some(graphic),
                 }
             }
         }
@@ -272,6 +837,9 @@ impl Filter for BiquadCombo {
         for filter in self.filters.iter_mut() {
             filter.process_waveform(waveform)?;
         }
+        if let Some(graphic) = &mut self.graphic {
+            graphic.process_waveform(self.samplerate, waveform)?;
+        }
         Ok(())
     }
 
@@ -280,6 +848,15 @@ impl Filter for BiquadCombo {
             parameters: conf, ..
         } = conf
         {
+            // Ramp the gains of a graphic equalizer if only the gains changed.
+            if let (Some(graphic), config::BiquadComboParameters::GraphicEqualizer(params)) =
+                (&mut self.graphic, &conf)
+            {
+                if graphic.is_compatible(params) {
+                    graphic.set_targets(&params.gains);
+                    return;
+                }
+            }
             let name = self.name.clone();
             *self = BiquadCombo::from_config(&name, self.samplerate, conf);
         } else {
@@ -289,43 +866,180 @@ impl Filter for BiquadCombo {
     }
 }
 
+// Highest supported order for Bessel filters.
+// The poles are found numerically, and this gets inaccurate for high orders.
+const MAX_BESSEL_ORDER: usize = 12;
+
+// Solve a small linear system by Gaussian elimination with partial pivoting.
+fn solve_linear<const N: usize>(mut matrix: [[f64; N]; N], mut rhs: [f64; N]) -> Option<[f64; N]> {
+    for col in 0..N {
+        let pivot = (col..N).max_by(|a, b| {
+            matrix[*a][col]
+                .abs()
+                .partial_cmp(&matrix[*b][col].abs())
+                .unwrap_or(std::cmp::Ordering::Equal)
+        })?;
+        if matrix[pivot][col].abs() < 1.0e-300 {
+            return None;
+        }
+        matrix.swap(col, pivot);
+        rhs.swap(col, pivot);
+        for row in (col + 1)..N {
+            let factor = matrix[row][col] / matrix[col][col];
+            let (upper, lower) = matrix.split_at_mut(row);
+            for (value, pivot_value) in lower[0][col..].iter_mut().zip(upper[col][col..].iter()) {
+                *value -= factor * pivot_value;
+            }
+            rhs[row] -= factor * rhs[col];
+        }
+    }
+    let mut solution = [0.0; N];
+    for row in (0..N).rev() {
+        let sum: f64 = ((row + 1)..N).map(|k| matrix[row][k] * solution[k]).sum();
+        solution[row] = (rhs[row] - sum) / matrix[row][row];
+    }
+    Some(solution)
+}
+
+// Check if both roots of c0*z^2 + c1*z + c2 are inside the unit circle.
+fn is_inside_unit_circle(c0: f64, c1: f64, c2: f64) -> bool {
+    let disc = Complex::new(c1 * c1 - 4.0 * c0 * c2, 0.0).sqrt();
+    let root1 = (-c1 + disc) / (2.0 * c0);
+    let root2 = (-c1 - disc) / (2.0 * c0);
+    root1.norm() < 1.0 && root2.norm() < 1.0
+}
+
+// Check that all the sections of a filter have their frequencies below the Nyquist frequency.
+fn validate_sections(
+    maxfreq: PrcFmt,
+    freq: PrcFmt,
+    sections: &[(PrcFmt, PrcFmt)],
+    highpass: bool,
+) -> Res<()> {
+    for (scale, _q) in sections.iter() {
+        let section_freq = if highpass { freq / scale } else { freq * scale };
+        if section_freq >= maxfreq {
+            let msg = format!(
+                "Frequency is too high, a section would be placed at {section_freq:.1} Hz which is above samplerate/2"
+            );
+            return Err(config::ConfigError::for_parameter("freq", &msg).into());
+        }
+    }
+    Ok(())
+}
+
+fn validate_frequency(freq: PrcFmt, maxfreq: PrcFmt) -> Res<()> {
+    if freq <= 0.0 {
+        return Err(config::ConfigError::for_parameter("freq", "Frequency must be > 0").into());
+    } else if freq >= maxfreq {
+        return Err(
+            config::ConfigError::for_parameter("freq", "Frequency must be < samplerate/2").into(),
+        );
+    }
+    Ok(())
+}
+
 /// Validate a BiquadCombo convolution config.
 pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters) -> Res<()> {
     let maxfreq = samplerate as PrcFmt / 2.0;
     match conf {
         config::BiquadComboParameters::LinkwitzRileyHighpass { freq, order }
         | config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order } => {
-            if *freq <= 0.0 {
-                return Err(config::ConfigError::new("Frequency must be > 0").into());
-            } else if *freq >= maxfreq {
-                return Err(config::ConfigError::new("Frequency must be < samplerate/2").into());
-            }
+            validate_frequency(*freq, maxfreq)?;
             if (*order % 2 > 0) || (*order == 0) {
-                return Err(
-                    config::ConfigError::new("LR order must be an even non-zero number").into(),
-                );
+                return Err(config::ConfigError::for_parameter(
+                    "order",
+                    "LR order must be an even non-zero number",
+                )
+                .into());
             }
             Ok(())
         }
         config::BiquadComboParameters::ButterworthHighpass { freq, order }
         | config::BiquadComboParameters::ButterworthLowpass { freq, order } => {
-            if *freq <= 0.0 {
-                return Err(config::ConfigError::new("Frequency must be > 0").into());
-            } else if *freq >= maxfreq {
-                return Err(config::ConfigError::new("Frequency must be < samplerate/2").into());
-            }
+            validate_frequency(*freq, maxfreq)?;
             if *order == 0 {
-                return Err(
-                    config::ConfigError::new("Butterworth order must be larger than zero").into(),
-                );
+                return Err(config::ConfigError::for_parameter(
+                    "order",
+                    "Butterworth order must be larger than zero",
+                )
+                .into());
             }
             Ok(())
         }
+        config::BiquadComboParameters::BesselHighpass {
+            freq,
+            order,
+            normalization,
+        }
+        | config::BiquadComboParameters::BesselLowpass {
+            freq,
+            order,
+            normalization,
+        } => {
+            validate_frequency(*freq, maxfreq)?;
+            if *order == 0 || *order > MAX_BESSEL_ORDER {
+                let msg = format!("Bessel order must be in the range 1 to {MAX_BESSEL_ORDER}");
+                return Err(config::ConfigError::for_parameter("order", &msg).into());
+            }
+            let poles = BiquadCombo::bessel_poles(
+                *order,
+                normalization.unwrap_or(config::BesselNormalization::Phase),
+            );
+            let highpass = matches!(conf, config::BiquadComboParameters::BesselHighpass { .. });
+            validate_sections(
+                maxfreq,
+                *freq,
+                &BiquadCombo::pole_sections(&poles),
+                highpass,
+            )
+        }
+        config::BiquadComboParameters::ChebyshevHighpass {
+            freq,
+            order,
+            ripple,
+        }
+        | config::BiquadComboParameters::ChebyshevLowpass {
+            freq,
+            order,
+            ripple,
+        } => {
+            validate_frequency(*freq, maxfreq)?;
+            if *order == 0 {
+                return Err(config::ConfigError::for_parameter(
+                    "order",
+                    "Chebyshev order must be larger than zero",
+                )
+                .into());
+            }
+            if *ripple <= 0.0 || *ripple > 6.0 {
+                return Err(config::ConfigError::for_parameter(
+                    "ripple",
+                    "Chebyshev ripple must be larger than 0 and at most 6 dB",
+                )
+                .into());
+            }
+            let poles = BiquadCombo::chebyshev_poles(*order, *ripple);
+            let highpass = matches!(
+                conf,
+                config::BiquadComboParameters::ChebyshevHighpass { .. }
+            );
+            validate_sections(
+                maxfreq,
+                *freq,
+                &BiquadCombo::pole_sections(&poles),
+                highpass,
+            )
+        }
         config::BiquadComboParameters::Tilt { gain } => {
             if *gain <= -100.0 {
-                return Err(config::ConfigError::new("Gain must be > -100").into());
+                return Err(
+                    config::ConfigError::for_parameter("gain", "Gain must be > -100").into(),
+                );
             } else if *gain >= 100.0 {
-                return Err(config::ConfigError::new("Gain must be < 100").into());
+                return Err(
+                    config::ConfigError::for_parameter("gain", "Gain must be < 100").into(),
+                );
             }
             Ok(())
         }
@@ -342,36 +1056,95 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
             qhs,
             ..
         } => {
-            if *qls <= 0.0 || *qhs <= 0.0 || *qp1 <= 0.0 || *qp2 <= 0.0 || *qp3 <= 0.0 {
-                return Err(config::ConfigError::new("All Q-values must be > 0").into());
-            } else if *fls >= maxfreq
-                || *fhs >= maxfreq
-                || *fp1 >= maxfreq
-                || *fp2 >= maxfreq
-                || *fp3 >= maxfreq
-            {
-                return Err(config::ConfigError::new("All frequencies must be > 0").into());
+            let qs = [
+                ("qls", qls),
+                ("qp1", qp1),
+                ("qp2", qp2),
+                ("qp3", qp3),
+                ("qhs", qhs),
+            ];
+            if let Some((parameter, _)) = qs.iter().find(|(_, q)| **q <= 0.0) {
+                return Err(config::ConfigError::for_parameter(
+                    parameter,
+                    "All Q-values must be > 0",
+                )
+                .into());
+            }
+            let freqs = [
+                ("fls", fls),
+                ("fp1", fp1),
+                ("fp2", fp2),
+                ("fp3", fp3),
+                ("fhs", fhs),
+            ];
+            if let Some((parameter, _)) = freqs.iter().find(|(_, f)| **f >= maxfreq) {
+                return Err(config::ConfigError::for_parameter(
+                    parameter,
+                    "All frequencies must be > 0",
+                )
+                .into());
+            }
+            Ok(())
+        }
+        config::BiquadComboParameters::EqFile(params) => {
+            let eq = eqfile::read_eq_file(&params.filename, params.channel())?;
+            for (nbr, filtconf) in eq.filters.iter().enumerate() {
+                if let Err(err) = biquad::validate_config(samplerate, filtconf) {
+                    let msg = format!(
+                        "Invalid filter {} in equalizer file '{}'. Reason: {}",
+                        nbr + 1,
+                        params.filename,
+                        err
+                    );
+                    return Err(config::ConfigError::for_parameter("filename", &msg).into());
+                }
+            }
+            Ok(())
+        }
+        config::BiquadComboParameters::Emphasis { curve, .. } => {
+            let (sections, _) = BiquadCombo::emphasis_sections(*curve);
+            for section in sections.iter() {
+                for corner in BiquadCombo::section_corners(section) {
+                    if corner >= samplerate as f64 / 2.0 {
+                        let msg = format!(
+                            "The {curve:?} curve has a corner frequency at {corner:.1} Hz, which is above samplerate/2"
+                        );
+                        return Err(config::ConfigError::for_parameter("curve", &msg).into());
+                    }
+                }
             }
             Ok(())
         }
         config::BiquadComboParameters::GraphicEqualizer(params) => {
-            if params.freq_min() <= 0.0 || params.freq_max() <= 0.0 {
-                return Err(config::ConfigError::new("Min and max requencies must be > 0").into());
-            } else if params.freq_min() >= maxfreq as f32 || params.freq_max() >= maxfreq as f32 {
-                return Err(config::ConfigError::new(
-                    "Min and max frequencies must be < samplerate/2",
-                )
-                .into());
+            for (parameter, freq) in [
+                ("freq_min", params.freq_min()),
+                ("freq_max", params.freq_max()),
+            ] {
+                if freq <= 0.0 {
+                    return Err(config::ConfigError::for_parameter(
+                        parameter,
+                        "Min and max requencies must be > 0",
+                    )
+                    .into());
+                } else if freq >= maxfreq as f32 {
+                    return Err(config::ConfigError::for_parameter(
+                        parameter,
+                        "Min and max frequencies must be < samplerate/2",
+                    )
+                    .into());
+                }
             }
             if params.freq_min() >= params.freq_max() {
-                return Err(config::ConfigError::new(
+                return Err(config::ConfigError::for_parameter(
+                    "freq_min",
                     "Min frequency must be lower than max frequency",
                 )
                 .into());
             }
-            for gain in params.gains.iter() {
-                if *gain > 40.0 || *gain < -40.0 {
-                    return Err(config::ConfigError::new(
+            for (band, gain) in params.gains.iter().enumerate() {
+                if gain.abs() > MAX_GRAPHIC_EQ_GAIN {
+                    return Err(config::ConfigError::for_parameter(
+                        &format!("gains[{band}]"),
                         "Equalizer gains must be withing +- 40 dB",
                     )
                     .into());
@@ -386,7 +1159,9 @@ pub fn validate_config(samplerate: usize, conf: &config::BiquadComboParameters)
 mod tests {
     use crate::biquadcombo;
     use crate::config;
+    use crate::filters::Filter;
     use crate::PrcFmt;
+    use num_complex::Complex;
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{left} - {right}");
@@ -401,6 +1176,28 @@ mod tests {
         }
         true
     }
+
+    // Get the gain and phase of a filter at a frequency, from the DFT of the impulse response.
+    fn gain_and_phase(
+        conf: config::BiquadComboParameters,
+        f: PrcFmt,
+        fs: usize,
+    ) -> (PrcFmt, PrcFmt) {
+        let mut filter = biquadcombo::BiquadCombo::from_config("test", fs, conf);
+        let mut impulse = vec![0.0; 16384];
+        impulse[0] = 1.0;
+        filter.process_waveform(&mut impulse).unwrap();
+        let pi = std::f64::consts::PI as PrcFmt;
+        let dft = impulse
+            .iter()
+            .enumerate()
+            .fold(Complex::new(0.0, 0.0), |acc, (n, v)| {
+                acc + Complex::from_polar(*v, -2.0 * pi * f * n as PrcFmt / fs as PrcFmt)
+            });
+        let (magn, ang) = dft.to_polar();
+        (20.0 * magn.log10(), 180.0 / pi * ang)
+    }
+
     #[test]
     fn make_butterworth_2() {
         let q = biquadcombo::BiquadCombo::butterworth_q(2);
@@ -500,4 +1297,229 @@ mod tests {
         };
         assert!(biquadcombo::validate_config(fs, &badconf4).is_err());
     }
+
+    #[test]
+    fn make_bessel_4() {
+        let poles = biquadcombo::BiquadCombo::bessel_poles(4, config::BesselNormalization::Phase);
+        let mut q: Vec<PrcFmt> = biquadcombo::BiquadCombo::pole_sections(&poles)
+            .iter()
+            .map(|(_scale, q)| *q)
+            .collect();
+        q.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let expect = vec![0.522, 0.806];
+        assert!(q.len() == 2);
+        assert!(compare_vecs(q, expect, 0.001));
+    }
+
+    #[test]
+    fn bessel_response() {
+        let fs = 48000;
+        let conf = config::BiquadComboParameters::BesselLowpass {
+            freq: 1000.0,
+            order: 4,
+            normalization: Some(config::BesselNormalization::Magnitude),
+        };
+        let (gain, _) = gain_and_phase(conf.clone(), 1000.0, fs);
+        assert!(is_close(gain, -3.01, 0.05));
+        let (gain, _) = gain_and_phase(conf, 20.0, fs);
+        assert!(is_close(gain, 0.0, 0.01));
+        // Phase normalized gives half of the total phase shift at the cutoff.
+        let conf = config::BiquadComboParameters::BesselHighpass {
+            freq: 1000.0,
+            order: 4,
+            normalization: None,
+        };
+        let (_, phase) = gain_and_phase(conf, 1000.0, fs);
+        assert!(is_close(phase.abs(), 180.0, 2.0));
+    }
+
+    #[test]
+    fn chebyshev_response() {
+        let fs = 48000;
+        let conf = config::BiquadComboParameters::ChebyshevLowpass {
+            freq: 1000.0,
+            order: 4,
+            ripple: 1.0,
+        };
+        let (gain, _) = gain_and_phase(conf.clone(), 1000.0, fs);
+        assert!(is_close(gain, -1.0, 0.05));
+        let (gain, _) = gain_and_phase(conf.clone(), 10.0, fs);
+        assert!(is_close(gain, -1.0, 0.05));
+        for f in [200.0, 500.0, 700.0, 900.0] {
+            let (gain, _) = gain_and_phase(conf.clone(), f, fs);
+            assert!(gain < 0.01 && gain > -1.05);
+        }
+        let conf = config::BiquadComboParameters::ChebyshevHighpass {
+            freq: 1000.0,
+            order: 3,
+            ripple: 0.5,
+        };
+        let (gain, _) = gain_and_phase(conf.clone(), 1000.0, fs);
+        assert!(is_close(gain, -0.5, 0.05));
+        let (gain, _) = gain_and_phase(conf.clone(), 10000.0, fs);
+        assert!(is_close(gain, 0.0, 0.05));
+        let (gain, _) = gain_and_phase(conf, 500.0, fs);
+        assert!(gain < -15.0);
+    }
+
+    #[test]
+    fn check_bessel_chebyshev() {
+        let fs = 48000;
+        let okconf = config::BiquadComboParameters::BesselLowpass {
+            freq: 1000.0,
+            order: 5,
+            normalization: Some(config::BesselNormalization::Delay),
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let badconf1 = config::BiquadComboParameters::BesselLowpass {
+            freq: 1000.0,
+            order: 13,
+            normalization: None,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf1).is_err());
+        let badconf2 = config::BiquadComboParameters::BesselLowpass {
+            freq: 15000.0,
+            order: 4,
+            normalization: Some(config::BesselNormalization::Delay),
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf2).is_err());
+        let okconf = config::BiquadComboParameters::ChebyshevHighpass {
+            freq: 1000.0,
+            order: 3,
+            ripple: 0.5,
+        };
+        assert!(biquadcombo::validate_config(fs, &okconf).is_ok());
+        let badconf3 = config::BiquadComboParameters::ChebyshevHighpass {
+            freq: 1000.0,
+            order: 3,
+            ripple: 0.0,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf3).is_err());
+        let badconf4 = config::BiquadComboParameters::ChebyshevHighpass {
+            freq: 1000.0,
+            order: 0,
+            ripple: 1.0,
+        };
+        assert!(biquadcombo::validate_config(fs, &badconf4).is_err());
+    }
+
+    #[test]
+    fn riaa_response() {
+        // Standard RIAA playback table, relative to 1 kHz.
+        let table = [
+            (20.0, 19.27),
+            (50.0, 16.95),
+            (100.0, 13.09),
+            (500.0, 2.65),
+            (1000.0, 0.0),
+            (2000.0, -2.59),
+            (5000.0, -8.21),
+            (10000.0, -13.73),
+            (15000.0, -17.16),
+            (20000.0, -19.62),
+        ];
+        for fs in [44100, 96000] {
+            let conf = config::BiquadComboParameters::Emphasis {
+                curve: config::EmphasisCurve::Riaa,
+                inverse: None,
+            };
+            for (f, expected) in table.iter() {
+                let (gain, _) = gain_and_phase(conf.clone(), *f, fs);
+                // The error grows close to the Nyquist frequency, but stays below 0.1 dB at 44.1 kHz.
+                let tolerance = if *f > 10000.0 { 0.1 } else { 0.03 };
+                assert!(is_close(gain, *expected, tolerance));
+            }
+        }
+    }
+
+    #[test]
+    fn cd_deemphasis_response() {
+        let pi = std::f64::consts::PI as PrcFmt;
+        let fs = 44100;
+        let conf = config::BiquadComboParameters::Emphasis {
+            curve: config::EmphasisCurve::Cd,
+            inverse: None,
+        };
+        let inverse = config::BiquadComboParameters::Emphasis {
+            curve: config::EmphasisCurve::Cd,
+            inverse: Some(true),
+        };
+        for f in [100.0, 1000.0, 3183.0, 5000.0, 10610.0, 16000.0, 20000.0] {
+            let w: PrcFmt = 2.0 * pi * f;
+            let expected =
+                10.0 * ((1.0 + (w * 15.0e-6).powi(2)) / (1.0 + (w * 50.0e-6).powi(2))).log10();
+            let (gain, _) = gain_and_phase(conf.clone(), f, fs);
+            assert!(is_close(gain, expected, 0.05));
+            // Emphasis followed by de-emphasis is flat.
+            let (gain_inv, _) = gain_and_phase(inverse.clone(), f, fs);
+            assert!(is_close(gain + gain_inv, 0.0, 0.001));
+        }
+    }
+
+    #[test]
+    fn check_emphasis() {
+        let conf = config::BiquadComboParameters::Emphasis {
+            curve: config::EmphasisCurve::Cd,
+            inverse: None,
+        };
+        assert!(biquadcombo::validate_config(44100, &conf).is_ok());
+        assert!(biquadcombo::validate_config(16000, &conf).is_err());
+    }
+
+    fn graphic_eq(gains: &str) -> config::Filter {
+        let yaml =
+            format!("type: BiquadCombo\nparameters:\n  type: GraphicEqualizer\n  gains: {gains}\n");
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    fn impulse_response(filter: &mut biquadcombo::BiquadCombo) -> Vec<PrcFmt> {
+        let mut impulse = vec![0.0; 1024];
+        impulse[0] = 1.0;
+        filter.process_waveform(&mut impulse).unwrap();
+        impulse
+    }
+
+    #[test]
+    fn graphic_eq_ramps_to_new_gains() {
+        let fs = 44100;
+        let start = graphic_eq("[0.0, 0.0, 3.0, 0.0, 0.0]");
+        let end = graphic_eq("[0.0, -6.0, 3.0, 0.0, 10.0]");
+        let params = |conf: config::Filter| match conf {
+            config::Filter::BiquadCombo { parameters, .. } => parameters,
+            _ => unreachable!(),
+        };
+        let mut filter = biquadcombo::BiquadCombo::from_config("test", fs, params(start));
+        filter.update_parameters(end.clone());
+        // The largest change is 10 dB, which takes 100 steps of 16 samples.
+        let mut waveform = vec![1.0; 1584];
+        filter.process_waveform(&mut waveform).unwrap();
+        let band = &filter.graphic.as_ref().unwrap().bands[4];
+        assert!(band.is_ramping());
+        assert!(is_close(band.gain, 9.9, 1.0e-4));
+        let mut waveform = vec![0.0; 8192];
+        filter.process_waveform(&mut waveform).unwrap();
+        assert!(!filter.graphic.as_ref().unwrap().bands[4].is_ramping());
+
+        let mut reference = biquadcombo::BiquadCombo::from_config("test", fs, params(end));
+        let ramped = impulse_response(&mut filter);
+        let expected = impulse_response(&mut reference);
+        assert!(compare_vecs(ramped, expected, 1.0e-6));
+    }
+
+    #[test]
+    fn graphic_eq_band_turns_off() {
+        let fs = 44100;
+        let conf = |gains: &str| match graphic_eq(gains) {
+            config::Filter::BiquadCombo { parameters, .. } => parameters,
+            _ => unreachable!(),
+        };
+        let mut filter = biquadcombo::BiquadCombo::from_config("test", fs, conf("[0.0, 0.2, 0.0]"));
+        assert!(filter.graphic.as_ref().unwrap().bands[1].filter.is_some());
+        filter.update_parameters(graphic_eq("[0.0, 0.0, 0.0]"));
+        let mut waveform = vec![0.0; 64];
+        filter.process_waveform(&mut waveform).unwrap();
+        let band = &filter.graphic.as_ref().unwrap().bands[1];
+        assert!(!band.is_ramping());
+        assert!(band.filter.is_none());
+    }
 }
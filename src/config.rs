@@ -1,12 +1,21 @@
+use crate::bassmanagement;
 use crate::compressor;
+use crate::crossfeed;
+use crate::crossover;
+use crate::ducker;
 use crate::filters;
+use crate::includes;
+use crate::midside;
 use crate::mixer;
 use crate::noisegate;
+use crate::protection;
+use crate::upmix;
+use crate::vars;
 use crate::wavtools::{find_data_in_wav_stream, WavParams};
 use parking_lot::RwLock;
 use serde::{de, Deserialize, Serialize};
 //use serde_with;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::error;
 use std::fmt;
 use std::fs::File;
@@ -38,6 +47,7 @@ lazy_static! {
 #[derive(Debug)]
 pub struct ConfigError {
     desc: String,
+    parameter: Option<String>,
 }
 
 impl fmt::Display for ConfigError {
@@ -56,6 +66,69 @@ impl ConfigError {
     pub fn new(desc: &str) -> Self {
         ConfigError {
             desc: desc.to_owned(),
+            parameter: None,
+        }
+    }
+
+    /// An error caused by the value of a parameter,
+    /// for example `freq` of a filter or `sections[2]` of a combined filter.
+    pub fn for_parameter(parameter: &str, desc: &str) -> Self {
+        ConfigError {
+            desc: desc.to_owned(),
+            parameter: Some(parameter.to_owned()),
+        }
+    }
+
+    /// The parameter that caused the error, if known.
+    pub fn parameter(&self) -> Option<&str> {
+        self.parameter.as_deref()
+    }
+}
+
+/// The path of the parameter that caused a validation error,
+/// for example `filters.lowpass.parameters.freq`.
+/// Errors that are not about a single parameter get the given path of the parameters.
+pub fn parameter_path(parameters_path: &str, err: &(dyn error::Error + 'static)) -> String {
+    match err
+        .downcast_ref::<ConfigError>()
+        .and_then(|e| e.parameter())
+    {
+        Some(parameter) => format!("{parameters_path}.{parameter}"),
+        None => parameters_path.to_string(),
+    }
+}
+
+/// A problem found when validating a config.
+/// The path tells where in the config the problem is, for example `filters.sub_lp.parameters`.
+/// The line and column are only known when the config was checked from its source text.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ConfigProblem {
+    pub path: String,
+    pub message: String,
+    pub line: Option<usize>,
+    pub column: Option<usize>,
+}
+
+impl ConfigProblem {
+    pub fn new(path: &str, message: &str) -> Self {
+        ConfigProblem {
+            path: path.to_owned(),
+            message: message.to_owned(),
+            line: None,
+            column: None,
+        }
+    }
+}
+
+impl fmt::Display for ConfigProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let (Some(line), Some(column)) = (self.line, self.column) {
+            write!(f, "line {line}, column {column}: ")?;
+        }
+        if self.path.is_empty() {
+            write!(f, "{}", self.message)
+        } else {
+            write!(f, "{}: {}", self.path, self.message)
         }
     }
 }
@@ -285,6 +358,36 @@ impl CaptureDevice {
             CaptureDevice::SignalGenerator { channels, .. } => *channels,
         }
     }
+
+    pub fn labels(&self) -> Option<&Vec<Option<String>>> {
+        match self {
+            #[cfg(target_os = "linux")]
+            CaptureDevice::Alsa { labels, .. } => labels.as_ref(),
+            #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
+            CaptureDevice::Bluez(dev) => dev.labels.as_ref(),
+            #[cfg(feature = "pulse-backend")]
+            CaptureDevice::Pulse { labels, .. } => labels.as_ref(),
+            CaptureDevice::RawFile(dev) => dev.labels.as_ref(),
+            CaptureDevice::WavFile(dev) => dev.labels.as_ref(),
+            CaptureDevice::Stdin(dev) => dev.labels.as_ref(),
+            #[cfg(target_os = "macos")]
+            CaptureDevice::CoreAudio(dev) => dev.labels.as_ref(),
+            #[cfg(target_os = "windows")]
+            CaptureDevice::Wasapi(dev) => dev.labels.as_ref(),
+            #[cfg(all(
+                feature = "cpal-backend",
+                feature = "jack-backend",
+                any(
+                    target_os = "linux",
+                    target_os = "dragonfly",
+                    target_os = "freebsd",
+                    target_os = "netbsd"
+                )
+            ))]
+            CaptureDevice::Jack { labels, .. } => labels.as_ref(),
+            CaptureDevice::SignalGenerator { labels, .. } => labels.as_ref(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -597,6 +700,8 @@ pub struct Devices {
     #[serde(default)]
     pub volume_limit: Option<f32>,
     #[serde(default)]
+    pub crossfade_time: Option<f32>,
+    #[serde(default)]
     pub multithreaded: Option<bool>,
     #[serde(default)]
     pub worker_threads: Option<usize>,
@@ -648,6 +753,10 @@ impl Devices {
         self.volume_limit.unwrap_or(50.0)
     }
 
+    pub fn crossfade_time(&self) -> f32 {
+        self.crossfade_time.unwrap_or(50.0)
+    }
+
     pub fn multithreaded(&self) -> bool {
         self.multithreaded.unwrap_or(false)
     }
@@ -773,6 +882,16 @@ pub enum Filter {
         description: Option<String>,
         parameters: LimiterParameters,
     },
+    DynamicEq {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: DynamicEqParameters,
+    },
+    ToneControls {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: ToneControlsParameters,
+    },
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -788,6 +907,7 @@ pub enum ConvParameters {
         #[serde(deserialize_with = "validate_nonzero_usize")]
         length: usize,
     },
+    LinearPhase(ConvParametersLinearPhase),
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
@@ -830,6 +950,24 @@ impl ConvParametersWav {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ConvParametersLinearPhase {
+    #[serde(default)]
+    pub highpass_freq: Option<PrcFmt>,
+    #[serde(default)]
+    pub lowpass_freq: Option<PrcFmt>,
+    pub slope: PrcFmt,
+    pub length: usize,
+}
+
+impl ConvParametersLinearPhase {
+    /// The latency of the filter in samples.
+    pub fn latency(&self) -> usize {
+        self.length.saturating_sub(1) / 2
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(untagged)]
 pub enum ShelfSteepness {
@@ -955,6 +1093,28 @@ pub enum BiquadComboParameters {
         freq: PrcFmt,
         order: usize,
     },
+    BesselHighpass {
+        freq: PrcFmt,
+        order: usize,
+        #[serde(default)]
+        normalization: Option<BesselNormalization>,
+    },
+    BesselLowpass {
+        freq: PrcFmt,
+        order: usize,
+        #[serde(default)]
+        normalization: Option<BesselNormalization>,
+    },
+    ChebyshevHighpass {
+        freq: PrcFmt,
+        order: usize,
+        ripple: PrcFmt,
+    },
+    ChebyshevLowpass {
+        freq: PrcFmt,
+        order: usize,
+        ripple: PrcFmt,
+    },
     Tilt {
         gain: PrcFmt,
     },
@@ -976,6 +1136,44 @@ pub enum BiquadComboParameters {
         ghs: PrcFmt,
     },
     GraphicEqualizer(GraphicEqualizerParameters),
+    EqFile(EqFileParameters),
+    Emphasis {
+        curve: EmphasisCurve,
+        #[serde(default)]
+        inverse: Option<bool>,
+    },
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum EmphasisCurve {
+    Riaa,
+    Cd,
+    Fm50,
+    Fm75,
+    Nab,
+    IecType1,
+    IecType2,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct EqFileParameters {
+    pub filename: String,
+    #[serde(default)]
+    pub channel: Option<usize>,
+}
+
+impl EqFileParameters {
+    pub fn channel(&self) -> usize {
+        self.channel.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum BesselNormalization {
+    Phase,
+    Delay,
+    Magnitude,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -998,6 +1196,65 @@ impl GraphicEqualizerParameters {
     }
 }
 
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum DynamicEqBandType {
+    Peaking,
+    Lowshelf,
+    Highshelf,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DynamicEqBand {
+    pub r#type: DynamicEqBandType,
+    pub freq: PrcFmt,
+    pub q: PrcFmt,
+    pub threshold: PrcFmt,
+    pub ratio: PrcFmt,
+    pub attack: PrcFmt,
+    pub release: PrcFmt,
+    pub max_gain: PrcFmt,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ToneControlsParameters {
+    #[serde(default)]
+    pub bass_freq: Option<PrcFmt>,
+    #[serde(default)]
+    pub treble_freq: Option<PrcFmt>,
+    #[serde(default)]
+    pub balance_side: Option<BalanceSide>,
+    #[serde(default)]
+    pub ramp_time: Option<f32>,
+}
+
+impl ToneControlsParameters {
+    pub fn bass_freq(&self) -> PrcFmt {
+        self.bass_freq.unwrap_or(100.0)
+    }
+
+    pub fn treble_freq(&self) -> PrcFmt {
+        self.treble_freq.unwrap_or(10000.0)
+    }
+
+    pub fn ramp_time(&self) -> f32 {
+        self.ramp_time.unwrap_or(200.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum BalanceSide {
+    Left,
+    Right,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct DynamicEqParameters {
+    pub bands: Vec<DynamicEqBand>,
+}
+
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
 pub enum VolumeFader {
     Aux1 = 1,
@@ -1108,6 +1365,10 @@ pub struct DelayParameters {
     pub unit: Option<TimeUnit>,
     #[serde(default)]
     pub subsample: Option<bool>,
+    #[serde(default)]
+    pub interpolation: Option<DelayInterpolation>,
+    #[serde(default)]
+    pub order: Option<usize>,
 }
 
 impl DelayParameters {
@@ -1118,6 +1379,33 @@ impl DelayParameters {
     pub fn subsample(&self) -> bool {
         self.subsample.unwrap_or_default()
     }
+
+    /// The interpolation used for the fractional part of the delay.
+    /// Setting `subsample` without an interpolation gives the first order allpass.
+    pub fn interpolation(&self) -> Option<DelayInterpolation> {
+        self.interpolation.or(if self.subsample() {
+            Some(DelayInterpolation::Allpass)
+        } else {
+            None
+        })
+    }
+
+    pub fn order(&self) -> usize {
+        self.order.unwrap_or(match self.interpolation() {
+            Some(DelayInterpolation::Sinc) => 16,
+            Some(DelayInterpolation::Allpass) => 1,
+            _ => 3,
+        })
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum DelayInterpolation {
+    Allpass,
+    Lagrange,
+    Thiran,
+    Sinc,
 }
 
 #[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
@@ -1245,6 +1533,98 @@ pub struct Mixer {
     pub labels: Option<Vec<Option<String>>>,
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+#[serde(deny_unknown_fields)]
+pub enum CrossoverFilter {
+    LinkwitzRiley {
+        order: usize,
+    },
+    Butterworth {
+        order: usize,
+    },
+    Bessel {
+        order: usize,
+        #[serde(default)]
+        normalization: Option<BesselNormalization>,
+    },
+    Chebyshev {
+        order: usize,
+        ripple: PrcFmt,
+    },
+    LinearPhase {
+        slope: PrcFmt,
+        length: usize,
+    },
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CrossoverWay {
+    #[serde(default)]
+    pub gain: Option<PrcFmt>,
+    #[serde(default)]
+    pub delay: Option<PrcFmt>,
+    #[serde(default)]
+    pub inverted: Option<bool>,
+}
+
+impl CrossoverWay {
+    pub fn gain(&self) -> PrcFmt {
+        self.gain.unwrap_or_default()
+    }
+
+    pub fn delay(&self) -> PrcFmt {
+        self.delay.unwrap_or_default()
+    }
+
+    pub fn is_inverted(&self) -> bool {
+        self.inverted.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Crossover {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub channels: usize,
+    #[serde(default)]
+    pub inputs: Option<Vec<usize>>,
+    pub ways: usize,
+    pub freqs: Vec<PrcFmt>,
+    pub filter: CrossoverFilter,
+    #[serde(default)]
+    pub highpass_filter: Option<CrossoverFilter>,
+    #[serde(default)]
+    pub settings: Option<Vec<CrossoverWay>>,
+}
+
+impl Crossover {
+    pub fn inputs(&self) -> Vec<usize> {
+        self.inputs
+            .clone()
+            .unwrap_or_else(|| (0..self.channels).collect())
+    }
+
+    pub fn highpass_filter(&self) -> &CrossoverFilter {
+        self.highpass_filter.as_ref().unwrap_or(&self.filter)
+    }
+
+    pub fn settings(&self) -> Vec<CrossoverWay> {
+        self.settings.clone().unwrap_or_else(|| {
+            vec![
+                CrossoverWay {
+                    gain: None,
+                    delay: None,
+                    inverted: None,
+                };
+                self.ways
+            ]
+        })
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(tag = "type")]
 #[serde(deny_unknown_fields)]
@@ -1259,6 +1639,53 @@ pub enum Processor {
         description: Option<String>,
         parameters: NoiseGateParameters,
     },
+    Ducker {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: DuckerParameters,
+    },
+    SpeakerProtection {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: SpeakerProtectionParameters,
+    },
+    Crossfeed {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: CrossfeedParameters,
+    },
+    MidSide {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: MidSideParameters,
+    },
+    Upmix {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: UpmixParameters,
+    },
+    BassManagement {
+        #[serde(default)]
+        description: Option<String>,
+        parameters: BassManagementParameters,
+    },
+}
+
+impl Processor {
+    /// Get the number of channels after the processor.
+    /// This is the same as the input for all processors except Upmix.
+    pub fn channels_out(&self) -> usize {
+        match self {
+            Processor::Compressor { parameters, .. } => parameters.channels,
+            Processor::NoiseGate { parameters, .. } => parameters.channels,
+            Processor::Ducker { parameters, .. } => parameters.channels,
+            Processor::SpeakerProtection { parameters, .. } => parameters.channels,
+            Processor::Crossfeed { parameters, .. } => parameters.channels,
+            Processor::MidSide { parameters, .. } => parameters.channels,
+            Processor::Upmix { parameters, .. } => parameters.layout.channels(),
+            Processor::BassManagement { parameters, .. } => parameters.channels,
+        }
+    }
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -1325,47 +1752,335 @@ impl NoiseGateParameters {
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct LimiterParameters {
+pub struct DuckerParameters {
+    pub channels: usize,
+    pub monitor_channels: Vec<usize>,
     #[serde(default)]
-    pub soft_clip: Option<bool>,
+    pub process_channels: Option<Vec<usize>>,
+    pub attack: PrcFmt,
     #[serde(default)]
-    pub clip_limit: PrcFmt,
+    pub hold: Option<PrcFmt>,
+    pub release: PrcFmt,
+    pub threshold: PrcFmt,
+    pub depth: PrcFmt,
 }
 
-impl LimiterParameters {
-    pub fn soft_clip(&self) -> bool {
-        self.soft_clip.unwrap_or_default()
+impl DuckerParameters {
+    pub fn process_channels(&self) -> Vec<usize> {
+        self.process_channels.clone().unwrap_or_default()
     }
-}
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
-#[serde(tag = "type")]
-#[serde(deny_unknown_fields)]
-pub enum PipelineStep {
-    Mixer(PipelineStepMixer),
-    Filter(PipelineStepFilter),
-    Processor(PipelineStepProcessor),
+    pub fn hold(&self) -> PrcFmt {
+        self.hold.unwrap_or_default()
+    }
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(deny_unknown_fields)]
-pub struct PipelineStepMixer {
-    pub name: String,
-    #[serde(default)]
-    pub description: Option<String>,
-    #[serde(default)]
-    pub bypassed: Option<bool>,
+pub enum ProtectionAction {
+    Limit,
+    Mute,
 }
 
-impl PipelineStepMixer {
-    pub fn is_bypassed(&self) -> bool {
-        self.bypassed.unwrap_or_default()
-    }
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct ThermalParameters {
+    pub power: PrcFmt,
+    pub resistance: PrcFmt,
+    pub time_constant: PrcFmt,
+    pub max_rise: PrcFmt,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
-pub struct PipelineStepFilter {
+pub struct SpeakerProtectionParameters {
+    pub channels: usize,
+    #[serde(default)]
+    pub process_channels: Option<Vec<usize>>,
+    #[serde(default)]
+    pub dc_cutoff: Option<PrcFmt>,
+    #[serde(default)]
+    pub dc_threshold: Option<PrcFmt>,
+    #[serde(default)]
+    pub dc_time: Option<PrcFmt>,
+    #[serde(default)]
+    pub thermal: Option<ThermalParameters>,
+    #[serde(default)]
+    pub action: Option<ProtectionAction>,
+    #[serde(default)]
+    pub attenuation: Option<PrcFmt>,
+}
+
+impl SpeakerProtectionParameters {
+    pub fn process_channels(&self) -> Vec<usize> {
+        self.process_channels.clone().unwrap_or_default()
+    }
+
+    pub fn dc_cutoff(&self) -> PrcFmt {
+        self.dc_cutoff.unwrap_or(10.0)
+    }
+
+    pub fn dc_threshold(&self) -> PrcFmt {
+        self.dc_threshold.unwrap_or(-30.0)
+    }
+
+    pub fn dc_time(&self) -> PrcFmt {
+        self.dc_time.unwrap_or(1.0)
+    }
+
+    pub fn action(&self) -> ProtectionAction {
+        self.action.clone().unwrap_or(ProtectionAction::Mute)
+    }
+
+    pub fn attenuation(&self) -> PrcFmt {
+        self.attenuation.unwrap_or(20.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum CrossfeedPreset {
+    Bs2b,
+    ChuMoy,
+    JanMeier,
+}
+
+impl CrossfeedPreset {
+    /// Get the cutoff frequency in Hz and the feed level in dB of a preset.
+    pub fn values(&self) -> (PrcFmt, PrcFmt) {
+        match self {
+            CrossfeedPreset::Bs2b => (700.0, 4.5),
+            CrossfeedPreset::ChuMoy => (700.0, 6.0),
+            CrossfeedPreset::JanMeier => (650.0, 9.5),
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct CrossfeedParameters {
+    pub channels: usize,
+    #[serde(default)]
+    pub left: Option<usize>,
+    #[serde(default)]
+    pub right: Option<usize>,
+    #[serde(default)]
+    pub preset: Option<CrossfeedPreset>,
+    #[serde(default)]
+    pub cutoff: Option<PrcFmt>,
+    #[serde(default)]
+    pub feed_level: Option<PrcFmt>,
+    #[serde(default)]
+    pub delay: Option<PrcFmt>,
+}
+
+impl CrossfeedParameters {
+    pub fn left(&self) -> usize {
+        self.left.unwrap_or(0)
+    }
+
+    pub fn right(&self) -> usize {
+        self.right.unwrap_or(1)
+    }
+
+    pub fn preset(&self) -> CrossfeedPreset {
+        self.preset.unwrap_or(CrossfeedPreset::Bs2b)
+    }
+
+    pub fn cutoff(&self) -> PrcFmt {
+        self.cutoff.unwrap_or(self.preset().values().0)
+    }
+
+    pub fn feed_level(&self) -> PrcFmt {
+        self.feed_level.unwrap_or(self.preset().values().1)
+    }
+
+    pub fn delay(&self) -> PrcFmt {
+        self.delay.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct MidSideParameters {
+    pub channels: usize,
+    #[serde(default)]
+    pub left: Option<usize>,
+    #[serde(default)]
+    pub right: Option<usize>,
+    #[serde(default)]
+    pub mid_filters: Option<Vec<String>>,
+    #[serde(default)]
+    pub side_filters: Option<Vec<String>>,
+    #[serde(default)]
+    pub width: Option<PrcFmt>,
+}
+
+impl MidSideParameters {
+    pub fn left(&self) -> usize {
+        self.left.unwrap_or(0)
+    }
+
+    pub fn right(&self) -> usize {
+        self.right.unwrap_or(1)
+    }
+
+    pub fn mid_filters(&self) -> Vec<String> {
+        self.mid_filters.clone().unwrap_or_default()
+    }
+
+    pub fn side_filters(&self) -> Vec<String> {
+        self.side_filters.clone().unwrap_or_default()
+    }
+
+    pub fn width(&self) -> PrcFmt {
+        self.width.unwrap_or(1.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum UpmixLayout {
+    #[serde(rename = "5.1")]
+    Surround51,
+    #[serde(rename = "7.1")]
+    Surround71,
+}
+
+impl UpmixLayout {
+    pub fn channels(&self) -> usize {
+        match self {
+            UpmixLayout::Surround51 => 6,
+            UpmixLayout::Surround71 => 8,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum UpmixMode {
+    Passive,
+    Adaptive,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct UpmixParameters {
+    pub channels: usize,
+    pub layout: UpmixLayout,
+    #[serde(default)]
+    pub mode: Option<UpmixMode>,
+    #[serde(default)]
+    pub center_width: Option<PrcFmt>,
+    #[serde(default)]
+    pub surround_delay: Option<PrcFmt>,
+    #[serde(default)]
+    pub lfe_cutoff: Option<PrcFmt>,
+}
+
+impl UpmixParameters {
+    pub fn mode(&self) -> UpmixMode {
+        self.mode.unwrap_or(UpmixMode::Passive)
+    }
+
+    pub fn center_width(&self) -> PrcFmt {
+        self.center_width.unwrap_or(0.5)
+    }
+
+    pub fn surround_delay(&self) -> PrcFmt {
+        self.surround_delay.unwrap_or(10.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub enum SpeakerType {
+    Large,
+    Small,
+    Subwoofer,
+    Unused,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct BassManagementParameters {
+    pub channels: usize,
+    pub speakers: Vec<SpeakerType>,
+    #[serde(default)]
+    pub lfe_channel: Option<usize>,
+    pub crossover_freq: PrcFmt,
+    #[serde(default)]
+    pub order: Option<usize>,
+    #[serde(default)]
+    pub lfe_gain: Option<PrcFmt>,
+}
+
+impl BassManagementParameters {
+    pub fn order(&self) -> usize {
+        self.order.unwrap_or(4)
+    }
+
+    pub fn lfe_gain(&self) -> PrcFmt {
+        self.lfe_gain.unwrap_or(10.0)
+    }
+
+    /// Get the channels of the given speaker type.
+    pub fn channels_of_type(&self, speaker_type: SpeakerType) -> Vec<usize> {
+        self.speakers
+            .iter()
+            .enumerate()
+            .filter(|(_, t)| **t == speaker_type)
+            .map(|(ch, _)| ch)
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct LimiterParameters {
+    #[serde(default)]
+    pub soft_clip: Option<bool>,
+    #[serde(default)]
+    pub clip_limit: PrcFmt,
+}
+
+impl LimiterParameters {
+    pub fn soft_clip(&self) -> bool {
+        self.soft_clip.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(tag = "type")]
+#[serde(deny_unknown_fields)]
+pub enum PipelineStep {
+    Mixer(PipelineStepMixer),
+    Filter(PipelineStepFilter),
+    Processor(PipelineStepProcessor),
+    Crossover(PipelineStepCrossover),
+    Branch(PipelineStepBranch),
+    Fragment(PipelineStepFragment),
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStepMixer {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub bypassed: Option<bool>,
+}
+
+impl PipelineStepMixer {
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStepFilter {
     #[serde(default)]
     pub channels: Option<Vec<usize>>,
     pub names: Vec<String>,
@@ -1397,6 +2112,85 @@ impl PipelineStepProcessor {
     }
 }
 
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStepCrossover {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub bypassed: Option<bool>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStepFragment {
+    pub name: String,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub bypassed: Option<bool>,
+}
+
+impl PipelineStepFragment {
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineStepBranch {
+    #[serde(default)]
+    pub channels: Option<Vec<usize>>,
+    pub branches: Vec<PipelineBranch>,
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub bypassed: Option<bool>,
+}
+
+impl PipelineStepBranch {
+    pub fn is_bypassed(&self) -> bool {
+        self.bypassed.unwrap_or_default()
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineBranch {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub gain: Option<PrcFmt>,
+    #[serde(default)]
+    pub inverted: Option<bool>,
+    #[serde(default)]
+    pub mute: Option<bool>,
+    #[serde(default)]
+    pub scale: Option<GainScale>,
+    #[serde(default)]
+    pub pipeline: Vec<PipelineStep>,
+}
+
+impl PipelineBranch {
+    pub fn gain(&self) -> PrcFmt {
+        self.gain.unwrap_or_default()
+    }
+
+    pub fn is_inverted(&self) -> bool {
+        self.inverted.unwrap_or_default()
+    }
+
+    pub fn is_mute(&self) -> bool {
+        self.mute.unwrap_or_default()
+    }
+
+    pub fn scale(&self) -> GainScale {
+        self.scale.unwrap_or(GainScale::Decibel)
+    }
+}
+
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
 pub struct Configuration {
@@ -1404,15 +2198,92 @@ pub struct Configuration {
     pub title: Option<String>,
     #[serde(default)]
     pub description: Option<String>,
+    #[serde(default)]
+    pub include: Option<Vec<Include>>,
+    #[serde(default)]
+    pub vars: Option<HashMap<String, serde_yaml::Value>>,
     pub devices: Devices,
     #[serde(default)]
     pub mixers: Option<HashMap<String, Mixer>>,
     #[serde(default)]
+    pub crossovers: Option<HashMap<String, Crossover>>,
+    #[serde(default)]
     pub filters: Option<HashMap<String, Filter>>,
     #[serde(default)]
     pub processors: Option<HashMap<String, Processor>>,
     #[serde(default)]
+    pub fragments: Option<HashMap<String, Vec<PipelineStep>>>,
+    #[serde(default)]
     pub pipeline: Option<Vec<PipelineStep>>,
+    #[serde(default)]
+    pub variants: Option<HashMap<String, PipelineVariant>>,
+    #[serde(skip)]
+    pub source: ConfigSource,
+}
+
+/// The config as written, before evaluating the variables.
+/// It is ignored when comparing configs, so that configs that only differ
+/// in how they are written are considered equal.
+#[derive(Clone, Debug, Default)]
+pub struct ConfigSource(pub Option<String>);
+
+impl PartialEq for ConfigSource {
+    fn eq(&self, _other: &Self) -> bool {
+        true
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct Include {
+    pub path: String,
+    #[serde(default)]
+    pub conflicts: Option<IncludeConflict>,
+}
+
+impl Include {
+    pub fn conflicts(&self) -> IncludeConflict {
+        self.conflicts.unwrap_or(IncludeConflict::Error)
+    }
+}
+
+/// How to handle an included definition with the same name as an existing one.
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, Eq, PartialEq)]
+pub enum IncludeConflict {
+    Error,
+    Keep,
+    Replace,
+}
+
+/// The contents of an included file.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct IncludedConfig {
+    #[serde(default)]
+    pub description: Option<String>,
+    #[serde(default)]
+    pub include: Option<Vec<Include>>,
+    #[serde(default)]
+    pub mixers: Option<HashMap<String, Mixer>>,
+    #[serde(default)]
+    pub crossovers: Option<HashMap<String, Crossover>>,
+    #[serde(default)]
+    pub filters: Option<HashMap<String, Filter>>,
+    #[serde(default)]
+    pub processors: Option<HashMap<String, Processor>>,
+    #[serde(default)]
+    pub fragments: Option<HashMap<String, Vec<PipelineStep>>>,
+}
+
+/// The name used for selecting the main pipeline when the config has pipeline variants.
+pub const DEFAULT_VARIANT: &str = "default";
+
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PipelineVariant {
+    #[serde(default)]
+    pub description: Option<String>,
+    pub pipeline: Vec<PipelineStep>,
 }
 
 fn validate_nonzero_usize<'de, D>(d: D) -> Result<usize, D::Error>
@@ -1446,7 +2317,7 @@ pub fn load_config(filename: &str) -> Res<Configuration> {
             return Err(ConfigError::new(&msg).into());
         }
     };
-    let configuration: Configuration = match serde_yaml::from_str(&contents) {
+    let configuration = match parse_config(&contents) {
         Ok(config) => config,
         Err(err) => {
             let msg = format!("Invalid config file!\n{err}");
@@ -1456,6 +2327,27 @@ pub fn load_config(filename: &str) -> Res<Configuration> {
     Ok(configuration)
 }
 
+/// Parse a config from a yaml string, and evaluate the variables.
+pub fn parse_config(contents: &str) -> Res<Configuration> {
+    if !vars::has_references(contents) {
+        // Nothing to substitute, parse directly to get the locations of any errors.
+        let mut configuration: Configuration = serde_yaml::from_str(contents)?;
+        configuration.source = ConfigSource(Some(contents.to_string()));
+        return Ok(configuration);
+    }
+    let value: serde_yaml::Value = serde_yaml::from_str(contents)?;
+    config_from_value(value, contents)
+}
+
+/// Create a config from a yaml or json value, and evaluate the variables.
+/// The source is kept, to be returned by the `GetConfigRaw` websocket command.
+pub fn config_from_value(mut value: serde_yaml::Value, source: &str) -> Res<Configuration> {
+    vars::resolve_vars(&mut value)?;
+    let mut configuration: Configuration = serde_yaml::from_value(value)?;
+    configuration.source = ConfigSource(Some(source.to_string()));
+    Ok(configuration)
+}
+
 fn apply_overrides(configuration: &mut Configuration) {
     let mut overrides = OVERRIDES.read().clone();
     // Only one match arm for now, might be more later.
@@ -1628,7 +2520,7 @@ fn apply_overrides(configuration: &mut Configuration) {
     }
 }
 
-fn replace_tokens(string: &str, samplerate: usize, channels: usize) -> String {
+pub fn replace_tokens(string: &str, samplerate: usize, channels: usize) -> String {
     let srate = format!("{samplerate}");
     let ch = format!("{channels}");
     string
@@ -1640,37 +2532,78 @@ fn replace_tokens_in_config(config: &mut Configuration) {
     let samplerate = config.devices.samplerate;
     let num_channels = config.devices.capture.channels();
     if let Some(filters) = &mut config.filters {
-        for (_name, filter) in filters.iter_mut() {
-            match filter {
-                Filter::Conv {
-                    parameters: ConvParameters::Raw(params),
-                    ..
-                } => {
-                    params.filename = replace_tokens(&params.filename, samplerate, num_channels);
-                }
-                Filter::Conv {
-                    parameters: ConvParameters::Wav(params),
-                    ..
-                } => {
-                    params.filename = replace_tokens(&params.filename, samplerate, num_channels);
-                }
-                _ => {}
+        replace_tokens_in_filters(filters, samplerate, num_channels);
+    }
+    if let Some(pipeline) = &mut config.pipeline {
+        replace_tokens_in_steps(pipeline, samplerate, num_channels);
+    }
+    if let Some(variants) = &mut config.variants {
+        for variant in variants.values_mut() {
+            replace_tokens_in_steps(&mut variant.pipeline, samplerate, num_channels);
+        }
+    }
+    if let Some(fragments) = &mut config.fragments {
+        for steps in fragments.values_mut() {
+            replace_tokens_in_steps(steps, samplerate, num_channels);
+        }
+    }
+}
+
+/// Replace the tokens in the coefficient file names of a set of filters.
+pub fn replace_tokens_in_filters(
+    filters: &mut HashMap<String, Filter>,
+    samplerate: usize,
+    num_channels: usize,
+) {
+    for (_name, filter) in filters.iter_mut() {
+        match filter {
+            Filter::Conv {
+                parameters: ConvParameters::Raw(params),
+                ..
+            } => {
+                params.filename = replace_tokens(&params.filename, samplerate, num_channels);
             }
+            Filter::Conv {
+                parameters: ConvParameters::Wav(params),
+                ..
+            } => {
+                params.filename = replace_tokens(&params.filename, samplerate, num_channels);
+            }
+            Filter::BiquadCombo {
+                parameters: BiquadComboParameters::EqFile(params),
+                ..
+            } => {
+                params.filename = replace_tokens(&params.filename, samplerate, num_channels);
+            }
+            _ => {}
         }
     }
-    if let Some(pipeline) = &mut config.pipeline {
-        for mut step in pipeline.iter_mut() {
-            match &mut step {
-                PipelineStep::Filter(step) => {
-                    for name in step.names.iter_mut() {
-                        *name = replace_tokens(name, samplerate, num_channels);
-                    }
-                }
-                PipelineStep::Mixer(step) => {
-                    step.name = replace_tokens(&step.name, samplerate, num_channels);
+}
+
+/// Replace the tokens in the names used by a list of pipeline steps.
+pub fn replace_tokens_in_steps(steps: &mut [PipelineStep], samplerate: usize, num_channels: usize) {
+    for mut step in steps.iter_mut() {
+        match &mut step {
+            PipelineStep::Filter(step) => {
+                for name in step.names.iter_mut() {
+                    *name = replace_tokens(name, samplerate, num_channels);
                 }
-                PipelineStep::Processor(step) => {
-                    step.name = replace_tokens(&step.name, samplerate, num_channels);
+            }
+            PipelineStep::Mixer(step) => {
+                step.name = replace_tokens(&step.name, samplerate, num_channels);
+            }
+            PipelineStep::Processor(step) => {
+                step.name = replace_tokens(&step.name, samplerate, num_channels);
+            }
+            PipelineStep::Crossover(step) => {
+                step.name = replace_tokens(&step.name, samplerate, num_channels);
+            }
+            PipelineStep::Fragment(step) => {
+                step.name = replace_tokens(&step.name, samplerate, num_channels);
+            }
+            PipelineStep::Branch(step) => {
+                for branch in step.branches.iter_mut() {
+                    replace_tokens_in_steps(&mut branch.pipeline, samplerate, num_channels);
                 }
             }
         }
@@ -1682,21 +2615,7 @@ fn replace_relative_paths_in_config(config: &mut Configuration, configname: &str
     if let Ok(config_file) = PathBuf::from(configname.to_owned()).canonicalize() {
         if let Some(config_dir) = config_file.parent() {
             if let Some(filters) = &mut config.filters {
-                for (_name, filter) in filters.iter_mut() {
-                    if let Filter::Conv {
-                        parameters: ConvParameters::Raw(params),
-                        ..
-                    } = filter
-                    {
-                        check_and_replace_relative_path(&mut params.filename, config_dir);
-                    } else if let Filter::Conv {
-                        parameters: ConvParameters::Wav(params),
-                        ..
-                    } = filter
-                    {
-                        check_and_replace_relative_path(&mut params.filename, config_dir);
-                    }
-                }
+                replace_relative_paths_in_filters(filters, config_dir);
             }
         } else {
             warn!("Can't find parent directory of config file");
@@ -1706,6 +2625,32 @@ fn replace_relative_paths_in_config(config: &mut Configuration, configname: &str
     }
 }
 
+/// Make the coefficient file paths of a set of filters relative to a config file directory,
+/// for the files that are found there.
+pub fn replace_relative_paths_in_filters(filters: &mut HashMap<String, Filter>, config_dir: &Path) {
+    for (_name, filter) in filters.iter_mut() {
+        if let Filter::Conv {
+            parameters: ConvParameters::Raw(params),
+            ..
+        } = filter
+        {
+            check_and_replace_relative_path(&mut params.filename, config_dir);
+        } else if let Filter::Conv {
+            parameters: ConvParameters::Wav(params),
+            ..
+        } = filter
+        {
+            check_and_replace_relative_path(&mut params.filename, config_dir);
+        } else if let Filter::BiquadCombo {
+            parameters: BiquadComboParameters::EqFile(params),
+            ..
+        } = filter
+        {
+            check_and_replace_relative_path(&mut params.filename, config_dir);
+        }
+    }
+}
+
 fn check_and_replace_relative_path(path_str: &mut String, config_path: &Path) {
     let path = PathBuf::from(path_str.to_owned());
     if path.is_absolute() {
@@ -1739,6 +2684,149 @@ pub enum ConfigChange {
     None,
 }
 
+/// Identifies a filter in the pipeline by the channel it is applied to, the filter name,
+/// and the number of earlier uses of the same filter on that channel.
+/// This is only unique for the steps before the first difference between two pipelines,
+/// where the filters get the same input in both.
+#[derive(Clone, Debug, Hash, PartialEq, Eq)]
+pub struct FilterInstance {
+    pub channel: usize,
+    pub name: String,
+    pub index: usize,
+}
+
+/// Counts the uses of each filter on each channel, to identify the filters of a pipeline.
+#[derive(Default)]
+pub struct FilterInstanceCounter {
+    counts: HashMap<(usize, String), usize>,
+}
+
+impl FilterInstanceCounter {
+    pub fn next(&mut self, channel: usize, name: &str) -> FilterInstance {
+        let count = self.counts.entry((channel, name.to_string())).or_insert(0);
+        let instance = FilterInstance {
+            channel,
+            name: name.to_string(),
+            index: *count,
+        };
+        *count += 1;
+        instance
+    }
+}
+
+/// List the filters of the first `nbr_steps` steps of the pipeline, in processing order.
+/// Filters inside branches are not listed, they are always rebuilt.
+/// The same goes for all filters of a config with pipeline variants.
+pub fn pipeline_filter_instances(conf: &Configuration, nbr_steps: usize) -> Vec<FilterInstance> {
+    let mut instances = Vec::new();
+    if conf.variants.as_ref().is_some_and(|v| !v.is_empty()) {
+        // Pipelines with variants are rebuilt completely, and crossfaded.
+        return instances;
+    }
+    let mut counter = FilterInstanceCounter::default();
+    let mut num_channels = conf.devices.capture.channels();
+    for step in conf.pipeline.iter().flatten().take(nbr_steps) {
+        match step {
+            PipelineStep::Mixer(step) => {
+                if let Some(mixconf) = conf.mixers.as_ref().and_then(|m| m.get(&step.name)) {
+                    if !step.is_bypassed() {
+                        num_channels = mixconf.channels.out;
+                    }
+                }
+            }
+            PipelineStep::Filter(step) => {
+                if !step.is_bypassed() {
+                    let channels = step
+                        .channels
+                        .clone()
+                        .unwrap_or_else(|| (0..num_channels).collect());
+                    for channel in channels {
+                        for name in step.names.iter() {
+                            instances.push(counter.next(channel, name));
+                        }
+                    }
+                }
+            }
+            PipelineStep::Processor(step) => {
+                if let Some(procconf) = conf.processors.as_ref().and_then(|p| p.get(&step.name)) {
+                    if !step.is_bypassed() {
+                        num_channels = procconf.channels_out();
+                    }
+                }
+            }
+            PipelineStep::Crossover(_) | PipelineStep::Branch(_) | PipelineStep::Fragment(_) => {}
+        }
+    }
+    instances
+}
+
+// Check if a pipeline step is the same in both configs,
+// including the definitions of the filters, mixers and processors it uses.
+fn step_is_unchanged(
+    currentconf: &Configuration,
+    newconf: &Configuration,
+    current: &PipelineStep,
+    new: &PipelineStep,
+) -> bool {
+    if current != new {
+        return false;
+    }
+    let same_filter = |name: &String| {
+        let current_filter = currentconf.filters.as_ref().and_then(|f| f.get(name));
+        current_filter.is_some()
+            && current_filter == newconf.filters.as_ref().and_then(|f| f.get(name))
+    };
+    match current {
+        PipelineStep::Mixer(step) => {
+            currentconf.mixers.as_ref().and_then(|m| m.get(&step.name))
+                == newconf.mixers.as_ref().and_then(|m| m.get(&step.name))
+        }
+        PipelineStep::Filter(step) => step.names.iter().all(same_filter),
+        PipelineStep::Processor(step) => {
+            let current_proc = currentconf
+                .processors
+                .as_ref()
+                .and_then(|p| p.get(&step.name));
+            let new_proc = newconf.processors.as_ref().and_then(|p| p.get(&step.name));
+            current_proc == new_proc
+                && match current_proc {
+                    Some(Processor::MidSide { parameters, .. }) => parameters
+                        .mid_filters()
+                        .iter()
+                        .chain(parameters.side_filters().iter())
+                        .all(same_filter),
+                    _ => true,
+                }
+        }
+        PipelineStep::Branch(step) => step.branches.iter().all(|branch| {
+            branch
+                .pipeline
+                .iter()
+                .all(|step| step_is_unchanged(currentconf, newconf, step, step))
+        }),
+        PipelineStep::Crossover(_) | PipelineStep::Fragment(_) => false,
+    }
+}
+
+/// Find the filters that can be moved to a rebuilt pipeline, and keep their state.
+/// These are the filters of the leading steps that are unchanged between the two pipelines.
+/// Everything before these filters is also unchanged, so they get the same input in both pipelines.
+pub fn unchanged_filters(
+    currentconf: &Configuration,
+    newconf: &Configuration,
+) -> HashSet<FilterInstance> {
+    let current_steps = currentconf.pipeline.as_deref().unwrap_or_default();
+    let new_steps = newconf.pipeline.as_deref().unwrap_or_default();
+    let nbr_unchanged = current_steps
+        .iter()
+        .zip(new_steps.iter())
+        .take_while(|(current, new)| step_is_unchanged(currentconf, newconf, current, new))
+        .count();
+    pipeline_filter_instances(newconf, nbr_unchanged)
+        .into_iter()
+        .collect()
+}
+
 pub fn load_validate_config(configname: &str) -> Res<Configuration> {
     let mut configuration = load_config(configname)?;
     validate_config(&mut configuration, Some(configname))?;
@@ -1752,7 +2840,7 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
     if currentconf.devices != newconf.devices {
         return ConfigChange::Devices;
     }
-    if currentconf.pipeline != newconf.pipeline {
+    if currentconf.pipeline != newconf.pipeline || currentconf.variants != newconf.variants {
         return ConfigChange::Pipeline;
     }
     if currentconf.mixers != newconf.mixers {
@@ -1775,7 +2863,9 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
                     | (Filter::Dither { .. }, Filter::Dither { .. })
                     | (Filter::DiffEq { .. }, Filter::DiffEq { .. })
                     | (Filter::Volume { .. }, Filter::Volume { .. })
-                    | (Filter::Loudness { .. }, Filter::Loudness { .. }) => {}
+                    | (Filter::Loudness { .. }, Filter::Loudness { .. })
+                    | (Filter::DynamicEq { .. }, Filter::DynamicEq { .. })
+                    | (Filter::ToneControls { .. }, Filter::ToneControls { .. }) => {}
                     _ => {
                         // A filter changed type, need to rebuild the pipeline
                         return ConfigChange::Pipeline;
@@ -1802,6 +2892,42 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
         for (proc, params) in newprocs {
             // The pipeline didn't change, any added processor isn't included and can be skipped
             if let Some(current_proc) = oldprocs.get(proc) {
+                // Did the processor change type?
+                match (params, current_proc) {
+                    (Processor::Compressor { .. }, Processor::Compressor { .. })
+                    | (Processor::NoiseGate { .. }, Processor::NoiseGate { .. })
+                    | (Processor::Ducker { .. }, Processor::Ducker { .. })
+                    | (Processor::SpeakerProtection { .. }, Processor::SpeakerProtection { .. })
+                    | (Processor::Crossfeed { .. }, Processor::Crossfeed { .. })
+                    | (Processor::BassManagement { .. }, Processor::BassManagement { .. }) => {}
+                    (Processor::Upmix { .. }, Processor::Upmix { .. }) => {
+                        // A change of layout changes the number of channels of the pipeline
+                        if params.channels_out() != current_proc.channels_out() {
+                            return ConfigChange::Pipeline;
+                        }
+                    }
+                    (
+                        Processor::MidSide {
+                            parameters: new_params,
+                            ..
+                        },
+                        Processor::MidSide {
+                            parameters: old_params,
+                            ..
+                        },
+                    ) => {
+                        // The filter chains are built with the pipeline, rebuild if they changed
+                        if new_params.mid_filters != old_params.mid_filters
+                            || new_params.side_filters != old_params.side_filters
+                        {
+                            return ConfigChange::Pipeline;
+                        }
+                    }
+                    _ => {
+                        // A processor changed type, need to rebuild the pipeline
+                        return ConfigChange::Pipeline;
+                    }
+                };
                 if params != current_proc {
                     processors.push(proc.to_string());
                 }
@@ -1817,12 +2943,33 @@ pub fn config_diff(currentconf: &Configuration, newconf: &Configuration) -> Conf
 
 /// Validate the loaded configuration, stop on errors and print a helpful message.
 pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<()> {
-    // pre-process by applying overrides and replacing tokens
+    match find_config_problems(conf, filename).first() {
+        Some(problem) => Err(ConfigError::new(&problem.to_string()).into()),
+        None => Ok(()),
+    }
+}
+
+/// Validate the loaded configuration, and return all the problems found.
+pub fn find_config_problems(
+    conf: &mut Configuration,
+    filename: Option<&str>,
+) -> Vec<ConfigProblem> {
+    // pre-process by applying overrides, merging included files and replacing tokens
     apply_overrides(conf);
+    let mut problems = Vec::new();
+    includes::resolve_includes(conf, filename, &mut problems);
+    if !problems.is_empty() {
+        // The rest of the config can't be checked without the included definitions.
+        return problems;
+    }
     replace_tokens_in_config(conf);
+    if let Err(err) = includes::expand_fragments(conf) {
+        return vec![ConfigProblem::new("fragments", &err.to_string())];
+    }
     if let Some(fname) = filename {
         replace_relative_paths_in_config(conf, fname);
     }
+    crossover::expand_crossovers(conf, &mut problems);
     #[cfg(target_os = "linux")]
     let target_level_limit = if matches!(conf.devices.playback, PlaybackDevice::Alsa { .. }) {
         4 * conf.devices.chunksize
@@ -1834,298 +2981,575 @@ pub fn validate_config(conf: &mut Configuration, filename: Option<&str>) -> Res<
 
     if conf.devices.target_level() > target_level_limit {
         let msg = format!("target_level cannot be larger than {}", target_level_limit);
-        return Err(ConfigError::new(&msg).into());
+        problems.push(ConfigProblem::new("devices.target_level", &msg));
     }
     if let Some(period) = conf.devices.adjust_period {
         if period <= 0.0 {
-            return Err(ConfigError::new("adjust_period must be positive and > 0").into());
+            problems.push(ConfigProblem::new(
+                "devices.adjust_period",
+                "adjust_period must be positive and > 0",
+            ));
         }
     }
     if let Some(threshold) = conf.devices.silence_threshold {
         if threshold > 0.0 {
-            return Err(
-                ConfigError::new("silence_threshold must be less than or equal to 0").into(),
-            );
+            problems.push(ConfigProblem::new(
+                "devices.silence_threshold",
+                "silence_threshold must be less than or equal to 0",
+            ));
         }
     }
     if let Some(timeout) = conf.devices.silence_timeout {
         if timeout < 0.0 {
-            return Err(ConfigError::new("silence_timeout cannot be negative").into());
+            problems.push(ConfigProblem::new(
+                "devices.silence_timeout",
+                "silence_timeout cannot be negative",
+            ));
         }
     }
     if conf.devices.ramp_time() < 0.0 {
-        return Err(ConfigError::new("Volume ramp time cannot be negative").into());
+        problems.push(ConfigProblem::new(
+            "devices.volume_ramp_time",
+            "Volume ramp time cannot be negative",
+        ));
+    }
+    if conf.devices.crossfade_time() < 0.0 {
+        problems.push(ConfigProblem::new(
+            "devices.crossfade_time",
+            "Crossfade time cannot be negative",
+        ));
     }
     if conf.devices.volume_limit() > 50.0 {
-        return Err(ConfigError::new("Volume limit cannot be above +50 dB").into());
+        problems.push(ConfigProblem::new(
+            "devices.volume_limit",
+            "Volume limit cannot be above +50 dB",
+        ));
     }
     if conf.devices.volume_limit() < -150.0 {
-        return Err(ConfigError::new("Volume limit cannot be less than -150 dB").into());
+        problems.push(ConfigProblem::new(
+            "devices.volume_limit",
+            "Volume limit cannot be less than -150 dB",
+        ));
     }
     #[cfg(target_os = "windows")]
     if let CaptureDevice::Wasapi(dev) = &conf.devices.capture {
         if dev.format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.capture.format",
                 "The Wasapi capture backend does not support FLOAT64LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(target_os = "windows")]
     if let CaptureDevice::Wasapi(dev) = &conf.devices.capture {
         if dev.format != SampleFormat::FLOAT32LE && !dev.is_exclusive() {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.capture.format",
                 "Wasapi shared mode capture must use FLOAT32LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(target_os = "windows")]
     if let CaptureDevice::Wasapi(dev) = &conf.devices.capture {
         if dev.is_loopback() && dev.is_exclusive() {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.capture.loopback",
                 "Wasapi loopback capture is only supported in shared mode",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(target_os = "windows")]
     if let PlaybackDevice::Wasapi(dev) = &conf.devices.playback {
         if dev.format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.playback.format",
                 "The Wasapi playback backend does not support FLOAT64LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(target_os = "windows")]
     if let PlaybackDevice::Wasapi(dev) = &conf.devices.playback {
         if dev.format != SampleFormat::FLOAT32LE && !dev.is_exclusive() {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.playback.format",
                 "Wasapi shared mode playback must use FLOAT32LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(feature = "pulse-backend")]
     if let CaptureDevice::Pulse { format, .. } = &conf.devices.capture {
         if *format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.capture.format",
                 "The PulseAudio capture backend does not support FLOAT64LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(feature = "pulse-backend")]
     if let PlaybackDevice::Pulse { format, .. } = &conf.devices.playback {
         if *format == SampleFormat::FLOAT64LE {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.playback.format",
                 "The PulseAudio playback backend does not support FLOAT64LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(target_os = "macos")]
     if let CaptureDevice::CoreAudio(dev) = &conf.devices.capture {
         if dev.format == Some(SampleFormat::FLOAT64LE) {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.capture.format",
                 "The CoreAudio capture backend does not support FLOAT64LE sample format",
-            )
-            .into());
+            ));
         }
     }
     #[cfg(target_os = "macos")]
     if let PlaybackDevice::CoreAudio(dev) = &conf.devices.playback {
         if dev.format == Some(SampleFormat::FLOAT64LE) {
-            return Err(ConfigError::new(
+            problems.push(ConfigProblem::new(
+                "devices.playback.format",
                 "The CoreAudio playback backend does not support FLOAT64LE sample format",
-            )
-            .into());
+            ));
         }
     }
     if let CaptureDevice::RawFile(dev) = &conf.devices.capture {
         let fname = &dev.filename;
-        match File::open(fname) {
-            Ok(f) => f,
-            Err(err) => {
-                let msg = format!("Could not open input file '{fname}'. Reason: {err}");
-                return Err(ConfigError::new(&msg).into());
-            }
-        };
+        if let Err(err) = File::open(fname) {
+            let msg = format!("Could not open input file '{fname}'. Reason: {err}");
+            problems.push(ConfigProblem::new("devices.capture.filename", &msg));
+        }
     }
     if let CaptureDevice::WavFile(dev) = &conf.devices.capture {
         let fname = &dev.filename;
-        let f = match File::open(fname) {
-            Ok(f) => f,
+        match File::open(fname) {
+            Ok(f) => {
+                let file = BufReader::new(&f);
+                if let Err(err) = find_data_in_wav_stream(file) {
+                    let msg = format!("Error reading wav file '{fname}'. Reason: {err}");
+                    problems.push(ConfigProblem::new("devices.capture.filename", &msg));
+                }
+            }
             Err(err) => {
                 let msg = format!("Could not open input file '{fname}'. Reason: {err}");
-                return Err(ConfigError::new(&msg).into());
+                problems.push(ConfigProblem::new("devices.capture.filename", &msg));
             }
-        };
-        let file = BufReader::new(&f);
-        let _wav_info = find_data_in_wav_stream(file).map_err(|err| {
-            let msg = format!("Error reading wav file '{fname}'. Reason: {err}");
-            ConfigError::new(&msg)
-        })?;
+        }
     }
-    let mut num_channels = conf.devices.capture.channels();
+    let num_channels = match &conf.pipeline {
+        Some(pipeline) => validate_pipeline_steps(
+            conf,
+            pipeline,
+            conf.devices.capture.channels(),
+            "pipeline",
+            &mut problems,
+        ),
+        None => Some(conf.devices.capture.channels()),
+    };
+    let num_channels_out = conf.devices.playback.channels();
+    if let Some(num_channels) = num_channels {
+        if num_channels != num_channels_out {
+            let msg = format!(
+                "Pipeline outputs {num_channels} channels, playback device has {num_channels_out}."
+            );
+            problems.push(ConfigProblem::new("pipeline", &msg));
+        }
+    }
+    if let Some(variants) = &conf.variants {
+        let mut names: Vec<&String> = variants.keys().collect();
+        names.sort();
+        for name in names {
+            let path = format!("variants.{name}");
+            if name == DEFAULT_VARIANT {
+                let msg = format!(
+                    "The variant name '{DEFAULT_VARIANT}' is reserved for the main pipeline"
+                );
+                problems.push(ConfigProblem::new(&path, &msg));
+                continue;
+            }
+            let num_channels = validate_pipeline_steps(
+                conf,
+                &variants[name].pipeline,
+                conf.devices.capture.channels(),
+                &format!("{path}.pipeline"),
+                &mut problems,
+            );
+            if let Some(num_channels) = num_channels {
+                if num_channels != num_channels_out {
+                    let msg = format!(
+                        "Pipeline variant '{name}' outputs {num_channels} channels, playback device has {num_channels_out}."
+                    );
+                    problems.push(ConfigProblem::new(&path, &msg));
+                }
+            }
+        }
+    }
+    // A definition that is used in several steps is only reported once.
+    let mut reported = HashSet::new();
+    problems.retain(|problem| reported.insert((problem.path.clone(), problem.message.clone())));
+    problems
+}
+
+// Check that the channels of a step exist, and are not duplicated.
+fn validate_step_channels(
+    channels: &[usize],
+    num_channels: usize,
+    path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    for channel in channels {
+        if *channel >= num_channels {
+            let msg = format!("Use of non existing channel {}", channel);
+            problems.push(ConfigProblem::new(path, &msg));
+        }
+    }
+    for idx in 1..channels.len() {
+        if channels[idx..].contains(&channels[idx - 1]) {
+            let msg = format!("Use of duplicated channel {}", &channels[idx - 1]);
+            problems.push(ConfigProblem::new(path, &msg));
+        }
+    }
+}
+
+// Validate a list of pipeline steps, starting with the given number of channels.
+// The problems found are given paths starting with the path of the list.
+// Returns the number of channels after the last step,
+// or None if this can't be determined because of a problem.
+fn validate_pipeline_steps(
+    conf: &Configuration,
+    steps: &[PipelineStep],
+    mut num_channels: usize,
+    path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) -> Option<usize> {
     let fs = conf.devices.samplerate;
-    if let Some(pipeline) = &conf.pipeline {
-        for step in pipeline {
-            match step {
-                PipelineStep::Mixer(step) => {
-                    if !step.is_bypassed() {
-                        if let Some(mixers) = &conf.mixers {
-                            if !mixers.contains_key(&step.name) {
-                                let msg = format!("Use of missing mixer '{}'", &step.name);
-                                return Err(ConfigError::new(&msg).into());
-                            } else {
-                                let chan_in = mixers.get(&step.name).unwrap().channels.r#in;
-                                if chan_in != num_channels {
-                                    let msg = format!(
-                                        "Mixer '{}' has wrong number of input channels. Expected {}, found {}.",
-                                        &step.name, num_channels, chan_in
-                                    );
-                                    return Err(ConfigError::new(&msg).into());
-                                }
-                                num_channels = mixers.get(&step.name).unwrap().channels.out;
-                                match mixer::validate_mixer(mixers.get(&step.name).unwrap()) {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        let msg = format!(
-                                            "Invalid mixer '{}'. Reason: {}",
-                                            &step.name, err
-                                        );
-                                        return Err(ConfigError::new(&msg).into());
-                                    }
-                                }
-                            }
-                        } else {
+    for (idx, step) in steps.iter().enumerate() {
+        let step_path = format!("{path}[{idx}]");
+        match step {
+            PipelineStep::Mixer(step) => {
+                if !step.is_bypassed() {
+                    let mixerconf = match conf.mixers.as_ref().and_then(|m| m.get(&step.name)) {
+                        Some(mixerconf) => mixerconf,
+                        None => {
                             let msg = format!("Use of missing mixer '{}'", &step.name);
-                            return Err(ConfigError::new(&msg).into());
+                            problems.push(ConfigProblem::new(&format!("{step_path}.name"), &msg));
+                            return None;
                         }
+                    };
+                    let mixer_path = format!("mixers.{}", step.name);
+                    let chan_in = mixerconf.channels.r#in;
+                    if chan_in != num_channels {
+                        let msg = format!(
+                            "Mixer '{}' has wrong number of input channels. Expected {}, found {}.",
+                            &step.name, num_channels, chan_in
+                        );
+                        problems.push(ConfigProblem::new(
+                            &format!("{mixer_path}.channels.in"),
+                            &msg,
+                        ));
+                    }
+                    num_channels = mixerconf.channels.out;
+                    if let Err(err) = mixer::validate_mixer(mixerconf) {
+                        let msg = format!("Invalid mixer '{}'. Reason: {}", &step.name, err);
+                        problems.push(ConfigProblem::new(&mixer_path, &msg));
                     }
                 }
-                PipelineStep::Filter(step) => {
-                    if !step.is_bypassed() {
-                        if let Some(channels) = &step.channels {
-                            for channel in channels {
-                                if *channel >= num_channels {
-                                    let msg = format!("Use of non existing channel {}", channel);
-                                    return Err(ConfigError::new(&msg).into());
-                                }
-                            }
-                            for idx in 1..channels.len() {
-                                if channels[idx..].contains(&channels[idx - 1]) {
-                                    let msg =
-                                        format!("Use of duplicated channel {}", &channels[idx - 1]);
-                                    return Err(ConfigError::new(&msg).into());
+            }
+            PipelineStep::Filter(step) => {
+                if !step.is_bypassed() {
+                    if let Some(channels) = &step.channels {
+                        let channels_path = format!("{step_path}.channels");
+                        validate_step_channels(channels, num_channels, &channels_path, problems);
+                    }
+                    for (name_idx, name) in step.names.iter().enumerate() {
+                        match conf.filters.as_ref().and_then(|f| f.get(name)) {
+                            Some(filterconf) => {
+                                if let Err(err) = filters::validate_filter(fs, filterconf) {
+                                    let msg = format!("Invalid filter '{name}'. Reason: {err}");
+                                    let path = parameter_path(
+                                        &format!("filters.{name}.parameters"),
+                                        err.as_ref(),
+                                    );
+                                    problems.push(ConfigProblem::new(&path, &msg));
                                 }
                             }
-                        }
-                        for name in &step.names {
-                            if let Some(filters) = &conf.filters {
-                                if !filters.contains_key(name) {
-                                    let msg = format!("Use of missing filter '{name}'");
-                                    return Err(ConfigError::new(&msg).into());
-                                }
-                                match filters::validate_filter(fs, filters.get(name).unwrap()) {
-                                    Ok(_) => {}
-                                    Err(err) => {
-                                        let msg = format!("Invalid filter '{name}'. Reason: {err}");
-                                        return Err(ConfigError::new(&msg).into());
-                                    }
-                                }
-                            } else {
+                            None => {
                                 let msg = format!("Use of missing filter '{name}'");
-                                return Err(ConfigError::new(&msg).into());
+                                let name_path = format!("{step_path}.names[{name_idx}]");
+                                problems.push(ConfigProblem::new(&name_path, &msg));
                             }
                         }
                     }
                 }
-                PipelineStep::Processor(step) => {
-                    if !step.is_bypassed() {
-                        if let Some(processors) = &conf.processors {
-                            if !processors.contains_key(&step.name) {
-                                let msg = format!("Use of missing processor '{}'", step.name);
-                                return Err(ConfigError::new(&msg).into());
-                            } else {
-                                let procconf = processors.get(&step.name).unwrap();
-                                match procconf {
-                                    Processor::Compressor { parameters, .. } => {
-                                        let channels = parameters.channels;
-                                        if channels != num_channels {
-                                            let msg = format!(
-                                                "Compressor '{}' has wrong number of channels. Expected {}, found {}.",
-                                                step.name, num_channels, channels
-                                            );
-                                            return Err(ConfigError::new(&msg).into());
-                                        }
-                                        match compressor::validate_compressor(parameters) {
-                                            Ok(_) => {}
-                                            Err(err) => {
-                                                let msg = format!(
-                                                    "Invalid processor '{}'. Reason: {}",
-                                                    step.name, err
-                                                );
-                                                return Err(ConfigError::new(&msg).into());
-                                            }
-                                        }
-                                    }
-                                    Processor::NoiseGate { parameters, .. } => {
-                                        let channels = parameters.channels;
-                                        if channels != num_channels {
-                                            let msg = format!(
-                                                "NoiseGate '{}' has wrong number of channels. Expected {}, found {}.",
-                                                step.name, num_channels, channels
-                                            );
-                                            return Err(ConfigError::new(&msg).into());
-                                        }
-                                        match noisegate::validate_noise_gate(parameters) {
-                                            Ok(_) => {}
-                                            Err(err) => {
-                                                let msg = format!(
-                                                    "Invalid noise gate '{}'. Reason: {}",
-                                                    step.name, err
-                                                );
-                                                return Err(ConfigError::new(&msg).into());
-                                            }
-                                        }
-                                    }
-                                }
-                            }
-                        } else {
+            }
+            PipelineStep::Processor(step) => {
+                if !step.is_bypassed() {
+                    let procconf = match conf.processors.as_ref().and_then(|p| p.get(&step.name)) {
+                        Some(procconf) => procconf,
+                        None => {
                             let msg = format!("Use of missing processor '{}'", step.name);
-                            return Err(ConfigError::new(&msg).into());
+                            problems.push(ConfigProblem::new(&format!("{step_path}.name"), &msg));
+                            return None;
                         }
+                    };
+                    let (kind, description, channels, result) = match procconf {
+                        Processor::Compressor { parameters, .. } => (
+                            "Compressor",
+                            "processor",
+                            parameters.channels,
+                            compressor::validate_compressor(parameters),
+                        ),
+                        Processor::NoiseGate { parameters, .. } => (
+                            "NoiseGate",
+                            "noise gate",
+                            parameters.channels,
+                            noisegate::validate_noise_gate(parameters),
+                        ),
+                        Processor::Ducker { parameters, .. } => (
+                            "Ducker",
+                            "ducker",
+                            parameters.channels,
+                            ducker::validate_ducker(parameters),
+                        ),
+                        Processor::SpeakerProtection { parameters, .. } => (
+                            "SpeakerProtection",
+                            "speaker protection",
+                            parameters.channels,
+                            protection::validate_protection(fs, parameters),
+                        ),
+                        Processor::Crossfeed { parameters, .. } => (
+                            "Crossfeed",
+                            "crossfeed",
+                            parameters.channels,
+                            crossfeed::validate_crossfeed(fs, parameters),
+                        ),
+                        Processor::MidSide { parameters, .. } => (
+                            "MidSide",
+                            "mid/side processor",
+                            parameters.channels,
+                            midside::validate_midside(fs, parameters, conf.filters.as_ref()),
+                        ),
+                        Processor::Upmix { parameters, .. } => (
+                            "Upmix",
+                            "upmix",
+                            parameters.channels,
+                            upmix::validate_upmix(fs, parameters),
+                        ),
+                        Processor::BassManagement { parameters, .. } => (
+                            "BassManagement",
+                            "bass management",
+                            parameters.channels,
+                            bassmanagement::validate_bass_management(fs, parameters),
+                        ),
+                    };
+                    let proc_path = format!("processors.{}", step.name);
+                    if channels != num_channels {
+                        let msg = format!(
+                            "{kind} '{}' has wrong number of channels. Expected {num_channels}, found {channels}.",
+                            step.name
+                        );
+                        problems.push(ConfigProblem::new(
+                            &format!("{proc_path}.parameters.channels"),
+                            &msg,
+                        ));
                     }
+                    if let Err(err) = result {
+                        let msg = format!("Invalid {description} '{}'. Reason: {err}", step.name);
+                        problems.push(ConfigProblem::new(&proc_path, &msg));
+                    }
+                    num_channels = procconf.channels_out();
+                }
+            }
+            PipelineStep::Crossover(_) => {
+                // Crossovers that could not be expanded have already been reported.
+                return None;
+            }
+            PipelineStep::Fragment(step) => {
+                // This should never happen, fragments are expanded before validating.
+                let msg = format!("Pipeline fragment '{}' was not expanded", step.name);
+                problems.push(ConfigProblem::new(&step_path, &msg));
+                return None;
+            }
+            PipelineStep::Branch(step) => {
+                if !step.is_bypassed() {
+                    validate_branch_step(conf, step, num_channels, &step_path, problems);
                 }
             }
         }
     }
-    let num_channels_out = conf.devices.playback.channels();
-    if num_channels != num_channels_out {
-        let msg = format!(
-            "Pipeline outputs {num_channels} channels, playback device has {num_channels_out}."
+    Some(num_channels)
+}
+
+// Validate a branch step. Each branch must keep the number of channels of the branch.
+fn validate_branch_step(
+    conf: &Configuration,
+    step: &PipelineStepBranch,
+    num_channels: usize,
+    path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    if let Some(channels) = &step.channels {
+        validate_step_channels(
+            channels,
+            num_channels,
+            &format!("{path}.channels"),
+            problems,
+        );
+    }
+    if step.branches.is_empty() {
+        problems.push(ConfigProblem::new(
+            &format!("{path}.branches"),
+            "A branch step must have at least one branch",
+        ));
+    }
+    let branch_channels = step
+        .channels
+        .as_ref()
+        .map(|channels| channels.len())
+        .unwrap_or(num_channels);
+    for (idx, branch) in step.branches.iter().enumerate() {
+        let branch_path = format!("{path}.branches[{idx}]");
+        if branch
+            .pipeline
+            .iter()
+            .any(|step| matches!(step, PipelineStep::Crossover(_)))
+        {
+            let msg = format!("Branch {idx} contains a crossover, these can't be used in branches");
+            problems.push(ConfigProblem::new(&branch_path, &msg));
+            continue;
+        }
+        let channels_out = validate_pipeline_steps(
+            conf,
+            &branch.pipeline,
+            branch_channels,
+            &format!("{branch_path}.pipeline"),
+            problems,
         );
-        return Err(ConfigError::new(&msg).into());
+        if let Some(channels_out) = channels_out {
+            if channels_out != branch_channels {
+                let msg = format!(
+                    "Branch {idx} outputs {channels_out} channels, expected {branch_channels}."
+                );
+                problems.push(ConfigProblem::new(&branch_path, &msg));
+            }
+        }
     }
-    Ok(())
 }
 
 /// Get a vector telling which channels are actually used in the pipeline
 pub fn used_capture_channels(conf: &Configuration) -> Vec<bool> {
-    if let Some(pipeline) = &conf.pipeline {
+    // Pipeline variants may use different channels.
+    let has_variants = conf.variants.as_ref().is_some_and(|v| !v.is_empty());
+    if let (Some(pipeline), false) = (&conf.pipeline, has_variants) {
         for step in pipeline.iter() {
-            if let PipelineStep::Mixer(mix) = step {
-                if !mix.is_bypassed() {
-                    // Safe to unwrap here since we have already verified that the mixer exists
-                    let mixerconf = conf.mixers.as_ref().unwrap().get(&mix.name).unwrap();
-                    return mixer::used_input_channels(mixerconf);
+            match step {
+                PipelineStep::Mixer(mix) => {
+                    if !mix.is_bypassed() {
+                        // Safe to unwrap here since we have already verified that the mixer exists
+                        let mixerconf = conf.mixers.as_ref().unwrap().get(&mix.name).unwrap();
+                        return mixer::used_input_channels(mixerconf);
+                    }
+                }
+                PipelineStep::Processor(proc) => {
+                    if !proc.is_bypassed() {
+                        // Safe to unwrap here since we have already verified that the processor exists
+                        let procconf = conf.processors.as_ref().unwrap().get(&proc.name).unwrap();
+                        if let Processor::Upmix { .. } = procconf {
+                            // All channels are needed by a processor that changes the number of channels
+                            break;
+                        }
+                    }
                 }
+                PipelineStep::Filter(_)
+                | PipelineStep::Crossover(_)
+                | PipelineStep::Branch(_)
+                | PipelineStep::Fragment(_) => {}
             }
         }
     }
     let capture_channels = conf.devices.capture.channels();
     vec![true; capture_channels]
 }
+
+/// Get the band gains of a GraphicEqualizer filter.
+pub fn graphic_eq_gains<'a>(conf: &'a Configuration, name: &str) -> Option<&'a Vec<f32>> {
+    match conf.filters.as_ref()?.get(name)? {
+        Filter::BiquadCombo {
+            parameters: BiquadComboParameters::GraphicEqualizer(params),
+            ..
+        } => Some(&params.gains),
+        _ => None,
+    }
+}
+
+/// Get mutable access to the band gains of a GraphicEqualizer filter.
+pub fn graphic_eq_gains_mut<'a>(
+    conf: &'a mut Configuration,
+    name: &str,
+) -> Option<&'a mut Vec<f32>> {
+    match conf.filters.as_mut()?.get_mut(name)? {
+        Filter::BiquadCombo {
+            parameters: BiquadComboParameters::GraphicEqualizer(params),
+            ..
+        } => Some(&mut params.gains),
+        _ => None,
+    }
+}
+
+/// Update the band gains of a GraphicEqualizer filter in the source text of a config.
+/// The source is parsed and written out again, which drops any comments.
+/// If the gains are not found in the source, for example when the filter
+/// is defined in an included file, the source is dropped,
+/// and the evaluated config is returned instead by `GetConfigRaw`.
+pub fn set_source_eq_gains(conf: &mut Configuration, name: &str, gains: &[f32]) {
+    let updated = conf.source.0.as_ref().and_then(|source| {
+        let mut value: serde_yaml::Value = serde_yaml::from_str(source).ok()?;
+        let current = value
+            .get_mut("filters")?
+            .get_mut(name)?
+            .get_mut("parameters")?
+            .get_mut("gains")?;
+        *current = serde_yaml::to_value(gains).ok()?;
+        serde_yaml::to_string(&value).ok()
+    });
+    conf.source = ConfigSource(updated);
+}
+
+/// Graphic equalizer band gains that were changed at runtime,
+/// together with the gains in the config that they replace.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct StoredEqGains {
+    pub gains: Vec<f32>,
+    pub config_gains: Vec<f32>,
+}
+
+/// Apply stored band gains to the GraphicEqualizer filters of a config.
+/// The stored gains are only used when the config still has the gains they were changed from.
+/// When the gains in the config were edited, the config wins and the stored gains are dropped.
+/// Returns the stored gains to keep. Gains for filters that are missing from the config are kept.
+pub fn apply_eq_gains(
+    conf: &mut Configuration,
+    stored: &HashMap<String, StoredEqGains>,
+) -> HashMap<String, StoredEqGains> {
+    let mut kept = HashMap::new();
+    for (name, entry) in stored.iter() {
+        match graphic_eq_gains_mut(conf, name) {
+            Some(current) if *current == entry.config_gains => {
+                debug!("Using stored band gains for graphic equalizer '{name}'");
+                current.clone_from(&entry.gains);
+                set_source_eq_gains(conf, name, &entry.gains);
+                kept.insert(name.clone(), entry.clone());
+            }
+            Some(_) => {
+                debug!("The gains of graphic equalizer '{name}' were changed in the config, dropping the stored band gains");
+            }
+            None => {
+                debug!(
+                    "No graphic equalizer '{name}' in the config, keeping the stored band gains"
+                );
+                kept.insert(name.clone(), entry.clone());
+            }
+        }
+    }
+    kept
+}
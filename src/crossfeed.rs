@@ -0,0 +1,252 @@
+use crate::audiodevice::AudioChunk;
+use crate::basicfilters::Delay;
+use crate::biquad;
+use crate::config;
+use crate::filters::{Filter, Processor};
+use crate::PrcFmt;
+use crate::Res;
+
+/// Crossfeed for headphones, in the style of Bauer stereophonic-to-binaural (bs2b).
+///
+/// The difference between the two channels is lowpass filtered, delayed and fed to both sides
+/// with opposite signs. Each output is then its own input plus the filtered signal of
+/// the opposite channel, minus the same filtered signal of itself.
+/// Since a centered signal has no difference between the channels,
+/// the level of the center image is preserved exactly at all frequencies.
+pub struct Crossfeed {
+    pub name: String,
+    pub channels: usize,
+    left: usize,
+    right: usize,
+    gain: PrcFmt,
+    lowpass: biquad::Biquad,
+    delay: Option<Delay>,
+    delay_time: PrcFmt,
+    samplerate: usize,
+    scratch: Vec<PrcFmt>,
+}
+
+/// Get the gain of the crossfed signal, from the feed level in dB.
+/// At low frequencies a signal panned hard to one side gives `1 - gain` on the near side
+/// and `gain` on the far side.
+pub fn feed_gain(feed_level: PrcFmt) -> PrcFmt {
+    1.0 / (1.0 + (10.0 as PrcFmt).powf(feed_level / 20.0))
+}
+
+/// Get the lowpass filter of the crossfed signal.
+pub fn lowpass_config(config: &config::CrossfeedParameters) -> config::BiquadParameters {
+    config::BiquadParameters::LowpassFO {
+        freq: config.cutoff(),
+    }
+}
+
+fn build_delay(name: &str, samplerate: usize, delay_ms: PrcFmt) -> Option<Delay> {
+    let samples = delay_ms / 1000.0 * samplerate as PrcFmt;
+    if samples >= 1.0 {
+        Some(Delay::new(name, samplerate, samples, true))
+    } else {
+        None
+    }
+}
+
+impl Crossfeed {
+    /// Creates a Crossfeed from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::CrossfeedParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let coeffs = biquad::BiquadCoefficients::from_config(samplerate, lowpass_config(&config));
+        debug!(
+            "Creating crossfeed '{}', channels: {} and {}, cutoff: {}, feed level: {}, delay: {}",
+            name,
+            config.left(),
+            config.right(),
+            config.cutoff(),
+            config.feed_level(),
+            config.delay()
+        );
+        Crossfeed {
+            name: name.to_string(),
+            channels: config.channels,
+            left: config.left(),
+            right: config.right(),
+            gain: feed_gain(config.feed_level()),
+            lowpass: biquad::Biquad::new("crossfeed", samplerate, coeffs),
+            delay: build_delay(name, samplerate, config.delay()),
+            delay_time: config.delay(),
+            samplerate,
+            scratch: vec![0.0; chunksize],
+        }
+    }
+}
+
+impl Processor for Crossfeed {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply a Crossfeed to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        self.scratch.clear();
+        self.scratch.extend(
+            input.waveforms[self.right]
+                .iter()
+                .zip(input.waveforms[self.left].iter())
+                .map(|(r, l)| self.gain * (r - l)),
+        );
+        self.lowpass.process_waveform(&mut self.scratch)?;
+        if let Some(delay) = &mut self.delay {
+            delay.process_waveform(&mut self.scratch)?;
+        }
+        for (val, feed) in input.waveforms[self.left]
+            .iter_mut()
+            .zip(self.scratch.iter())
+        {
+            *val += feed;
+        }
+        for (val, feed) in input.waveforms[self.right]
+            .iter_mut()
+            .zip(self.scratch.iter())
+        {
+            *val -= feed;
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::Crossfeed {
+            parameters: config, ..
+        } = config
+        {
+            self.lowpass.update_parameters(config::Filter::Biquad {
+                description: None,
+                parameters: lowpass_config(&config),
+            });
+            self.channels = config.channels;
+            self.left = config.left();
+            self.right = config.right();
+            self.gain = feed_gain(config.feed_level());
+            // Only rebuild the delay line if the delay changed, to avoid clicks.
+            if config.delay() != self.delay_time {
+                self.delay = build_delay(&self.name, self.samplerate, config.delay());
+                self.delay_time = config.delay();
+            }
+            debug!(
+                "Updated crossfeed '{}', channels: {} and {}, cutoff: {}, feed level: {}, delay: {}",
+                self.name,
+                self.left,
+                self.right,
+                config.cutoff(),
+                config.feed_level(),
+                config.delay()
+            );
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the crossfeed config, to give a helpful message intead of a panic.
+pub fn validate_crossfeed(samplerate: usize, config: &config::CrossfeedParameters) -> Res<()> {
+    let channels = config.channels;
+    for ch in [config.left(), config.right()] {
+        if ch >= channels {
+            let msg = format!("Invalid channel: {}, max is: {}.", ch, channels - 1);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if config.left() == config.right() {
+        let msg = "The left and right channels must be different.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.feed_level() <= 0.0 {
+        let msg = "Feed level must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.delay() < 0.0 {
+        let msg = "Delay cannot be negative.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    biquad::validate_config(samplerate, &lowpass_config(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::{CrossfeedParameters, CrossfeedPreset};
+    use crate::crossfeed::{validate_crossfeed, Crossfeed};
+    use crate::filters::Processor;
+    use crate::PrcFmt;
+
+    fn make_params(delay: PrcFmt) -> CrossfeedParameters {
+        CrossfeedParameters {
+            channels: 2,
+            left: None,
+            right: None,
+            preset: Some(CrossfeedPreset::ChuMoy),
+            cutoff: None,
+            feed_level: None,
+            delay: Some(delay),
+        }
+    }
+
+    fn sine(freq: PrcFmt, start: usize) -> Vec<PrcFmt> {
+        (start..start + 1000)
+            .map(|n| (2.0 * std::f64::consts::PI as PrcFmt * freq * n as PrcFmt / 48000.0).sin())
+            .collect()
+    }
+
+    fn peak(wave: &[PrcFmt]) -> PrcFmt {
+        wave.iter().fold(0.0, |acc: PrcFmt, v| acc.max(v.abs()))
+    }
+
+    #[test]
+    fn keeps_center_level() {
+        let mut crossfeed = Crossfeed::from_config("test", make_params(0.3), 48000, 1000);
+        for chunk in 0..10 {
+            let wave = sine(100.0, chunk * 1000);
+            let mut audio =
+                AudioChunk::new(vec![wave.clone(), wave.clone()], 1.0, -1.0, 1000, 1000);
+            crossfeed.process_chunk(&mut audio).unwrap();
+            for (out, inp) in audio.waveforms[0].iter().zip(wave.iter()) {
+                assert!((out - inp).abs() < 1.0e-9);
+            }
+        }
+    }
+
+    #[test]
+    fn feeds_low_frequencies() {
+        let mut crossfeed = Crossfeed::from_config("test", make_params(0.0), 48000, 1000);
+        let mut audio = AudioChunk::new(vec![vec![], vec![]], 1.0, -1.0, 1000, 1000);
+        for chunk in 0..20 {
+            let wave = sine(48.0, chunk * 1000);
+            audio = AudioChunk::new(vec![wave, vec![0.0; 1000]], 1.0, -1.0, 1000, 1000);
+            crossfeed.process_chunk(&mut audio).unwrap();
+        }
+        // The level difference at low frequencies should be close to the feed level.
+        let diff = 20.0 * (peak(&audio.waveforms[0]) / peak(&audio.waveforms[1])).log10();
+        assert!((diff - 6.0).abs() < 0.1, "{diff}");
+        // High frequencies are not fed to the other side.
+        for chunk in 0..5 {
+            let wave = sine(10000.0, chunk * 1000);
+            audio = AudioChunk::new(vec![wave, vec![0.0; 1000]], 1.0, -1.0, 1000, 1000);
+            crossfeed.process_chunk(&mut audio).unwrap();
+        }
+        assert!(peak(&audio.waveforms[1]) < 0.03);
+        assert!((peak(&audio.waveforms[0]) - 1.0).abs() < 0.03);
+    }
+
+    #[test]
+    fn check_config() {
+        let mut conf = make_params(0.3);
+        assert!(validate_crossfeed(48000, &conf).is_ok());
+        conf.right = Some(0);
+        assert!(validate_crossfeed(48000, &conf).is_err());
+        let mut conf = make_params(0.3);
+        conf.cutoff = Some(30000.0);
+        assert!(validate_crossfeed(48000, &conf).is_err());
+    }
+}
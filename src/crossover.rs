@@ -0,0 +1,637 @@
+use std::collections::HashMap;
+
+use crate::biquadcombo;
+use crate::config;
+use crate::fftconv;
+use crate::PrcFmt;
+use crate::Res;
+
+// Crossovers are a shorthand for a mixer that splits the channels into the ways,
+// followed by a filter step for each way.
+// They are expanded to ordinary mixers, filters and pipeline steps when the config is loaded,
+// so that the rest of the application never sees them.
+// Linear-phase crossovers use one FIR filter per way, and the channels that are passed through
+// unchanged get a delay that matches the latency of the FIR filters.
+
+fn way_filter_name(name: &str, way: usize, kind: &str) -> String {
+    format!("{name}_way{way}_{kind}")
+}
+
+fn highpass_params(
+    filter: &config::CrossoverFilter,
+    freq: PrcFmt,
+) -> config::BiquadComboParameters {
+    match *filter {
+        config::CrossoverFilter::LinkwitzRiley { order } => {
+            config::BiquadComboParameters::LinkwitzRileyHighpass { freq, order }
+        }
+        config::CrossoverFilter::Butterworth { order } => {
+            config::BiquadComboParameters::ButterworthHighpass { freq, order }
+        }
+        config::CrossoverFilter::Bessel {
+            order,
+            normalization,
+        } => config::BiquadComboParameters::BesselHighpass {
+            freq,
+            order,
+            normalization,
+        },
+        config::CrossoverFilter::Chebyshev { order, ripple } => {
+            config::BiquadComboParameters::ChebyshevHighpass {
+                freq,
+                order,
+                ripple,
+            }
+        }
+        config::CrossoverFilter::LinearPhase { .. } => {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Linear phase crossovers do not use IIR filters!");
+        }
+    }
+}
+
+fn lowpass_params(filter: &config::CrossoverFilter, freq: PrcFmt) -> config::BiquadComboParameters {
+    match *filter {
+        config::CrossoverFilter::LinkwitzRiley { order } => {
+            config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order }
+        }
+        config::CrossoverFilter::Butterworth { order } => {
+            config::BiquadComboParameters::ButterworthLowpass { freq, order }
+        }
+        config::CrossoverFilter::Bessel {
+            order,
+            normalization,
+        } => config::BiquadComboParameters::BesselLowpass {
+            freq,
+            order,
+            normalization,
+        },
+        config::CrossoverFilter::Chebyshev { order, ripple } => {
+            config::BiquadComboParameters::ChebyshevLowpass {
+                freq,
+                order,
+                ripple,
+            }
+        }
+        config::CrossoverFilter::LinearPhase { .. } => {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Linear phase crossovers do not use IIR filters!");
+        }
+    }
+}
+
+/// Generate the mixer that splits each input channel into the ways.
+/// Returns the mixer, and the mixer output channels for each way.
+pub fn splitting_mixer(config: &config::Crossover) -> (config::Mixer, Vec<Vec<usize>>) {
+    let inputs = config.inputs();
+    let settings = config.settings();
+    let mut mapping = Vec::new();
+    let mut way_channels = vec![Vec::new(); config.ways];
+    let mut dest = 0;
+    for channel in 0..config.channels {
+        if inputs.contains(&channel) {
+            for (way, setting) in settings.iter().enumerate() {
+                mapping.push(config::MixerMapping {
+                    dest,
+                    sources: vec![config::MixerSource {
+                        channel,
+                        gain: Some(setting.gain()),
+                        inverted: Some(setting.is_inverted()),
+                        mute: None,
+                        scale: None,
+                    }],
+                    mute: None,
+                });
+                way_channels[way].push(dest);
+                dest += 1;
+            }
+        } else {
+            mapping.push(config::MixerMapping {
+                dest,
+                sources: vec![config::MixerSource {
+                    channel,
+                    gain: Some(0.0),
+                    inverted: None,
+                    mute: None,
+                    scale: None,
+                }],
+                mute: None,
+            });
+            dest += 1;
+        }
+    }
+    let mixer = config::Mixer {
+        description: config.description.clone(),
+        channels: config::MixerChannels {
+            r#in: config.channels,
+            out: dest,
+        },
+        mapping,
+        labels: None,
+    };
+    (mixer, way_channels)
+}
+
+/// Generate the filters of each way, as a list of (name, filter) for each way.
+pub fn way_filters(name: &str, config: &config::Crossover) -> Vec<Vec<(String, config::Filter)>> {
+    let settings = config.settings();
+    let mut ways = Vec::with_capacity(config.ways);
+    for (way, setting) in settings.iter().enumerate() {
+        let mut filters = Vec::new();
+        if let Some(params) = linear_phase_params(config, way) {
+            filters.push((
+                way_filter_name(name, way, "fir"),
+                config::Filter::Conv {
+                    description: None,
+                    parameters: config::ConvParameters::LinearPhase(params),
+                },
+            ));
+        } else {
+            if way > 0 {
+                filters.push((
+                    way_filter_name(name, way, "highpass"),
+                    config::Filter::BiquadCombo {
+                        description: None,
+                        parameters: highpass_params(
+                            config.highpass_filter(),
+                            config.freqs[way - 1],
+                        ),
+                    },
+                ));
+            }
+            if way < config.ways - 1 {
+                filters.push((
+                    way_filter_name(name, way, "lowpass"),
+                    config::Filter::BiquadCombo {
+                        description: None,
+                        parameters: lowpass_params(&config.filter, config.freqs[way]),
+                    },
+                ));
+            }
+        }
+        if setting.delay() > 0.0 {
+            filters.push((
+                way_filter_name(name, way, "delay"),
+                config::Filter::Delay {
+                    description: None,
+                    parameters: config::DelayParameters {
+                        delay: setting.delay(),
+                        unit: Some(config::TimeUnit::Milliseconds),
+                        subsample: Some(false),
+                        interpolation: None,
+                        order: None,
+                    },
+                },
+            ));
+        }
+        ways.push(filters);
+    }
+    ways
+}
+
+fn is_linear_phase(config: &config::Crossover) -> bool {
+    matches!(config.filter, config::CrossoverFilter::LinearPhase { .. })
+}
+
+// The parameters of the linear phase FIR filter of a way, for linear phase crossovers.
+fn linear_phase_params(
+    config: &config::Crossover,
+    way: usize,
+) -> Option<config::ConvParametersLinearPhase> {
+    if let config::CrossoverFilter::LinearPhase { slope, length } = config.filter {
+        Some(config::ConvParametersLinearPhase {
+            highpass_freq: (way > 0).then(|| config.freqs[way - 1]),
+            lowpass_freq: (way < config.ways - 1).then(|| config.freqs[way]),
+            slope,
+            length,
+        })
+    } else {
+        None
+    }
+}
+
+/// Generate the delay that compensates the latency of a linear phase crossover,
+/// for the channels that are passed through unchanged.
+pub fn latency_filter(name: &str, config: &config::Crossover) -> Option<(String, config::Filter)> {
+    let latency = linear_phase_params(config, 0)?.latency();
+    Some((
+        format!("{name}_latency"),
+        config::Filter::Delay {
+            description: None,
+            parameters: config::DelayParameters {
+                delay: latency as PrcFmt,
+                unit: Some(config::TimeUnit::Samples),
+                subsample: Some(false),
+                interpolation: None,
+                order: None,
+            },
+        },
+    ))
+}
+
+/// Replace all crossovers of a config by the equivalent mixers, filters and pipeline steps.
+pub fn expand_crossovers(
+    conf: &mut config::Configuration,
+    problems: &mut Vec<config::ConfigProblem>,
+) {
+    let crossovers = conf.crossovers.take().unwrap_or_default();
+    let samplerate = conf.devices.samplerate;
+    let mut expanded_steps = HashMap::new();
+    let mut names: Vec<&String> = crossovers.keys().collect();
+    names.sort();
+    for name in names {
+        let crossover = &crossovers[name];
+        let path = format!("crossovers.{name}");
+        if let Err(err) = validate_crossover(samplerate, crossover) {
+            let msg = format!("Invalid crossover '{name}'. Reason: {err}");
+            let path = config::parameter_path(&path, err.as_ref());
+            problems.push(config::ConfigProblem::new(&path, &msg));
+            continue;
+        }
+        let (mixer, mut way_channels) = splitting_mixer(crossover);
+        let mixers = conf.mixers.get_or_insert_with(HashMap::new);
+        if mixers.contains_key(name) {
+            let msg = format!("Crossover '{name}' conflicts with a mixer with the same name");
+            problems.push(config::ConfigProblem::new(&path, &msg));
+            continue;
+        }
+        let passthrough: Vec<usize> = (0..mixer.channels.out)
+            .filter(|ch| !way_channels.iter().any(|way| way.contains(ch)))
+            .collect();
+        let mut filterlists = way_filters(name, crossover);
+        if let Some(latency) = latency_filter(name, crossover) {
+            if !passthrough.is_empty() {
+                filterlists.push(vec![latency]);
+                way_channels.push(passthrough);
+            }
+        }
+        let filters = conf.filters.get_or_insert_with(HashMap::new);
+        let mut conflicting = false;
+        for (filtername, _) in filterlists.iter().flatten() {
+            if filters.contains_key(filtername) {
+                let msg = format!(
+                    "Crossover '{name}' conflicts with a filter with the name '{filtername}'"
+                );
+                problems.push(config::ConfigProblem::new(&path, &msg));
+                conflicting = true;
+            }
+        }
+        if conflicting {
+            continue;
+        }
+        mixers.insert(name.to_string(), mixer);
+        let mut steps = vec![config::PipelineStep::Mixer(config::PipelineStepMixer {
+            name: name.to_string(),
+            description: crossover.description.clone(),
+            bypassed: None,
+        })];
+        for (filterlist, channels) in filterlists.into_iter().zip(way_channels) {
+            let mut names = Vec::with_capacity(filterlist.len());
+            for (filtername, filter) in filterlist {
+                filters.insert(filtername.clone(), filter);
+                names.push(filtername);
+            }
+            steps.push(config::PipelineStep::Filter(config::PipelineStepFilter {
+                channels: Some(channels),
+                names,
+                description: None,
+                bypassed: None,
+            }));
+        }
+        expanded_steps.insert(name.to_string(), steps);
+    }
+    if let Some(pipeline) = conf.pipeline.take() {
+        conf.pipeline = Some(expand_steps(
+            pipeline,
+            &expanded_steps,
+            &crossovers,
+            "pipeline",
+            problems,
+        ));
+    }
+    if let Some(variants) = &mut conf.variants {
+        let mut variants: Vec<_> = variants.iter_mut().collect();
+        variants.sort_by(|a, b| a.0.cmp(b.0));
+        for (name, variant) in variants {
+            let pipeline = std::mem::take(&mut variant.pipeline);
+            variant.pipeline = expand_steps(
+                pipeline,
+                &expanded_steps,
+                &crossovers,
+                &format!("variants.{name}.pipeline"),
+                problems,
+            );
+        }
+    }
+}
+
+// Replace the crossover steps of a pipeline by their expanded steps.
+// Steps using crossovers that could not be expanded are kept as they are.
+fn expand_steps(
+    pipeline: Vec<config::PipelineStep>,
+    expanded_steps: &HashMap<String, Vec<config::PipelineStep>>,
+    crossovers: &HashMap<String, config::Crossover>,
+    path: &str,
+    problems: &mut Vec<config::ConfigProblem>,
+) -> Vec<config::PipelineStep> {
+    let mut new_pipeline = Vec::with_capacity(pipeline.len());
+    for (idx, step) in pipeline.into_iter().enumerate() {
+        if let config::PipelineStep::Crossover(step) = step {
+            match expanded_steps.get(&step.name) {
+                Some(steps) => {
+                    debug!(
+                        "Expanding crossover '{}' to {} steps",
+                        step.name,
+                        steps.len()
+                    );
+                    for mut expanded in steps.iter().cloned() {
+                        match &mut expanded {
+                            config::PipelineStep::Mixer(mixstep) => {
+                                mixstep.bypassed = step.bypassed;
+                                if step.description.is_some() {
+                                    mixstep.description = step.description.clone();
+                                }
+                            }
+                            config::PipelineStep::Filter(filtstep) => {
+                                filtstep.bypassed = step.bypassed;
+                            }
+                            _ => {}
+                        }
+                        new_pipeline.push(expanded);
+                    }
+                }
+                None => {
+                    if !crossovers.contains_key(&step.name) {
+                        let msg = format!("Use of missing crossover '{}'", step.name);
+                        problems.push(config::ConfigProblem::new(
+                            &format!("{path}[{idx}].name"),
+                            &msg,
+                        ));
+                    }
+                    new_pipeline.push(config::PipelineStep::Crossover(step));
+                }
+            }
+        } else {
+            new_pipeline.push(step);
+        }
+    }
+    new_pipeline
+}
+
+/// Validate a crossover config, to give a helpful message intead of a panic.
+pub fn validate_crossover(samplerate: usize, config: &config::Crossover) -> Res<()> {
+    if config.ways < 2 {
+        let msg = "A crossover must have at least two ways.";
+        return Err(config::ConfigError::for_parameter("ways", msg).into());
+    }
+    if config.freqs.len() != config.ways - 1 {
+        let msg = format!(
+            "A {}-way crossover needs {} crossover frequencies, found {}.",
+            config.ways,
+            config.ways - 1,
+            config.freqs.len()
+        );
+        return Err(config::ConfigError::for_parameter("freqs", &msg).into());
+    }
+    for pair in config.freqs.windows(2) {
+        if pair[1] <= pair[0] {
+            let msg = "The crossover frequencies must be in increasing order.";
+            return Err(config::ConfigError::for_parameter("freqs", msg).into());
+        }
+    }
+    let inputs = config.inputs();
+    for (idx, channel) in inputs.iter().enumerate() {
+        if *channel >= config.channels {
+            let msg = format!(
+                "Invalid input channel: {}, max is: {}.",
+                channel,
+                config.channels - 1
+            );
+            return Err(config::ConfigError::for_parameter("inputs", &msg).into());
+        }
+        if inputs[idx + 1..].contains(channel) {
+            let msg = format!("Use of duplicated input channel {channel}.");
+            return Err(config::ConfigError::for_parameter("inputs", &msg).into());
+        }
+    }
+    if let Some(settings) = &config.settings {
+        if settings.len() != config.ways {
+            let msg = format!(
+                "The settings must have one entry per way, expected {}, found {}.",
+                config.ways,
+                settings.len()
+            );
+            return Err(config::ConfigError::for_parameter("settings", &msg).into());
+        }
+        if settings.iter().any(|s| s.delay() < 0.0) {
+            let msg = "Delay cannot be negative.";
+            return Err(config::ConfigError::for_parameter("settings", msg).into());
+        }
+    }
+    if is_linear_phase(config) {
+        if config
+            .highpass_filter
+            .as_ref()
+            .is_some_and(|highpass| *highpass != config.filter)
+        {
+            let msg = "A linear phase crossover must use the same filter for the highpass side.";
+            return Err(config::ConfigError::for_parameter("highpass_filter", msg).into());
+        }
+        for way in 0..config.ways {
+            if let Some(params) = linear_phase_params(config, way) {
+                let params = config::ConvParameters::LinearPhase(params);
+                fftconv::validate_config(samplerate, &params).map_err(|err| {
+                    config::ConfigError::for_parameter("filter", &err.to_string())
+                })?;
+            }
+        }
+        return Ok(());
+    }
+    if matches!(
+        config.highpass_filter,
+        Some(config::CrossoverFilter::LinearPhase { .. })
+    ) {
+        let msg = "A linear phase highpass filter can only be used with a linear phase crossover.";
+        return Err(config::ConfigError::for_parameter("highpass_filter", msg).into());
+    }
+    for (idx, freq) in config.freqs.iter().enumerate() {
+        biquadcombo::validate_config(samplerate, &lowpass_params(&config.filter, *freq))
+            .and_then(|_| {
+                biquadcombo::validate_config(
+                    samplerate,
+                    &highpass_params(config.highpass_filter(), *freq),
+                )
+            })
+            .map_err(|err| {
+                let parameter = match err
+                    .downcast_ref::<config::ConfigError>()
+                    .and_then(|e| e.parameter())
+                {
+                    Some("freq") => format!("freqs[{idx}]"),
+                    _ => "filter".to_string(),
+                };
+                config::ConfigError::for_parameter(&parameter, &err.to_string())
+            })?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{
+        validate_config, Configuration, ConvParameters, CrossoverFilter, Filter, PipelineStep,
+    };
+    use crate::crossover::validate_crossover;
+
+    fn make_config(crossover: &str, pipeline: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 4
+    format: S16LE
+crossovers:
+  speakers:
+{crossover}
+pipeline:
+{pipeline}
+"
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    const THREEWAY: &str = "
+    channels: 2
+    inputs: [1]
+    ways: 3
+    freqs: [300, 3000]
+    filter:
+      type: LinkwitzRiley
+      order: 4
+    settings:
+      - gain: -2.0
+      - delay: 0.5
+      - inverted: true";
+
+    #[test]
+    fn expands_three_way() {
+        let mut conf = make_config(THREEWAY, "  - type: Crossover\n    name: speakers");
+        validate_config(&mut conf, None).unwrap();
+        assert!(conf.crossovers.is_none());
+        let mixer = &conf.mixers.as_ref().unwrap()["speakers"];
+        assert_eq!(mixer.channels.r#in, 2);
+        assert_eq!(mixer.channels.out, 4);
+        // The unsplit channel 0 is kept first, followed by the ways of channel 1.
+        assert_eq!(mixer.mapping[0].sources[0].channel, 0);
+        assert_eq!(mixer.mapping[1].sources[0].gain, Some(-2.0));
+        assert_eq!(mixer.mapping[3].sources[0].inverted, Some(true));
+        let pipeline = conf.pipeline.as_ref().unwrap();
+        assert_eq!(pipeline.len(), 4);
+        if let PipelineStep::Filter(step) = &pipeline[2] {
+            assert_eq!(step.channels, Some(vec![2]));
+            assert_eq!(
+                step.names,
+                vec![
+                    "speakers_way1_highpass",
+                    "speakers_way1_lowpass",
+                    "speakers_way1_delay"
+                ]
+            );
+        } else {
+            panic!("Expected a filter step");
+        }
+        assert_eq!(conf.filters.as_ref().unwrap().len(), 5);
+        // Validating the expanded config again changes nothing.
+        let expanded = conf.clone();
+        validate_config(&mut conf, None).unwrap();
+        assert_eq!(conf, expanded);
+    }
+
+    #[test]
+    fn expands_linear_phase() {
+        let crossover = "
+    channels: 2
+    inputs: [1]
+    ways: 3
+    freqs: [300, 3000]
+    filter:
+      type: LinearPhase
+      slope: 48
+      length: 1023";
+        let mut conf = make_config(crossover, "  - type: Crossover\n    name: speakers");
+        validate_config(&mut conf, None).unwrap();
+        let filters = conf.filters.as_ref().unwrap();
+        match &filters["speakers_way1_fir"] {
+            Filter::Conv {
+                parameters: ConvParameters::LinearPhase(params),
+                ..
+            } => {
+                assert_eq!(params.highpass_freq, Some(300.0));
+                assert_eq!(params.lowpass_freq, Some(3000.0));
+            }
+            _ => panic!("Expected a linear phase filter"),
+        }
+        // The unsplit channel 0 is delayed by the latency of the FIR filters.
+        match &filters["speakers_latency"] {
+            Filter::Delay { parameters, .. } => assert_eq!(parameters.delay, 511.0),
+            _ => panic!("Expected a delay filter"),
+        }
+        let pipeline = conf.pipeline.as_ref().unwrap();
+        assert_eq!(pipeline.len(), 5);
+        if let PipelineStep::Filter(step) = &pipeline[4] {
+            assert_eq!(step.channels, Some(vec![0]));
+            assert_eq!(step.names, vec!["speakers_latency"]);
+        } else {
+            panic!("Expected a filter step");
+        }
+    }
+
+    #[test]
+    fn missing_crossover() {
+        let mut conf = make_config(THREEWAY, "  - type: Crossover\n    name: other");
+        assert!(validate_config(&mut conf, None).is_err());
+    }
+
+    #[test]
+    fn check_config() {
+        let conf = make_config(THREEWAY, "  - type: Crossover\n    name: speakers");
+        let mut crossover = conf.crossovers.unwrap()["speakers"].clone();
+        assert!(validate_crossover(48000, &crossover).is_ok());
+        crossover.freqs = vec![3000.0, 300.0];
+        assert!(validate_crossover(48000, &crossover).is_err());
+        crossover.freqs = vec![300.0];
+        assert!(validate_crossover(48000, &crossover).is_err());
+        crossover.freqs = vec![300.0, 3000.0];
+        crossover.inputs = Some(vec![2]);
+        assert!(validate_crossover(48000, &crossover).is_err());
+        crossover.inputs = None;
+        crossover.highpass_filter = Some(CrossoverFilter::Bessel {
+            order: 3,
+            normalization: None,
+        });
+        assert!(validate_crossover(48000, &crossover).is_ok());
+        crossover.highpass_filter = Some(CrossoverFilter::Bessel {
+            order: 13,
+            normalization: None,
+        });
+        assert!(validate_crossover(48000, &crossover).is_err());
+        crossover.highpass_filter = None;
+        crossover.filter = CrossoverFilter::LinearPhase {
+            slope: 48.0,
+            length: 1023,
+        };
+        assert!(validate_crossover(48000, &crossover).is_ok());
+        crossover.filter = CrossoverFilter::LinearPhase {
+            slope: 48.0,
+            length: 1024,
+        };
+        assert!(validate_crossover(48000, &crossover).is_err());
+    }
+}
@@ -0,0 +1,516 @@
+use crate::config::{
+    self, ConfigProblem, Configuration, Crossover, Devices, Filter, Include, Mixer, PipelineStep,
+    PipelineVariant, Processor,
+};
+use crate::vars;
+use serde::de::DeserializeOwned;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use yaml_rust::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust::scanner::Marker;
+
+/// Parse and validate a config given as yaml text, and return all the problems found.
+/// Each problem gets the line and column of the offending item, when it can be found in the text.
+pub fn check_config(
+    source: &str,
+    filename: Option<&str>,
+) -> Result<Configuration, Vec<ConfigProblem>> {
+    let locations = SourceLocations::new(source);
+    let mut problems = match parse_and_validate(source, filename) {
+        Ok(conf) => return Ok(conf),
+        Err(problems) => problems,
+    };
+    for problem in problems.iter_mut() {
+        if problem.line.is_none() {
+            if let Some((line, column)) = locations.find(&problem.path) {
+                problem.line = Some(line);
+                problem.column = Some(column);
+            }
+        }
+    }
+    Err(problems)
+}
+
+/// Read a config file, and check it with [check_config].
+pub fn check_config_file(filename: &str) -> Result<Configuration, Vec<ConfigProblem>> {
+    match std::fs::read_to_string(filename) {
+        Ok(source) => check_config(&source, Some(filename)),
+        Err(err) => {
+            let msg = format!("Could not read config file '{filename}'. Reason: {err}");
+            Err(vec![ConfigProblem::new("", &msg)])
+        }
+    }
+}
+
+fn parse_and_validate(
+    source: &str,
+    filename: Option<&str>,
+) -> Result<Configuration, Vec<ConfigProblem>> {
+    let mut value: Value = match serde_yaml::from_str(source) {
+        Ok(value) => value,
+        Err(err) => {
+            let mut problem = ConfigProblem::new("", &err.to_string());
+            if let Some(location) = err.location() {
+                problem.line = Some(location.line());
+                problem.column = Some(location.column());
+            }
+            return Err(vec![problem]);
+        }
+    };
+    if let Err(err) = vars::resolve_vars(&mut value) {
+        return Err(vec![ConfigProblem::new("vars", &err.to_string())]);
+    }
+    let mut conf: Configuration = match serde_yaml::from_value(value.clone()) {
+        Ok(conf) => conf,
+        Err(err) => {
+            let mut problems = find_deserialization_problems(&value);
+            if problems.is_empty() {
+                problems.push(ConfigProblem::new("", &err.to_string()));
+            }
+            return Err(problems);
+        }
+    };
+    conf.source = config::ConfigSource(Some(source.to_string()));
+    let problems = config::find_config_problems(&mut conf, filename);
+    if problems.is_empty() {
+        Ok(conf)
+    } else {
+        Err(problems)
+    }
+}
+
+// Deserialize each part of the config separately,
+// to find all the parts that are invalid instead of only the first one.
+fn find_deserialization_problems(value: &Value) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let mapping = match value.as_mapping() {
+        Some(mapping) => mapping,
+        None => {
+            problems.push(ConfigProblem::new("", "The config must be a mapping"));
+            return problems;
+        }
+    };
+    for (key, item) in mapping.iter() {
+        let section = match key.as_str() {
+            Some(section) => section,
+            None => {
+                problems.push(ConfigProblem::new("", "Section names must be strings"));
+                continue;
+            }
+        };
+        match section {
+            "title" | "description" => check_item::<Option<String>>(item, section, &mut problems),
+            "include" => check_list::<Include>(item, section, &mut problems),
+            "vars" => {}
+            "devices" => check_item::<Devices>(item, section, &mut problems),
+            "mixers" => check_definitions::<Mixer>(item, section, &mut problems),
+            "crossovers" => check_definitions::<Crossover>(item, section, &mut problems),
+            "filters" => check_definitions::<Filter>(item, section, &mut problems),
+            "processors" => check_definitions::<Processor>(item, section, &mut problems),
+            "fragments" => {
+                if let Some(fragments) = as_definitions(item, section, &mut problems) {
+                    for (name, steps) in fragments {
+                        let path = format!("{section}.{name}");
+                        check_list::<PipelineStep>(steps, &path, &mut problems);
+                    }
+                }
+            }
+            "pipeline" => check_list::<PipelineStep>(item, section, &mut problems),
+            "variants" => check_definitions::<PipelineVariant>(item, section, &mut problems),
+            _ => {
+                let msg = format!("Unknown section `{section}`");
+                problems.push(ConfigProblem::new(section, &msg));
+            }
+        }
+    }
+    problems
+}
+
+fn check_item<T: DeserializeOwned>(item: &Value, path: &str, problems: &mut Vec<ConfigProblem>) {
+    if let Err(err) = serde_yaml::from_value::<T>(item.clone()) {
+        let message = err.to_string();
+        let path = refine_path(item, path, &message);
+        problems.push(ConfigProblem::new(&path, &message));
+    }
+}
+
+fn check_list<T: DeserializeOwned>(item: &Value, path: &str, problems: &mut Vec<ConfigProblem>) {
+    match item {
+        Value::Sequence(items) => {
+            for (idx, item) in items.iter().enumerate() {
+                check_item::<T>(item, &format!("{path}[{idx}]"), problems);
+            }
+        }
+        Value::Null => {}
+        _ => problems.push(ConfigProblem::new(path, "Expected a list")),
+    }
+}
+
+fn check_definitions<T: DeserializeOwned>(
+    item: &Value,
+    path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    if let Some(definitions) = as_definitions(item, path, problems) {
+        for (name, definition) in definitions {
+            check_item::<T>(definition, &format!("{path}.{name}"), problems);
+        }
+    }
+}
+
+// Get the named definitions of a section, such as the filters.
+fn as_definitions<'a>(
+    item: &'a Value,
+    path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) -> Option<Vec<(String, &'a Value)>> {
+    match item {
+        Value::Mapping(mapping) => Some(
+            mapping
+                .iter()
+                .map(|(name, definition)| (yaml_key_to_string(name), definition))
+                .collect(),
+        ),
+        Value::Null => None,
+        _ => {
+            problems.push(ConfigProblem::new(path, "Expected a mapping of names"));
+            None
+        }
+    }
+}
+
+fn yaml_key_to_string(key: &Value) -> String {
+    match key {
+        Value::String(key) => key.clone(),
+        other => serde_yaml::to_string(other)
+            .map(|s| s.trim_start_matches("---").trim().to_string())
+            .unwrap_or_default(),
+    }
+}
+
+// Unknown fields are named in the error message, use this to point at the field.
+fn refine_path(item: &Value, path: &str, message: &str) -> String {
+    let field = match message
+        .strip_prefix("unknown field `")
+        .and_then(|rest| rest.split('`').next())
+    {
+        Some(field) => field,
+        None => return path.to_string(),
+    };
+    match find_key(item, field) {
+        Some(subpath) => format!("{path}{subpath}"),
+        None => path.to_string(),
+    }
+}
+
+// Find the path to a mapping key, relative to the given value.
+fn find_key(item: &Value, key: &str) -> Option<String> {
+    match item {
+        Value::Mapping(mapping) => {
+            for (name, value) in mapping.iter() {
+                let name = yaml_key_to_string(name);
+                if name == key {
+                    return Some(format!(".{name}"));
+                }
+                if let Some(subpath) = find_key(value, key) {
+                    return Some(format!(".{name}{subpath}"));
+                }
+            }
+            None
+        }
+        Value::Sequence(items) => items
+            .iter()
+            .enumerate()
+            .find_map(|(idx, item)| find_key(item, key).map(|subpath| format!("[{idx}]{subpath}"))),
+        _ => None,
+    }
+}
+
+/// The lines and columns of the items in a yaml text, by their paths.
+/// Paths are written like `pipeline[2].names[0]`.
+pub struct SourceLocations {
+    locations: HashMap<String, (usize, usize)>,
+}
+
+impl SourceLocations {
+    pub fn new(source: &str) -> Self {
+        let mut collector = LocationCollector {
+            stack: Vec::new(),
+            locations: HashMap::new(),
+        };
+        // Errors in the yaml syntax are reported by the config parser.
+        let _ = Parser::new(source.chars()).load(&mut collector, false);
+        SourceLocations {
+            locations: collector.locations,
+        }
+    }
+
+    /// Find the line and column of a path.
+    /// If the path is not found, the location of the closest parent is returned.
+    pub fn find(&self, path: &str) -> Option<(usize, usize)> {
+        let mut path = path;
+        loop {
+            if let Some(location) = self.locations.get(path) {
+                return Some(*location);
+            }
+            match path.rfind(['.', '[']) {
+                Some(idx) => path = &path[..idx],
+                None => return None,
+            }
+        }
+    }
+}
+
+enum Node {
+    Mapping { path: String, key: Option<String> },
+    Sequence { path: String, index: usize },
+}
+
+struct LocationCollector {
+    stack: Vec<Node>,
+    locations: HashMap<String, (usize, usize)>,
+}
+
+impl LocationCollector {
+    // The path of the next value in the current node, or None if the next scalar is a mapping key.
+    fn value_path(&self) -> Option<String> {
+        match self.stack.last() {
+            None => Some(String::new()),
+            Some(Node::Mapping { key: None, .. }) => None,
+            Some(Node::Mapping {
+                path,
+                key: Some(key),
+            }) => Some(join_path(path, key)),
+            Some(Node::Sequence { path, index }) => Some(format!("{path}[{index}]")),
+        }
+    }
+
+    // Move on to the next item of the current node, after a value is complete.
+    fn value_done(&mut self) {
+        match self.stack.last_mut() {
+            Some(Node::Mapping { key, .. }) => *key = None,
+            Some(Node::Sequence { index, .. }) => *index += 1,
+            None => {}
+        }
+    }
+
+    fn record(&mut self, path: &str, mark: &Marker) {
+        self.locations
+            .entry(path.to_string())
+            .or_insert((mark.line(), mark.col() + 1));
+    }
+}
+
+fn join_path(path: &str, key: &str) -> String {
+    if path.is_empty() {
+        key.to_string()
+    } else {
+        format!("{path}.{key}")
+    }
+}
+
+impl MarkedEventReceiver for LocationCollector {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(text, ..) => match self.value_path() {
+                Some(path) => {
+                    self.record(&path, &mark);
+                    self.value_done();
+                }
+                None => {
+                    if let Some(Node::Mapping { path, key }) = self.stack.last_mut() {
+                        // The start of a block mapping is marked at the end of the first key,
+                        // use the location of the first key for the mapping instead.
+                        let mapping_path = path.clone();
+                        let key_path = join_path(path, &text);
+                        *key = Some(text);
+                        self.record(&mapping_path, &mark);
+                        self.record(&key_path, &mark);
+                    }
+                }
+            },
+            Event::Alias(_) => self.value_done(),
+            Event::MappingStart(_) | Event::SequenceStart(_) => {
+                // Complex mapping keys are not supported, and get the path of the mapping.
+                let path = match self.value_path() {
+                    Some(path) => path,
+                    None => match self.stack.last() {
+                        Some(Node::Mapping { path, .. }) => path.clone(),
+                        _ => String::new(),
+                    },
+                };
+                let node = if matches!(event, Event::MappingStart(_)) {
+                    Node::Mapping { path, key: None }
+                } else {
+                    self.record(&path, &mark);
+                    Node::Sequence { path, index: 0 }
+                };
+                self.stack.push(node);
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.value_done();
+            }
+            _ => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::diagnostics::{check_config, SourceLocations};
+
+    #[test]
+    fn locate_paths() {
+        let yaml = "
+filters:
+  sub_lp:
+    type: Biquad
+    parameters: {type: Lowpass, freq: 80, q: 0.5}
+pipeline:
+  - type: Filter
+    names:
+      - sub_lp
+";
+        let locations = SourceLocations::new(yaml);
+        assert_eq!(locations.find("filters.sub_lp"), Some((3, 3)));
+        assert_eq!(
+            locations.find("filters.sub_lp.parameters.freq"),
+            Some((5, 33))
+        );
+        assert_eq!(locations.find("pipeline[0].names[0]"), Some((9, 9)));
+        assert_eq!(locations.find("pipeline[0].missing"), Some((7, 5)));
+        assert_eq!(locations.find("other"), None);
+    }
+
+    #[test]
+    fn report_all_problems() {
+        let yaml = "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  crossfade_time: -1.0
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  sub_lp:
+    type: Biquad
+    parameters:
+      type: Lowpass
+      freq: 30000
+      q: 0.5
+pipeline:
+  - type: Filter
+    channels: [0, 2]
+    names:
+      - sub_lp
+      - missing
+";
+        let problems = check_config(yaml, None).unwrap_err();
+        let found: Vec<(&str, Option<usize>)> =
+            problems.iter().map(|p| (p.path.as_str(), p.line)).collect();
+        assert_eq!(
+            found,
+            vec![
+                ("devices.crossfade_time", Some(5)),
+                ("pipeline[0].channels", Some(23)),
+                ("filters.sub_lp.parameters.freq", Some(19)),
+                ("pipeline[0].names[1]", Some(26)),
+            ]
+        );
+    }
+
+    #[test]
+    fn report_invalid_definitions() {
+        let yaml = "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  sub_lp:
+    type: Biquad
+    parameters:
+      type: Lowpass
+      frq: 80
+      q: 0.5
+  gain:
+    type: Gain
+    parameters:
+      gain: loud
+";
+        let problems = check_config(yaml, None).unwrap_err();
+        assert_eq!(problems.len(), 2);
+        let unknown = problems
+            .iter()
+            .find(|p| p.path == "filters.sub_lp.parameters.frq")
+            .unwrap();
+        assert_eq!(unknown.line, Some(18));
+        assert!(problems.iter().any(|p| p.path == "filters.gain"));
+    }
+
+    #[test]
+    fn report_all_include_and_crossover_problems() {
+        let devices = "
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 4
+    format: S16LE
+";
+        let yaml = format!("{devices}include:\n  - path: missing_a.yml\n  - path: missing_b.yml\n");
+        let problems = check_config(&yaml, None).unwrap_err();
+        let paths: Vec<&str> = problems.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(paths, vec!["include[0]", "include[1]"]);
+
+        let yaml = format!(
+            "{devices}
+crossovers:
+  first:
+    channels: 2
+    ways: 2
+    freqs: [100000]
+    filter: {{type: LinkwitzRiley, order: 4}}
+  second:
+    channels: 2
+    ways: 3
+    freqs: [100]
+    filter: {{type: LinkwitzRiley, order: 4}}
+pipeline:
+  - type: Crossover
+    name: first
+  - type: Crossover
+    name: third
+"
+        );
+        let problems = check_config(&yaml, None).unwrap_err();
+        let paths: Vec<&str> = problems.iter().map(|p| p.path.as_str()).collect();
+        assert_eq!(
+            paths,
+            vec![
+                "crossovers.first.freqs[0]",
+                "crossovers.second.freqs",
+                "pipeline[1].name"
+            ]
+        );
+    }
+}
@@ -611,15 +611,27 @@ pub fn validate_config(conf: &config::DitherParameters) -> Res<()> {
         | config::DitherParameters::ShibataLow192 { bits } => bits,
     };
     if *bits <= 1 {
-        return Err(config::ConfigError::new("Dither bit depth must be at least 2").into());
+        return Err(config::ConfigError::for_parameter(
+            "bits",
+            "Dither bit depth must be at least 2",
+        )
+        .into());
     }
 
     if let config::DitherParameters::Flat { amplitude, .. } = conf {
         if *amplitude < 0.0 {
-            return Err(config::ConfigError::new("Dither amplitude cannot be negative").into());
+            return Err(config::ConfigError::for_parameter(
+                "amplitude",
+                "Dither amplitude cannot be negative",
+            )
+            .into());
         }
         if *amplitude > 100.0 {
-            return Err(config::ConfigError::new("Dither amplitude must be less than 100").into());
+            return Err(config::ConfigError::for_parameter(
+                "amplitude",
+                "Dither amplitude must be less than 100",
+            )
+            .into());
         }
     }
 
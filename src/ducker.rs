@@ -0,0 +1,292 @@
+use crate::audiodevice::AudioChunk;
+use crate::config;
+use crate::filters::Processor;
+use crate::PrcFmt;
+use crate::Res;
+
+#[derive(Clone, Debug)]
+pub struct Ducker {
+    pub name: String,
+    pub channels: usize,
+    pub monitor_channels: Vec<usize>,
+    pub process_channels: Vec<usize>,
+    pub attack: PrcFmt,
+    pub release: PrcFmt,
+    pub hold: usize,
+    pub threshold: PrcFmt,
+    pub depth: PrcFmt,
+    pub samplerate: usize,
+    pub scratch: Vec<PrcFmt>,
+    pub prev_loudness: PrcFmt,
+    pub prev_gain: PrcFmt,
+    pub hold_counter: usize,
+}
+
+/// Get the program channels, defaults to all channels not used for monitoring.
+fn program_channels(config: &config::DuckerParameters) -> Vec<usize> {
+    let process_channels = config.process_channels();
+    if process_channels.is_empty() {
+        (0..config.channels)
+            .filter(|ch| !config.monitor_channels.contains(ch))
+            .collect()
+    } else {
+        process_channels
+    }
+}
+
+impl Ducker {
+    /// Creates a Ducker from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::DuckerParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let name = name.to_string();
+        let channels = config.channels;
+        let srate = samplerate as PrcFmt;
+        let monitor_channels = config.monitor_channels.clone();
+        let process_channels = program_channels(&config);
+        let attack = (-1.0 / srate / config.attack).exp();
+        let release = (-1.0 / srate / config.release).exp();
+        let hold = (config.hold() * srate).round() as usize;
+        let scratch = vec![0.0; chunksize];
+
+        debug!("Creating ducker '{}', channels: {}, monitor_channels: {:?}, process_channels: {:?}, attack: {}, release: {}, hold: {}, threshold: {}, depth: {}",
+                name, channels, monitor_channels, process_channels, attack, release, config.hold(), config.threshold, config.depth);
+
+        Ducker {
+            name,
+            channels,
+            monitor_channels,
+            process_channels,
+            attack,
+            release,
+            hold,
+            threshold: config.threshold,
+            depth: config.depth,
+            samplerate,
+            scratch,
+            prev_loudness: -100.0,
+            prev_gain: 0.0,
+            hold_counter: 0,
+        }
+    }
+
+    /// Sum all channels that are included in loudness monitoring, store result in self.scratch
+    fn sum_monitor_channels(&mut self, input: &AudioChunk) {
+        let ch = self.monitor_channels[0];
+        self.scratch.copy_from_slice(&input.waveforms[ch]);
+        for ch in self.monitor_channels.iter().skip(1) {
+            for (acc, val) in self.scratch.iter_mut().zip(input.waveforms[*ch].iter()) {
+                *acc += *val;
+            }
+        }
+    }
+
+    /// Estimate loudness, store result in self.scratch
+    fn estimate_loudness(&mut self) {
+        for val in self.scratch.iter_mut() {
+            // convert to dB
+            *val = 20.0 * (val.abs() + 1.0e-9).log10();
+            if *val >= self.prev_loudness {
+                *val = self.attack * self.prev_loudness + (1.0 - self.attack) * *val;
+            } else {
+                *val = self.release * self.prev_loudness + (1.0 - self.release) * *val;
+            }
+            self.prev_loudness = *val;
+        }
+    }
+
+    /// Calculate linear gain, store result in self.scratch.
+    /// The gain is held at the full depth for the hold time after
+    /// the priority signal has dropped below the threshold,
+    /// and then released back to unity.
+    fn calculate_linear_gain(&mut self) {
+        for val in self.scratch.iter_mut() {
+            let target = if *val > self.threshold {
+                self.hold_counter = self.hold;
+                -self.depth
+            } else if self.hold_counter > 0 {
+                self.hold_counter -= 1;
+                -self.depth
+            } else {
+                0.0
+            };
+            let gain = if target < self.prev_gain {
+                self.attack * self.prev_gain + (1.0 - self.attack) * target
+            } else {
+                self.release * self.prev_gain + (1.0 - self.release) * target
+            };
+            self.prev_gain = gain;
+            *val = (10.0 as PrcFmt).powf(gain / 20.0);
+        }
+    }
+
+    fn apply_gain(&self, input: &mut [PrcFmt]) {
+        for (val, gain) in input.iter_mut().zip(self.scratch.iter()) {
+            *val *= gain;
+        }
+    }
+}
+
+impl Processor for Ducker {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply a Ducker to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        self.sum_monitor_channels(input);
+        self.estimate_loudness();
+        self.calculate_linear_gain();
+        for ch in self.process_channels.iter() {
+            self.apply_gain(&mut input.waveforms[*ch]);
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::Ducker {
+            parameters: config, ..
+        } = config
+        {
+            let srate = self.samplerate as PrcFmt;
+            let monitor_channels = config.monitor_channels.clone();
+            let process_channels = program_channels(&config);
+            let attack = (-1.0 / srate / config.attack).exp();
+            let release = (-1.0 / srate / config.release).exp();
+            let hold = (config.hold() * srate).round() as usize;
+
+            self.monitor_channels = monitor_channels;
+            self.process_channels = process_channels;
+            self.attack = attack;
+            self.release = release;
+            self.hold = hold;
+            self.hold_counter = self.hold_counter.min(hold);
+            self.threshold = config.threshold;
+            self.depth = config.depth;
+
+            debug!("Updated ducker '{}', monitor_channels: {:?}, process_channels: {:?}, attack: {}, release: {}, hold: {}, threshold: {}, depth: {}",
+                self.name, self.monitor_channels, self.process_channels, attack, release, config.hold(), config.threshold, config.depth);
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the ducker config, to give a helpful message intead of a panic.
+pub fn validate_ducker(config: &config::DuckerParameters) -> Res<()> {
+    let channels = config.channels;
+    if config.attack <= 0.0 {
+        let msg = "Attack value must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.release <= 0.0 {
+        let msg = "Release value must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.hold() < 0.0 {
+        let msg = "Hold value cannot be negative.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.depth < 0.0 {
+        let msg = "Depth value cannot be negative.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.monitor_channels.is_empty() {
+        let msg = "At least one monitor channel must be given.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    for ch in config.monitor_channels.iter() {
+        if *ch >= channels {
+            let msg = format!(
+                "Invalid monitor channel: {}, max is: {}.",
+                *ch,
+                channels - 1
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    for ch in config.process_channels().iter() {
+        if *ch >= channels {
+            let msg = format!(
+                "Invalid channel to process: {}, max is: {}.",
+                *ch,
+                channels - 1
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+        if config.monitor_channels.contains(ch) {
+            let msg = format!(
+                "Channel {} can not be both a monitor channel and a channel to process.",
+                *ch
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::DuckerParameters;
+    use crate::ducker::{validate_ducker, Ducker};
+    use crate::filters::Processor;
+    use crate::PrcFmt;
+
+    fn make_params(hold: PrcFmt) -> DuckerParameters {
+        DuckerParameters {
+            channels: 3,
+            monitor_channels: vec![2],
+            process_channels: None,
+            attack: 0.001,
+            release: 0.001,
+            hold: Some(hold),
+            threshold: -30.0,
+            depth: 20.0,
+        }
+    }
+
+    fn make_chunk(priority: PrcFmt) -> AudioChunk {
+        let waveforms = vec![vec![1.0; 1000], vec![1.0; 1000], vec![priority; 1000]];
+        AudioChunk::new(waveforms, 1.0, -1.0, 1000, 1000)
+    }
+
+    #[test]
+    fn ducks_program_channels() {
+        let mut ducker = Ducker::from_config("test", make_params(0.0), 48000, 1000);
+        assert_eq!(ducker.process_channels, vec![0, 1]);
+        let mut chunk = make_chunk(0.0);
+        ducker.process_chunk(&mut chunk).unwrap();
+        assert!((chunk.waveforms[0][999] - 1.0).abs() < 1.0e-6);
+        let mut chunk = make_chunk(0.5);
+        ducker.process_chunk(&mut chunk).unwrap();
+        assert!((chunk.waveforms[0][999] - 0.1).abs() < 1.0e-3);
+        assert!((chunk.waveforms[1][999] - 0.1).abs() < 1.0e-3);
+        assert!((chunk.waveforms[2][999] - 0.5).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn holds_before_release() {
+        let mut ducker = Ducker::from_config("test", make_params(1.0), 48000, 1000);
+        let mut chunk = make_chunk(0.5);
+        ducker.process_chunk(&mut chunk).unwrap();
+        // Priority signal stops, gain should stay at full depth during hold time.
+        for _ in 0..10 {
+            let mut chunk = make_chunk(0.0);
+            ducker.process_chunk(&mut chunk).unwrap();
+            assert!((chunk.waveforms[0][999] - 0.1).abs() < 1.0e-3);
+        }
+    }
+
+    #[test]
+    fn check_overlapping_channels() {
+        let mut params = make_params(0.0);
+        assert!(validate_ducker(&params).is_ok());
+        params.process_channels = Some(vec![0, 2]);
+        assert!(validate_ducker(&params).is_err());
+    }
+}
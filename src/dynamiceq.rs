@@ -0,0 +1,335 @@
+use crate::biquad;
+use crate::config;
+use crate::filters::Filter;
+
+use crate::PrcFmt;
+use crate::Res;
+
+// Number of samples between updates of the filter coefficients.
+const SUBBLOCK: usize = 16;
+// Largest change of the band gain in dB for each coefficient update.
+const MAX_GAIN_STEP: PrcFmt = 0.1;
+
+#[derive(Clone, Debug)]
+struct DynamicBand {
+    conf: config::DynamicEqBand,
+    attack: PrcFmt,
+    release: PrcFmt,
+    detector: biquad::Biquad,
+    eq: biquad::Biquad,
+    level: PrcFmt,
+    gain: PrcFmt,
+}
+
+impl DynamicBand {
+    fn from_config(samplerate: usize, conf: &config::DynamicEqBand) -> Self {
+        let srate = samplerate as PrcFmt;
+        let attack = (-1.0 / srate / conf.attack).exp();
+        let release = (-1.0 / srate / conf.release).exp();
+        let detector_coeffs =
+            biquad::BiquadCoefficients::from_config(samplerate, detector_config(conf));
+        let eq_coeffs = biquad::BiquadCoefficients::from_config(samplerate, eq_config(conf, 0.0));
+        DynamicBand {
+            conf: conf.clone(),
+            attack,
+            release,
+            detector: biquad::Biquad::new("detector", samplerate, detector_coeffs),
+            eq: biquad::Biquad::new("eq", samplerate, eq_coeffs),
+            level: -100.0,
+            gain: 0.0,
+        }
+    }
+
+    /// Update the band-limited level estimate using the detector output.
+    fn estimate_level(&mut self, detected: &[PrcFmt]) {
+        for val in detected.iter() {
+            let level = 20.0 * (val.abs() + 1.0e-9).log10();
+            if level >= self.level {
+                self.level = self.attack * self.level + (1.0 - self.attack) * level;
+            } else {
+                self.level = self.release * self.level + (1.0 - self.release) * level;
+            }
+        }
+    }
+
+    /// Calculate the wanted gain of the band from the current level.
+    /// A negative max_gain cuts the band when the level is above the threshold,
+    /// a positive one boosts it when the level is below the threshold.
+    fn target_gain(&self) -> PrcFmt {
+        let slope = 1.0 - 1.0 / self.conf.ratio;
+        if self.conf.max_gain < 0.0 {
+            let gain = -(self.level - self.conf.threshold).max(0.0) * slope;
+            gain.max(self.conf.max_gain)
+        } else {
+            let gain = (self.conf.threshold - self.level).max(0.0) * slope;
+            gain.min(self.conf.max_gain)
+        }
+    }
+
+    /// Move the gain towards the target in small steps, and update the eq coefficients.
+    fn update_gain(&mut self) {
+        let step = (self.target_gain() - self.gain).clamp(-MAX_GAIN_STEP, MAX_GAIN_STEP);
+        if step.abs() > 1.0e-6 {
+            self.set_gain(self.gain + step);
+        }
+    }
+
+    fn set_gain(&mut self, gain: PrcFmt) {
+        self.gain = gain;
+        self.eq.update_parameters(config::Filter::Biquad {
+            description: None,
+            parameters: eq_config(&self.conf, gain),
+        });
+    }
+}
+
+/// Get the biquad that measures the level in the band.
+fn detector_config(conf: &config::DynamicEqBand) -> config::BiquadParameters {
+    match conf.r#type {
+        config::DynamicEqBandType::Peaking => {
+            config::BiquadParameters::Bandpass(config::NotchWidth::Q {
+                freq: conf.freq,
+                q: conf.q,
+            })
+        }
+        config::DynamicEqBandType::Lowshelf => config::BiquadParameters::Lowpass {
+            freq: conf.freq,
+            q: 0.5f64.sqrt() as PrcFmt,
+        },
+        config::DynamicEqBandType::Highshelf => config::BiquadParameters::Highpass {
+            freq: conf.freq,
+            q: 0.5f64.sqrt() as PrcFmt,
+        },
+    }
+}
+
+/// Get the biquad that applies the gain to the band.
+fn eq_config(conf: &config::DynamicEqBand, gain: PrcFmt) -> config::BiquadParameters {
+    match conf.r#type {
+        config::DynamicEqBandType::Peaking => {
+            config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+                freq: conf.freq,
+                q: conf.q,
+                gain,
+            })
+        }
+        config::DynamicEqBandType::Lowshelf => {
+            config::BiquadParameters::Lowshelf(config::ShelfSteepness::Q {
+                freq: conf.freq,
+                q: conf.q,
+                gain,
+            })
+        }
+        config::DynamicEqBandType::Highshelf => {
+            config::BiquadParameters::Highshelf(config::ShelfSteepness::Q {
+                freq: conf.freq,
+                q: conf.q,
+                gain,
+            })
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DynamicEq {
+    pub name: String,
+    samplerate: usize,
+    bands: Vec<DynamicBand>,
+    input: Vec<PrcFmt>,
+    detected: Vec<PrcFmt>,
+}
+
+impl DynamicEq {
+    /// Creates a DynamicEq from a config struct
+    pub fn from_config(name: &str, samplerate: usize, conf: config::DynamicEqParameters) -> Self {
+        let bands = conf
+            .bands
+            .iter()
+            .map(|band| DynamicBand::from_config(samplerate, band))
+            .collect();
+        debug!(
+            "Creating dynamic eq '{}' with {} bands",
+            name,
+            conf.bands.len()
+        );
+        DynamicEq {
+            name: name.to_string(),
+            samplerate,
+            bands,
+            input: Vec::new(),
+            detected: Vec::new(),
+        }
+    }
+}
+
+impl Filter for DynamicEq {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        // All detectors look at the unprocessed input.
+        self.input.clear();
+        self.input.extend_from_slice(waveform);
+        for band in self.bands.iter_mut() {
+            self.detected.clear();
+            self.detected.extend_from_slice(&self.input);
+            band.detector.process_waveform(&mut self.detected)?;
+            for (block, detected) in waveform
+                .chunks_mut(SUBBLOCK)
+                .zip(self.detected.chunks(SUBBLOCK))
+            {
+                band.estimate_level(detected);
+                band.update_gain();
+                band.eq.process_waveform(block)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if let config::Filter::DynamicEq {
+            parameters: conf, ..
+        } = conf
+        {
+            let mut bands: Vec<DynamicBand> = conf
+                .bands
+                .iter()
+                .map(|band| DynamicBand::from_config(self.samplerate, band))
+                .collect();
+            // Keep the state of existing bands to avoid clicks.
+            for (new, old) in bands.iter_mut().zip(self.bands.iter()) {
+                new.level = old.level;
+                new.detector.s1 = old.detector.s1;
+                new.detector.s2 = old.detector.s2;
+                new.eq.s1 = old.eq.s1;
+                new.eq.s2 = old.eq.s2;
+                new.set_gain(
+                    old.gain
+                        .clamp(new.conf.max_gain.min(0.0), new.conf.max_gain.max(0.0)),
+                );
+            }
+            self.bands = bands;
+            debug!(
+                "Updated dynamic eq '{}', {} bands",
+                self.name,
+                self.bands.len()
+            );
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate a DynamicEq config.
+pub fn validate_config(samplerate: usize, conf: &config::DynamicEqParameters) -> Res<()> {
+    for (n, band) in conf.bands.iter().enumerate() {
+        if band.attack <= 0.0 {
+            let msg = format!("Band {n}: Attack value must be larger than zero.");
+            return Err(
+                config::ConfigError::for_parameter(&format!("bands[{n}].attack"), &msg).into(),
+            );
+        }
+        if band.release <= 0.0 {
+            let msg = format!("Band {n}: Release value must be larger than zero.");
+            return Err(
+                config::ConfigError::for_parameter(&format!("bands[{n}].release"), &msg).into(),
+            );
+        }
+        if band.ratio < 1.0 {
+            let msg = format!("Band {n}: Ratio must be at least 1.0.");
+            return Err(
+                config::ConfigError::for_parameter(&format!("bands[{n}].ratio"), &msg).into(),
+            );
+        }
+        if band.max_gain.abs() > 40.0 {
+            let msg = format!("Band {n}: Max gain must be within +- 40 dB.");
+            return Err(
+                config::ConfigError::for_parameter(&format!("bands[{n}].max_gain"), &msg).into(),
+            );
+        }
+        for params in [detector_config(band), eq_config(band, band.max_gain)] {
+            if let Err(err) = biquad::validate_config(samplerate, &params) {
+                let msg = format!("Band {n}: {err}");
+                let parameter = config::parameter_path(&format!("bands[{n}]"), err.as_ref());
+                return Err(config::ConfigError::for_parameter(&parameter, &msg).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{DynamicEqBand, DynamicEqBandType, DynamicEqParameters};
+    use crate::dynamiceq::{validate_config, DynamicEq};
+    use crate::filters::Filter;
+    use crate::PrcFmt;
+
+    fn make_band(max_gain: PrcFmt) -> DynamicEqParameters {
+        DynamicEqParameters {
+            bands: vec![DynamicEqBand {
+                r#type: DynamicEqBandType::Peaking,
+                freq: 100.0,
+                q: 1.0,
+                threshold: -20.0,
+                ratio: 100.0,
+                attack: 0.001,
+                release: 0.001,
+                max_gain,
+            }],
+        }
+    }
+
+    fn sine_peak(filter: &mut DynamicEq, freq: PrcFmt, ampl: PrcFmt) -> PrcFmt {
+        let fs = 48000.0;
+        let mut peak = 0.0;
+        for chunk in 0..20 {
+            let mut wave: Vec<PrcFmt> = (0..4800)
+                .map(|n| {
+                    let t = (chunk * 4800 + n) as PrcFmt / fs;
+                    ampl * (2.0 * std::f64::consts::PI as PrcFmt * freq * t).sin()
+                })
+                .collect();
+            filter.process_waveform(&mut wave).unwrap();
+            peak = wave.iter().fold(0.0, |acc: PrcFmt, v| acc.max(v.abs()));
+        }
+        peak
+    }
+
+    #[test]
+    fn cuts_loud_band() {
+        let mut filter = DynamicEq::from_config("test", 48000, make_band(-6.0));
+        // Quiet signal is below the threshold and passes unchanged.
+        let quiet = sine_peak(&mut filter, 100.0, 0.01);
+        assert!((quiet - 0.01).abs() < 0.0005);
+        // Loud signal is cut by the full max gain.
+        let loud = sine_peak(&mut filter, 100.0, 1.0);
+        assert!((loud - 0.5).abs() < 0.02, "{loud}");
+        // Loud signal outside the band is unaffected.
+        let mut filter = DynamicEq::from_config("test", 48000, make_band(-6.0));
+        let outside = sine_peak(&mut filter, 5000.0, 1.0);
+        assert!((outside - 1.0).abs() < 0.02, "{outside}");
+    }
+
+    #[test]
+    fn boosts_quiet_band() {
+        let mut filter = DynamicEq::from_config("test", 48000, make_band(6.0));
+        let quiet = sine_peak(&mut filter, 100.0, 0.01);
+        assert!((quiet - 0.02).abs() < 0.001, "{quiet}");
+        let loud = sine_peak(&mut filter, 100.0, 1.0);
+        assert!((loud - 1.0).abs() < 0.02, "{loud}");
+    }
+
+    #[test]
+    fn check_config() {
+        let mut conf = make_band(-6.0);
+        assert!(validate_config(48000, &conf).is_ok());
+        conf.bands[0].ratio = 0.5;
+        assert!(validate_config(48000, &conf).is_err());
+        let mut conf = make_band(-6.0);
+        conf.bands[0].freq = 30000.0;
+        assert!(validate_config(48000, &conf).is_err());
+    }
+}
@@ -0,0 +1,355 @@
+// Read equalizer settings exported by measurement and equalizer tools.
+// Supported formats are the text format used by EqualizerAPO and AutoEQ,
+// the REW text export (which uses the same filter lines), and the REW XML export.
+
+use std::fs::File;
+use std::io::Read;
+
+use crate::config;
+use crate::PrcFmt;
+use crate::Res;
+
+/// The filters and preamp gain read from an equalizer file.
+#[derive(Clone, Debug, PartialEq)]
+pub struct EqFile {
+    pub preamp: PrcFmt,
+    pub filters: Vec<config::BiquadParameters>,
+}
+
+/// Read an equalizer file. The format is detected from the contents.
+/// The channel is only used for REW XML files, that may contain filters for several speakers.
+pub fn read_eq_file(filename: &str, channel: usize) -> Res<EqFile> {
+    let mut file = match File::open(filename) {
+        Ok(f) => f,
+        Err(err) => {
+            let msg = format!("Could not open equalizer file '{filename}'. Reason: {err}");
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    };
+    let mut contents = String::new();
+    if let Err(err) = file.read_to_string(&mut contents) {
+        let msg = format!("Could not read equalizer file '{filename}'. Reason: {err}");
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    let result = if contents.trim_start().starts_with('<') {
+        parse_rew_xml(&contents, channel)
+    } else {
+        parse_text(&contents)
+    };
+    match result {
+        Ok(eqfile) => {
+            debug!(
+                "Read equalizer file '{}', preamp: {} dB, number of filters: {}",
+                filename,
+                eqfile.preamp,
+                eqfile.filters.len()
+            );
+            Ok(eqfile)
+        }
+        Err(err) => {
+            let msg = format!("Invalid equalizer file '{filename}'. Reason: {err}");
+            Err(config::ConfigError::new(&msg).into())
+        }
+    }
+}
+
+// Parse the text format of EqualizerAPO, AutoEQ and REW.
+// Lines that are not filters or preamp settings are ignored.
+fn parse_text(contents: &str) -> Result<EqFile, String> {
+    let mut preamp = 0.0;
+    let mut filters = Vec::new();
+    for (nbr, line) in contents.lines().enumerate() {
+        let line = line.trim();
+        if let Some(value) = line.strip_prefix("Preamp:") {
+            let tokens: Vec<&str> = value.split_whitespace().collect();
+            preamp += parse_number(tokens.first().copied())
+                .map_err(|err| format!("Line {}: {}", nbr + 1, err))?;
+        } else if line.starts_with("Filter") {
+            if let Some((_, settings)) = line.split_once(':') {
+                let tokens: Vec<&str> = settings.split_whitespace().collect();
+                if let Some(filter) =
+                    parse_filter(&tokens).map_err(|err| format!("Line {}: {}", nbr + 1, err))?
+                {
+                    filters.push(filter);
+                }
+            }
+        }
+    }
+    Ok(EqFile { preamp, filters })
+}
+
+fn parse_number(token: Option<&str>) -> Result<PrcFmt, String> {
+    match token {
+        Some(value) => value
+            .parse()
+            .map_err(|_| format!("Can't parse '{value}' as a number")),
+        None => Err("Missing value".to_string()),
+    }
+}
+
+// Get the value following a keyword, for example the frequency after "Fc".
+fn value_after(tokens: &[&str], key: &str) -> Result<Option<PrcFmt>, String> {
+    match tokens.iter().position(|t| *t == key) {
+        Some(idx) => parse_number(tokens.get(idx + 1).copied()).map(Some),
+        None => Ok(None),
+    }
+}
+
+// Parse the settings of a filter line, for example "ON PK Fc 100 Hz Gain -3.0 dB Q 2.0".
+// Returns None for filters that are disabled or unused.
+fn parse_filter(tokens: &[&str]) -> Result<Option<config::BiquadParameters>, String> {
+    if tokens.first() != Some(&"ON") {
+        return Ok(None);
+    }
+    let filtertype = match tokens.get(1) {
+        Some(filtertype) => *filtertype,
+        None => return Err("Missing filter type".to_string()),
+    };
+    if filtertype == "None" {
+        return Ok(None);
+    }
+    let mut freq = value_after(tokens, "Fc")?.ok_or("Missing frequency")?;
+    if tokens.contains(&"kHz") {
+        freq *= 1000.0;
+    }
+    let gain = value_after(tokens, "Gain")?;
+    let q = value_after(tokens, "Q")?;
+    let bandwidth = value_after(tokens, "Oct")?;
+    // Shelving filters may give the slope after the type, like "LS 12dB".
+    let slope = tokens
+        .get(2)
+        .and_then(|t| t.strip_suffix("dB"))
+        .and_then(|t| t.parse::<PrcFmt>().ok());
+    let filter = match filtertype {
+        "PK" | "PEQ" | "Modal" => {
+            let gain = gain.ok_or("Missing gain")?;
+            match (q, bandwidth) {
+                (Some(q), _) => {
+                    config::BiquadParameters::Peaking(config::PeakingWidth::Q { freq, q, gain })
+                }
+                (None, Some(bandwidth)) => {
+                    config::BiquadParameters::Peaking(config::PeakingWidth::Bandwidth {
+                        freq,
+                        bandwidth,
+                        gain,
+                    })
+                }
+                (None, None) => return Err("Missing Q or bandwidth".to_string()),
+            }
+        }
+        "LS" | "LSC" | "LSQ" | "HS" | "HSC" | "HSQ" => {
+            let gain = gain.ok_or("Missing gain")?;
+            let low = filtertype.starts_with('L');
+            let steepness = match (q, slope) {
+                (Some(q), _) => config::ShelfSteepness::Q { freq, q, gain },
+                (None, Some(6.0)) => {
+                    return Ok(Some(if low {
+                        config::BiquadParameters::LowshelfFO { freq, gain }
+                    } else {
+                        config::BiquadParameters::HighshelfFO { freq, gain }
+                    }));
+                }
+                (None, slope) => config::ShelfSteepness::Slope {
+                    freq,
+                    slope: slope.unwrap_or(12.0),
+                    gain,
+                },
+            };
+            if low {
+                config::BiquadParameters::Lowshelf(steepness)
+            } else {
+                config::BiquadParameters::Highshelf(steepness)
+            }
+        }
+        "LP" | "LPQ" => config::BiquadParameters::Lowpass {
+            freq,
+            q: q.unwrap_or(std::f64::consts::FRAC_1_SQRT_2 as PrcFmt),
+        },
+        "HP" | "HPQ" => config::BiquadParameters::Highpass {
+            freq,
+            q: q.unwrap_or(std::f64::consts::FRAC_1_SQRT_2 as PrcFmt),
+        },
+        "LP1" => config::BiquadParameters::LowpassFO { freq },
+        "HP1" => config::BiquadParameters::HighpassFO { freq },
+        "NO" => config::BiquadParameters::Notch(config::NotchWidth::Q {
+            freq,
+            q: q.unwrap_or(30.0),
+        }),
+        "BP" => config::BiquadParameters::Bandpass(config::NotchWidth::Q {
+            freq,
+            q: q.unwrap_or(std::f64::consts::FRAC_1_SQRT_2 as PrcFmt),
+        }),
+        "AP" => config::BiquadParameters::Allpass(config::NotchWidth::Q {
+            freq,
+            q: q.unwrap_or(std::f64::consts::FRAC_1_SQRT_2 as PrcFmt),
+        }),
+        _ => return Err(format!("Unsupported filter type '{filtertype}'")),
+    };
+    Ok(Some(filter))
+}
+
+// Get the text of the first element with the given tag.
+fn xml_element<'a>(block: &'a str, tag: &str) -> Option<&'a str> {
+    let start = block.find(&format!("<{tag}>"))? + tag.len() + 2;
+    let end = start + block[start..].find(&format!("</{tag}>"))?;
+    Some(block[start..end].trim())
+}
+
+// Split a document into the contents of all elements with the given tag.
+// The returned blocks include the attributes of the opening tag.
+fn xml_blocks<'a>(contents: &'a str, tag: &str) -> Vec<&'a str> {
+    let open = format!("<{tag}");
+    let close = format!("</{tag}>");
+    let mut blocks = Vec::new();
+    let mut rest = contents;
+    while let Some(start) = rest.find(&open) {
+        let after = &rest[start + open.len()..];
+        // Make sure that the tag name is not just a prefix of a longer one.
+        if !after.starts_with([' ', '>', '\t', '\n', '\r']) {
+            rest = after;
+            continue;
+        }
+        match after.find(&close) {
+            Some(end) => {
+                blocks.push(&after[..end]);
+                rest = &after[end + close.len()..];
+            }
+            None => break,
+        }
+    }
+    blocks
+}
+
+// Parse a REW XML export, that contains a list of peaking filters per speaker.
+fn parse_rew_xml(contents: &str, channel: usize) -> Result<EqFile, String> {
+    let speakers = xml_blocks(contents, "speaker");
+    let speaker = match speakers.get(channel) {
+        Some(speaker) => speaker,
+        None => {
+            return Err(format!(
+                "Can't read speaker {} from a file with {} speakers",
+                channel,
+                speakers.len()
+            ))
+        }
+    };
+    let mut filters = Vec::new();
+    for filter in xml_blocks(speaker, "filter") {
+        let attributes = &filter[..filter.find('>').unwrap_or(0)];
+        if attributes.contains("enabled=\"false\"") {
+            continue;
+        }
+        let freq = parse_number(xml_element(filter, "frequency"))?;
+        let gain = parse_number(xml_element(filter, "level"))?;
+        let q = parse_number(xml_element(filter, "Q"))?;
+        filters.push(config::BiquadParameters::Peaking(config::PeakingWidth::Q {
+            freq,
+            q,
+            gain,
+        }));
+    }
+    Ok(EqFile {
+        preamp: 0.0,
+        filters,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BiquadParameters, PeakingWidth, ShelfSteepness};
+    use crate::eqfile::{parse_rew_xml, parse_text};
+
+    #[test]
+    fn read_autoeq() {
+        let contents = "Preamp: -6.2 dB
+Filter 1: ON LSC Fc 105 Hz Gain 5.5 dB Q 0.70
+Filter 2: ON PK Fc 2200 Hz Gain -3.1 dB Q 1.41
+Filter 3: OFF PK Fc 4000 Hz Gain 2.0 dB Q 1.41
+Filter 4: ON HSC Fc 10 kHz Gain -2.0 dB Q 0.70
+";
+        let eq = parse_text(contents).unwrap();
+        assert_eq!(eq.preamp, -6.2);
+        assert_eq!(eq.filters.len(), 3);
+        assert_eq!(
+            eq.filters[0],
+            BiquadParameters::Lowshelf(ShelfSteepness::Q {
+                freq: 105.0,
+                q: 0.7,
+                gain: 5.5
+            })
+        );
+        assert_eq!(
+            eq.filters[2],
+            BiquadParameters::Highshelf(ShelfSteepness::Q {
+                freq: 10000.0,
+                q: 0.7,
+                gain: -2.0
+            })
+        );
+        assert!(parse_text("Filter 1: ON XX Fc 100 Hz").is_err());
+        assert!(parse_text("Filter 1: ON PK Fc 100 Hz Q 1.0").is_err());
+    }
+
+    #[test]
+    fn read_rew_text() {
+        let contents = "Filter Settings file
+
+Room EQ V5.20
+Notes:
+
+Equaliser: Generic
+Filter  1: ON  PK       Fc   63.10 Hz  Gain  -5.00 dB  Q  4.000
+Filter  2: ON  LS 6dB   Fc   100.0 Hz  Gain   3.00 dB
+Filter  3: ON  HP       Fc   20.00 Hz
+Filter  4: ON  None
+";
+        let eq = parse_text(contents).unwrap();
+        assert_eq!(eq.preamp, 0.0);
+        assert_eq!(eq.filters.len(), 3);
+        assert_eq!(
+            eq.filters[1],
+            BiquadParameters::LowshelfFO {
+                freq: 100.0,
+                gain: 3.0
+            }
+        );
+    }
+
+    #[test]
+    fn read_rew_xml() {
+        let contents = r#"<?xml version="1.0" encoding="UTF-8"?>
+<filters>
+  <speaker location="Left">
+    <filter number="1" enabled="true">
+      <frequency>45.5</frequency>
+      <level>-8.3</level>
+      <Q>5.2</Q>
+    </filter>
+    <filter number="2" enabled="false">
+      <frequency>120.0</frequency>
+      <level>-2.0</level>
+      <Q>2.0</Q>
+    </filter>
+  </speaker>
+  <speaker location="Right">
+    <filter number="1" enabled="true">
+      <frequency>52.0</frequency>
+      <level>-6.1</level>
+      <Q>4.0</Q>
+    </filter>
+  </speaker>
+</filters>
+"#;
+        let eq = parse_rew_xml(contents, 1).unwrap();
+        assert_eq!(
+            eq.filters,
+            vec![BiquadParameters::Peaking(PeakingWidth::Q {
+                freq: 52.0,
+                q: 4.0,
+                gain: -6.1
+            })]
+        );
+        assert_eq!(parse_rew_xml(contents, 0).unwrap().filters.len(), 1);
+        assert!(parse_rew_xml(contents, 2).is_err());
+    }
+}
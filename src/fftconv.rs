@@ -13,6 +13,7 @@ use crate::Res;
 
 pub struct FftConv {
     name: String,
+    samplerate: usize,
     npoints: usize,
     nsegments: usize,
     overlap: Vec<PrcFmt>,
@@ -60,6 +61,7 @@ impl FftConv {
 
         FftConv {
             name,
+            samplerate: 0,
             npoints: data_length,
             nsegments,
             overlap: vec![0.0; data_length],
@@ -76,7 +78,12 @@ impl FftConv {
         }
     }
 
-    pub fn from_config(name: &str, data_length: usize, conf: config::ConvParameters) -> Self {
+    pub fn from_config(
+        name: &str,
+        data_length: usize,
+        samplerate: usize,
+        conf: config::ConvParameters,
+    ) -> Self {
         let values = match conf {
             config::ConvParameters::Values { values } => values,
             config::ConvParameters::Raw(params) => filters::read_coeff_file(
@@ -94,8 +101,18 @@ impl FftConv {
                 values[0] = 1.0;
                 values
             }
+            config::ConvParameters::LinearPhase(params) => {
+                info!(
+                    "Linear phase filter {} has a latency of {} samples",
+                    name,
+                    params.latency()
+                );
+                linear_phase_coefficients(samplerate, &params)
+            }
         };
-        FftConv::new(name, data_length, &values)
+        let mut conv = FftConv::new(name, data_length, &values);
+        conv.samplerate = samplerate;
+        conv
     }
 }
 
@@ -182,6 +199,9 @@ impl Filter for FftConv {
                     values[0] = 1.0;
                     values
                 }
+                config::ConvParameters::LinearPhase(params) => {
+                    linear_phase_coefficients(self.samplerate, &params)
+                }
             };
 
             let nsegments = ((coeffs.len() as PrcFmt) / (self.npoints as PrcFmt)).ceil() as usize;
@@ -218,8 +238,55 @@ impl Filter for FftConv {
     }
 }
 
+// Zero phase amplitude of the lowpass prototype, -6 dB at the cutoff.
+// The amplitudes of a lowpass and the highpass with the same cutoff sum to one.
+fn lowpass_amplitude(freq: PrcFmt, cutoff: PrcFmt, slope: PrcFmt) -> PrcFmt {
+    1.0 / (1.0 + (freq / cutoff).powf(slope / 6.0))
+}
+
+/// Design a linear phase highpass, lowpass or bandpass FIR filter.
+/// The highpass is made as a delta minus the lowpass, and the bandpass as the difference of two lowpasses,
+/// so that filters sharing the same frequencies and slope sum to a pure delay of `(length-1)/2` samples.
+pub fn linear_phase_coefficients(
+    samplerate: usize,
+    params: &config::ConvParametersLinearPhase,
+) -> Vec<PrcFmt> {
+    // Sample the response on a dense grid, to avoid time aliasing when truncating.
+    let npoints = 8 * params.length.next_power_of_two();
+    let mut spectrum: Vec<Complex<PrcFmt>> = (0..(npoints / 2 + 1))
+        .map(|n| {
+            let freq = n as PrcFmt * samplerate as PrcFmt / npoints as PrcFmt;
+            let lowpass = params
+                .lowpass_freq
+                .map(|cutoff| lowpass_amplitude(freq, cutoff, params.slope))
+                .unwrap_or(1.0);
+            let highpass_complement = params
+                .highpass_freq
+                .map(|cutoff| lowpass_amplitude(freq, cutoff, params.slope))
+                .unwrap_or(0.0);
+            Complex::new(lowpass - highpass_complement, 0.0)
+        })
+        .collect();
+    let mut planner = RealFftPlanner::<PrcFmt>::new();
+    let ifft = planner.plan_fft_inverse(npoints);
+    let mut impulse = ifft.make_output_vec();
+    ifft.process(&mut spectrum, &mut impulse).unwrap();
+
+    // Center the zero phase impulse and apply a Blackman window, that is exactly one at the center.
+    let center = params.latency();
+    let pi = std::f64::consts::PI as PrcFmt;
+    (0..params.length)
+        .map(|n| {
+            let index = (n + npoints - center) % npoints;
+            let x = 2.0 * pi * n as PrcFmt / (2 * center) as PrcFmt;
+            let window = 0.42 - 0.5 * x.cos() + 0.08 * (2.0 * x).cos();
+            window * impulse[index] / npoints as PrcFmt
+        })
+        .collect()
+}
+
 /// Validate a FFT convolution config.
-pub fn validate_config(conf: &config::ConvParameters) -> Res<()> {
+pub fn validate_config(samplerate: usize, conf: &config::ConvParameters) -> Res<()> {
     match conf {
         config::ConvParameters::Values { .. } | config::ConvParameters::Dummy { .. } => Ok(()),
         config::ConvParameters::Raw(params) => {
@@ -230,24 +297,68 @@ pub fn validate_config(conf: &config::ConvParameters) -> Res<()> {
                 params.skip_bytes_lines(),
             )?;
             if coeffs.is_empty() {
-                return Err(config::ConfigError::new("Conv coefficients are empty").into());
+                return Err(config::ConfigError::for_parameter(
+                    "filename",
+                    "Conv coefficients are empty",
+                )
+                .into());
             }
             Ok(())
         }
         config::ConvParameters::Wav(params) => {
             let coeffs = filters::read_wav(&params.filename, params.channel())?;
             if coeffs.is_empty() {
-                return Err(config::ConfigError::new("Conv coefficients are empty").into());
+                return Err(config::ConfigError::for_parameter(
+                    "filename",
+                    "Conv coefficients are empty",
+                )
+                .into());
             }
             Ok(())
         }
+        config::ConvParameters::LinearPhase(params) => {
+            let maxfreq = samplerate as PrcFmt / 2.0;
+            if params.length < 3 || params.length % 2 == 0 {
+                return Err(config::ConfigError::for_parameter(
+                    "length",
+                    "Length of a linear phase filter must be odd and at least 3",
+                )
+                .into());
+            }
+            if params.slope <= 0.0 {
+                return Err(
+                    config::ConfigError::for_parameter("slope", "Slope must be > 0").into(),
+                );
+            }
+            for (parameter, freq) in [
+                ("highpass_freq", params.highpass_freq),
+                ("lowpass_freq", params.lowpass_freq),
+            ] {
+                if let Some(freq) = freq.filter(|f| *f <= 0.0 || *f >= maxfreq) {
+                    let msg = format!("Frequency must be > 0 and < samplerate/2, got {freq}");
+                    return Err(config::ConfigError::for_parameter(parameter, &msg).into());
+                }
+            }
+            match (params.highpass_freq, params.lowpass_freq) {
+                (None, None) => Err(config::ConfigError::new(
+                    "A linear phase filter needs a highpass or lowpass frequency, or both",
+                )
+                .into()),
+                (Some(high), Some(low)) if high >= low => Err(config::ConfigError::for_parameter(
+                    "highpass_freq",
+                    "Highpass frequency must be lower than lowpass frequency",
+                )
+                .into()),
+                _ => Ok(()),
+            }
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::ConvParameters;
-    use crate::fftconv::FftConv;
+    use crate::config::{ConvParameters, ConvParametersLinearPhase};
+    use crate::fftconv::{linear_phase_coefficients, validate_config, FftConv};
     use crate::filters::Filter;
     use crate::PrcFmt;
 
@@ -269,7 +380,7 @@ mod tests {
     fn check_result() {
         let coeffs = vec![0.5, 0.5];
         let conf = ConvParameters::Values { values: coeffs };
-        let mut filter = FftConv::from_config("test", 8, conf);
+        let mut filter = FftConv::from_config("test", 8, 44100, conf);
         let mut wave1 = vec![1.0, 1.0, 1.0, 0.0, 0.0, -1.0, 0.0, 0.0];
         let expected = vec![0.5, 1.0, 1.0, 0.5, 0.0, -0.5, -0.5, 0.0];
         filter.process_waveform(&mut wave1).unwrap();
@@ -308,4 +419,76 @@ mod tests {
         assert!(compare_waveforms(wave4, exp4, 1e-5));
         assert!(compare_waveforms(wave5, exp5, 1e-5));
     }
+
+    fn linear_phase(
+        highpass_freq: Option<PrcFmt>,
+        lowpass_freq: Option<PrcFmt>,
+    ) -> ConvParametersLinearPhase {
+        ConvParametersLinearPhase {
+            highpass_freq,
+            lowpass_freq,
+            slope: 48.0,
+            length: 1023,
+        }
+    }
+
+    // Gain of a linear phase filter at a frequency, with the latency removed.
+    fn amplitude(coeffs: &[PrcFmt], freq: PrcFmt, fs: usize) -> PrcFmt {
+        let center = (coeffs.len() - 1) / 2;
+        let w = 2.0 * std::f64::consts::PI as PrcFmt * freq / fs as PrcFmt;
+        coeffs
+            .iter()
+            .enumerate()
+            .map(|(n, c)| c * (w * (n as PrcFmt - center as PrcFmt)).cos())
+            .sum()
+    }
+
+    #[test]
+    fn linear_phase_response() {
+        let fs = 48000;
+        let lowpass = linear_phase_coefficients(fs, &linear_phase(None, Some(2000.0)));
+        let highpass = linear_phase_coefficients(fs, &linear_phase(Some(2000.0), None));
+        assert!(is_close(amplitude(&lowpass, 100.0, fs), 1.0, 1e-3));
+        assert!(is_close(amplitude(&lowpass, 2000.0, fs), 0.5, 1e-3));
+        assert!(is_close(amplitude(&lowpass, 8000.0, fs), 0.0, 1e-3));
+        assert!(is_close(amplitude(&highpass, 100.0, fs), 0.0, 1e-3));
+        assert!(is_close(amplitude(&highpass, 2000.0, fs), 0.5, 1e-3));
+        assert!(is_close(amplitude(&highpass, 8000.0, fs), 1.0, 1e-3));
+        // Symmetric coefficients give linear phase.
+        for n in 0..lowpass.len() {
+            assert!(is_close(lowpass[n], lowpass[lowpass.len() - 1 - n], 1e-9));
+        }
+    }
+
+    #[test]
+    fn linear_phase_sums_to_delay() {
+        let fs = 44100;
+        let low = linear_phase_coefficients(fs, &linear_phase(None, Some(300.0)));
+        let mid = linear_phase_coefficients(fs, &linear_phase(Some(300.0), Some(3000.0)));
+        let high = linear_phase_coefficients(fs, &linear_phase(Some(3000.0), None));
+        let mut expected = vec![0.0; 1023];
+        expected[511] = 1.0;
+        let sum: Vec<PrcFmt> = low
+            .iter()
+            .zip(mid.iter())
+            .zip(high.iter())
+            .map(|((l, m), h)| l + m + h)
+            .collect();
+        assert!(compare_waveforms(sum, expected, 1e-6));
+    }
+
+    #[test]
+    fn check_linear_phase() {
+        let conf = ConvParameters::LinearPhase(linear_phase(Some(300.0), Some(3000.0)));
+        assert!(validate_config(44100, &conf).is_ok());
+        let conf = ConvParameters::LinearPhase(linear_phase(Some(3000.0), Some(300.0)));
+        assert!(validate_config(44100, &conf).is_err());
+        let conf = ConvParameters::LinearPhase(linear_phase(None, None));
+        assert!(validate_config(44100, &conf).is_err());
+        let conf = ConvParameters::LinearPhase(linear_phase(None, Some(30000.0)));
+        assert!(validate_config(44100, &conf).is_err());
+        let mut even = linear_phase(None, Some(300.0));
+        even.length = 1024;
+        assert!(validate_config(44100, &ConvParameters::LinearPhase(even)).is_err());
+    }
 }
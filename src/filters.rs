@@ -1,25 +1,36 @@
 use crate::audiodevice::AudioChunk;
 use crate::basicfilters;
+use crate::bassmanagement;
 use crate::biquad;
 use crate::biquadcombo;
 use crate::compressor;
 use crate::config;
 use crate::conversions;
+use crate::crossfeed;
 use crate::diffeq;
 use crate::dither;
+use crate::ducker;
+use crate::dynamiceq;
 use crate::fftconv;
 use crate::limiter;
 use crate::loudness;
+use crate::midside;
 use crate::mixer;
 use crate::noisegate;
+use crate::processing;
+use crate::protection;
+use crate::tonecontrols;
+use crate::upmix;
 use rawsample::SampleReader;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fs::File;
 use std::io::BufReader;
 use std::io::{BufRead, Seek, SeekFrom};
-use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Weak};
 use std::time::Instant;
 
+use parking_lot::Mutex;
 use rayon::prelude::*;
 
 use crate::PrcFmt;
@@ -43,6 +54,19 @@ pub trait Processor {
 
     fn update_parameters(&mut self, config: config::Processor);
 
+    // Number of channels after processing, for processors that change the number of channels.
+    fn channels_out(&self) -> Option<usize> {
+        None
+    }
+
+    // Update the parameters of filters used internally by the processor.
+    fn update_filter_parameters(
+        &mut self,
+        _filterconfigs: HashMap<String, config::Filter>,
+        _changed: &[String],
+    ) {
+    }
+
     fn name(&self) -> &str;
 }
 
@@ -170,9 +194,109 @@ pub fn read_wav(filename: &str, channel: usize) -> Res<Vec<PrcFmt>> {
     Ok(data)
 }
 
+// Placeholder for a filter that was moved to a new pipeline.
+// The filter gets the same input in both pipelines,
+// so the placeholder can give the output of the moved filter.
+struct ReusedFilter {
+    name: String,
+    output: Arc<Mutex<Vec<PrcFmt>>>,
+}
+
+impl Filter for ReusedFilter {
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        let output = self.output.lock();
+        if output.len() == waveform.len() {
+            waveform.copy_from_slice(&output);
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, _config: config::Filter) {}
+
+    fn name(&self) -> &str {
+        &self.name
+    }
+}
+
+/// Runtime bypass of a pipeline step.
+/// The step keeps processing while bypassed, so that its state stays up to date,
+/// and the output is crossfaded between the processed and unprocessed signal
+/// when the bypass is switched.
+pub struct StepBypass {
+    switch: Arc<AtomicBool>,
+    bypassed: bool,
+    fade_position: usize,
+    fade_length: usize,
+    dry: Vec<Vec<PrcFmt>>,
+}
+
+impl StepBypass {
+    pub fn new(switch: Arc<AtomicBool>, fade_length: usize) -> Self {
+        let bypassed = switch.load(Ordering::Relaxed);
+        StepBypass {
+            switch,
+            bypassed,
+            fade_position: fade_length,
+            fade_length,
+            dry: Vec::new(),
+        }
+    }
+
+    fn is_fading(&self) -> bool {
+        self.fade_position < self.fade_length
+    }
+
+    /// Check the bypass switch, and keep a copy of the input if it is needed for the output.
+    /// Returns true if the processed output must then be updated with `apply`.
+    fn save_dry(&mut self, waveforms: &[Vec<PrcFmt>]) -> bool {
+        let bypassed = self.switch.load(Ordering::Relaxed);
+        if bypassed != self.bypassed {
+            self.bypassed = bypassed;
+            // Start a new fade, or reverse a running one.
+            self.fade_position = self.fade_length - self.fade_position;
+        }
+        if !self.bypassed && !self.is_fading() {
+            return false;
+        }
+        self.dry.resize_with(waveforms.len(), Vec::new);
+        for (dry, waveform) in self.dry.iter_mut().zip(waveforms.iter()) {
+            dry.clone_from(waveform);
+        }
+        true
+    }
+
+    /// Replace the processed output with the stored input, or crossfade between them.
+    fn apply(&mut self, waveforms: &mut [Vec<PrcFmt>]) {
+        if !self.is_fading() {
+            for (wet, dry) in waveforms.iter_mut().zip(self.dry.iter()) {
+                wet.clone_from(dry);
+            }
+            return;
+        }
+        let pi = std::f64::consts::PI as PrcFmt;
+        let mut frames = 0;
+        for (wet, dry) in waveforms.iter_mut().zip(self.dry.iter()) {
+            if wet.len() != dry.len() {
+                wet.resize(dry.len(), 0.0);
+            }
+            for (n, (wet_value, dry_value)) in wet.iter_mut().zip(dry.iter()).enumerate() {
+                let pos = (self.fade_position + n).min(self.fade_length);
+                let fade = 0.5 - 0.5 * (pi * pos as PrcFmt / self.fade_length as PrcFmt).cos();
+                let wet_gain = if self.bypassed { 1.0 - fade } else { fade };
+                *wet_value = wet_gain * *wet_value + (1.0 - wet_gain) * dry_value;
+            }
+            frames = frames.max(dry.len());
+        }
+        self.fade_position = (self.fade_position + frames).min(self.fade_length);
+    }
+}
+
 pub struct FilterGroup {
     channel: usize,
     filters: Vec<Box<dyn Filter + Send>>,
+    bypass: Option<StepBypass>,
+    // Filters whose output is shared with a previous pipeline, given by their index.
+    shared_outputs: Vec<(usize, Weak<Mutex<Vec<PrcFmt>>>)>,
 }
 
 impl FilterGroup {
@@ -190,55 +314,73 @@ impl FilterGroup {
         for name in names {
             let filter_cfg = filter_configs[name].clone();
             trace!("Create filter {} with config {:?}", name, filter_cfg);
-            let filter: Box<dyn Filter + Send> =
-                match filter_cfg {
-                    config::Filter::Conv { parameters, .. } => Box::new(
-                        fftconv::FftConv::from_config(name, waveform_length, parameters),
-                    ),
-                    config::Filter::Biquad { parameters, .. } => Box::new(biquad::Biquad::new(
+            let filter: Box<dyn Filter + Send> = match filter_cfg {
+                config::Filter::Conv { parameters, .. } => Box::new(fftconv::FftConv::from_config(
+                    name,
+                    waveform_length,
+                    sample_freq,
+                    parameters,
+                )),
+                config::Filter::Biquad { parameters, .. } => Box::new(biquad::Biquad::new(
+                    name,
+                    sample_freq,
+                    biquad::BiquadCoefficients::from_config(sample_freq, parameters),
+                )),
+                config::Filter::BiquadCombo { parameters, .. } => Box::new(
+                    biquadcombo::BiquadCombo::from_config(name, sample_freq, parameters),
+                ),
+                config::Filter::Delay { parameters, .. } => Box::new(
+                    basicfilters::Delay::from_config(name, sample_freq, parameters),
+                ),
+                config::Filter::Gain { parameters, .. } => {
+                    Box::new(basicfilters::Gain::from_config(name, parameters))
+                }
+                config::Filter::Volume { parameters, .. } => {
+                    Box::new(basicfilters::Volume::from_config(
                         name,
+                        parameters,
+                        waveform_length,
                         sample_freq,
-                        biquad::BiquadCoefficients::from_config(sample_freq, parameters),
-                    )),
-                    config::Filter::BiquadCombo { parameters, .. } => Box::new(
-                        biquadcombo::BiquadCombo::from_config(name, sample_freq, parameters),
-                    ),
-                    config::Filter::Delay { parameters, .. } => Box::new(
-                        basicfilters::Delay::from_config(name, sample_freq, parameters),
-                    ),
-                    config::Filter::Gain { parameters, .. } => {
-                        Box::new(basicfilters::Gain::from_config(name, parameters))
-                    }
-                    config::Filter::Volume { parameters, .. } => {
-                        Box::new(basicfilters::Volume::from_config(
-                            name,
-                            parameters,
-                            waveform_length,
-                            sample_freq,
-                            processing_params.clone(),
-                        ))
-                    }
-                    config::Filter::Loudness { parameters, .. } => {
-                        Box::new(loudness::Loudness::from_config(
-                            name,
-                            parameters,
-                            sample_freq,
-                            processing_params.clone(),
-                        ))
-                    }
-                    config::Filter::Dither { parameters, .. } => {
-                        Box::new(dither::Dither::from_config(name, parameters))
-                    }
-                    config::Filter::DiffEq { parameters, .. } => {
-                        Box::new(diffeq::DiffEq::from_config(name, parameters))
-                    }
-                    config::Filter::Limiter { parameters, .. } => {
-                        Box::new(limiter::Limiter::from_config(name, parameters))
-                    }
-                };
+                        processing_params.clone(),
+                    ))
+                }
+                config::Filter::Loudness { parameters, .. } => {
+                    Box::new(loudness::Loudness::from_config(
+                        name,
+                        parameters,
+                        sample_freq,
+                        processing_params.clone(),
+                    ))
+                }
+                config::Filter::Dither { parameters, .. } => {
+                    Box::new(dither::Dither::from_config(name, parameters))
+                }
+                config::Filter::DiffEq { parameters, .. } => {
+                    Box::new(diffeq::DiffEq::from_config(name, parameters))
+                }
+                config::Filter::Limiter { parameters, .. } => {
+                    Box::new(limiter::Limiter::from_config(name, parameters))
+                }
+                config::Filter::DynamicEq { parameters, .. } => Box::new(
+                    dynamiceq::DynamicEq::from_config(name, sample_freq, parameters),
+                ),
+                config::Filter::ToneControls { parameters, .. } => {
+                    Box::new(tonecontrols::ToneControls::from_config(
+                        name,
+                        sample_freq,
+                        parameters,
+                        processing_params.clone(),
+                    ))
+                }
+            };
             filters.push(filter);
         }
-        FilterGroup { channel, filters }
+        FilterGroup {
+            channel,
+            filters,
+            bypass: None,
+            shared_outputs: Vec::new(),
+        }
     }
 
     pub fn update_parameters(
@@ -254,7 +396,7 @@ impl FilterGroup {
     }
 
     /// Apply all the filters to an AudioChunk.
-    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+    pub fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
         if !input.waveforms[self.channel].is_empty() {
             // Zeroes all sse registers on x86_64 architecturesto work around
             // rustc bug https://github.com/rust-lang/rust/issues/116359
@@ -280,16 +422,40 @@ impl FilterGroup {
                     "xorpd xmm15, xmm15"
                 )
             }
-            for filter in &mut self.filters {
-                filter.process_waveform(&mut input.waveforms[self.channel])?;
+            self.process_waveform(&mut input.waveforms[self.channel])?;
+        }
+        Ok(())
+    }
+
+    // Apply all the filters to a waveform, taking the runtime bypass into account.
+    fn process_waveform(&mut self, waveform: &mut Vec<PrcFmt>) -> Res<()> {
+        let waveforms = std::slice::from_mut(waveform);
+        let bypassing = match &mut self.bypass {
+            Some(bypass) => bypass.save_dry(waveforms),
+            None => false,
+        };
+        for (idx, filter) in self.filters.iter_mut().enumerate() {
+            filter.process_waveform(&mut waveforms[0])?;
+            for (_, shared) in self.shared_outputs.iter().filter(|(i, _)| *i == idx) {
+                if let Some(shared) = shared.upgrade() {
+                    let mut output = shared.lock();
+                    output.clear();
+                    output.extend_from_slice(&waveforms[0]);
+                }
             }
         }
+        // Stop sharing when the previous pipeline is gone.
+        self.shared_outputs
+            .retain(|(_, shared)| shared.strong_count() > 0);
+        if let (true, Some(bypass)) = (bypassing, &mut self.bypass) {
+            bypass.apply(waveforms);
+        }
         Ok(())
     }
 }
 
 pub struct ParallelFilters {
-    filters: Vec<Vec<Box<dyn Filter + Send>>>,
+    groups: Vec<Vec<FilterGroup>>,
 }
 
 impl ParallelFilters {
@@ -298,43 +464,199 @@ impl ParallelFilters {
         filterconfigs: HashMap<String, config::Filter>,
         changed: &[String],
     ) {
-        for channel_filters in &mut self.filters {
-            for filter in channel_filters {
-                if changed.iter().any(|n| n == filter.name()) {
-                    filter.update_parameters(filterconfigs[filter.name()].clone());
-                }
-            }
+        for group in self.groups.iter_mut().flatten() {
+            group.update_parameters(filterconfigs.clone(), changed);
         }
     }
 
     /// Apply all the filters to an AudioChunk.
     fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
-        self.filters
+        self.groups
             .par_iter_mut()
             .zip(input.waveforms.par_iter_mut())
-            .filter(|(f, w)| !f.is_empty() && !w.is_empty())
-            .for_each(|(f, w)| {
-                for filt in f {
-                    let _ = filt.process_waveform(w);
+            .filter(|(g, w)| !g.is_empty() && !w.is_empty())
+            .for_each(|(g, w)| {
+                for group in g {
+                    let _ = group.process_waveform(w);
                 }
             });
         Ok(())
     }
 }
 
+/// A Branch runs copies of a set of channels through parallel sub-pipelines,
+/// and sums the outputs back into the same channels using the gain of each branch.
+/// The steps of a branch number the channels relative to the branch.
+pub struct Branch {
+    channels: Vec<usize>,
+    branches: Vec<BranchPath>,
+    sums: Vec<Vec<PrcFmt>>,
+}
+
+struct BranchPath {
+    gain: PrcFmt,
+    steps: Vec<PipelineStep>,
+    waveforms: Vec<Vec<PrcFmt>>,
+}
+
+impl Branch {
+    fn from_config(
+        conf: &config::Configuration,
+        step: config::PipelineStepBranch,
+        channels: Vec<usize>,
+        processing_params: &Arc<ProcessingParameters>,
+    ) -> Self {
+        let nbr_channels = channels.len();
+        let chunksize = conf.devices.chunksize;
+        let branches = step
+            .branches
+            .into_iter()
+            .filter(|branch| !branch.is_mute())
+            .map(|branch| {
+                let linear = branch.scale() == config::GainScale::Linear;
+                let gain = mixer::calculate_gain(branch.gain(), branch.is_inverted(), linear);
+                // Filters in branches are not moved over when rebuilding the pipeline.
+                let steps = steps_from_config(
+                    conf,
+                    branch.pipeline,
+                    nbr_channels,
+                    processing_params,
+                    &|_| None,
+                );
+                BranchPath {
+                    gain,
+                    steps,
+                    waveforms: waveform_buffers(nbr_channels, chunksize),
+                }
+            })
+            .collect();
+        Branch {
+            channels,
+            branches,
+            sums: waveform_buffers(nbr_channels, chunksize),
+        }
+    }
+
+    /// Process the branches and replace the channels of the chunk by the sum of the outputs.
+    /// The inputs of the branches and the sums use buffers that are allocated up front.
+    fn process_chunk(&mut self, chunk: &mut AudioChunk) {
+        for (sum, channel) in self.sums.iter_mut().zip(self.channels.iter()) {
+            sum.clear();
+            sum.resize(chunk.waveforms[*channel].len(), 0.0);
+        }
+        for branch in self.branches.iter_mut() {
+            let mut waveforms = std::mem::take(&mut branch.waveforms);
+            for (waveform, channel) in waveforms.iter_mut().zip(self.channels.iter()) {
+                copy_waveform(&chunk.waveforms[*channel], waveform);
+            }
+            let input = AudioChunk::new(
+                waveforms,
+                chunk.maxval,
+                chunk.minval,
+                chunk.frames,
+                chunk.valid_frames,
+            );
+            let output = process_steps(&mut branch.steps, input);
+            for (sum, waveform) in self.sums.iter_mut().zip(output.waveforms.iter()) {
+                for (acc, value) in sum.iter_mut().zip(waveform.iter()) {
+                    *acc += branch.gain * value;
+                }
+            }
+            branch.waveforms = output.waveforms;
+        }
+        for (channel, sum) in self.channels.iter().zip(self.sums.iter()) {
+            chunk.waveforms[*channel].copy_from_slice(sum);
+        }
+    }
+}
+
+// Allocate buffers for the waveforms of a number of channels.
+fn waveform_buffers(channels: usize, chunksize: usize) -> Vec<Vec<PrcFmt>> {
+    (0..channels)
+        .map(|_| Vec::with_capacity(chunksize))
+        .collect()
+}
+
+// Copy a waveform into a buffer, which only allocates if the buffer is too small.
+fn copy_waveform(source: &[PrcFmt], target: &mut Vec<PrcFmt>) {
+    target.resize(source.len(), 0.0);
+    target.copy_from_slice(source);
+}
+
 /// A Pipeline is made up of a series of PipelineSteps,
-/// each one can be a single Mixer or a group of Filters
+/// each one can be a single Mixer or a group of Filters.
+/// Mixer, processor and branch steps that can be bypassed at runtime have a StepBypass.
 pub enum PipelineStep {
-    MixerStep(mixer::Mixer),
+    MixerStep(mixer::Mixer, Option<StepBypass>),
     FilterStep(FilterGroup),
     ParallelFiltersStep(ParallelFilters),
-    ProcessorStep(Box<dyn Processor>),
+    ProcessorStep(Box<dyn Processor + Send>, Option<StepBypass>),
+    BranchStep(Branch, Option<StepBypass>),
 }
 
-pub struct Pipeline {
+// Time constant in seconds for the averaged output level of pipeline variants.
+const VARIANT_LEVEL_TIME: f32 = 3.0;
+// Largest gain in dB applied when loudness matching pipeline variants.
+const MAX_LOUDNESS_MATCH_GAIN: PrcFmt = 12.0;
+
+/// A named set of steps. The main pipeline is the first variant,
+/// and all variants process every chunk, so that their filters stay up to date.
+struct PipelineVariant {
+    name: String,
     steps: Vec<PipelineStep>,
+    gain: PrcFmt,
+    mean_square: PrcFmt,
+    // Buffers for a copy of the input, when this variant is not the selected one.
+    waveforms: Vec<Vec<PrcFmt>>,
+}
+
+impl PipelineVariant {
+    fn new(name: &str, steps: Vec<PipelineStep>, channels: usize, chunksize: usize) -> Self {
+        PipelineVariant {
+            name: name.to_string(),
+            steps,
+            gain: 1.0,
+            mean_square: 0.0,
+            waveforms: waveform_buffers(channels, chunksize),
+        }
+    }
+
+    // Update the averaged mean square of the output.
+    fn update_level(&mut self, chunk: &AudioChunk, decay: PrcFmt) {
+        let (sum, count) = chunk
+            .waveforms
+            .iter()
+            .flat_map(|waveform| waveform.iter().take(chunk.valid_frames))
+            .fold((0.0, 0), |(sum, count), value| {
+                (sum + value * value, count + 1)
+            });
+        if count > 0 {
+            self.mean_square = decay * self.mean_square + (1.0 - decay) * sum / count as PrcFmt;
+        }
+    }
+
+    // The gain that makes this variant as loud as the current one.
+    fn matching_gain(&self, current: &PipelineVariant) -> PrcFmt {
+        if self.mean_square <= 0.0 || current.mean_square <= 0.0 {
+            return 1.0;
+        }
+        let max_gain = (10.0 as PrcFmt).powf(MAX_LOUDNESS_MATCH_GAIN / 20.0);
+        let gain = current.gain * (current.mean_square / self.mean_square).sqrt();
+        gain.clamp(1.0 / max_gain, max_gain)
+    }
+}
+
+pub struct Pipeline {
+    variants: Vec<PipelineVariant>,
+    selected: usize,
+    previous: Option<usize>,
+    variant_generation: usize,
+    fade_position: usize,
+    fade_length: usize,
+    level_decay: PrcFmt,
     volume: basicfilters::Volume,
     secs_per_chunk: f32,
+    chunksize: usize,
     processing_params: Arc<ProcessingParameters>,
 }
 
@@ -346,81 +668,31 @@ impl Pipeline {
     ) -> Self {
         debug!("Build new pipeline");
         trace!("Pipeline config {:?}", conf.pipeline);
-        let mut steps = Vec::<PipelineStep>::new();
-        let mut num_channels = conf.devices.capture.channels();
-        for step in conf.pipeline.unwrap_or_default() {
-            match step {
-                config::PipelineStep::Mixer(step) => {
-                    if !step.is_bypassed() {
-                        let mixconf = conf.mixers.as_ref().unwrap()[&step.name].clone();
-                        num_channels = mixconf.channels.out;
-                        debug!(
-                            "Add Mixer step with mixer {}, pipeline becomes {} channels wide",
-                            step.name, mixconf.channels.out
-                        );
-                        let mixer = mixer::Mixer::from_config(step.name, mixconf);
-                        steps.push(PipelineStep::MixerStep(mixer));
-                    }
-                }
-                config::PipelineStep::Filter(step) => {
-                    if !step.is_bypassed() {
-                        let channels_iter: Box<dyn Iterator<Item = usize>> = if let Some(channels) =
-                            &step.channels
-                        {
-                            debug!(
-                                "Add Filter step with filters {:?} to channels {:?}",
-                                step.names, channels
-                            );
-                            Box::new(channels.iter().copied()) as Box<dyn Iterator<Item = usize>>
-                        } else {
-                            debug!(
-                                "Add Filter step with filters {:?} to all {} channels",
-                                step.names, num_channels
-                            );
-                            Box::new(0..num_channels) as Box<dyn Iterator<Item = usize>>
-                        };
-                        for channel in channels_iter {
-                            let fltgrp = FilterGroup::from_config(
-                                channel,
-                                &step.names,
-                                conf.filters.as_ref().unwrap().clone(),
-                                conf.devices.chunksize,
-                                conf.devices.samplerate,
-                                processing_params.clone(),
-                            );
-                            steps.push(PipelineStep::FilterStep(fltgrp));
-                        }
-                    }
-                }
-                config::PipelineStep::Processor(step) => {
-                    if !step.is_bypassed() {
-                        debug!("Add Processor step with processor {}", step.name);
-                        let procconf = conf.processors.as_ref().unwrap()[&step.name].clone();
-                        let proc = match procconf {
-                            config::Processor::Compressor { parameters, .. } => {
-                                let comp = compressor::Compressor::from_config(
-                                    &step.name,
-                                    parameters,
-                                    conf.devices.samplerate,
-                                    conf.devices.chunksize,
-                                );
-                                Box::new(comp) as Box<dyn Processor>
-                            }
-                            config::Processor::NoiseGate { parameters, .. } => {
-                                let gate = noisegate::NoiseGate::from_config(
-                                    &step.name,
-                                    parameters,
-                                    conf.devices.samplerate,
-                                    conf.devices.chunksize,
-                                );
-                                Box::new(gate) as Box<dyn Processor>
-                            }
-                        };
-                        steps.push(PipelineStep::ProcessorStep(proc));
-                    }
-                }
-            }
-        }
+        processing_params.clear_protection_status();
+        let pipeline_steps = conf.pipeline.clone().unwrap_or_default();
+        let switchable: Vec<bool> = pipeline_steps
+            .iter()
+            .map(|step| is_bypass_switchable(&conf, step))
+            .collect();
+        let switches = processing_params.register_step_bypass(
+            config::DEFAULT_VARIANT,
+            &pipeline_steps,
+            &switchable,
+        );
+        let fade_length = (conf.devices.crossfade_time() / 1000.0 * conf.devices.samplerate as f32)
+            .round() as usize;
+        let step_bypass = |index: usize| {
+            switches[index]
+                .clone()
+                .map(|s| StepBypass::new(s, fade_length))
+        };
+        let mut steps = steps_from_config(
+            &conf,
+            pipeline_steps,
+            conf.devices.capture.channels(),
+            &processing_params,
+            &step_bypass,
+        );
         let current_volume = processing_params.current_volume(0);
         let mute = processing_params.is_mute(0);
         let volume = basicfilters::Volume::new(
@@ -438,10 +710,68 @@ impl Pipeline {
         if conf.devices.multithreaded() {
             steps = parallelize_filters(&mut steps, conf.devices.capture.channels());
         }
-        Pipeline {
+        let capture_channels = conf.devices.capture.channels();
+        let chunksize = conf.devices.chunksize;
+        let mut variants = vec![PipelineVariant::new(
+            config::DEFAULT_VARIANT,
             steps,
+            capture_channels,
+            chunksize,
+        )];
+        let mut variant_configs: Vec<_> = conf
+            .variants
+            .clone()
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+        variant_configs.sort_by(|a, b| a.0.cmp(&b.0));
+        for (name, variant) in variant_configs {
+            debug!("Build pipeline variant {}", name);
+            let switchable: Vec<bool> = variant
+                .pipeline
+                .iter()
+                .map(|step| is_bypass_switchable(&conf, step))
+                .collect();
+            let switches =
+                processing_params.register_step_bypass(&name, &variant.pipeline, &switchable);
+            let step_bypass = |index: usize| {
+                switches[index]
+                    .clone()
+                    .map(|s| StepBypass::new(s, fade_length))
+            };
+            let mut steps = steps_from_config(
+                &conf,
+                variant.pipeline,
+                conf.devices.capture.channels(),
+                &processing_params,
+                &step_bypass,
+            );
+            if conf.devices.multithreaded() {
+                steps = parallelize_filters(&mut steps, conf.devices.capture.channels());
+            }
+            variants.push(PipelineVariant::new(
+                &name,
+                steps,
+                capture_channels,
+                chunksize,
+            ));
+        }
+        let variant_generation = processing_params.variant_generation();
+        let names: Vec<String> = variants.iter().map(|v| v.name.clone()).collect();
+        let selected_name = processing_params.register_variants(&names);
+        let selected = names.iter().position(|n| *n == selected_name).unwrap_or(0);
+        let level_decay = (-secs_per_chunk / VARIANT_LEVEL_TIME).exp() as PrcFmt;
+        Pipeline {
+            variants,
+            selected,
+            previous: None,
+            variant_generation,
+            fade_position: 0,
+            fade_length,
+            level_decay,
             volume,
             secs_per_chunk,
+            chunksize: conf.devices.chunksize,
             processing_params,
         }
     }
@@ -454,49 +784,93 @@ impl Pipeline {
         processors: &[String],
     ) {
         debug!("Updating parameters");
-        for mut step in &mut self.steps {
-            match &mut step {
-                PipelineStep::MixerStep(mix) => {
-                    if mixers.iter().any(|n| n == &mix.name) {
-                        mix.update_parameters(conf.mixers.as_ref().unwrap()[&mix.name].clone());
-                    }
+        for variant in self.variants.iter_mut() {
+            update_steps_parameters(&mut variant.steps, &conf, filters, mixers, processors);
+        }
+    }
+
+    // Get all filter groups of the main pipeline, in processing order for each channel.
+    fn filter_groups_mut(&mut self) -> Vec<&mut FilterGroup> {
+        let mut groups = Vec::new();
+        for step in self.variants[0].steps.iter_mut() {
+            match step {
+                PipelineStep::FilterStep(flt) => groups.push(flt),
+                PipelineStep::ParallelFiltersStep(flt) => {
+                    groups.extend(flt.groups.iter_mut().flatten());
                 }
-                PipelineStep::FilterStep(flt) => {
-                    flt.update_parameters(conf.filters.as_ref().unwrap().clone(), filters);
+                PipelineStep::MixerStep(..)
+                | PipelineStep::ProcessorStep(..)
+                | PipelineStep::BranchStep(..) => {}
+            }
+        }
+        groups
+    }
+
+    /// Move unchanged filters from the previous pipeline into this one,
+    /// replacing the newly built filters. The moved filters keep their state.
+    /// The previous pipeline gets placeholders that give the output of the moved filters,
+    /// so that it can keep running while the two pipelines are crossfaded.
+    /// Filters that are not found in the previous pipeline are left as newly built.
+    pub fn reuse_filters(
+        &mut self,
+        previous: &mut Pipeline,
+        reused: &HashSet<config::FilterInstance>,
+    ) {
+        let mut counter = config::FilterInstanceCounter::default();
+        let mut wanted = HashSet::new();
+        for group in self.filter_groups_mut() {
+            for filter in group.filters.iter() {
+                let instance = counter.next(group.channel, filter.name());
+                if reused.contains(&instance) {
+                    wanted.insert(instance);
                 }
-                PipelineStep::ParallelFiltersStep(flt) => {
-                    flt.update_parameters(conf.filters.as_ref().unwrap().clone(), filters);
+            }
+        }
+        let mut previous_filters = HashMap::new();
+        let mut counter = config::FilterInstanceCounter::default();
+        for group in previous.filter_groups_mut() {
+            for filter in group.filters.iter_mut() {
+                let name = filter.name().to_string();
+                let instance = counter.next(group.channel, &name);
+                if wanted.contains(&instance) {
+                    let output = Arc::new(Mutex::new(Vec::with_capacity(self.chunksize)));
+                    let placeholder = Box::new(ReusedFilter {
+                        name,
+                        output: output.clone(),
+                    });
+                    let filter = std::mem::replace(filter, placeholder);
+                    previous_filters.insert(instance, (filter, output));
                 }
-                PipelineStep::ProcessorStep(proc) => {
-                    if processors.iter().any(|n| n == proc.name()) {
-                        proc.update_parameters(
-                            conf.processors.as_ref().unwrap()[proc.name()].clone(),
-                        );
-                    }
+            }
+        }
+        let nbr_missing = wanted.len() - previous_filters.len();
+        let mut counter = config::FilterInstanceCounter::default();
+        for group in self.filter_groups_mut() {
+            for (idx, filter) in group.filters.iter_mut().enumerate() {
+                let instance = counter.next(group.channel, filter.name());
+                if let Some((previous_filter, output)) = previous_filters.remove(&instance) {
+                    *filter = previous_filter;
+                    group.shared_outputs.push((idx, Arc::downgrade(&output)));
                 }
             }
         }
+        if nbr_missing > 0 {
+            warn!(
+                "Unable to reuse {} filters from the previous pipeline, using new filters",
+                nbr_missing
+            );
+        }
     }
 
     /// Process an AudioChunk by calling either a MixerStep or a FilterStep
     pub fn process_chunk(&mut self, mut chunk: AudioChunk) -> AudioChunk {
         let start = Instant::now();
         self.volume.process_chunk(&mut chunk);
-        for mut step in &mut self.steps {
-            match &mut step {
-                PipelineStep::MixerStep(mix) => {
-                    chunk = mix.process_chunk(&chunk);
-                }
-                PipelineStep::FilterStep(flt) => {
-                    flt.process_chunk(&mut chunk).unwrap();
-                }
-                PipelineStep::ParallelFiltersStep(flt) => {
-                    flt.process_chunk(&mut chunk).unwrap();
-                }
-                PipelineStep::ProcessorStep(comp) => {
-                    comp.process_chunk(&mut chunk).unwrap();
-                }
-            }
+        if self.variants.len() == 1 {
+            chunk = process_steps(&mut self.variants[0].steps, chunk);
+        } else {
+            self.update_variant_selection();
+            chunk = self.process_variants(chunk);
         }
         let secs_elapsed = start.elapsed().as_secs_f32();
         let load = 100.0 * secs_elapsed / self.secs_per_chunk;
@@ -504,6 +878,385 @@ impl Pipeline {
         trace!("Processing load: {load}%");
         chunk
     }
+
+    // Switch to a newly selected variant, and start a crossfade from the current one.
+    fn update_variant_selection(&mut self) {
+        let generation = self.processing_params.variant_generation();
+        if generation == self.variant_generation {
+            return;
+        }
+        let (name, loudness_match) = self.processing_params.selected_variant();
+        let selected = self.variants.iter().position(|v| v.name == name);
+        if self.previous.is_some() && selected.is_some() && selected != self.previous {
+            // Finish the running crossfade before switching to a third variant.
+            return;
+        }
+        self.variant_generation = generation;
+        let selected = match selected {
+            Some(selected) if selected != self.selected => selected,
+            _ => return,
+        };
+        let gain = if loudness_match {
+            self.variants[selected].matching_gain(&self.variants[self.selected])
+        } else {
+            1.0
+        };
+        debug!(
+            "Switch to pipeline variant {} with gain {:.2} dB",
+            name,
+            20.0 * gain.log10()
+        );
+        self.variants[selected].gain = gain;
+        if self.previous == Some(selected) {
+            // Reverse the running crossfade.
+            self.fade_position = self.fade_length.saturating_sub(self.fade_position);
+        } else {
+            self.fade_position = 0;
+        }
+        self.previous = Some(self.selected);
+        self.selected = selected;
+    }
+
+    // Process the chunk with all variants, and output the selected one.
+    // The selected variant processes the chunk itself,
+    // while the others process copies in their own buffers.
+    fn process_variants(&mut self, chunk: AudioChunk) -> AudioChunk {
+        let mut previous_output = None;
+        for (idx, variant) in self.variants.iter_mut().enumerate() {
+            if idx == self.selected {
+                continue;
+            }
+            let mut waveforms = std::mem::take(&mut variant.waveforms);
+            waveforms.resize_with(chunk.waveforms.len(), Vec::new);
+            for (waveform, source) in waveforms.iter_mut().zip(chunk.waveforms.iter()) {
+                copy_waveform(source, waveform);
+            }
+            let input = AudioChunk::new(
+                waveforms,
+                chunk.maxval,
+                chunk.minval,
+                chunk.frames,
+                chunk.valid_frames,
+            );
+            let output = process_steps(&mut variant.steps, input);
+            variant.update_level(&output, self.level_decay);
+            if Some(idx) == self.previous {
+                previous_output = Some(output);
+            } else {
+                variant.waveforms = output.waveforms;
+            }
+        }
+        let frames = chunk.frames;
+        let selected = &mut self.variants[self.selected];
+        let mut output = process_steps(&mut selected.steps, chunk);
+        selected.update_level(&output, self.level_decay);
+        apply_gain(&mut output, selected.gain);
+        if let (Some(previous), Some(mut previous_output)) = (self.previous, previous_output) {
+            apply_gain(&mut previous_output, self.variants[previous].gain);
+            processing::crossfade_chunks(
+                &previous_output,
+                &mut output,
+                self.fade_position,
+                self.fade_length,
+            );
+            self.variants[previous].waveforms = previous_output.waveforms;
+            self.fade_position += frames;
+            if self.fade_position >= self.fade_length {
+                self.previous = None;
+            }
+        }
+        output
+    }
+}
+
+fn apply_gain(chunk: &mut AudioChunk, gain: PrcFmt) {
+    if gain != 1.0 {
+        for value in chunk.waveforms.iter_mut().flatten() {
+            *value *= gain;
+        }
+    }
+}
+
+// Update the parameters of the filters, mixers and processors in a list of steps.
+fn update_steps_parameters(
+    steps: &mut [PipelineStep],
+    conf: &config::Configuration,
+    filters: &[String],
+    mixers: &[String],
+    processors: &[String],
+) {
+    for mut step in steps.iter_mut() {
+        match &mut step {
+            PipelineStep::MixerStep(mix, _) => {
+                if mixers.iter().any(|n| n == &mix.name) {
+                    mix.update_parameters(conf.mixers.as_ref().unwrap()[&mix.name].clone());
+                }
+            }
+            PipelineStep::FilterStep(flt) => {
+                flt.update_parameters(conf.filters.as_ref().unwrap().clone(), filters);
+            }
+            PipelineStep::ParallelFiltersStep(flt) => {
+                flt.update_parameters(conf.filters.as_ref().unwrap().clone(), filters);
+            }
+            PipelineStep::ProcessorStep(proc, _) => {
+                if processors.iter().any(|n| n == proc.name()) {
+                    proc.update_parameters(conf.processors.as_ref().unwrap()[proc.name()].clone());
+                }
+                if !filters.is_empty() {
+                    proc.update_filter_parameters(conf.filters.as_ref().unwrap().clone(), filters);
+                }
+            }
+            PipelineStep::BranchStep(branch, _) => {
+                for path in branch.branches.iter_mut() {
+                    update_steps_parameters(&mut path.steps, conf, filters, mixers, processors);
+                }
+            }
+        }
+    }
+}
+
+// Process an AudioChunk through a list of steps.
+fn process_steps(steps: &mut [PipelineStep], mut chunk: AudioChunk) -> AudioChunk {
+    for mut step in steps.iter_mut() {
+        match &mut step {
+            PipelineStep::MixerStep(mix, Some(bypass)) => {
+                let bypassing = bypass.save_dry(&chunk.waveforms);
+                chunk = mix.process_chunk(&chunk);
+                if bypassing {
+                    bypass.apply(&mut chunk.waveforms);
+                }
+            }
+            PipelineStep::MixerStep(mix, None) => {
+                chunk = mix.process_chunk(&chunk);
+            }
+            PipelineStep::FilterStep(flt) => {
+                flt.process_chunk(&mut chunk).unwrap();
+            }
+            PipelineStep::ParallelFiltersStep(flt) => {
+                flt.process_chunk(&mut chunk).unwrap();
+            }
+            PipelineStep::ProcessorStep(comp, Some(bypass)) => {
+                let bypassing = bypass.save_dry(&chunk.waveforms);
+                comp.process_chunk(&mut chunk).unwrap();
+                if bypassing {
+                    bypass.apply(&mut chunk.waveforms);
+                }
+            }
+            PipelineStep::ProcessorStep(comp, None) => {
+                comp.process_chunk(&mut chunk).unwrap();
+            }
+            PipelineStep::BranchStep(branch, Some(bypass)) => {
+                let bypassing = bypass.save_dry(&chunk.waveforms);
+                branch.process_chunk(&mut chunk);
+                if bypassing {
+                    bypass.apply(&mut chunk.waveforms);
+                }
+            }
+            PipelineStep::BranchStep(branch, None) => {
+                branch.process_chunk(&mut chunk);
+            }
+        }
+    }
+    chunk
+}
+
+// Build the steps for a list of pipeline steps, starting with the given number of channels.
+fn steps_from_config(
+    conf: &config::Configuration,
+    pipeline_steps: Vec<config::PipelineStep>,
+    mut num_channels: usize,
+    processing_params: &Arc<ProcessingParameters>,
+    step_bypass: &dyn Fn(usize) -> Option<StepBypass>,
+) -> Vec<PipelineStep> {
+    let mut steps = Vec::<PipelineStep>::new();
+    for (index, step) in pipeline_steps.into_iter().enumerate() {
+        match step {
+            config::PipelineStep::Mixer(step) => {
+                if !step.is_bypassed() {
+                    let mixconf = conf.mixers.as_ref().unwrap()[&step.name].clone();
+                    num_channels = mixconf.channels.out;
+                    debug!(
+                        "Add Mixer step with mixer {}, pipeline becomes {} channels wide",
+                        step.name, mixconf.channels.out
+                    );
+                    let mixer = mixer::Mixer::from_config(step.name, mixconf);
+                    steps.push(PipelineStep::MixerStep(mixer, step_bypass(index)));
+                }
+            }
+            config::PipelineStep::Filter(step) => {
+                if !step.is_bypassed() {
+                    let channels_iter: Box<dyn Iterator<Item = usize>> =
+                        if let Some(channels) = &step.channels {
+                            debug!(
+                                "Add Filter step with filters {:?} to channels {:?}",
+                                step.names, channels
+                            );
+                            Box::new(channels.iter().copied()) as Box<dyn Iterator<Item = usize>>
+                        } else {
+                            debug!(
+                                "Add Filter step with filters {:?} to all {} channels",
+                                step.names, num_channels
+                            );
+                            Box::new(0..num_channels) as Box<dyn Iterator<Item = usize>>
+                        };
+                    for channel in channels_iter {
+                        let mut fltgrp = FilterGroup::from_config(
+                            channel,
+                            &step.names,
+                            conf.filters.as_ref().unwrap().clone(),
+                            conf.devices.chunksize,
+                            conf.devices.samplerate,
+                            processing_params.clone(),
+                        );
+                        fltgrp.bypass = step_bypass(index);
+                        steps.push(PipelineStep::FilterStep(fltgrp));
+                    }
+                }
+            }
+            config::PipelineStep::Processor(step) => {
+                if !step.is_bypassed() {
+                    debug!("Add Processor step with processor {}", step.name);
+                    let procconf = conf.processors.as_ref().unwrap()[&step.name].clone();
+                    let proc = match procconf {
+                        config::Processor::Compressor { parameters, .. } => {
+                            let comp = compressor::Compressor::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                            );
+                            Box::new(comp) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::NoiseGate { parameters, .. } => {
+                            let gate = noisegate::NoiseGate::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                            );
+                            Box::new(gate) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::Ducker { parameters, .. } => {
+                            let ducker = ducker::Ducker::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                            );
+                            Box::new(ducker) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::SpeakerProtection { parameters, .. } => {
+                            let protection = protection::SpeakerProtection::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                                processing_params.clone(),
+                            );
+                            Box::new(protection) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::Crossfeed { parameters, .. } => {
+                            let crossfeed = crossfeed::Crossfeed::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                            );
+                            Box::new(crossfeed) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::MidSide { parameters, .. } => {
+                            let midside = midside::MidSide::from_config(
+                                &step.name,
+                                parameters,
+                                conf.filters.clone().unwrap_or_default(),
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                                processing_params.clone(),
+                            );
+                            Box::new(midside) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::Upmix { parameters, .. } => {
+                            let upmix = upmix::Upmix::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                            );
+                            Box::new(upmix) as Box<dyn Processor + Send>
+                        }
+                        config::Processor::BassManagement { parameters, .. } => {
+                            let bm = bassmanagement::BassManagement::from_config(
+                                &step.name,
+                                parameters,
+                                conf.devices.samplerate,
+                                conf.devices.chunksize,
+                            );
+                            Box::new(bm) as Box<dyn Processor + Send>
+                        }
+                    };
+                    if let Some(channels) = proc.channels_out() {
+                        num_channels = channels;
+                        debug!(
+                            "Processor {} makes the pipeline {} channels wide",
+                            step.name, channels
+                        );
+                    }
+                    steps.push(PipelineStep::ProcessorStep(proc, step_bypass(index)));
+                }
+            }
+            config::PipelineStep::Crossover(step) => {
+                // Crossovers are expanded to mixers and filters when the config is validated.
+                warn!("Ignoring unexpanded crossover step {}", step.name);
+            }
+            config::PipelineStep::Fragment(step) => {
+                // Fragments are expanded when the config is validated.
+                warn!("Ignoring unexpanded pipeline fragment step {}", step.name);
+            }
+            config::PipelineStep::Branch(step) => {
+                if !step.is_bypassed() {
+                    let channels = step
+                        .channels
+                        .clone()
+                        .unwrap_or_else(|| (0..num_channels).collect());
+                    debug!(
+                        "Add Branch step with {} branches to channels {:?}",
+                        step.branches.len(),
+                        channels
+                    );
+                    let branch = Branch::from_config(conf, step, channels, processing_params);
+                    steps.push(PipelineStep::BranchStep(branch, step_bypass(index)));
+                }
+            }
+        }
+    }
+    steps
+}
+
+// Check if a pipeline step can be bypassed at runtime.
+// Steps that are bypassed in the config are not built,
+// and steps that change the number of channels can't be bypassed.
+fn is_bypass_switchable(conf: &config::Configuration, step: &config::PipelineStep) -> bool {
+    match step {
+        config::PipelineStep::Mixer(step) => {
+            !step.is_bypassed()
+                && conf
+                    .mixers
+                    .as_ref()
+                    .and_then(|mixers| mixers.get(&step.name))
+                    .is_some_and(|mixer| mixer.channels.r#in == mixer.channels.out)
+        }
+        config::PipelineStep::Filter(step) => !step.is_bypassed(),
+        config::PipelineStep::Processor(step) => {
+            !step.is_bypassed()
+                && conf
+                    .processors
+                    .as_ref()
+                    .and_then(|processors| processors.get(&step.name))
+                    .is_some_and(|processor| !matches!(processor, config::Processor::Upmix { .. }))
+        }
+        config::PipelineStep::Branch(step) => !step.is_bypassed(),
+        config::PipelineStep::Crossover(_) | config::PipelineStep::Fragment(_) => false,
+    }
 }
 
 // Loop trough the pipeline to merge individual filter steps,
@@ -515,7 +1268,7 @@ fn parallelize_filters(steps: &mut Vec<PipelineStep>, nbr_channels: usize) -> Ve
     let mut active_channels = nbr_channels;
     for step in steps.drain(..) {
         match step {
-            PipelineStep::MixerStep(ref mix) => {
+            PipelineStep::MixerStep(ref mix, _) => {
                 if parfilt.is_some() {
                     debug!("Append parallel filter step to pipeline");
                     new_steps.push(PipelineStep::ParallelFiltersStep(parfilt.take().unwrap()));
@@ -524,11 +1277,14 @@ fn parallelize_filters(steps: &mut Vec<PipelineStep>, nbr_channels: usize) -> Ve
                 debug!("Append mixer step to pipeline");
                 new_steps.push(step);
             }
-            PipelineStep::ProcessorStep(_) => {
+            PipelineStep::ProcessorStep(ref proc, _) => {
                 if parfilt.is_some() {
                     debug!("Append parallel filter step to pipeline");
                     new_steps.push(PipelineStep::ParallelFiltersStep(parfilt.take().unwrap()));
                 }
+                if let Some(channels) = proc.channels_out() {
+                    active_channels = channels;
+                }
                 debug!("Append processor step to pipeline");
                 new_steps.push(step);
             }
@@ -540,14 +1296,22 @@ fn parallelize_filters(steps: &mut Vec<PipelineStep>, nbr_channels: usize) -> Ve
                 debug!("Append existing parallel filter step to pipeline");
                 new_steps.push(step);
             }
-            PipelineStep::FilterStep(mut flt) => {
+            PipelineStep::BranchStep(..) => {
+                if parfilt.is_some() {
+                    debug!("Append parallel filter step to pipeline");
+                    new_steps.push(PipelineStep::ParallelFiltersStep(parfilt.take().unwrap()));
+                }
+                debug!("Append branch step to pipeline");
+                new_steps.push(step);
+            }
+            PipelineStep::FilterStep(flt) => {
                 if parfilt.is_none() {
                     debug!("Start new parallel filter step");
-                    let mut filters = Vec::with_capacity(active_channels);
+                    let mut groups = Vec::with_capacity(active_channels);
                     for _ in 0..active_channels {
-                        filters.push(Vec::new());
+                        groups.push(Vec::new());
                     }
-                    parfilt = Some(ParallelFilters { filters });
+                    parfilt = Some(ParallelFilters { groups });
                 }
                 if let Some(ref mut f) = parfilt {
                     debug!(
@@ -555,7 +1319,8 @@ fn parallelize_filters(steps: &mut Vec<PipelineStep>, nbr_channels: usize) -> Ve
                         flt.filters.len(),
                         flt.channel
                     );
-                    f.filters[flt.channel].append(&mut flt.filters);
+                    let channel = flt.channel;
+                    f.groups[channel].push(flt);
                 }
             }
         }
@@ -570,7 +1335,7 @@ fn parallelize_filters(steps: &mut Vec<PipelineStep>, nbr_channels: usize) -> Ve
 /// Validate the filter config, to give a helpful message intead of a panic.
 pub fn validate_filter(fs: usize, filter_config: &config::Filter) -> Res<()> {
     match filter_config {
-        config::Filter::Conv { parameters, .. } => fftconv::validate_config(parameters),
+        config::Filter::Conv { parameters, .. } => fftconv::validate_config(fs, parameters),
         config::Filter::Biquad { parameters, .. } => biquad::validate_config(fs, parameters),
         config::Filter::Delay { parameters, .. } => basicfilters::validate_delay_config(parameters),
         config::Filter::Gain { parameters, .. } => basicfilters::validate_gain_config(parameters),
@@ -584,15 +1349,22 @@ pub fn validate_filter(fs: usize, filter_config: &config::Filter) -> Res<()> {
             biquadcombo::validate_config(fs, parameters)
         }
         config::Filter::Limiter { parameters, .. } => limiter::validate_config(parameters),
+        config::Filter::DynamicEq { parameters, .. } => dynamiceq::validate_config(fs, parameters),
+        config::Filter::ToneControls { parameters, .. } => {
+            tonecontrols::validate_config(fs, parameters)
+        }
     }
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::config::FileFormat;
+    use crate::audiodevice::AudioChunk;
+    use crate::config::{self, FileFormat};
     use crate::filters::read_wav;
-    use crate::filters::{pad_vector, read_coeff_file};
+    use crate::filters::{pad_vector, read_coeff_file, Pipeline};
     use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::sync::Arc;
 
     fn is_close(left: PrcFmt, right: PrcFmt, maxdiff: PrcFmt) -> bool {
         println!("{} - {} = {}", left, right, left - right);
@@ -744,4 +1516,293 @@ mod tests {
         let bad = read_wav("testdata/int32.wav", 1);
         assert!(bad.is_err());
     }
+
+    fn make_config(lowpass_freq: f32, pipeline: &str) -> config::Configuration {
+        let mut conf = parse_config(lowpass_freq, pipeline);
+        config::validate_config(&mut conf, None).unwrap();
+        conf
+    }
+
+    fn parse_config(lowpass_freq: f32, pipeline: &str) -> config::Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  delay:
+    type: Delay
+    parameters:
+      delay: 1500
+      unit: samples
+  long_delay:
+    type: Delay
+    parameters:
+      delay: 4000
+      unit: samples
+  lowpass:
+    type: Biquad
+    parameters:
+      type: Lowpass
+      freq: {lowpass_freq}
+      q: 0.7
+  gain:
+    type: Gain
+    parameters:
+      gain: -3.0
+pipeline:
+{pipeline}
+"
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    const PIPELINE: &str = "
+  - type: Filter
+    channels: [0]
+    names: [delay]
+  - type: Filter
+    channels: [1]
+    names: [lowpass]";
+
+    #[test]
+    fn find_unchanged_filters() {
+        let current = make_config(1000.0, PIPELINE);
+        let added = make_config(
+            1000.0,
+            &format!(
+                "{PIPELINE}\n  - type: Filter\n    channels: [0, 1]\n    names: [gain, delay]"
+            ),
+        );
+        let unchanged = config::unchanged_filters(&current, &added);
+        assert_eq!(unchanged.len(), 2);
+        // The second use of the delay on channel 0 is new.
+        assert!(!unchanged.contains(&config::FilterInstance {
+            channel: 0,
+            name: "delay".to_string(),
+            index: 1
+        }));
+        let changed = make_config(2000.0, PIPELINE);
+        let unchanged = config::unchanged_filters(&current, &changed);
+        assert_eq!(unchanged.len(), 1);
+        assert!(unchanged.contains(&config::FilterInstance {
+            channel: 0,
+            name: "delay".to_string(),
+            index: 0
+        }));
+    }
+
+    #[test]
+    fn rebuilt_pipeline_keeps_filter_state() {
+        let params = Arc::new(ProcessingParameters::default());
+        let current = make_config(1000.0, PIPELINE);
+        let mut pipeline = Pipeline::from_config(current.clone(), params.clone());
+        let mut impulse = vec![0.0; 1024];
+        impulse[0] = 1.0;
+        let chunk = AudioChunk::new(vec![impulse.clone(), impulse], 1.0, -1.0, 1024, 1024);
+        pipeline.process_chunk(chunk);
+
+        // Add a filter to channel 1, the delay on channel 0 is unchanged.
+        let new = make_config(
+            1000.0,
+            &format!("{PIPELINE}\n  - type: Filter\n    channels: [1]\n    names: [gain]"),
+        );
+        let reused = config::unchanged_filters(&current, &new);
+        let mut new_pipeline = Pipeline::from_config(new, params);
+        new_pipeline.reuse_filters(&mut pipeline, &reused);
+
+        // The impulse is still in the delay line and comes out in the next chunk.
+        let zeros = || AudioChunk::new(vec![vec![0.0; 1024]; 2], 1.0, -1.0, 1024, 1024);
+        let output = new_pipeline.process_chunk(zeros());
+        assert!(is_close(output.waveforms[0][476], 1.0, 1.0e-6));
+        assert!(output.waveforms[1].iter().any(|v| *v != 0.0));
+        // The previous pipeline can keep running for a crossfade,
+        // and gives the output of the moved filter.
+        let previous_output = pipeline.process_chunk(zeros());
+        assert_eq!(previous_output.waveforms[0], output.waveforms[0]);
+    }
+
+    #[test]
+    fn no_reuse_after_changed_step() {
+        let current = make_config(1000.0, PIPELINE);
+        // Inserting a step before the filters changes their input.
+        let new = make_config(
+            1000.0,
+            &format!("  - type: Filter\n    channels: [0]\n    names: [gain]{PIPELINE}"),
+        );
+        assert!(config::unchanged_filters(&current, &new).is_empty());
+    }
+
+    #[test]
+    fn bypassed_step_keeps_running() {
+        let params = Arc::new(ProcessingParameters::default());
+        let pipeline_conf = "
+  - type: Filter
+    description: room correction
+    channels: [0]
+    names: [long_delay]";
+        let conf = make_config(1000.0, pipeline_conf);
+        let mut pipeline = Pipeline::from_config(conf, params.clone());
+        let mut impulse = vec![0.0; 1024];
+        impulse[0] = 1.0;
+        let zeros = || AudioChunk::new(vec![vec![0.0; 1024]; 2], 1.0, -1.0, 1024, 1024);
+
+        assert_eq!(
+            params.step_bypass(None, Some("room correction")),
+            Some(false)
+        );
+        params
+            .set_step_bypass(None, Some("room correction"), true)
+            .unwrap();
+        assert!(params.set_step_bypass(Some(1), None, true).is_err());
+        // Let the 50 ms crossfade finish.
+        for _ in 0..3 {
+            pipeline.process_chunk(zeros());
+        }
+        let chunk = AudioChunk::new(vec![impulse.clone(), impulse], 1.0, -1.0, 1024, 1024);
+        let output = pipeline.process_chunk(chunk);
+        assert_eq!(output.waveforms[0][0], 1.0);
+
+        // The delay kept running while bypassed, and the impulse comes out after 4000 samples.
+        params.set_step_bypass(Some(0), None, false).unwrap();
+        let mut outputs = Vec::new();
+        for _ in 0..3 {
+            outputs.extend(pipeline.process_chunk(zeros()).waveforms[0].clone());
+        }
+        assert!(is_close(outputs[4000 - 1024], 1.0, 1.0e-6));
+    }
+
+    #[test]
+    fn branches_are_summed() {
+        let params = Arc::new(ProcessingParameters::default());
+        let pipeline_conf = "
+  - type: Branch
+    channels: [1]
+    branches:
+      - gain: 0.5
+        scale: linear
+      - gain: 0.25
+        scale: linear
+        inverted: true
+        pipeline:
+          - type: Filter
+            names: [delay]";
+        let conf = make_config(1000.0, pipeline_conf);
+        let mut pipeline = Pipeline::from_config(conf, params);
+        let mut impulse = vec![0.0; 1024];
+        impulse[0] = 1.0;
+        let chunk = AudioChunk::new(vec![impulse.clone(), impulse], 1.0, -1.0, 1024, 1024);
+        let output = pipeline.process_chunk(chunk);
+        assert_eq!(output.waveforms[0][0], 1.0);
+        assert!(is_close(output.waveforms[1][0], 0.5, 1.0e-6));
+        let chunk = AudioChunk::new(vec![vec![0.0; 1024]; 2], 1.0, -1.0, 1024, 1024);
+        let output = pipeline.process_chunk(chunk);
+        assert!(is_close(output.waveforms[1][1500 - 1024], -0.25, 1.0e-6));
+    }
+
+    #[test]
+    fn branch_must_keep_channels() {
+        let pipeline_conf = "
+  - type: Branch
+    branches:
+      - pipeline:
+          - type: Filter
+            channels: [2]
+            names: [gain]";
+        let mut conf = parse_config(1000.0, pipeline_conf);
+        assert!(config::validate_config(&mut conf, None).is_err());
+        let pipeline_conf = "
+  - type: Branch
+    channels: [1]
+    branches:
+      - pipeline:
+          - type: Filter
+            channels: [0]
+            names: [gain]";
+        let mut conf = parse_config(1000.0, pipeline_conf);
+        assert!(config::validate_config(&mut conf, None).is_ok());
+    }
+
+    #[test]
+    fn switch_variants_with_loudness_match() {
+        let params = Arc::new(ProcessingParameters::default());
+        let pipeline_conf = "  []
+variants:
+  quiet:
+    pipeline:
+      - type: Filter
+        names: [gain]";
+        let conf = make_config(1000.0, pipeline_conf);
+        let mut pipeline = Pipeline::from_config(conf, params.clone());
+        assert_eq!(params.variants(), vec!["default", "quiet"]);
+        assert!(params.select_variant("loud", false).is_err());
+        let mut process = || {
+            let chunk = AudioChunk::new(vec![vec![0.5; 1024]; 2], 1.0, -1.0, 1024, 1024);
+            pipeline.process_chunk(chunk).waveforms[0][1023]
+        };
+        assert_eq!(process(), 0.5);
+
+        // Let the 50 ms crossfade finish after each switch.
+        params.select_variant("quiet", true).unwrap();
+        let values: Vec<PrcFmt> = (0..3).map(|_| process()).collect();
+        assert!(is_close(values[2], 0.5, 1.0e-6));
+        params.select_variant("default", false).unwrap();
+        process();
+        params.select_variant("quiet", false).unwrap();
+        let values: Vec<PrcFmt> = (0..3).map(|_| process()).collect();
+        assert!(is_close(
+            values[2],
+            0.5 * (10.0 as PrcFmt).powf(-3.0 / 20.0),
+            1.0e-6
+        ));
+    }
+
+    #[test]
+    fn variant_steps_bypass_and_chained_switch() {
+        let params = Arc::new(ProcessingParameters::default());
+        let pipeline_conf = "  []
+variants:
+  quiet:
+    pipeline:
+      - type: Filter
+        names: [gain]
+  quieter:
+    pipeline:
+      - type: Filter
+        names: [gain]
+      - type: Filter
+        description: extra
+        names: [gain]";
+        let conf = make_config(1000.0, pipeline_conf);
+        let mut pipeline = Pipeline::from_config(conf, params.clone());
+        let mut process = || {
+            let chunk = AudioChunk::new(vec![vec![0.5; 1024]; 2], 1.0, -1.0, 1024, 1024);
+            pipeline.process_chunk(chunk).waveforms[0][1023]
+        };
+        let gain = (10.0 as PrcFmt).powf(-3.0 / 20.0);
+        assert!(params.step_bypass(None, Some("extra")).is_none());
+
+        // A switch during a crossfade waits for the crossfade to finish.
+        params.select_variant("quiet", false).unwrap();
+        process();
+        params.select_variant("quieter", false).unwrap();
+        let values: Vec<PrcFmt> = (0..6).map(|_| process()).collect();
+        assert!(values[0] > 0.5 * gain);
+        assert!(is_close(values[1], 0.5 * gain, 1.0e-6));
+        assert!(is_close(values[5], 0.5 * gain * gain, 1.0e-6));
+
+        // Steps of the selected variant can be bypassed.
+        assert_eq!(params.step_bypass(None, Some("extra")), Some(false));
+        params.set_step_bypass(None, Some("extra"), true).unwrap();
+        let values: Vec<PrcFmt> = (0..3).map(|_| process()).collect();
+        assert!(is_close(values[2], 0.5 * gain, 1.0e-6));
+    }
 }
@@ -0,0 +1,772 @@
+use crate::audiodevice::AudioChunk;
+use crate::bassmanagement;
+use crate::config::{self, Configuration, Filter, GainScale, PipelineStep, Processor, SpeakerType};
+use crate::crossfeed;
+use crate::filters::FilterGroup;
+use crate::mixer;
+use crate::tonecontrols::MAX_TONE_GAIN;
+use crate::PrcFmt;
+use crate::ProcessingParameters;
+use crate::Res;
+use num_complex::Complex;
+use realfft::RealFftPlanner;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::Arc;
+
+// The frequency responses of filters are measured with an impulse of this length.
+const IMPULSE_LENGTH: usize = 65536;
+const CHUNK_LENGTH: usize = 4096;
+
+// Levels up to this are not reported as clipping, to allow for rounding errors.
+const CLIP_MARGIN: PrcFmt = 0.01;
+
+// Upper bound of the magnitude response of a channel, for each frequency bin.
+type Spectrum = Vec<PrcFmt>;
+
+/// The worst case levels of the channels after a pipeline step.
+pub struct StepLevels {
+    pub step: String,
+    pub levels: Vec<PrcFmt>,
+}
+
+/// The worst case levels in a pipeline, in dB relative to a full scale input.
+/// The level of each channel is the highest level over all frequencies.
+pub struct GainAnalysis {
+    pub pipeline: String,
+    pub steps: Vec<StepLevels>,
+    pub notes: Vec<String>,
+}
+
+impl GainAnalysis {
+    /// Get the playback channels that may clip, with their worst case levels.
+    pub fn clipping_channels(&self) -> Vec<(usize, PrcFmt)> {
+        match self.steps.last() {
+            Some(last) => last
+                .levels
+                .iter()
+                .enumerate()
+                .filter(|(_, level)| **level > CLIP_MARGIN)
+                .map(|(channel, level)| (channel, *level))
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    // The first step where the level of a channel goes above 0 dB, and stays above.
+    fn first_step_above_zero(&self, channel: usize) -> Option<&str> {
+        let mut first = None;
+        for step in self.steps.iter() {
+            if step.levels.get(channel).is_some_and(|l| *l > CLIP_MARGIN) {
+                first = first.or(Some(step.step.as_str()));
+            } else {
+                first = None;
+            }
+        }
+        first
+    }
+}
+
+impl fmt::Display for GainAnalysis {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(
+            f,
+            "Gain staging of pipeline '{}', worst case levels in dB for a full scale input:",
+            self.pipeline
+        )?;
+        let width = self
+            .steps
+            .iter()
+            .map(|s| s.step.chars().count())
+            .max()
+            .unwrap_or_default();
+        for step in self.steps.iter() {
+            write!(f, "  {:width$}", step.step)?;
+            for level in step.levels.iter() {
+                if level.is_finite() {
+                    write!(f, " {:>7.1}", level)?;
+                } else {
+                    write!(f, " {:>7}", "-inf")?;
+                }
+            }
+            writeln!(f)?;
+        }
+        for note in self.notes.iter() {
+            writeln!(f, "  Note: {note}")?;
+        }
+        for (channel, level) in self.clipping_channels() {
+            let step = self.first_step_above_zero(channel).unwrap_or_default();
+            writeln!(
+                f,
+                "  Warning: playback channel {channel} may clip, reaching {level:+.1} dB. \
+                It goes above 0 dB in step {step}. Attenuate by at least {level:.1} dB."
+            )?;
+        }
+        Ok(())
+    }
+}
+
+enum FilterGain {
+    // The magnitude response of a filter.
+    Response(Spectrum),
+    // A limiter, that keeps the level below a linear limit.
+    Limit(PrcFmt),
+}
+
+/// Analyze the gain staging of the main pipeline and the pipeline variants of a validated config.
+/// Volume faders are assumed to be at their limit,
+/// while loudness and tone controls are assumed to give their maximum boost.
+pub fn analyze_config(conf: &Configuration) -> Res<Vec<GainAnalysis>> {
+    let mut analyzer = Analyzer {
+        conf,
+        filters: conf.filters.clone().unwrap_or_default(),
+        responses: HashMap::new(),
+    };
+    let mut pipelines = vec![(
+        config::DEFAULT_VARIANT.to_string(),
+        conf.pipeline.clone().unwrap_or_default(),
+    )];
+    if let Some(variants) = &conf.variants {
+        let mut names: Vec<&String> = variants.keys().collect();
+        names.sort();
+        for name in names {
+            pipelines.push((name.clone(), variants[name].pipeline.clone()));
+        }
+    }
+    let mut analyses = Vec::with_capacity(pipelines.len());
+    for (name, steps) in pipelines {
+        analyses.push(analyzer.analyze_pipeline(&name, &steps)?);
+    }
+    Ok(analyses)
+}
+
+struct Analyzer<'a> {
+    conf: &'a Configuration,
+    filters: HashMap<String, Filter>,
+    responses: HashMap<String, FilterGain>,
+}
+
+impl Analyzer<'_> {
+    fn analyze_pipeline(&mut self, name: &str, steps: &[PipelineStep]) -> Res<GainAnalysis> {
+        let capture_channels = self.conf.devices.capture.channels();
+        let mut channels = vec![flat(1.0); capture_channels];
+        let mut analysis = GainAnalysis {
+            pipeline: name.to_string(),
+            steps: vec![StepLevels {
+                step: "Capture".to_string(),
+                levels: levels(&channels),
+            }],
+            notes: Vec::new(),
+        };
+        // The main volume is applied before the pipeline.
+        let volume_limit = self.conf.devices.volume_limit() as PrcFmt;
+        if volume_limit != 0.0 {
+            apply_gain(&mut channels, db_to_linear(volume_limit));
+            analysis.steps.push(StepLevels {
+                step: "Main volume".to_string(),
+                levels: levels(&channels),
+            });
+            if volume_limit > 0.0 {
+                analysis.notes.push(format!(
+                    "The main volume can boost by up to {volume_limit:.1} dB, \
+                    lower the volume_limit of the devices to avoid this"
+                ));
+            }
+        }
+        self.analyze_steps(steps, channels, &mut Some(&mut analysis))?;
+        Ok(analysis)
+    }
+
+    // Update the channels for each step. The levels after each step are added to the analysis, if given.
+    fn analyze_steps(
+        &mut self,
+        steps: &[PipelineStep],
+        mut channels: Vec<Spectrum>,
+        analysis: &mut Option<&mut GainAnalysis>,
+    ) -> Res<Vec<Spectrum>> {
+        for (idx, step) in steps.iter().enumerate() {
+            if is_bypassed(step) {
+                continue;
+            }
+            let description = match step {
+                PipelineStep::Mixer(step) => {
+                    let mixerconf = &self.conf.mixers.as_ref().unwrap()[&step.name];
+                    channels = mix(mixerconf, &channels);
+                    format!("{idx}: Mixer '{}'", step.name)
+                }
+                PipelineStep::Filter(step) => {
+                    let selected = step
+                        .channels
+                        .clone()
+                        .unwrap_or_else(|| (0..channels.len()).collect());
+                    for name in step.names.iter() {
+                        let gain = self.filter_gain(name)?;
+                        for channel in selected.iter() {
+                            apply_filter_gain(&mut channels[*channel], gain);
+                        }
+                    }
+                    format!("{idx}: Filter '{}'", step.names.join("', '"))
+                }
+                PipelineStep::Processor(step) => {
+                    let conf = self.conf;
+                    let procconf = &conf.processors.as_ref().unwrap()[&step.name];
+                    if let Some(note) = self.process(procconf, &mut channels)? {
+                        if let Some(analysis) = analysis {
+                            analysis
+                                .notes
+                                .push(format!("Processor '{}' {note}", step.name));
+                        }
+                    }
+                    format!("{idx}: Processor '{}'", step.name)
+                }
+                PipelineStep::Branch(step) => {
+                    let selected = step
+                        .channels
+                        .clone()
+                        .unwrap_or_else(|| (0..channels.len()).collect());
+                    let mut sums = vec![flat(0.0); selected.len()];
+                    for branch in step.branches.iter() {
+                        if branch.is_mute() {
+                            continue;
+                        }
+                        let linear = branch.scale() == GainScale::Linear;
+                        let gain = mixer::calculate_gain(branch.gain(), false, linear);
+                        let inputs = selected.iter().map(|c| channels[*c].clone()).collect();
+                        let outputs = self.analyze_steps(&branch.pipeline, inputs, &mut None)?;
+                        for (sum, output) in sums.iter_mut().zip(outputs.iter()) {
+                            add_scaled(sum, output, gain);
+                        }
+                    }
+                    for (channel, sum) in selected.iter().zip(sums) {
+                        channels[*channel] = sum;
+                    }
+                    format!("{idx}: Branch")
+                }
+                PipelineStep::Crossover(_) | PipelineStep::Fragment(_) => continue,
+            };
+            if let Some(analysis) = analysis {
+                analysis.steps.push(StepLevels {
+                    step: description,
+                    levels: levels(&channels),
+                });
+            }
+        }
+        Ok(channels)
+    }
+
+    fn filter_gain(&mut self, name: &str) -> Res<&FilterGain> {
+        if !self.responses.contains_key(name) {
+            let gain = match &self.filters[name] {
+                Filter::Volume { parameters, .. } => {
+                    FilterGain::Response(flat(db_to_linear(parameters.limit() as PrcFmt)))
+                }
+                Filter::Loudness { parameters, .. } => {
+                    let boost = if parameters.attenuate_mid() {
+                        0.0
+                    } else {
+                        parameters.high_boost().max(parameters.low_boost()).max(0.0)
+                    };
+                    FilterGain::Response(flat(db_to_linear(boost as PrcFmt)))
+                }
+                Filter::ToneControls { .. } => {
+                    FilterGain::Response(flat(db_to_linear(MAX_TONE_GAIN as PrcFmt)))
+                }
+                Filter::DynamicEq { parameters, .. } => {
+                    let boost = parameters
+                        .bands
+                        .iter()
+                        .fold(0.0, |acc: PrcFmt, band| acc.max(band.max_gain));
+                    FilterGain::Response(flat(db_to_linear(boost)))
+                }
+                Filter::Limiter { parameters, .. } => {
+                    FilterGain::Limit(db_to_linear(parameters.clip_limit))
+                }
+                Filter::Delay { .. } | Filter::Dither { .. } => FilterGain::Response(flat(1.0)),
+                Filter::Conv { .. }
+                | Filter::Biquad { .. }
+                | Filter::BiquadCombo { .. }
+                | Filter::Gain { .. }
+                | Filter::DiffEq { .. } => FilterGain::Response(self.measure_response(name)?),
+            };
+            self.responses.insert(name.to_string(), gain);
+        }
+        Ok(&self.responses[name])
+    }
+
+    // Measure the magnitude response of a linear filter.
+    fn measure_response(&self, name: &str) -> Res<Spectrum> {
+        let response = self.measure_filters(self.filters.clone(), &[name.to_string()])?;
+        Ok(magnitudes(&response))
+    }
+
+    // Measure the complex response of a filter that is not defined in the config.
+    fn measure_filter(&self, filter: Filter) -> Res<Vec<Complex<PrcFmt>>> {
+        let name = "measured".to_string();
+        let filters = HashMap::from([(name.clone(), filter)]);
+        self.measure_filters(filters, &[name])
+    }
+
+    // Measure the complex response of a chain of linear filters, by filtering an impulse.
+    fn measure_filters(
+        &self,
+        filters: HashMap<String, Filter>,
+        names: &[String],
+    ) -> Res<Vec<Complex<PrcFmt>>> {
+        let mut group = FilterGroup::from_config(
+            0,
+            names,
+            filters,
+            CHUNK_LENGTH,
+            self.conf.devices.samplerate,
+            Arc::new(ProcessingParameters::default()),
+        );
+        let mut impulse = Vec::with_capacity(IMPULSE_LENGTH);
+        for n in 0..IMPULSE_LENGTH / CHUNK_LENGTH {
+            let mut waveform = vec![0.0; CHUNK_LENGTH];
+            if n == 0 {
+                waveform[0] = 1.0;
+            }
+            let mut chunk = AudioChunk::new(vec![waveform], 1.0, -1.0, CHUNK_LENGTH, CHUNK_LENGTH);
+            group.process_chunk(&mut chunk)?;
+            impulse.append(&mut chunk.waveforms[0]);
+        }
+        let fft = RealFftPlanner::<PrcFmt>::new().plan_fft_forward(IMPULSE_LENGTH);
+        let mut spectrum = fft.make_output_vec();
+        fft.process(&mut impulse, &mut spectrum)?;
+        Ok(spectrum)
+    }
+
+    // Get the complex response of a chain of filters, or None if it contains a limiter.
+    // Filters with a level dependent gain are included with their highest gain.
+    fn linear_response(&mut self, names: &[String]) -> Res<Option<Vec<Complex<PrcFmt>>>> {
+        let mut measured = Vec::new();
+        let mut scale = 1.0;
+        for name in names.iter() {
+            match &self.filters[name] {
+                Filter::Conv { .. }
+                | Filter::Biquad { .. }
+                | Filter::BiquadCombo { .. }
+                | Filter::Gain { .. }
+                | Filter::DiffEq { .. }
+                | Filter::Delay { .. } => measured.push(name.clone()),
+                _ => match self.filter_gain(name)? {
+                    FilterGain::Response(response) => scale *= peak(response),
+                    FilterGain::Limit(_) => return Ok(None),
+                },
+            }
+        }
+        let response = if measured.is_empty() {
+            vec![Complex::new(1.0, 0.0); IMPULSE_LENGTH / 2 + 1]
+        } else {
+            self.measure_filters(self.filters.clone(), &measured)?
+        };
+        Ok(Some(response.iter().map(|value| value * scale).collect()))
+    }
+
+    // Update the channels for a processor. The routed channels are summed like in a mixer,
+    // using the magnitude of the response of each route.
+    // Returns a note for processors that are not analyzed, and are assumed to not raise the level.
+    fn process(
+        &mut self,
+        procconf: &Processor,
+        channels: &mut Vec<Spectrum>,
+    ) -> Res<Option<String>> {
+        match procconf {
+            Processor::Compressor { parameters, .. } => {
+                let selected = parameters
+                    .process_channels
+                    .clone()
+                    .unwrap_or_else(|| (0..channels.len()).collect());
+                let makeup_gain = db_to_linear(parameters.makeup_gain.unwrap_or_default().max(0.0));
+                let limit = parameters.clip_limit.map(db_to_linear);
+                for channel in selected {
+                    for value in channels[channel].iter_mut() {
+                        *value *= makeup_gain;
+                        if let Some(limit) = limit {
+                            *value = value.min(limit);
+                        }
+                    }
+                }
+                Ok(None)
+            }
+            Processor::NoiseGate { .. }
+            | Processor::Ducker { .. }
+            | Processor::SpeakerProtection { .. } => Ok(None),
+            Processor::Crossfeed { parameters, .. } => {
+                // Each side gets itself minus the feed, plus the feed from the other side.
+                let lowpass = self.measure_filter(Filter::Biquad {
+                    description: None,
+                    parameters: crossfeed::lowpass_config(parameters),
+                })?;
+                let gain = crossfeed::feed_gain(parameters.feed_level());
+                // Delays shorter than one sample are not applied.
+                let delay = parameters.delay() / 1000.0 * self.conf.devices.samplerate as PrcFmt;
+                let delay = if delay >= 1.0 { delay } else { 0.0 };
+                let pi = std::f64::consts::PI as PrcFmt;
+                let feed: Vec<Complex<PrcFmt>> = lowpass
+                    .iter()
+                    .enumerate()
+                    .map(|(bin, value)| {
+                        let phase = -2.0 * pi * bin as PrcFmt * delay / IMPULSE_LENGTH as PrcFmt;
+                        value * gain * Complex::from_polar(1.0, phase)
+                    })
+                    .collect();
+                let near = feed.iter().map(|value| (1.0 - value).norm()).collect();
+                let far = magnitudes(&feed);
+                let (left, right) = (parameters.left(), parameters.right());
+                let (left_in, right_in) = (channels[left].clone(), channels[right].clone());
+                channels[left] = route(&[(&left_in, &near), (&right_in, &far)]);
+                channels[right] = route(&[(&left_in, &far), (&right_in, &near)]);
+                Ok(None)
+            }
+            Processor::MidSide { parameters, .. } => {
+                let width = parameters.width();
+                let (left, right) = (parameters.left(), parameters.right());
+                let (left_in, right_in) = (channels[left].clone(), channels[right].clone());
+                let mid = self.linear_response(&parameters.mid_filters())?;
+                let side = self.linear_response(&parameters.side_filters())?;
+                if let (Some(mid), Some(side)) = (mid, side) {
+                    // Left gets 0.5 * (mid + width * side) of the left input,
+                    // and 0.5 * (mid - width * side) of the right input.
+                    let direct = mid
+                        .iter()
+                        .zip(side.iter())
+                        .map(|(m, s)| 0.5 * (m + s * width).norm())
+                        .collect();
+                    let cross = mid
+                        .iter()
+                        .zip(side.iter())
+                        .map(|(m, s)| 0.5 * (m - s * width).norm())
+                        .collect();
+                    channels[left] = route(&[(&left_in, &direct), (&right_in, &cross)]);
+                    channels[right] = route(&[(&left_in, &cross), (&right_in, &direct)]);
+                } else {
+                    // With limiters in the mid or side filters, add up the levels of mid and side.
+                    let half = flat(0.5);
+                    let mut mid = route(&[(&left_in, &half), (&right_in, &half)]);
+                    let mut side = mid.clone();
+                    for name in parameters.mid_filters().iter() {
+                        apply_filter_gain(&mut mid, self.filter_gain(name)?);
+                    }
+                    for name in parameters.side_filters().iter() {
+                        apply_filter_gain(&mut side, self.filter_gain(name)?);
+                    }
+                    add_scaled(&mut mid, &side, width);
+                    channels[left] = mid.clone();
+                    channels[right] = mid;
+                }
+                Ok(None)
+            }
+            Processor::BassManagement { parameters, .. } => {
+                // Add the bass of each small speaker as an extra channel, for the summing mixer.
+                let freq = parameters.crossover_freq;
+                let order = parameters.order();
+                let highpass = magnitudes(&self.measure_filter(Filter::BiquadCombo {
+                    description: None,
+                    parameters: config::BiquadComboParameters::LinkwitzRileyHighpass {
+                        freq,
+                        order,
+                    },
+                })?);
+                let lowpass = magnitudes(&self.measure_filter(Filter::BiquadCombo {
+                    description: None,
+                    parameters: config::BiquadComboParameters::LinkwitzRileyLowpass { freq, order },
+                })?);
+                let mut extended = channels.clone();
+                for channel in parameters.channels_of_type(SpeakerType::Small) {
+                    extended.push(route(&[(&channels[channel], &lowpass)]));
+                    extended[channel] = route(&[(&channels[channel], &highpass)]);
+                }
+                *channels = mix(&bassmanagement::summing_mixer(parameters), &extended);
+                Ok(None)
+            }
+            Processor::Upmix { .. } => {
+                // Each output may contain any of the inputs, use the highest level for all outputs.
+                let highest = channels.iter().fold(flat(0.0), |acc, channel| {
+                    acc.iter().zip(channel).map(|(a, b)| a.max(*b)).collect()
+                });
+                *channels = vec![highest; procconf.channels_out()];
+                Ok(Some(
+                    "is not analyzed, and is assumed to not raise the level".to_string(),
+                ))
+            }
+        }
+    }
+}
+
+fn is_bypassed(step: &PipelineStep) -> bool {
+    match step {
+        PipelineStep::Mixer(step) => step.is_bypassed(),
+        PipelineStep::Filter(step) => step.is_bypassed(),
+        PipelineStep::Processor(step) => step.is_bypassed(),
+        PipelineStep::Branch(step) => step.is_bypassed(),
+        // Crossovers and fragments are expanded when the config is validated.
+        PipelineStep::Crossover(_) | PipelineStep::Fragment(_) => true,
+    }
+}
+
+// Sum the sources of each output channel, with the absolute values of the gains.
+fn mix(mixerconf: &config::Mixer, channels: &[Spectrum]) -> Vec<Spectrum> {
+    let mut outputs = vec![flat(0.0); mixerconf.channels.out];
+    for mapping in mixerconf.mapping.iter() {
+        if mapping.is_mute() {
+            continue;
+        }
+        for source in mapping.sources.iter() {
+            if source.is_mute() {
+                continue;
+            }
+            let linear = source.scale() == GainScale::Linear;
+            let gain = mixer::calculate_gain(source.gain(), false, linear);
+            add_scaled(&mut outputs[mapping.dest], &channels[source.channel], gain);
+        }
+    }
+    outputs
+}
+
+fn apply_filter_gain(channel: &mut Spectrum, gain: &FilterGain) {
+    match gain {
+        FilterGain::Response(response) => {
+            for (value, magnitude) in channel.iter_mut().zip(response) {
+                *value *= magnitude;
+            }
+        }
+        FilterGain::Limit(limit) => {
+            for value in channel.iter_mut() {
+                *value = value.min(*limit);
+            }
+        }
+    }
+}
+
+fn apply_gain(channels: &mut [Spectrum], gain: PrcFmt) {
+    for channel in channels.iter_mut() {
+        for value in channel.iter_mut() {
+            *value *= gain;
+        }
+    }
+}
+
+// Sum the channels, each filtered by the magnitude response of its route.
+fn route(sources: &[(&Spectrum, &Spectrum)]) -> Spectrum {
+    let mut sum = flat(0.0);
+    for (channel, response) in sources.iter() {
+        for ((value, other), magnitude) in sum.iter_mut().zip(channel.iter()).zip(response.iter()) {
+            *value += other * magnitude;
+        }
+    }
+    sum
+}
+
+fn add_scaled(sum: &mut Spectrum, channel: &Spectrum, gain: PrcFmt) {
+    for (value, other) in sum.iter_mut().zip(channel) {
+        *value += gain.abs() * other;
+    }
+}
+
+fn flat(value: PrcFmt) -> Spectrum {
+    vec![value; IMPULSE_LENGTH / 2 + 1]
+}
+
+fn db_to_linear(gain: PrcFmt) -> PrcFmt {
+    (10.0 as PrcFmt).powf(gain / 20.0)
+}
+
+fn magnitudes(response: &[Complex<PrcFmt>]) -> Spectrum {
+    response.iter().map(|value| value.norm()).collect()
+}
+
+fn peak(channel: &Spectrum) -> PrcFmt {
+    channel
+        .iter()
+        .fold(0.0, |acc: PrcFmt, value| acc.max(*value))
+}
+
+fn levels(channels: &[Spectrum]) -> Vec<PrcFmt> {
+    channels
+        .iter()
+        .map(|channel| 20.0 * peak(channel).log10())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{self, Configuration};
+    use crate::gainstaging::analyze_config;
+    use crate::PrcFmt;
+
+    fn analyzed_config(filters: &str, pipeline: &str) -> Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  volume_limit: 0
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+mixers:
+  sum:
+    channels:
+      in: 2
+      out: 2
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+            gain: 0
+          - channel: 1
+            gain: 0
+      - dest: 1
+        sources:
+          - channel: 1
+            gain: -6
+processors:
+  crossfeed:
+    type: Crossfeed
+    parameters:
+      channels: 2
+  widen:
+    type: MidSide
+    parameters:
+      channels: 2
+      width: 2.0
+  bass:
+    type: BassManagement
+    parameters:
+      channels: 2
+      speakers: [Small, Subwoofer]
+      lfe_channel: 1
+      crossover_freq: 80.0
+filters:
+{filters}
+pipeline:
+{pipeline}
+"
+        );
+        let mut conf: Configuration = serde_yaml::from_str(&yaml).unwrap();
+        config::validate_config(&mut conf, None).unwrap();
+        conf
+    }
+
+    #[test]
+    fn mixer_sum_clips() {
+        let filters = "
+  attenuate:
+    type: Gain
+    parameters:
+      gain: -6.1";
+        let pipeline = "
+  - type: Mixer
+    name: sum
+  - type: Filter
+    channels: [0]
+    names:
+      - attenuate";
+        let conf = analyzed_config(filters, pipeline);
+        let analyses = analyze_config(&conf).unwrap();
+        let steps = &analyses[0].steps;
+        assert_eq!(steps.len(), 3);
+        assert!((steps[1].levels[0] - 6.02).abs() < 0.01);
+        assert!((steps[1].levels[1] + 6.0).abs() < 0.01);
+        assert!((steps[2].levels[0] + 0.08).abs() < 0.01);
+        assert!(analyses[0].clipping_channels().is_empty());
+
+        let pipeline = "
+  - type: Mixer
+    name: sum";
+        let conf = analyzed_config(filters, pipeline);
+        let analyses = analyze_config(&conf).unwrap();
+        let clipping = analyses[0].clipping_channels();
+        assert_eq!(clipping.len(), 1);
+        assert_eq!(clipping[0].0, 0);
+    }
+
+    #[test]
+    fn filter_responses() {
+        let filters = "
+  peak:
+    type: Biquad
+    parameters:
+      type: Peaking
+      freq: 1000
+      q: 1.0
+      gain: 6.0
+  limit:
+    type: Limiter
+    parameters:
+      clip_limit: -1.0";
+        let pipeline = "
+  - type: Filter
+    names:
+      - peak
+  - type: Filter
+    channels: [1]
+    names:
+      - limit";
+        let conf = analyzed_config(filters, pipeline);
+        let analyses = analyze_config(&conf).unwrap();
+        let steps = &analyses[0].steps;
+        assert!((steps[1].levels[0] - 6.0).abs() < 0.05);
+        assert!((steps[2].levels[0] - 6.0).abs() < 0.05);
+        assert!((steps[2].levels[1] + 1.0).abs() < 0.01);
+        assert_eq!(analyses[0].clipping_channels().len(), 1);
+    }
+
+    #[test]
+    fn volume_boost() {
+        let filters = "
+  volume:
+    type: Volume
+    parameters:
+      fader: Aux1
+      limit: 6.0";
+        let pipeline = "
+  - type: Filter
+    names:
+      - volume";
+        let mut conf = analyzed_config(filters, pipeline);
+        conf.devices.volume_limit = Some(3.0);
+        let analyses = analyze_config(&conf).unwrap();
+        let steps = &analyses[0].steps;
+        assert_eq!(steps[1].step, "Main volume");
+        assert!((steps[1].levels[0] - 3.0).abs() < 0.01);
+        assert!((steps[2].levels[0] - 9.0).abs() < 0.01);
+        assert_eq!(analyses[0].notes.len(), 1);
+        assert_eq!(analyses[0].clipping_channels().len(), 2);
+    }
+
+    #[test]
+    fn processor_routing() {
+        let pipeline = "
+  - type: Processor
+    name: crossfeed
+  - type: Processor
+    name: widen";
+        let conf = analyzed_config("", pipeline);
+        let analyses = analyze_config(&conf).unwrap();
+        assert!(analyses[0].notes.is_empty());
+        let steps = &analyses[0].steps;
+        // The crossfeed keeps the level at low frequencies, with a small rise around the cutoff.
+        assert!(steps[1].levels[0] > -0.01 && steps[1].levels[0] < 1.0);
+        // A side signal is doubled by a width of 2.
+        let widened = steps[2].levels[0] - steps[1].levels[0];
+        assert!((widened - 6.02).abs() < 0.01);
+
+        let pipeline = "
+  - type: Processor
+    name: bass";
+        let conf = analyzed_config("", pipeline);
+        let analyses = analyze_config(&conf).unwrap();
+        let steps = &analyses[0].steps;
+        // The subwoofer gets the bass of the small speaker, and the LFE with 10 dB gain.
+        let sub = 20.0 * (1.0 + (10.0 as PrcFmt).powf(0.5)).log10();
+        assert!(steps[1].levels[0].abs() < 0.01);
+        assert!((steps[1].levels[1] - sub).abs() < 0.01);
+    }
+}
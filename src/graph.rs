@@ -0,0 +1,534 @@
+use crate::bassmanagement;
+use crate::config::{self, Configuration, GainScale, PipelineStep, Processor};
+use crate::PrcFmt;
+use crate::Res;
+use serde::{Deserialize, Serialize};
+use std::fmt::Write as _;
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+#[derive(Clone, Copy, Debug, Deserialize, PartialEq, Eq)]
+pub enum GraphFormat {
+    #[serde(alias = "dot")]
+    Dot,
+    #[serde(alias = "svg")]
+    Svg,
+    #[serde(alias = "json")]
+    Json,
+}
+
+impl GraphFormat {
+    pub fn from_name(name: &str) -> Option<GraphFormat> {
+        match name {
+            "dot" => Some(GraphFormat::Dot),
+            "svg" => Some(GraphFormat::Svg),
+            "json" => Some(GraphFormat::Json),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, Serialize, PartialEq, Eq)]
+pub enum GroupKind {
+    Capture,
+    Mixer,
+    Filter,
+    Processor,
+    Branch,
+    Playback,
+}
+
+/// A group of nodes, such as the output channels of a mixer.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct GraphGroup {
+    pub id: String,
+    pub kind: GroupKind,
+    pub label: String,
+}
+
+/// A channel at some point in the pipeline.
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct GraphNode {
+    pub id: String,
+    pub group: String,
+    pub label: String,
+}
+
+#[derive(Clone, Debug, Serialize, PartialEq)]
+pub struct GraphEdge {
+    pub from: String,
+    pub to: String,
+    pub label: Option<String>,
+}
+
+/// A graph of the flow of the channels through the pipeline,
+/// from the capture device to the playback device.
+#[derive(Clone, Debug, Default, Serialize, PartialEq)]
+pub struct PipelineGraph {
+    pub groups: Vec<GraphGroup>,
+    pub nodes: Vec<GraphNode>,
+    pub edges: Vec<GraphEdge>,
+}
+
+impl PipelineGraph {
+    /// Build the graph of the main pipeline of a validated config.
+    pub fn from_config(conf: &Configuration) -> Self {
+        Self::from_variant(conf, config::DEFAULT_VARIANT)
+    }
+
+    /// Build the graph of a pipeline variant of a validated config.
+    /// The main pipeline is used if there is no variant with this name.
+    pub fn from_variant(conf: &Configuration, variant: &str) -> Self {
+        let mut graph = PipelineGraph::default();
+        graph.add_group("capture", GroupKind::Capture, "Capture");
+        let labels = conf.devices.capture.labels();
+        let mut current = Vec::new();
+        for channel in 0..conf.devices.capture.channels() {
+            let label = match labels.and_then(|l| l.get(channel)).and_then(|l| l.as_ref()) {
+                Some(label) => format!("{channel}: {label}"),
+                None => channel.to_string(),
+            };
+            current.push(graph.add_node(&format!("capture_{channel}"), "capture", &label));
+        }
+        let steps = match conf.variants.as_ref().and_then(|v| v.get(variant)) {
+            Some(variant) => variant.pipeline.clone(),
+            None => conf.pipeline.clone().unwrap_or_default(),
+        };
+        let current = graph.add_steps(conf, &steps, current, "step", "");
+        graph.add_group("playback", GroupKind::Playback, "Playback");
+        for (channel, from) in current.iter().enumerate() {
+            let id = format!("playback_{channel}");
+            graph.add_node(&id, "playback", &channel.to_string());
+            graph.add_edge(from, &id, None);
+        }
+        graph
+    }
+
+    fn add_group(&mut self, id: &str, kind: GroupKind, label: &str) {
+        self.groups.push(GraphGroup {
+            id: id.to_string(),
+            kind,
+            label: label.to_string(),
+        });
+    }
+
+    fn add_node(&mut self, id: &str, group: &str, label: &str) -> String {
+        self.nodes.push(GraphNode {
+            id: id.to_string(),
+            group: group.to_string(),
+            label: label.to_string(),
+        });
+        id.to_string()
+    }
+
+    fn add_edge(&mut self, from: &str, to: &str, label: Option<String>) {
+        self.edges.push(GraphEdge {
+            from: from.to_string(),
+            to: to.to_string(),
+            label,
+        });
+    }
+
+    // Add the steps of a pipeline, starting from the given nodes, one per channel.
+    // Returns the nodes of the channels after the last step.
+    fn add_steps(
+        &mut self,
+        conf: &Configuration,
+        steps: &[PipelineStep],
+        mut current: Vec<String>,
+        prefix: &str,
+        label_prefix: &str,
+    ) -> Vec<String> {
+        for (idx, step) in steps.iter().enumerate() {
+            let group = format!("{prefix}{idx}");
+            match step {
+                PipelineStep::Mixer(step) if !step.is_bypassed() => {
+                    let mixerconf = &conf.mixers.as_ref().unwrap()[&step.name];
+                    let label = format!("{label_prefix}Mixer '{}'", step.name);
+                    self.add_group(&group, GroupKind::Mixer, &label);
+                    let labels = mixerconf.labels.as_ref();
+                    let mut outputs = Vec::with_capacity(mixerconf.channels.out);
+                    for channel in 0..mixerconf.channels.out {
+                        let label =
+                            match labels.and_then(|l| l.get(channel)).and_then(|l| l.as_ref()) {
+                                Some(label) => format!("{channel}: {label}"),
+                                None => channel.to_string(),
+                            };
+                        let id = format!("{group}_{channel}");
+                        outputs.push(self.add_node(&id, &group, &label));
+                    }
+                    for mapping in mixerconf.mapping.iter() {
+                        if mapping.is_mute() {
+                            continue;
+                        }
+                        for source in mapping.sources.iter() {
+                            if source.is_mute() {
+                                continue;
+                            }
+                            let label =
+                                gain_label(source.gain(), source.scale(), source.is_inverted());
+                            self.add_edge(
+                                &current[source.channel],
+                                &outputs[mapping.dest],
+                                Some(label),
+                            );
+                        }
+                    }
+                    current = outputs;
+                }
+                PipelineStep::Filter(step) if !step.is_bypassed() => {
+                    let label = format!("{label_prefix}Filter");
+                    self.add_group(&group, GroupKind::Filter, &label);
+                    let channels = step
+                        .channels
+                        .clone()
+                        .unwrap_or_else(|| (0..current.len()).collect());
+                    for channel in channels {
+                        let id = format!("{group}_{channel}");
+                        self.add_node(&id, &group, &step.names.join("\n"));
+                        self.add_edge(&current[channel], &id, None);
+                        current[channel] = id;
+                    }
+                }
+                PipelineStep::Processor(step) if !step.is_bypassed() => {
+                    let procconf = &conf.processors.as_ref().unwrap()[&step.name];
+                    let label = format!("{label_prefix}Processor '{}'", step.name);
+                    self.add_group(&group, GroupKind::Processor, &label);
+                    let channels_out = procconf.channels_out();
+                    let mut outputs = Vec::with_capacity(channels_out);
+                    for channel in 0..channels_out {
+                        let id = format!("{group}_{channel}");
+                        outputs.push(self.add_node(&id, &group, &channel.to_string()));
+                    }
+                    for (from, to) in processor_routes(procconf, current.len()) {
+                        self.add_edge(&current[from], &outputs[to], None);
+                    }
+                    current = outputs;
+                }
+                PipelineStep::Branch(step) if !step.is_bypassed() => {
+                    let channels = step
+                        .channels
+                        .clone()
+                        .unwrap_or_else(|| (0..current.len()).collect());
+                    let inputs: Vec<String> =
+                        channels.iter().map(|c| current[*c].clone()).collect();
+                    let mut branch_outputs = Vec::with_capacity(step.branches.len());
+                    for (branch_idx, branch) in step.branches.iter().enumerate() {
+                        if branch.is_mute() {
+                            continue;
+                        }
+                        let outputs = self.add_steps(
+                            conf,
+                            &branch.pipeline,
+                            inputs.clone(),
+                            &format!("{group}_branch{branch_idx}_step"),
+                            &format!("{label_prefix}Branch {branch_idx}, "),
+                        );
+                        let label = gain_label(branch.gain(), branch.scale(), branch.is_inverted());
+                        branch_outputs.push((outputs, label));
+                    }
+                    let label = format!("{label_prefix}Branch sum");
+                    self.add_group(&group, GroupKind::Branch, &label);
+                    for (idx, channel) in channels.iter().enumerate() {
+                        let id = format!("{group}_{channel}");
+                        self.add_node(&id, &group, &channel.to_string());
+                        for (outputs, label) in branch_outputs.iter() {
+                            self.add_edge(&outputs[idx], &id, Some(label.clone()));
+                        }
+                        current[*channel] = id;
+                    }
+                }
+                _ => {}
+            }
+        }
+        current
+    }
+
+    /// Write the graph in the dot language of Graphviz.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        dot.push_str("digraph pipeline {\n  rankdir=LR;\n  node [shape=box];\n");
+        for group in self.groups.iter() {
+            let _ = writeln!(dot, "  subgraph \"cluster_{}\" {{", group.id);
+            let _ = writeln!(dot, "    label=\"{}\";", escape(&group.label));
+            for node in self.nodes.iter().filter(|n| n.group == group.id) {
+                let _ = writeln!(
+                    dot,
+                    "    \"{}\" [label=\"{}\"];",
+                    node.id,
+                    escape(&node.label)
+                );
+            }
+            dot.push_str("  }\n");
+        }
+        for edge in self.edges.iter() {
+            match &edge.label {
+                Some(label) => {
+                    let _ = writeln!(
+                        dot,
+                        "  \"{}\" -> \"{}\" [label=\"{}\"];",
+                        edge.from,
+                        edge.to,
+                        escape(label)
+                    );
+                }
+                None => {
+                    let _ = writeln!(dot, "  \"{}\" -> \"{}\";", edge.from, edge.to);
+                }
+            }
+        }
+        dot.push_str("}\n");
+        dot
+    }
+
+    /// Write the graph in the given format.
+    /// Rendering as svg uses the `dot` command from Graphviz, that must be installed.
+    pub fn render(&self, format: GraphFormat) -> Res<String> {
+        match format {
+            GraphFormat::Dot => Ok(self.to_dot()),
+            GraphFormat::Json => Ok(serde_json::to_string_pretty(self)?),
+            GraphFormat::Svg => render_svg(&self.to_dot()),
+        }
+    }
+}
+
+fn render_svg(dot: &str) -> Res<String> {
+    let mut child = match Command::new("dot")
+        .arg("-Tsvg")
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+    {
+        Ok(child) => child,
+        Err(err) => {
+            let msg = format!("Unable to run the Graphviz 'dot' command. Reason: {err}");
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    };
+    if let Some(mut stdin) = child.stdin.take() {
+        stdin.write_all(dot.as_bytes())?;
+    }
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        let msg = format!(
+            "Graphviz failed to render the graph: {}",
+            String::from_utf8_lossy(&output.stderr)
+        );
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+// The routes from input to output channels of a processor.
+fn processor_routes(procconf: &Processor, channels_in: usize) -> Vec<(usize, usize)> {
+    let mut routes: Vec<(usize, usize)> = (0..channels_in).map(|c| (c, c)).collect();
+    match procconf {
+        Processor::Crossfeed { parameters, .. } => {
+            routes.push((parameters.left(), parameters.right()));
+            routes.push((parameters.right(), parameters.left()));
+        }
+        Processor::MidSide { parameters, .. } => {
+            routes.push((parameters.left(), parameters.right()));
+            routes.push((parameters.right(), parameters.left()));
+        }
+        Processor::BassManagement { parameters, .. } => {
+            // The extra inputs of the summing mixer are the bass of the small speakers.
+            let small = parameters.channels_of_type(config::SpeakerType::Small);
+            let mixer = bassmanagement::summing_mixer(parameters);
+            routes = mixer
+                .mapping
+                .iter()
+                .flat_map(|mapping| {
+                    mapping.sources.iter().map(|source| {
+                        let from = match source.channel.checked_sub(parameters.channels) {
+                            Some(idx) => small[idx],
+                            None => source.channel,
+                        };
+                        (from, mapping.dest)
+                    })
+                })
+                .collect();
+        }
+        Processor::Upmix { .. } => {
+            // Each output may depend on any of the inputs.
+            routes = (0..channels_in)
+                .flat_map(|from| (0..procconf.channels_out()).map(move |to| (from, to)))
+                .collect();
+        }
+        Processor::Compressor { .. }
+        | Processor::NoiseGate { .. }
+        | Processor::Ducker { .. }
+        | Processor::SpeakerProtection { .. } => {}
+    }
+    routes
+}
+
+fn gain_label(gain: PrcFmt, scale: GainScale, inverted: bool) -> String {
+    let gain = match scale {
+        GainScale::Decibel => format!("{gain} dB"),
+        GainScale::Linear => format!("x{gain}"),
+    };
+    if inverted {
+        format!("{gain}, inverted")
+    } else {
+        gain
+    }
+}
+
+fn escape(text: &str) -> String {
+    text.replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{self, Configuration};
+    use crate::graph::{GroupKind, PipelineGraph};
+
+    fn make_config() -> Configuration {
+        let yaml = "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+    labels: [L, R]
+  playback:
+    type: Stdout
+    channels: 3
+    format: S16LE
+mixers:
+  to3:
+    channels:
+      in: 2
+      out: 3
+    labels: [L, R, Sub]
+    mapping:
+      - dest: 0
+        sources:
+          - channel: 0
+            gain: -3
+      - dest: 1
+        sources:
+          - channel: 1
+            gain: -3
+            inverted: true
+      - dest: 2
+        sources:
+          - channel: 0
+            gain: 0
+          - channel: 1
+            gain: 0
+filters:
+  lowpass:
+    type: Gain
+    parameters:
+      gain: -1
+pipeline:
+  - type: Mixer
+    name: to3
+  - type: Filter
+    channels: [2]
+    names:
+      - lowpass
+";
+        let mut conf: Configuration = serde_yaml::from_str(yaml).unwrap();
+        config::validate_config(&mut conf, None).unwrap();
+        conf
+    }
+
+    #[test]
+    fn mixer_routing() {
+        let graph = PipelineGraph::from_config(&make_config());
+        let kinds: Vec<GroupKind> = graph.groups.iter().map(|g| g.kind).collect();
+        assert_eq!(
+            kinds,
+            vec![
+                GroupKind::Capture,
+                GroupKind::Mixer,
+                GroupKind::Filter,
+                GroupKind::Playback
+            ]
+        );
+        let capture_labels: Vec<&str> = graph
+            .nodes
+            .iter()
+            .filter(|n| n.group == "capture")
+            .map(|n| n.label.as_str())
+            .collect();
+        assert_eq!(capture_labels, vec!["0: L", "1: R"]);
+        let to_sub: Vec<&str> = graph
+            .edges
+            .iter()
+            .filter(|e| e.to == "step0_2")
+            .map(|e| e.from.as_str())
+            .collect();
+        assert_eq!(to_sub, vec!["capture_0", "capture_1"]);
+        assert!(graph
+            .edges
+            .iter()
+            .any(|e| e.from == "capture_1" && e.label.as_deref() == Some("-3 dB, inverted")));
+        // Only the filtered channel passes the filter step.
+        assert!(graph
+            .edges
+            .iter()
+            .any(|e| e.from == "step1_2" && e.to == "playback_2"));
+        assert!(graph
+            .edges
+            .iter()
+            .any(|e| e.from == "step0_0" && e.to == "playback_0"));
+    }
+
+    #[test]
+    fn selected_variant_and_crossfeed_routing() {
+        let yaml = "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+processors:
+  crossfeed:
+    type: Crossfeed
+    parameters:
+      channels: 2
+pipeline: []
+variants:
+  headphones:
+    pipeline:
+      - type: Processor
+        name: crossfeed
+";
+        let mut conf: Configuration = serde_yaml::from_str(yaml).unwrap();
+        config::validate_config(&mut conf, None).unwrap();
+        let graph = PipelineGraph::from_config(&conf);
+        assert_eq!(graph.groups.len(), 2);
+        let graph = PipelineGraph::from_variant(&conf, "headphones");
+        assert_eq!(graph.groups[1].kind, GroupKind::Processor);
+        // Both inputs reach both outputs of the crossfeed.
+        for from in ["capture_0", "capture_1"] {
+            for to in ["step0_0", "step0_1"] {
+                assert!(graph.edges.iter().any(|e| e.from == from && e.to == to));
+            }
+        }
+    }
+
+    #[test]
+    fn dot_output() {
+        let dot = PipelineGraph::from_config(&make_config()).to_dot();
+        assert!(dot.starts_with("digraph pipeline {"));
+        assert!(dot.contains("subgraph \"cluster_step0\" {"));
+        assert!(dot.contains("label=\"Mixer 'to3'\";"));
+        assert!(dot.contains("\"capture_0\" -> \"step0_0\" [label=\"-3 dB\"];"));
+    }
+}
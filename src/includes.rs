@@ -0,0 +1,395 @@
+use crate::config::{
+    self, ConfigError, ConfigProblem, Configuration, Include, IncludeConflict, IncludedConfig,
+    PipelineStep,
+};
+use crate::vars;
+use crate::Res;
+use serde_yaml::Value;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, Read};
+use std::path::{Path, PathBuf};
+
+/// Merge the mixers, crossovers, filters, processors and pipeline fragments
+/// of the included files into the config.
+/// Included files are found relative to the including file,
+/// or relative to the current directory when the config was not read from a file.
+/// All problems found are added to the list of problems, and the remaining files are still merged.
+pub fn resolve_includes(
+    conf: &mut Configuration,
+    filename: Option<&str>,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    let includes = match conf.include.take() {
+        Some(includes) => includes,
+        None => return,
+    };
+    let mut stack = Vec::new();
+    let base_dir = match filename.and_then(|f| PathBuf::from(f).canonicalize().ok()) {
+        Some(path) => {
+            let dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+            stack.push(path);
+            dir
+        }
+        None => PathBuf::from("."),
+    };
+    let mut merged = IncludedConfig {
+        description: None,
+        include: None,
+        mixers: conf.mixers.take(),
+        crossovers: conf.crossovers.take(),
+        filters: conf.filters.take(),
+        processors: conf.processors.take(),
+        fragments: conf.fragments.take(),
+    };
+    let tokens = (conf.devices.samplerate, conf.devices.capture.channels());
+    let vars = conf.vars.clone().unwrap_or_default();
+    merge_includes(
+        &mut merged,
+        &includes,
+        &base_dir,
+        &mut stack,
+        tokens,
+        &vars,
+        None,
+        problems,
+    );
+    conf.mixers = merged.mixers;
+    conf.crossovers = merged.crossovers;
+    conf.filters = merged.filters;
+    conf.processors = merged.processors;
+    conf.fragments = merged.fragments;
+}
+
+// Merge the included files into the target.
+// The problems found in nested includes are reported at the path of the top level include.
+#[allow(clippy::too_many_arguments)]
+fn merge_includes(
+    target: &mut IncludedConfig,
+    includes: &[Include],
+    dir: &Path,
+    stack: &mut Vec<PathBuf>,
+    tokens: (usize, usize),
+    vars: &HashMap<String, Value>,
+    problem_path: Option<&str>,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    let (samplerate, num_channels) = tokens;
+    for (idx, include) in includes.iter().enumerate() {
+        let problem_path = problem_path
+            .map(|p| p.to_string())
+            .unwrap_or_else(|| format!("include[{idx}]"));
+        let include_path = config::replace_tokens(&include.path, samplerate, num_channels);
+        let path = match dir.join(&include_path).canonicalize() {
+            Ok(path) => path,
+            Err(err) => {
+                let msg = format!("Could not find included file '{include_path}'. Reason: {err}");
+                problems.push(ConfigProblem::new(&problem_path, &msg));
+                continue;
+            }
+        };
+        if stack.contains(&path) {
+            let msg = format!("The file '{include_path}' is included recursively");
+            problems.push(ConfigProblem::new(&problem_path, &msg));
+            continue;
+        }
+        debug!("Including definitions from {:?}", path);
+        let mut included = match load_included_config(&path, vars) {
+            Ok(included) => included,
+            Err(err) => {
+                problems.push(ConfigProblem::new(&problem_path, &err.to_string()));
+                continue;
+            }
+        };
+        let included_dir = path.parent().map(|p| p.to_path_buf()).unwrap_or_default();
+        if let Some(filters) = &mut included.filters {
+            config::replace_tokens_in_filters(filters, samplerate, num_channels);
+            config::replace_relative_paths_in_filters(filters, &included_dir);
+        }
+        if let Some(nested) = included.include.take() {
+            stack.push(path);
+            merge_includes(
+                &mut included,
+                &nested,
+                &included_dir,
+                stack,
+                tokens,
+                vars,
+                Some(&problem_path),
+                problems,
+            );
+            stack.pop();
+        }
+        let conflicts = include.conflicts();
+        let source = include_path.as_str();
+        merge_definitions(
+            &mut target.mixers,
+            included.mixers,
+            "mixer",
+            source,
+            conflicts,
+            &problem_path,
+            problems,
+        );
+        merge_definitions(
+            &mut target.crossovers,
+            included.crossovers,
+            "crossover",
+            source,
+            conflicts,
+            &problem_path,
+            problems,
+        );
+        merge_definitions(
+            &mut target.filters,
+            included.filters,
+            "filter",
+            source,
+            conflicts,
+            &problem_path,
+            problems,
+        );
+        merge_definitions(
+            &mut target.processors,
+            included.processors,
+            "processor",
+            source,
+            conflicts,
+            &problem_path,
+            problems,
+        );
+        merge_definitions(
+            &mut target.fragments,
+            included.fragments,
+            "pipeline fragment",
+            source,
+            conflicts,
+            &problem_path,
+            problems,
+        );
+    }
+}
+
+// Read an included file, and substitute the variables of the including config.
+fn load_included_config(path: &Path, vars: &HashMap<String, Value>) -> Res<IncludedConfig> {
+    let file = match File::open(path) {
+        Ok(f) => f,
+        Err(err) => {
+            let msg = format!("Could not open included file {path:?}. Reason: {err}");
+            return Err(ConfigError::new(&msg).into());
+        }
+    };
+    let mut contents = String::new();
+    if let Err(err) = BufReader::new(file).read_to_string(&mut contents) {
+        let msg = format!("Could not read included file {path:?}. Reason: {err}");
+        return Err(ConfigError::new(&msg).into());
+    }
+    let parsed: Res<IncludedConfig> = if vars::has_references(&contents) {
+        serde_yaml::from_str(&contents)
+            .map_err(|err| err.into())
+            .and_then(|mut value| {
+                vars::substitute_vars(&mut value, vars)?;
+                Ok(serde_yaml::from_value(value)?)
+            })
+    } else {
+        serde_yaml::from_str(&contents).map_err(|err| err.into())
+    };
+    match parsed {
+        Ok(included) => Ok(included),
+        Err(err) => {
+            let msg = format!("Invalid included file {path:?}!\n{err}");
+            Err(ConfigError::new(&msg).into())
+        }
+    }
+}
+
+// Add included definitions. Identical definitions with the same name are not a conflict.
+fn merge_definitions<T: PartialEq>(
+    target: &mut Option<HashMap<String, T>>,
+    included: Option<HashMap<String, T>>,
+    kind: &str,
+    source: &str,
+    conflicts: IncludeConflict,
+    problem_path: &str,
+    problems: &mut Vec<ConfigProblem>,
+) {
+    let included = match included {
+        Some(included) => included,
+        None => return,
+    };
+    let target = target.get_or_insert_with(HashMap::new);
+    for (name, definition) in included {
+        match target.get(&name) {
+            Some(existing) if *existing != definition => match conflicts {
+                IncludeConflict::Error => {
+                    let msg = format!(
+                        "The {kind} '{name}' from '{source}' conflicts with an existing {kind} with the same name"
+                    );
+                    problems.push(ConfigProblem::new(problem_path, &msg));
+                }
+                IncludeConflict::Keep => {
+                    debug!("Keeping existing {kind} '{name}', ignoring the one from '{source}'");
+                }
+                IncludeConflict::Replace => {
+                    debug!("Replacing {kind} '{name}' by the one from '{source}'");
+                    target.insert(name, definition);
+                }
+            },
+            Some(_) => {}
+            None => {
+                target.insert(name, definition);
+            }
+        }
+    }
+}
+
+/// Replace the fragment steps in the pipeline, the variants and the branches
+/// by the steps of the fragments.
+pub fn expand_fragments(conf: &mut Configuration) -> Res<()> {
+    let fragments = conf.fragments.take().unwrap_or_default();
+    if let Some(pipeline) = conf.pipeline.take() {
+        conf.pipeline = Some(expand_steps(pipeline, &fragments, &mut Vec::new())?);
+    }
+    if let Some(variants) = &mut conf.variants {
+        for variant in variants.values_mut() {
+            let pipeline = std::mem::take(&mut variant.pipeline);
+            variant.pipeline = expand_steps(pipeline, &fragments, &mut Vec::new())?;
+        }
+    }
+    Ok(())
+}
+
+fn expand_steps(
+    steps: Vec<PipelineStep>,
+    fragments: &HashMap<String, Vec<PipelineStep>>,
+    stack: &mut Vec<String>,
+) -> Res<Vec<PipelineStep>> {
+    let mut expanded = Vec::with_capacity(steps.len());
+    for step in steps {
+        match step {
+            PipelineStep::Fragment(step) => {
+                let fragment = match fragments.get(&step.name) {
+                    Some(fragment) => fragment.clone(),
+                    None => {
+                        let msg = format!("Use of missing pipeline fragment '{}'", step.name);
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                };
+                if stack.contains(&step.name) {
+                    let msg = format!("Pipeline fragment '{}' is used recursively", step.name);
+                    return Err(ConfigError::new(&msg).into());
+                }
+                stack.push(step.name.clone());
+                let fragment_steps = expand_steps(fragment, fragments, stack)?;
+                stack.pop();
+                debug!(
+                    "Expanding pipeline fragment '{}' to {} steps",
+                    step.name,
+                    fragment_steps.len()
+                );
+                for mut fragment_step in fragment_steps {
+                    if step.is_bypassed() {
+                        set_bypassed(&mut fragment_step);
+                    }
+                    expanded.push(fragment_step);
+                }
+            }
+            PipelineStep::Branch(mut step) => {
+                for branch in step.branches.iter_mut() {
+                    let pipeline = std::mem::take(&mut branch.pipeline);
+                    branch.pipeline = expand_steps(pipeline, fragments, stack)?;
+                }
+                expanded.push(PipelineStep::Branch(step));
+            }
+            step => expanded.push(step),
+        }
+    }
+    Ok(expanded)
+}
+
+fn set_bypassed(step: &mut PipelineStep) {
+    match step {
+        PipelineStep::Mixer(step) => step.bypassed = Some(true),
+        PipelineStep::Filter(step) => step.bypassed = Some(true),
+        PipelineStep::Processor(step) => step.bypassed = Some(true),
+        PipelineStep::Crossover(step) => step.bypassed = Some(true),
+        PipelineStep::Branch(step) => step.bypassed = Some(true),
+        PipelineStep::Fragment(step) => step.bypassed = Some(true),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{self, Configuration, IncludeConflict, PipelineStep};
+
+    fn make_config(conflicts: Option<IncludeConflict>) -> Configuration {
+        let conflicts = match conflicts {
+            Some(conflicts) => format!("\n    conflicts: {conflicts:?}"),
+            None => String::new(),
+        };
+        let yaml = format!(
+            "
+include:
+  - path: testdata/includes/drivers.yml{conflicts}
+devices:
+  samplerate: 44100
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  woofer_eq:
+    type: Gain
+    parameters:
+      gain: -1.0
+pipeline:
+  - type: Fragment
+    name: woofer
+    bypassed: true
+"
+        );
+        serde_yaml::from_str(&yaml).unwrap()
+    }
+
+    #[test]
+    fn include_conflicts() {
+        let mut conf = make_config(None);
+        assert!(config::validate_config(&mut conf, None).is_err());
+
+        let mut conf = make_config(Some(IncludeConflict::Keep));
+        config::validate_config(&mut conf, None).unwrap();
+        let filters = conf.filters.as_ref().unwrap();
+        assert!(matches!(filters["woofer_eq"], config::Filter::Gain { .. }));
+
+        let mut conf = make_config(Some(IncludeConflict::Replace));
+        config::validate_config(&mut conf, None).unwrap();
+        let filters = conf.filters.as_ref().unwrap();
+        assert!(matches!(
+            filters["woofer_eq"],
+            config::Filter::Biquad { .. }
+        ));
+    }
+
+    #[test]
+    fn expand_included_fragment() {
+        let mut conf = make_config(Some(IncludeConflict::Replace));
+        config::validate_config(&mut conf, None).unwrap();
+        assert!(conf.include.is_none());
+        assert!(conf.fragments.is_none());
+        let pipeline = conf.pipeline.unwrap();
+        assert_eq!(pipeline.len(), 1);
+        match &pipeline[0] {
+            PipelineStep::Filter(step) => {
+                // The filter from the nested include, with the samplerate token replaced.
+                assert_eq!(step.names, vec!["woofer_eq", "attenuate_44100"]);
+                assert!(step.is_bypassed());
+            }
+            _ => panic!("Fragment was not expanded"),
+        }
+    }
+}
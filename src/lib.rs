@@ -42,10 +42,11 @@ extern crate log;
 
 use parking_lot::{Mutex, RwLock};
 use serde::Serialize;
+use std::collections::HashMap;
 use std::error;
 use std::fmt;
 use std::sync::{
-    atomic::{AtomicBool, AtomicU32, Ordering},
+    atomic::{AtomicBool, AtomicU32, AtomicUsize, Ordering},
     Arc,
 };
 
@@ -108,6 +109,7 @@ pub mod alsadevice_buffermanager;
 pub mod alsadevice_utils;
 pub mod audiodevice;
 pub mod basicfilters;
+pub mod bassmanagement;
 pub mod biquad;
 pub mod biquadcombo;
 pub mod compressor;
@@ -118,8 +120,14 @@ pub mod coreaudiodevice;
 pub mod countertimer;
 #[cfg(feature = "cpal-backend")]
 pub mod cpaldevice;
+pub mod crossfeed;
+pub mod crossover;
+pub mod diagnostics;
 pub mod diffeq;
 pub mod dither;
+pub mod ducker;
+pub mod dynamiceq;
+pub mod eqfile;
 pub mod fftconv;
 pub mod filedevice;
 #[cfg(all(target_os = "linux", feature = "bluez-backend"))]
@@ -129,18 +137,26 @@ pub mod filereader;
 #[cfg(target_os = "linux")]
 pub mod filereader_nonblock;
 pub mod filters;
+pub mod gainstaging;
 pub mod generatordevice;
+pub mod graph;
 pub mod helpers;
+pub mod includes;
 pub mod limiter;
 pub mod loudness;
+pub mod midside;
 pub mod mixer;
 pub mod noisegate;
 pub mod processing;
+pub mod protection;
 #[cfg(feature = "pulse-backend")]
 pub mod pulsedevice;
 #[cfg(feature = "websocket")]
 pub mod socketserver;
 pub mod statefile;
+pub mod tonecontrols;
+pub mod upmix;
+pub mod vars;
 #[cfg(target_os = "windows")]
 pub mod wasapidevice;
 pub mod wavtools;
@@ -173,6 +189,8 @@ pub enum ExitState {
 pub enum ControllerMessage {
     // Config must be boxed, to prevent "large size difference between variants" warning
     ConfigChanged(Box<config::Configuration>),
+    // Updated config where only the parameters of the named filters have changed.
+    FilterParametersChanged(Box<config::Configuration>, Vec<String>),
     Stop,
     Exit,
 }
@@ -212,6 +230,14 @@ pub struct PlaybackStatus {
     pub signal_peak: countertimer::ValueHistory,
 }
 
+/// The runtime adjustable tone controls.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum ToneControl {
+    Bass = 0,
+    Treble = 1,
+    Balance = 2,
+}
+
 #[derive(Debug)]
 pub struct ProcessingParameters {
     // Optimization: volumes are actually `f32`s, but by representing their
@@ -221,10 +247,64 @@ pub struct ProcessingParameters {
     current_volume: [AtomicU32; Self::NUM_FADERS],
     mute: [AtomicBool; Self::NUM_FADERS],
     processing_load: AtomicU32,
+    pub protection_status: Mutex<HashMap<String, Vec<protection::ProtectionStatus>>>,
+    // Runtime adjustable stereo widths, with the configured width and the current target.
+    stereo_widths: RwLock<HashMap<String, (f32, Arc<AtomicU32>)>>,
+    // Bass, treble and balance, in dB.
+    tone_controls: [AtomicU32; Self::NUM_TONE_CONTROLS],
+    // Graphic equalizer band gains that were changed at runtime, by filter name.
+    eq_gains: Mutex<HashMap<String, config::StoredEqGains>>,
+    // Runtime bypass switches for the steps of the active pipeline, by variant name.
+    step_bypass: RwLock<HashMap<String, Vec<StepBypassSwitch>>>,
+    // Pipeline variants of the active pipeline, and the selected variant.
+    variants: RwLock<VariantSelection>,
+    // Incremented each time a variant is selected.
+    variant_generation: AtomicUsize,
+}
+
+#[derive(Debug)]
+struct VariantSelection {
+    names: Vec<String>,
+    selected: String,
+    loudness_match: bool,
+}
+
+// The runtime bypass switch of a pipeline step.
+// The switch is None for steps that can't be bypassed at runtime.
+#[derive(Debug)]
+struct StepBypassSwitch {
+    step: config::PipelineStep,
+    switch: Option<Arc<AtomicBool>>,
+}
+
+impl StepBypassSwitch {
+    fn matches_name(&self, name: &str) -> bool {
+        let (step_name, description) = match &self.step {
+            config::PipelineStep::Mixer(step) => (Some(&step.name), &step.description),
+            config::PipelineStep::Processor(step) => (Some(&step.name), &step.description),
+            config::PipelineStep::Filter(step) => (None, &step.description),
+            config::PipelineStep::Crossover(step) => (Some(&step.name), &step.description),
+            config::PipelineStep::Branch(step) => (None, &step.description),
+            config::PipelineStep::Fragment(step) => (Some(&step.name), &step.description),
+        };
+        step_name.is_some_and(|n| n == name) || description.as_ref().is_some_and(|d| d == name)
+    }
+
+    fn is_bypassed_in_config(&self) -> bool {
+        match &self.step {
+            config::PipelineStep::Mixer(step) => step.is_bypassed(),
+            config::PipelineStep::Processor(step) => step.is_bypassed(),
+            config::PipelineStep::Filter(step) => step.is_bypassed(),
+            config::PipelineStep::Crossover(step) => step.bypassed.unwrap_or_default(),
+            config::PipelineStep::Branch(step) => step.is_bypassed(),
+            config::PipelineStep::Fragment(step) => step.is_bypassed(),
+        }
+    }
 }
 
 impl ProcessingParameters {
     pub const NUM_FADERS: usize = 5;
+    pub const NUM_TONE_CONTROLS: usize = 3;
 
     pub const DEFAULT_VOLUME: f32 = 0.0;
     pub const DEFAULT_MUTE: bool = false;
@@ -253,6 +333,21 @@ impl ProcessingParameters {
                 AtomicBool::new(initial_mutes[4]),
             ],
             processing_load: AtomicU32::new(0.0f32.to_bits()),
+            protection_status: Mutex::new(HashMap::new()),
+            stereo_widths: RwLock::new(HashMap::new()),
+            tone_controls: [
+                AtomicU32::new(0.0f32.to_bits()),
+                AtomicU32::new(0.0f32.to_bits()),
+                AtomicU32::new(0.0f32.to_bits()),
+            ],
+            eq_gains: Mutex::new(HashMap::new()),
+            step_bypass: RwLock::new(HashMap::new()),
+            variants: RwLock::new(VariantSelection {
+                names: vec![config::DEFAULT_VARIANT.to_string()],
+                selected: config::DEFAULT_VARIANT.to_string(),
+                loudness_match: false,
+            }),
+            variant_generation: AtomicUsize::new(0),
         }
     }
 
@@ -312,6 +407,210 @@ impl ProcessingParameters {
     pub fn processing_load(&self) -> f32 {
         f32::from_bits(self.processing_load.load(Ordering::Relaxed))
     }
+
+    pub fn protection_status(&self) -> HashMap<String, Vec<protection::ProtectionStatus>> {
+        self.protection_status.lock().clone()
+    }
+
+    pub fn clear_protection_status(&self) {
+        self.protection_status.lock().clear()
+    }
+
+    /// Get the shared target width of a mid/side processor.
+    /// A width set at runtime is kept when the pipeline is rebuilt,
+    /// unless the width in the config has changed.
+    pub fn register_stereo_width(&self, name: &str, config_width: f32) -> Arc<AtomicU32> {
+        let mut widths = self.stereo_widths.write();
+        if let Some((width, target)) = widths.get_mut(name) {
+            if *width != config_width {
+                *width = config_width;
+                target.store(config_width.to_bits(), Ordering::Relaxed);
+            }
+            return target.clone();
+        }
+        let target = Arc::new(AtomicU32::new(config_width.to_bits()));
+        widths.insert(name.to_string(), (config_width, target.clone()));
+        target
+    }
+
+    pub fn stereo_width(&self, name: &str) -> Option<f32> {
+        self.stereo_widths
+            .read()
+            .get(name)
+            .map(|(_, target)| f32::from_bits(target.load(Ordering::Relaxed)))
+    }
+
+    pub fn set_stereo_width(&self, name: &str, width: f32) -> bool {
+        if let Some((_, target)) = self.stereo_widths.read().get(name) {
+            target.store(width.to_bits(), Ordering::Relaxed);
+            true
+        } else {
+            false
+        }
+    }
+
+    pub fn tone_control(&self, control: ToneControl) -> f32 {
+        f32::from_bits(self.tone_controls[control as usize].load(Ordering::Relaxed))
+    }
+
+    pub fn set_tone_control(&self, control: ToneControl, value: f32) {
+        self.tone_controls[control as usize].store(value.to_bits(), Ordering::Relaxed)
+    }
+
+    pub fn eq_gains(&self) -> HashMap<String, config::StoredEqGains> {
+        self.eq_gains.lock().clone()
+    }
+
+    /// Store changed band gains. The config gains are only recorded for the first change,
+    /// so that they are the gains from the loaded config.
+    pub fn set_eq_gains(&self, filter: &str, gains: &[f32], config_gains: &[f32]) {
+        self.eq_gains
+            .lock()
+            .entry(filter.to_string())
+            .and_modify(|entry| entry.gains = gains.to_vec())
+            .or_insert_with(|| config::StoredEqGains {
+                gains: gains.to_vec(),
+                config_gains: config_gains.to_vec(),
+            });
+    }
+
+    /// Replace all stored band gains, returns true if they were changed.
+    pub fn set_all_eq_gains(&self, gains: HashMap<String, config::StoredEqGains>) -> bool {
+        let mut stored = self.eq_gains.lock();
+        let changed = *stored != gains;
+        *stored = gains;
+        changed
+    }
+
+    /// Create the runtime bypass switches for the steps of a pipeline variant of a new pipeline.
+    /// A bypass set at runtime is kept when the pipeline is rebuilt,
+    /// for steps that are unchanged and at the same position in the pipeline.
+    pub fn register_step_bypass(
+        &self,
+        variant: &str,
+        steps: &[config::PipelineStep],
+        switchable: &[bool],
+    ) -> Vec<Option<Arc<AtomicBool>>> {
+        let mut all_switches = self.step_bypass.write();
+        let switches = all_switches.entry(variant.to_string()).or_default();
+        let new_switches: Vec<StepBypassSwitch> = steps
+            .iter()
+            .zip(switchable.iter())
+            .enumerate()
+            .map(|(index, (step, switchable))| {
+                let switch = switchable.then(|| {
+                    let bypassed = switches
+                        .get(index)
+                        .filter(|previous| previous.step == *step)
+                        .and_then(|previous| previous.switch.as_ref())
+                        .is_some_and(|previous| previous.load(Ordering::Relaxed));
+                    Arc::new(AtomicBool::new(bypassed))
+                });
+                StepBypassSwitch {
+                    step: step.clone(),
+                    switch,
+                }
+            })
+            .collect();
+        *switches = new_switches;
+        switches.iter().map(|s| s.switch.clone()).collect()
+    }
+
+    // Find a pipeline step by index, or by name or description.
+    fn find_step(
+        switches: &[StepBypassSwitch],
+        index: Option<usize>,
+        name: Option<&str>,
+    ) -> Option<usize> {
+        match (index, name) {
+            (Some(index), _) => (index < switches.len()).then_some(index),
+            (None, Some(name)) => switches.iter().position(|s| s.matches_name(name)),
+            (None, None) => None,
+        }
+    }
+
+    /// Get the bypass state of a pipeline step of the selected pipeline variant.
+    pub fn step_bypass(&self, index: Option<usize>, name: Option<&str>) -> Option<bool> {
+        let selected = self.variants.read().selected.clone();
+        let all_switches = self.step_bypass.read();
+        let switches = all_switches.get(&selected)?;
+        let step = &switches[Self::find_step(switches, index, name)?];
+        Some(match &step.switch {
+            Some(switch) => switch.load(Ordering::Relaxed),
+            None => step.is_bypassed_in_config(),
+        })
+    }
+
+    /// Bypass or enable a pipeline step of the selected pipeline variant at runtime.
+    pub fn set_step_bypass(
+        &self,
+        index: Option<usize>,
+        name: Option<&str>,
+        bypassed: bool,
+    ) -> Result<(), String> {
+        let selected = self.variants.read().selected.clone();
+        let all_switches = self.step_bypass.read();
+        let switches = all_switches
+            .get(&selected)
+            .map(|s| s.as_slice())
+            .unwrap_or_default();
+        let step = Self::find_step(switches, index, name)
+            .map(|idx| &switches[idx])
+            .ok_or("No matching pipeline step")?;
+        match &step.switch {
+            Some(switch) => {
+                switch.store(bypassed, Ordering::Relaxed);
+                Ok(())
+            }
+            None if step.is_bypassed_in_config() => {
+                Err("The step is bypassed in the config".to_string())
+            }
+            None => Err("The step changes the number of channels".to_string()),
+        }
+    }
+
+    /// Set the names of the pipeline variants of a new pipeline.
+    /// The selected variant is kept if it exists in the new pipeline.
+    /// Returns the name of the selected variant.
+    pub fn register_variants(&self, names: &[String]) -> String {
+        self.step_bypass
+            .write()
+            .retain(|name, _| names.contains(name));
+        let mut variants = self.variants.write();
+        variants.names = names.to_vec();
+        if !names.contains(&variants.selected) {
+            variants.selected = config::DEFAULT_VARIANT.to_string();
+        }
+        variants.selected.clone()
+    }
+
+    /// Get the names of the available pipeline variants.
+    pub fn variants(&self) -> Vec<String> {
+        self.variants.read().names.clone()
+    }
+
+    /// Get the name of the selected pipeline variant, and if loudness matching was requested.
+    pub fn selected_variant(&self) -> (String, bool) {
+        let variants = self.variants.read();
+        (variants.selected.clone(), variants.loudness_match)
+    }
+
+    /// Select a pipeline variant.
+    pub fn select_variant(&self, name: &str, loudness_match: bool) -> Result<(), String> {
+        let mut variants = self.variants.write();
+        if !variants.names.iter().any(|n| n == name) {
+            return Err(format!("No pipeline variant named '{name}'"));
+        }
+        variants.selected = name.to_string();
+        variants.loudness_match = loudness_match;
+        self.variant_generation.fetch_add(1, Ordering::Relaxed);
+        Ok(())
+    }
+
+    /// A counter that changes each time a variant is selected.
+    pub fn variant_generation(&self) -> usize {
+        self.variant_generation.load(Ordering::Relaxed)
+    }
 }
 
 impl Default for ProcessingParameters {
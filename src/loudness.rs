@@ -275,9 +275,17 @@ impl Filter for Loudness {
 /// Validate a Loudness config.
 pub fn validate_config(conf: &config::LoudnessParameters) -> Res<()> {
     if conf.reference_level > 0.0 {
-        return Err(config::ConfigError::new("Reference level must be less than 0").into());
+        return Err(config::ConfigError::for_parameter(
+            "reference_level",
+            "Reference level must be less than 0",
+        )
+        .into());
     } else if conf.reference_level < -100.0 {
-        return Err(config::ConfigError::new("Reference level must be higher than -100").into());
+        return Err(config::ConfigError::for_parameter(
+            "reference_level",
+            "Reference level must be higher than -100",
+        )
+        .into());
     // } else if conf.high_boost() < 0.0 {
     //     return Err(config::ConfigError::new("High boost cannot be less than 0").into());
     // } else if conf.low_boost() < 0.0 {
@@ -0,0 +1,304 @@
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Arc;
+
+use crate::audiodevice::AudioChunk;
+use crate::config;
+use crate::filters::{validate_filter, FilterGroup, Processor};
+use crate::PrcFmt;
+use crate::ProcessingParameters;
+use crate::Res;
+
+// Largest allowed stereo width.
+pub const MAX_WIDTH: PrcFmt = 4.0;
+
+/// Convert a channel pair to mid and side, apply separate filter chains to them,
+/// and convert back to left and right.
+/// The side signal is scaled by the stereo width, that can be changed at runtime.
+pub struct MidSide {
+    pub name: String,
+    pub channels: usize,
+    left: usize,
+    right: usize,
+    mid_filters: FilterGroup,
+    side_filters: FilterGroup,
+    width: PrcFmt,
+    target_width: Arc<AtomicU32>,
+    midside: AudioChunk,
+    processing_params: Arc<ProcessingParameters>,
+}
+
+impl MidSide {
+    /// Creates a MidSide from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::MidSideParameters,
+        filter_configs: HashMap<String, config::Filter>,
+        samplerate: usize,
+        chunksize: usize,
+        processing_params: Arc<ProcessingParameters>,
+    ) -> Self {
+        let mid_filters = FilterGroup::from_config(
+            0,
+            &config.mid_filters(),
+            filter_configs.clone(),
+            chunksize,
+            samplerate,
+            processing_params.clone(),
+        );
+        let side_filters = FilterGroup::from_config(
+            1,
+            &config.side_filters(),
+            filter_configs,
+            chunksize,
+            samplerate,
+            processing_params.clone(),
+        );
+        let target_width = processing_params.register_stereo_width(name, config.width() as f32);
+        let width = f32::from_bits(target_width.load(Ordering::Relaxed)) as PrcFmt;
+        let midside = AudioChunk::new(
+            vec![vec![0.0; chunksize], vec![0.0; chunksize]],
+            0.0,
+            0.0,
+            chunksize,
+            chunksize,
+        );
+        debug!(
+            "Creating mid/side processor '{}', channels: {} and {}, mid filters: {:?}, side filters: {:?}, width: {}",
+            name,
+            config.left(),
+            config.right(),
+            config.mid_filters(),
+            config.side_filters(),
+            width
+        );
+        MidSide {
+            name: name.to_string(),
+            channels: config.channels,
+            left: config.left(),
+            right: config.right(),
+            mid_filters,
+            side_filters,
+            width,
+            target_width,
+            midside,
+            processing_params,
+        }
+    }
+}
+
+impl Processor for MidSide {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply a MidSide to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        let frames = input.waveforms[self.left].len();
+        if frames == 0 || input.waveforms[self.right].len() != frames {
+            return Ok(());
+        }
+        for waveform in self.midside.waveforms.iter_mut() {
+            waveform.resize(frames, 0.0);
+        }
+        let (mid, side) = self.midside.waveforms.split_at_mut(1);
+        for (((m, s), l), r) in mid[0]
+            .iter_mut()
+            .zip(side[0].iter_mut())
+            .zip(input.waveforms[self.left].iter())
+            .zip(input.waveforms[self.right].iter())
+        {
+            *m = 0.5 * (l + r);
+            *s = 0.5 * (l - r);
+        }
+        self.mid_filters.process_chunk(&mut self.midside)?;
+        self.side_filters.process_chunk(&mut self.midside)?;
+
+        // Ramp the width over the chunk to avoid clicks.
+        let target = f32::from_bits(self.target_width.load(Ordering::Relaxed)) as PrcFmt;
+        let step = (target - self.width) / frames as PrcFmt;
+        let (mid, side) = (&self.midside.waveforms[0], &self.midside.waveforms[1]);
+        let mut width = self.width;
+        for (n, (m, s)) in mid.iter().zip(side.iter()).enumerate() {
+            width += step;
+            input.waveforms[self.left][n] = m + width * s;
+            input.waveforms[self.right][n] = m - width * s;
+        }
+        self.width = target;
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::MidSide {
+            parameters: config, ..
+        } = config
+        {
+            self.channels = config.channels;
+            self.left = config.left();
+            self.right = config.right();
+            self.target_width = self
+                .processing_params
+                .register_stereo_width(&self.name, config.width() as f32);
+            debug!(
+                "Updated mid/side processor '{}', channels: {} and {}, width: {}",
+                self.name,
+                self.left,
+                self.right,
+                config.width()
+            );
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+
+    fn update_filter_parameters(
+        &mut self,
+        filterconfigs: HashMap<String, config::Filter>,
+        changed: &[String],
+    ) {
+        self.mid_filters
+            .update_parameters(filterconfigs.clone(), changed);
+        self.side_filters.update_parameters(filterconfigs, changed);
+    }
+}
+
+/// Validate the mid/side config, to give a helpful message intead of a panic.
+pub fn validate_midside(
+    samplerate: usize,
+    config: &config::MidSideParameters,
+    filters: Option<&HashMap<String, config::Filter>>,
+) -> Res<()> {
+    let channels = config.channels;
+    for ch in [config.left(), config.right()] {
+        if ch >= channels {
+            let msg = format!("Invalid channel: {}, max is: {}.", ch, channels - 1);
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if config.left() == config.right() {
+        let msg = "The left and right channels must be different.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if !(0.0..=MAX_WIDTH).contains(&config.width()) {
+        let msg = format!("Width must be in the range 0 to {MAX_WIDTH}.");
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    for name in config
+        .mid_filters()
+        .iter()
+        .chain(config.side_filters().iter())
+    {
+        match filters.and_then(|f| f.get(name)) {
+            Some(filter) => {
+                if let Err(err) = validate_filter(samplerate, filter) {
+                    let msg = format!("Invalid filter '{name}'. Reason: {err}");
+                    return Err(config::ConfigError::new(&msg).into());
+                }
+            }
+            None => {
+                let msg = format!("Use of missing filter '{name}'");
+                return Err(config::ConfigError::new(&msg).into());
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::{Filter, GainParameters, MidSideParameters};
+    use crate::filters::Processor;
+    use crate::midside::{validate_midside, MidSide};
+    use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::collections::HashMap;
+    use std::sync::Arc;
+
+    fn make_params(width: PrcFmt) -> MidSideParameters {
+        MidSideParameters {
+            channels: 2,
+            left: None,
+            right: None,
+            mid_filters: None,
+            side_filters: Some(vec!["sidegain".to_string()]),
+            width: Some(width),
+        }
+    }
+
+    fn make_filters() -> HashMap<String, Filter> {
+        let mut filters = HashMap::new();
+        filters.insert(
+            "sidegain".to_string(),
+            Filter::Gain {
+                description: None,
+                parameters: GainParameters {
+                    gain: -6.0206,
+                    inverted: None,
+                    mute: None,
+                    scale: None,
+                },
+            },
+        );
+        filters
+    }
+
+    fn process(midside: &mut MidSide, left: PrcFmt, right: PrcFmt) -> (PrcFmt, PrcFmt) {
+        let mut chunk =
+            AudioChunk::new(vec![vec![left; 100], vec![right; 100]], 1.0, -1.0, 100, 100);
+        midside.process_chunk(&mut chunk).unwrap();
+        (chunk.waveforms[0][99], chunk.waveforms[1][99])
+    }
+
+    #[test]
+    fn filters_side() {
+        let params = Arc::new(ProcessingParameters::default());
+        let mut midside =
+            MidSide::from_config("test", make_params(1.0), make_filters(), 48000, 100, params);
+        // Mono is unchanged.
+        let (l, r) = process(&mut midside, 0.5, 0.5);
+        assert!((l - 0.5).abs() < 1.0e-6);
+        assert!((r - 0.5).abs() < 1.0e-6);
+        // Side is halved by the filter.
+        let (l, r) = process(&mut midside, 0.5, -0.5);
+        assert!((l - 0.25).abs() < 1.0e-4);
+        assert!((r + 0.25).abs() < 1.0e-4);
+    }
+
+    #[test]
+    fn runtime_width() {
+        let params = Arc::new(ProcessingParameters::default());
+        let mut midside = MidSide::from_config(
+            "test",
+            make_params(1.0),
+            make_filters(),
+            48000,
+            100,
+            params.clone(),
+        );
+        assert!(params.set_stereo_width("test", 0.0));
+        assert!(!params.set_stereo_width("other", 0.0));
+        // Ramps to mono during the first chunk.
+        process(&mut midside, 1.0, 0.0);
+        let (l, r) = process(&mut midside, 1.0, 0.0);
+        assert!((l - 0.5).abs() < 1.0e-6);
+        assert!((r - 0.5).abs() < 1.0e-6);
+        // The runtime width is kept when rebuilding with the same config.
+        let mut midside =
+            MidSide::from_config("test", make_params(1.0), make_filters(), 48000, 100, params);
+        let (l, r) = process(&mut midside, 1.0, 0.0);
+        assert!((l - 0.5).abs() < 1.0e-6);
+        assert!((r - 0.5).abs() < 1.0e-6);
+    }
+
+    #[test]
+    fn check_config() {
+        let filters = make_filters();
+        let conf = make_params(1.0);
+        assert!(validate_midside(48000, &conf, Some(&filters)).is_ok());
+        assert!(validate_midside(48000, &conf, None).is_err());
+        let conf = make_params(5.0);
+        assert!(validate_midside(48000, &conf, Some(&filters)).is_err());
+    }
+}
@@ -17,7 +17,7 @@ pub struct MixerSource {
     pub gain: PrcFmt,
 }
 
-fn calculate_gain(gain_value: PrcFmt, inverted: bool, linear: bool) -> PrcFmt {
+pub fn calculate_gain(gain_value: PrcFmt, inverted: bool, linear: bool) -> PrcFmt {
     let mut gain = if linear {
         gain_value
     } else {
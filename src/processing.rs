@@ -1,14 +1,143 @@
 use crate::audiodevice::*;
 use crate::config;
 use crate::filters;
+use crate::PrcFmt;
 use crate::ProcessingParameters;
 use audio_thread_priority::{
     demote_current_thread_from_real_time, promote_current_thread_to_real_time,
 };
+use std::collections::HashSet;
 use std::sync::mpsc;
 use std::sync::{Arc, Barrier};
 use std::thread;
 
+// Messages from the pipeline builder thread to the processing loop.
+enum PipelineMessage {
+    // A new pipeline, built from a changed config.
+    // The unchanged filters are moved over from the current pipeline,
+    // before crossfading from the current pipeline to the new one.
+    Rebuilt {
+        pipeline: Box<filters::Pipeline>,
+        reused: HashSet<config::FilterInstance>,
+        fade_length: usize,
+    },
+    // Config changes that are applied directly by the processing loop.
+    Update(config::ConfigChange, Box<config::Configuration>),
+}
+
+/// A previous pipeline that is kept running while it is faded out after a config change.
+struct PipelineCrossfade {
+    pipeline: Box<filters::Pipeline>,
+    position: usize,
+    length: usize,
+}
+
+impl PipelineCrossfade {
+    fn process_chunk(
+        &mut self,
+        new_pipeline: &mut filters::Pipeline,
+        chunk: AudioChunk,
+    ) -> AudioChunk {
+        // The new pipeline goes first, since the previous one uses the output
+        // of the filters that were moved to the new pipeline.
+        let old_input = AudioChunk::from(&chunk, chunk.waveforms.clone());
+        let mut new_chunk = new_pipeline.process_chunk(chunk);
+        let old_chunk = self.pipeline.process_chunk(old_input);
+        crossfade_chunks(&old_chunk, &mut new_chunk, self.position, self.length);
+        self.position += new_chunk.frames;
+        new_chunk
+    }
+
+    fn is_done(&self) -> bool {
+        self.position >= self.length
+    }
+}
+
+/// The running pipeline, and the previous pipeline while crossfading after a config change.
+struct ActivePipeline {
+    pipeline: Box<filters::Pipeline>,
+    crossfade: Option<PipelineCrossfade>,
+    // Pipelines that are no longer used are sent to a separate thread to be dropped,
+    // to avoid freeing their buffers on the processing thread.
+    retired: mpsc::Sender<Box<filters::Pipeline>>,
+}
+
+impl ActivePipeline {
+    fn process_chunk(&mut self, chunk: AudioChunk) -> AudioChunk {
+        let chunk = match &mut self.crossfade {
+            Some(fade) => fade.process_chunk(&mut self.pipeline, chunk),
+            None => self.pipeline.process_chunk(chunk),
+        };
+        if self.crossfade.as_ref().is_some_and(|fade| fade.is_done()) {
+            debug!("Crossfade done, dropping previous pipeline.");
+            if let Some(fade) = self.crossfade.take() {
+                self.retire(fade.pipeline);
+            }
+        }
+        chunk
+    }
+
+    // Get the next message from the pipeline builder.
+    // Messages are left in the queue while a crossfade is running,
+    // so that a following config change is applied once the output has fully switched over.
+    fn next_message(&self, rx: &mpsc::Receiver<PipelineMessage>) -> Option<PipelineMessage> {
+        if self.crossfade.is_some() {
+            return None;
+        }
+        rx.try_recv().ok()
+    }
+
+    // Switch to a new pipeline, moving over the unchanged filters from the current one.
+    fn switch_to(
+        &mut self,
+        new_pipeline: Box<filters::Pipeline>,
+        reused: &HashSet<config::FilterInstance>,
+        fade_length: usize,
+    ) {
+        let mut old_pipeline = std::mem::replace(&mut self.pipeline, new_pipeline);
+        if !reused.is_empty() {
+            debug!("Moving {} unchanged filters to new pipeline.", reused.len());
+            self.pipeline.reuse_filters(&mut old_pipeline, reused);
+        }
+        if fade_length > 0 {
+            debug!("Crossfading to new pipeline over {} frames.", fade_length);
+            self.crossfade = Some(PipelineCrossfade {
+                pipeline: old_pipeline,
+                position: 0,
+                length: fade_length,
+            });
+        } else {
+            debug!("Switching to new pipeline.");
+            self.retire(old_pipeline);
+        }
+    }
+
+    fn retire(&self, pipeline: Box<filters::Pipeline>) {
+        self.retired.send(pipeline).unwrap_or(());
+    }
+}
+
+// Blend the output of the old pipeline into the output of the new one, using a raised cosine fade.
+// Channels that are unused in the old pipeline are faded in from silence.
+pub fn crossfade_chunks(old: &AudioChunk, new: &mut AudioChunk, position: usize, length: usize) {
+    let pi = std::f64::consts::PI as PrcFmt;
+    for (channel, new_waveform) in new.waveforms.iter_mut().enumerate() {
+        let old_waveform = old
+            .waveforms
+            .get(channel)
+            .filter(|wf| wf.len() == new_waveform.len());
+        for (n, value) in new_waveform.iter_mut().enumerate() {
+            let pos = position + n;
+            if pos >= length {
+                break;
+            }
+            let gain = 0.5 - 0.5 * (pi * pos as PrcFmt / length as PrcFmt).cos();
+            let old_value = old_waveform.map(|wf| wf[n]).unwrap_or_default();
+            *value = gain * *value + (1.0 - gain) * old_value;
+        }
+    }
+}
+
 pub fn run_processing(
     conf_proc: config::Configuration,
     barrier_proc: Arc<Barrier>,
@@ -43,9 +172,58 @@ pub fn run_processing(
                    Performance can improve by adding more threads or disabling multithreading."
             );
         }
-        let mut pipeline = filters::Pipeline::from_config(conf_proc, processing_params.clone());
+        let mut current_config = conf_proc.clone();
+        let (tx_retired, rx_retired) = mpsc::channel::<Box<filters::Pipeline>>();
+        thread::spawn(move || {
+            for pipeline in rx_retired.iter() {
+                drop(pipeline);
+            }
+            debug!("Pipeline reaper thread exits");
+        });
+        let mut active = ActivePipeline {
+            pipeline: Box::new(filters::Pipeline::from_config(
+                conf_proc,
+                processing_params.clone(),
+            )),
+            crossfade: None,
+            retired: tx_retired,
+        };
         debug!("build filters, waiting to start processing loop");
 
+        // New pipelines are built in a separate thread,
+        // to avoid interrupting the processing while loading filters.
+        let (tx_pipeline, rx_pipeline) = mpsc::channel();
+        let builder_params = processing_params.clone();
+        thread::spawn(move || {
+            for (diff, new_config) in rx_pipeconf.iter() {
+                let msg = match diff {
+                    config::ConfigChange::Pipeline | config::ConfigChange::MixerParameters => {
+                        let reused = config::unchanged_filters(&current_config, &new_config);
+                        debug!("Building new pipeline, reusing {} filters.", reused.len());
+                        let fade_length = (new_config.devices.crossfade_time() / 1000.0
+                            * new_config.devices.samplerate as f32)
+                            .round() as usize;
+                        current_config = new_config.clone();
+                        let new_pipeline =
+                            filters::Pipeline::from_config(new_config, builder_params.clone());
+                        PipelineMessage::Rebuilt {
+                            pipeline: Box::new(new_pipeline),
+                            reused,
+                            fade_length,
+                        }
+                    }
+                    _ => {
+                        current_config = new_config.clone();
+                        PipelineMessage::Update(diff, Box::new(new_config))
+                    }
+                };
+                if tx_pipeline.send(msg).is_err() {
+                    break;
+                }
+            }
+            debug!("Pipeline builder thread exits");
+        });
+
         let thread_handle =
             match promote_current_thread_to_real_time(chunksize as u32, samplerate as u32) {
                 Ok(h) => {
@@ -105,7 +283,7 @@ pub fn run_processing(
             match rx_cap.recv() {
                 Ok(AudioMessage::Audio(mut chunk)) => {
                     //trace!("AudioMessage::Audio received");
-                    chunk = pipeline.process_chunk(chunk);
+                    chunk = active.process_chunk(chunk);
                     let msg = AudioMessage::Audio(chunk);
                     if tx_pb.send(msg).is_err() {
                         info!("Playback thread has already stopped.");
@@ -137,33 +315,43 @@ pub fn run_processing(
                     break;
                 }
             }
-            if let Ok((diff, new_config)) = rx_pipeconf.try_recv() {
-                trace!("Message received on config channel");
-                match diff {
-                    config::ConfigChange::Pipeline | config::ConfigChange::MixerParameters => {
-                        debug!("Rebuilding pipeline.");
-                        let new_pipeline =
-                            filters::Pipeline::from_config(new_config, processing_params.clone());
-                        pipeline = new_pipeline;
-                    }
-                    config::ConfigChange::FilterParameters {
-                        filters,
-                        mixers,
-                        processors,
-                    } => {
-                        debug!(
-                            "Updating parameters of filters: {:?}, mixers: {:?}.",
-                            filters, mixers
-                        );
-                        pipeline.update_parameters(new_config, &filters, &mixers, &processors);
-                    }
-                    config::ConfigChange::Devices => {
-                        let msg = AudioMessage::EndOfStream;
-                        tx_pb.send(msg).unwrap();
-                        break;
-                    }
-                    _ => {}
-                };
+            match active.next_message(&rx_pipeline) {
+                Some(PipelineMessage::Rebuilt {
+                    pipeline: new_pipeline,
+                    reused,
+                    fade_length,
+                }) => {
+                    trace!("New pipeline received");
+                    active.switch_to(new_pipeline, &reused, fade_length);
+                }
+                Some(PipelineMessage::Update(diff, new_config)) => {
+                    trace!("Message received on config channel");
+                    match diff {
+                        config::ConfigChange::FilterParameters {
+                            filters,
+                            mixers,
+                            processors,
+                        } => {
+                            debug!(
+                                "Updating parameters of filters: {:?}, mixers: {:?}.",
+                                filters, mixers
+                            );
+                            active.pipeline.update_parameters(
+                                *new_config,
+                                &filters,
+                                &mixers,
+                                &processors,
+                            );
+                        }
+                        config::ConfigChange::Devices => {
+                            let msg = AudioMessage::EndOfStream;
+                            tx_pb.send(msg).unwrap();
+                            break;
+                        }
+                        _ => {}
+                    };
+                }
+                None => {}
             };
         }
         processing_params.set_processing_load(0.0);
@@ -179,3 +367,103 @@ pub fn run_processing(
         }
     })
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config;
+    use crate::filters::Pipeline;
+    use crate::processing::{crossfade_chunks, ActivePipeline, PipelineMessage};
+    use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::collections::HashSet;
+    use std::sync::mpsc;
+    use std::sync::Arc;
+
+    fn make_pipeline(gain: f32) -> Box<Pipeline> {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 48000
+  chunksize: 256
+  capture:
+    type: Stdin
+    channels: 1
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 1
+    format: S16LE
+filters:
+  gain:
+    type: Gain
+    parameters:
+      gain: {gain}
+      scale: linear
+pipeline:
+  - type: Filter
+    names: [gain]
+"
+        );
+        let mut conf: config::Configuration = serde_yaml::from_str(&yaml).unwrap();
+        config::validate_config(&mut conf, None).unwrap();
+        Box::new(Pipeline::from_config(
+            conf,
+            Arc::new(ProcessingParameters::default()),
+        ))
+    }
+
+    #[test]
+    fn crossfade_between_chunks() {
+        let old = AudioChunk::new(vec![vec![1.0; 8], Vec::new()], 1.0, -1.0, 8, 8);
+        let mut new = AudioChunk::new(vec![vec![0.0; 8], vec![1.0; 8]], 1.0, -1.0, 8, 8);
+        crossfade_chunks(&old, &mut new, 0, 6);
+        // Halfway through the fade, both pipelines contribute equally.
+        assert!((new.waveforms[0][3] - 0.5).abs() < 1.0e-6);
+        assert!((new.waveforms[1][3] - 0.5).abs() < 1.0e-6);
+        // The fade starts from the old output and ends with the new.
+        assert_eq!(new.waveforms[0][0], 1.0);
+        assert_eq!(new.waveforms[1][0], 0.0);
+        assert_eq!(new.waveforms[0][6], 0.0);
+        assert_eq!(new.waveforms[1][7], 1.0);
+    }
+
+    #[test]
+    fn back_to_back_rebuilds() {
+        let (tx_retired, rx_retired) = mpsc::channel();
+        let mut active = ActivePipeline {
+            pipeline: make_pipeline(1.0),
+            crossfade: None,
+            retired: tx_retired,
+        };
+        let (tx_pipeline, rx_pipeline) = mpsc::channel();
+        for gain in [2.0, 3.0] {
+            let msg = PipelineMessage::Rebuilt {
+                pipeline: make_pipeline(gain),
+                reused: HashSet::new(),
+                fade_length: 1024,
+            };
+            tx_pipeline.send(msg).unwrap();
+        }
+        let mut output: Vec<PrcFmt> = Vec::new();
+        for _ in 0..16 {
+            let chunk = AudioChunk::new(vec![vec![1.0; 256]], 1.0, -1.0, 256, 256);
+            output.extend(active.process_chunk(chunk).waveforms[0].iter());
+            if let Some(PipelineMessage::Rebuilt {
+                pipeline,
+                reused,
+                fade_length,
+            }) = active.next_message(&rx_pipeline)
+            {
+                active.switch_to(pipeline, &reused, fade_length);
+            }
+        }
+        // The second rebuild waits for the first crossfade, so the output changes smoothly.
+        for pair in output.windows(2) {
+            assert!((pair[1] - pair[0]).abs() < 0.01);
+        }
+        assert!((output[output.len() - 1] - 3.0).abs() < 1.0e-6);
+        // Both replaced pipelines are dropped outside of the processing.
+        assert_eq!(rx_retired.try_iter().count(), 2);
+    }
+}
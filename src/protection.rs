@@ -0,0 +1,479 @@
+use serde::Serialize;
+use std::sync::Arc;
+
+use crate::audiodevice::AudioChunk;
+use crate::biquad;
+use crate::config;
+use crate::filters::{Filter, Processor};
+use crate::PrcFmt;
+use crate::ProcessingParameters;
+use crate::Res;
+
+// The subsonic level must drop this many dB below the threshold to clear a trip.
+const DC_HYSTERESIS: PrcFmt = 6.0;
+// The temperature rise must drop below this fraction of the limit to clear a trip.
+const THERMAL_RECOVERY: PrcFmt = 0.8;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq, Serialize)]
+pub enum ProtectionTrip {
+    Dc,
+    Thermal,
+}
+
+/// The protection state of a channel, as reported over the websocket.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ProtectionStatus {
+    pub channel: usize,
+    pub tripped: Option<ProtectionTrip>,
+    pub dc_level: f32,
+    pub temperature_rise: f32,
+    pub trips: usize,
+}
+
+#[derive(Clone, Debug)]
+struct ChannelGuard {
+    channel: usize,
+    detector: biquad::Biquad,
+    dc_power: PrcFmt,
+    temperature: PrcFmt,
+    gain: PrcFmt,
+    tripped: Option<ProtectionTrip>,
+    trips: usize,
+}
+
+impl ChannelGuard {
+    fn new(
+        channel: usize,
+        samplerate: usize,
+        config: &config::SpeakerProtectionParameters,
+    ) -> Self {
+        let coeffs = biquad::BiquadCoefficients::from_config(samplerate, detector_config(config));
+        ChannelGuard {
+            channel,
+            detector: biquad::Biquad::new("detector", samplerate, coeffs),
+            dc_power: 0.0,
+            temperature: 0.0,
+            gain: 1.0,
+            tripped: None,
+            trips: 0,
+        }
+    }
+
+    fn dc_level(&self) -> PrcFmt {
+        10.0 * (self.dc_power + 1.0e-18).log10()
+    }
+
+    fn status(&self) -> ProtectionStatus {
+        ProtectionStatus {
+            channel: self.channel,
+            tripped: self.tripped,
+            dc_level: self.dc_level() as f32,
+            temperature_rise: self.temperature as f32,
+            trips: self.trips,
+        }
+    }
+
+    fn write_status(&self, status: &mut ProtectionStatus) {
+        status.channel = self.channel;
+        status.tripped = self.tripped;
+        status.dc_level = self.dc_level() as f32;
+        status.temperature_rise = self.temperature as f32;
+        status.trips = self.trips;
+    }
+}
+
+/// Get the lowpass filter used to isolate DC and subsonic content.
+fn detector_config(config: &config::SpeakerProtectionParameters) -> config::BiquadParameters {
+    config::BiquadParameters::Lowpass {
+        freq: config.dc_cutoff(),
+        q: 0.5f64.sqrt() as PrcFmt,
+    }
+}
+
+fn mean_square(values: &[PrcFmt]) -> PrcFmt {
+    values.iter().map(|v| v * v).sum::<PrcFmt>() / values.len().max(1) as PrcFmt
+}
+
+pub struct SpeakerProtection {
+    pub name: String,
+    pub channels: usize,
+    guards: Vec<ChannelGuard>,
+    dc_threshold: PrcFmt,
+    dc_decay: PrcFmt,
+    thermal: Option<config::ThermalParameters>,
+    thermal_decay: PrcFmt,
+    trip_gain: PrcFmt,
+    samplerate: usize,
+    chunksize: usize,
+    scratch: Vec<PrcFmt>,
+    processing_params: Arc<ProcessingParameters>,
+}
+
+/// Get the channels to protect, defaults to all channels.
+fn protected_channels(config: &config::SpeakerProtectionParameters) -> Vec<usize> {
+    let process_channels = config.process_channels();
+    if process_channels.is_empty() {
+        (0..config.channels).collect()
+    } else {
+        process_channels
+    }
+}
+
+impl SpeakerProtection {
+    /// Creates a SpeakerProtection from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::SpeakerProtectionParameters,
+        samplerate: usize,
+        chunksize: usize,
+        processing_params: Arc<ProcessingParameters>,
+    ) -> Self {
+        let guards = protected_channels(&config)
+            .into_iter()
+            .map(|ch| ChannelGuard::new(ch, samplerate, &config))
+            .collect();
+        let mut protection = SpeakerProtection {
+            name: name.to_string(),
+            channels: config.channels,
+            guards,
+            dc_threshold: 0.0,
+            dc_decay: 0.0,
+            thermal: None,
+            thermal_decay: 0.0,
+            trip_gain: 0.0,
+            samplerate,
+            chunksize,
+            scratch: vec![0.0; chunksize],
+            processing_params,
+        };
+        protection.apply_config(&config);
+        debug!(
+            "Creating speaker protection '{}', channels: {:?}, dc_threshold: {}, thermal: {:?}, action: {:?}",
+            protection.name,
+            protected_channels(&config),
+            config.dc_threshold(),
+            config.thermal,
+            config.action()
+        );
+        protection.publish_status();
+        protection
+    }
+
+    fn apply_config(&mut self, config: &config::SpeakerProtectionParameters) {
+        let chunk_time = self.chunksize as PrcFmt / self.samplerate as PrcFmt;
+        self.dc_threshold = config.dc_threshold();
+        self.dc_decay = (-chunk_time / config.dc_time()).exp();
+        self.thermal_decay = config
+            .thermal
+            .as_ref()
+            .map(|thermal| (-chunk_time / thermal.time_constant).exp())
+            .unwrap_or_default();
+        self.thermal = config.thermal.clone();
+        self.trip_gain = match config.action() {
+            config::ProtectionAction::Mute => 0.0,
+            config::ProtectionAction::Limit => (10.0 as PrcFmt).powf(-config.attenuation() / 20.0),
+        };
+    }
+
+    /// Check the limits of a channel, and update the trip state.
+    fn check_limits(&self, guard: &mut ChannelGuard) {
+        let dc_level = guard.dc_level();
+        let max_rise = self.thermal.as_ref().map(|thermal| thermal.max_rise);
+        match guard.tripped {
+            None => {
+                let trip = if dc_level > self.dc_threshold {
+                    Some(ProtectionTrip::Dc)
+                } else if max_rise.is_some_and(|max_rise| guard.temperature > max_rise) {
+                    Some(ProtectionTrip::Thermal)
+                } else {
+                    None
+                };
+                if let Some(trip) = trip {
+                    warn!(
+                        "Speaker protection '{}' tripped on channel {}: {:?}, subsonic level {:.1} dB, temperature rise {:.1} K",
+                        self.name, guard.channel, trip, dc_level, guard.temperature
+                    );
+                    guard.tripped = Some(trip);
+                    guard.trips += 1;
+                }
+            }
+            Some(trip) => {
+                let dc_ok = dc_level < self.dc_threshold - DC_HYSTERESIS;
+                let thermal_ok = max_rise
+                    .map(|max_rise| guard.temperature < THERMAL_RECOVERY * max_rise)
+                    .unwrap_or(true);
+                if dc_ok && thermal_ok {
+                    info!(
+                        "Speaker protection '{}' recovered on channel {} after {:?} trip",
+                        self.name, guard.channel, trip
+                    );
+                    guard.tripped = None;
+                }
+            }
+        }
+    }
+
+    /// Update the status shared with the websocket server.
+    /// Skipped if the status is currently being read, it is then updated on the next chunk.
+    /// The existing entry is updated in place, only the first publish after a change
+    /// of the protected channels needs to allocate.
+    fn publish_status(&self) {
+        if let Some(mut all_statuses) = self.processing_params.protection_status.try_lock() {
+            if let Some(statuses) = all_statuses.get_mut(&self.name) {
+                if statuses.len() == self.guards.len() {
+                    for (guard, status) in self.guards.iter().zip(statuses.iter_mut()) {
+                        guard.write_status(status);
+                    }
+                    return;
+                }
+            }
+            let statuses = self.guards.iter().map(|guard| guard.status()).collect();
+            all_statuses.insert(self.name.clone(), statuses);
+        }
+    }
+
+    /// Process the protected channels of a chunk, and update the state of their guards.
+    fn process_guards(&mut self, guards: &mut [ChannelGuard], input: &mut AudioChunk) -> Res<()> {
+        for guard in guards.iter_mut() {
+            let waveform = &mut input.waveforms[guard.channel];
+            if waveform.is_empty() {
+                continue;
+            }
+            // Measure the subsonic content of the input.
+            self.scratch.resize(waveform.len(), 0.0);
+            self.scratch.copy_from_slice(waveform);
+            guard.detector.process_waveform(&mut self.scratch)?;
+            guard.dc_power =
+                self.dc_decay * guard.dc_power + (1.0 - self.dc_decay) * mean_square(&self.scratch);
+
+            // Ramp the gain over the chunk to avoid clicks.
+            let target = if guard.tripped.is_some() {
+                self.trip_gain
+            } else {
+                1.0
+            };
+            let step = (target - guard.gain) / waveform.len() as PrcFmt;
+            for value in waveform.iter_mut() {
+                guard.gain += step;
+                *value *= guard.gain;
+            }
+            guard.gain = target;
+
+            // Update the thermal model from the output power.
+            // The power is given for a full scale sine, that has a mean square value of 0.5.
+            if let Some(thermal) = &self.thermal {
+                let power = 2.0 * mean_square(waveform) * thermal.power;
+                guard.temperature = self.thermal_decay * guard.temperature
+                    + (1.0 - self.thermal_decay) * power * thermal.resistance;
+            }
+            self.check_limits(guard);
+        }
+        Ok(())
+    }
+}
+
+impl Processor for SpeakerProtection {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply a SpeakerProtection to an AudioChunk, modifying it in-place.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        // The guards are put back also when processing fails, to keep their state.
+        let mut guards = std::mem::take(&mut self.guards);
+        let result = self.process_guards(&mut guards, input);
+        self.guards = guards;
+        result?;
+        self.publish_status();
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::SpeakerProtection {
+            parameters: config, ..
+        } = config
+        {
+            // Keep the state of channels that are still protected.
+            let old_guards = std::mem::take(&mut self.guards);
+            self.guards = protected_channels(&config)
+                .into_iter()
+                .map(|ch| {
+                    let mut guard = ChannelGuard::new(ch, self.samplerate, &config);
+                    if let Some(old) = old_guards.iter().find(|g| g.channel == ch) {
+                        guard.dc_power = old.dc_power;
+                        guard.temperature = old.temperature;
+                        guard.gain = old.gain;
+                        guard.tripped = old.tripped;
+                        guard.trips = old.trips;
+                    }
+                    guard
+                })
+                .collect();
+            self.channels = config.channels;
+            self.apply_config(&config);
+            debug!(
+                "Updated speaker protection '{}', dc_threshold: {}, thermal: {:?}, action: {:?}",
+                self.name,
+                config.dc_threshold(),
+                config.thermal,
+                config.action()
+            );
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate the speaker protection config, to give a helpful message intead of a panic.
+pub fn validate_protection(
+    samplerate: usize,
+    config: &config::SpeakerProtectionParameters,
+) -> Res<()> {
+    let channels = config.channels;
+    for ch in config.process_channels().iter() {
+        if *ch >= channels {
+            let msg = format!(
+                "Invalid channel to process: {}, max is: {}.",
+                *ch,
+                channels - 1
+            );
+            return Err(config::ConfigError::new(&msg).into());
+        }
+    }
+    if config.dc_time() <= 0.0 {
+        let msg = "DC averaging time must be larger than zero.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.attenuation() < 0.0 {
+        let msg = "Attenuation cannot be negative.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if let Some(thermal) = &config.thermal {
+        if thermal.power <= 0.0 || thermal.resistance <= 0.0 {
+            let msg = "Thermal power and resistance must be larger than zero.";
+            return Err(config::ConfigError::new(msg).into());
+        }
+        if thermal.time_constant <= 0.0 {
+            let msg = "Thermal time constant must be larger than zero.";
+            return Err(config::ConfigError::new(msg).into());
+        }
+        if thermal.max_rise <= 0.0 {
+            let msg = "Maximum temperature rise must be larger than zero.";
+            return Err(config::ConfigError::new(msg).into());
+        }
+    }
+    biquad::validate_config(samplerate, &detector_config(config))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::{ProtectionAction, SpeakerProtectionParameters, ThermalParameters};
+    use crate::filters::Processor;
+    use crate::protection::{validate_protection, ProtectionTrip, SpeakerProtection};
+    use crate::PrcFmt;
+    use crate::ProcessingParameters;
+    use std::sync::Arc;
+
+    fn make_params(action: ProtectionAction) -> SpeakerProtectionParameters {
+        SpeakerProtectionParameters {
+            channels: 2,
+            process_channels: None,
+            dc_cutoff: None,
+            dc_threshold: Some(-30.0),
+            dc_time: Some(0.1),
+            thermal: Some(ThermalParameters {
+                power: 100.0,
+                resistance: 1.0,
+                time_constant: 0.5,
+                max_rise: 20.0,
+            }),
+            action: Some(action),
+            attenuation: Some(20.0),
+        }
+    }
+
+    fn make_chunk(dc: PrcFmt, ampl: PrcFmt) -> AudioChunk {
+        let wave: Vec<PrcFmt> = (0..1000)
+            .map(|n| dc + ampl * (n as PrcFmt * 0.3).sin())
+            .collect();
+        AudioChunk::new(vec![wave, vec![0.01; 1000]], 1.0, -1.0, 1000, 1000)
+    }
+
+    fn run(prot: &mut SpeakerProtection, dc: PrcFmt, ampl: PrcFmt, chunks: usize) -> AudioChunk {
+        let mut chunk = make_chunk(dc, ampl);
+        for _ in 0..chunks {
+            chunk = make_chunk(dc, ampl);
+            prot.process_chunk(&mut chunk).unwrap();
+        }
+        chunk
+    }
+
+    #[test]
+    fn mutes_on_dc() {
+        let params = Arc::new(ProcessingParameters::default());
+        let mut prot = SpeakerProtection::from_config(
+            "test",
+            make_params(ProtectionAction::Mute),
+            48000,
+            1000,
+            params.clone(),
+        );
+        let chunk = run(&mut prot, 0.0, 0.1, 20);
+        assert!((chunk.waveforms[0][999] - 0.1 * (999.0 as PrcFmt * 0.3).sin()).abs() < 1.0e-9);
+        let chunk = run(&mut prot, 0.2, 0.0, 20);
+        assert_eq!(chunk.waveforms[0][999], 0.0);
+        // The other channel is not affected.
+        assert_eq!(chunk.waveforms[1][999], 0.01);
+        let status = params.protection_status();
+        assert_eq!(status["test"][0].tripped, Some(ProtectionTrip::Dc));
+        assert_eq!(status["test"][0].trips, 1);
+        assert_eq!(status["test"][1].tripped, None);
+        // Recovers when the DC is gone.
+        let chunk = run(&mut prot, 0.0, 0.1, 100);
+        assert!((chunk.waveforms[0][999] - 0.1 * (999.0 as PrcFmt * 0.3).sin()).abs() < 1.0e-9);
+        assert_eq!(params.protection_status()["test"][0].tripped, None);
+    }
+
+    #[test]
+    fn limits_on_overheat() {
+        let params = Arc::new(ProcessingParameters::default());
+        let mut prot = SpeakerProtection::from_config(
+            "test",
+            make_params(ProtectionAction::Limit),
+            48000,
+            1000,
+            params.clone(),
+        );
+        // A full scale sine gives 100 W and would give a final temperature rise of 100 K.
+        let mut chunks = 0;
+        while params.protection_status()["test"][0].tripped.is_none() {
+            run(&mut prot, 0.0, 1.0, 1);
+            chunks += 1;
+            assert!(chunks < 20);
+        }
+        assert_eq!(
+            params.protection_status()["test"][0].tripped,
+            Some(ProtectionTrip::Thermal)
+        );
+        // The gain is ramped down during the first chunk after the trip.
+        run(&mut prot, 0.0, 1.0, 1);
+        let chunk = run(&mut prot, 0.0, 1.0, 1);
+        let peak = chunk.waveforms[0]
+            .iter()
+            .fold(0.0, |acc: PrcFmt, v| acc.max(v.abs()));
+        assert!((peak - 0.1).abs() < 0.001, "{peak}");
+    }
+
+    #[test]
+    fn check_config() {
+        let mut conf = make_params(ProtectionAction::Mute);
+        assert!(validate_protection(48000, &conf).is_ok());
+        conf.process_channels = Some(vec![2]);
+        assert!(validate_protection(48000, &conf).is_err());
+        let mut conf = make_params(ProtectionAction::Mute);
+        conf.thermal.as_mut().unwrap().time_constant = 0.0;
+        assert!(validate_protection(48000, &conf).is_err());
+    }
+}
@@ -4,6 +4,7 @@ use crossbeam_channel::TrySendError;
 use native_tls::{Identity, TlsAcceptor, TlsStream};
 use parking_lot::{Mutex, RwLock};
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 #[cfg(feature = "secure-websocket")]
 use std::fs::File;
 #[cfg(feature = "secure-websocket")]
@@ -18,13 +19,19 @@ use tungstenite::accept;
 use tungstenite::Message;
 use tungstenite::WebSocket;
 
+use crate::biquadcombo;
+use crate::diagnostics;
+use crate::graph;
 use crate::helpers::linear_to_db;
+use crate::midside;
+use crate::protection::ProtectionStatus;
+use crate::tonecontrols;
 use crate::ProcessingState;
 use crate::Res;
 use crate::{config, ControllerMessage};
 use crate::{
     list_available_devices, list_supported_devices, CaptureStatus, PlaybackStatus,
-    ProcessingParameters, ProcessingStatus, StopReason,
+    ProcessingParameters, ProcessingStatus, StopReason, ToneControl,
 };
 
 #[derive(Debug, Clone)]
@@ -74,6 +81,7 @@ enum WsCommand {
     SetConfigJson(String),
     Reload,
     GetConfig,
+    GetConfigRaw,
     GetConfigTitle,
     GetConfigDescription,
     GetPreviousConfig,
@@ -81,6 +89,7 @@ enum WsCommand {
     ReadConfigFile(String),
     ValidateConfig(String),
     GetConfigJson,
+    GetPipelineGraph(graph::GraphFormat),
     GetConfigFilePath,
     GetStateFilePath,
     GetStateFileUpdated,
@@ -130,6 +139,48 @@ enum WsCommand {
     GetAvailableCaptureDevices(String),
     GetAvailablePlaybackDevices(String),
     GetProcessingLoad,
+    GetProtectionStatus,
+    GetStereoWidth(String),
+    SetStereoWidth(String, f32),
+    GetBass,
+    SetBass(f32),
+    AdjustBass(ValueWithOptionalLimits),
+    GetTreble,
+    SetTreble(f32),
+    AdjustTreble(ValueWithOptionalLimits),
+    GetBalance,
+    SetBalance(f32),
+    AdjustBalance(ValueWithOptionalLimits),
+    GetEqBands(String),
+    GetEqBand {
+        filter: String,
+        band: usize,
+    },
+    SetEqBand {
+        filter: String,
+        band: usize,
+        gain: f32,
+    },
+    GetStepBypass {
+        #[serde(default)]
+        index: Option<usize>,
+        #[serde(default)]
+        name: Option<String>,
+    },
+    SetStepBypass {
+        #[serde(default)]
+        index: Option<usize>,
+        #[serde(default)]
+        name: Option<String>,
+        bypassed: bool,
+    },
+    GetVariants,
+    GetVariant,
+    SelectVariant {
+        name: String,
+        #[serde(default)]
+        loudness_match: bool,
+    },
     Exit,
     Stop,
     None,
@@ -179,6 +230,10 @@ enum WsReply {
         result: WsResult,
         value: String,
     },
+    GetConfigRaw {
+        result: WsResult,
+        value: String,
+    },
     GetConfigTitle {
         result: WsResult,
         value: String,
@@ -202,11 +257,16 @@ enum WsReply {
     ValidateConfig {
         result: WsResult,
         value: String,
+        problems: Vec<config::ConfigProblem>,
     },
     GetConfigJson {
         result: WsResult,
         value: String,
     },
+    GetPipelineGraph {
+        result: WsResult,
+        value: String,
+    },
     GetConfigFilePath {
         result: WsResult,
         value: Option<String>,
@@ -395,6 +455,79 @@ enum WsReply {
         result: WsResult,
         value: f32,
     },
+    GetProtectionStatus {
+        result: WsResult,
+        value: HashMap<String, Vec<ProtectionStatus>>,
+    },
+    GetStereoWidth {
+        result: WsResult,
+        value: f32,
+    },
+    SetStereoWidth {
+        result: WsResult,
+    },
+    GetBass {
+        result: WsResult,
+        value: f32,
+    },
+    SetBass {
+        result: WsResult,
+    },
+    AdjustBass {
+        result: WsResult,
+        value: f32,
+    },
+    GetTreble {
+        result: WsResult,
+        value: f32,
+    },
+    SetTreble {
+        result: WsResult,
+    },
+    AdjustTreble {
+        result: WsResult,
+        value: f32,
+    },
+    GetBalance {
+        result: WsResult,
+        value: f32,
+    },
+    SetBalance {
+        result: WsResult,
+    },
+    AdjustBalance {
+        result: WsResult,
+        value: f32,
+    },
+    GetEqBands {
+        result: WsResult,
+        value: Vec<f32>,
+    },
+    GetEqBand {
+        result: WsResult,
+        value: f32,
+    },
+    SetEqBand {
+        result: WsResult,
+    },
+    GetStepBypass {
+        result: WsResult,
+        value: bool,
+    },
+    SetStepBypass {
+        result: WsResult,
+    },
+    GetVariants {
+        result: WsResult,
+        value: Vec<String>,
+    },
+    GetVariant {
+        result: WsResult,
+        value: String,
+    },
+    SelectVariant {
+        result: WsResult,
+    },
     Exit {
         result: WsResult,
     },
@@ -571,6 +704,7 @@ fn handle_command(
                         match config::validate_config(&mut conf, Some(path.as_str())) {
                             Ok(()) => {
                                 debug!("WS: Config file loaded successfully, send to controller");
+                                apply_eq_gains(shared_data_inst, &mut conf);
                                 match shared_data_inst
                                     .command_sender
                                     .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
@@ -1095,6 +1229,20 @@ fn handle_command(
             result: WsResult::Ok,
             value: serde_yaml::to_string(&*shared_data_inst.active_config.lock()).unwrap(),
         }),
+        WsCommand::GetConfigRaw => {
+            let optional_config = shared_data_inst.active_config.lock();
+            let value = match &*optional_config {
+                Some(config) => match &config.source.0 {
+                    Some(source) => source.clone(),
+                    None => serde_yaml::to_string(config).unwrap(),
+                },
+                None => serde_yaml::to_string(&*optional_config).unwrap(),
+            };
+            Some(WsReply::GetConfigRaw {
+                result: WsResult::Ok,
+                value,
+            })
+        }
         WsCommand::GetConfigTitle => {
             let optional_config = shared_data_inst.active_config.lock();
             let value = if let Some(config) = &*optional_config {
@@ -1127,6 +1275,28 @@ fn handle_command(
             result: WsResult::Ok,
             value: serde_json::to_string(&*shared_data_inst.active_config.lock()).unwrap(),
         }),
+        WsCommand::GetPipelineGraph(format) => {
+            // Rendering may run an external program, don't hold the lock while doing that.
+            let active_config = shared_data_inst.active_config.lock().clone();
+            let (variant, _) = shared_data_inst.processing_params.selected_variant();
+            let rendered = match active_config {
+                Some(conf) => graph::PipelineGraph::from_variant(&conf, &variant).render(format),
+                None => Err(config::ConfigError::new("No active config").into()),
+            };
+            match rendered {
+                Ok(value) => Some(WsReply::GetPipelineGraph {
+                    result: WsResult::Ok,
+                    value,
+                }),
+                Err(error) => {
+                    error!("Error writing pipeline graph: {}", error);
+                    Some(WsReply::GetPipelineGraph {
+                        result: WsResult::Error,
+                        value: error.to_string(),
+                    })
+                }
+            }
+        }
         WsCommand::GetConfigFilePath => Some(WsReply::GetConfigFilePath {
             result: WsResult::Ok,
             value: shared_data_inst
@@ -1166,50 +1336,53 @@ fn handle_command(
                 })
             }
         },
-        WsCommand::SetConfig(config_yml) => {
-            match serde_yaml::from_str::<config::Configuration>(&config_yml) {
-                Ok(mut conf) => match config::validate_config(&mut conf, None) {
-                    Ok(()) => {
-                        match shared_data_inst
-                            .command_sender
-                            .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
-                        {
-                            Ok(()) => Some(WsReply::SetConfig {
-                                result: WsResult::Ok,
-                            }),
-                            Err(TrySendError::Full(_)) => {
-                                error!("Error sending new config, too many requests");
-                                Some(WsReply::SetConfig {
-                                    result: WsResult::Error,
-                                })
-                            }
-                            Err(TrySendError::Disconnected(_)) => {
-                                error!("Error sending new config, channel was disconnected");
-                                Some(WsReply::SetConfig {
-                                    result: WsResult::Error,
-                                })
-                            }
+        WsCommand::SetConfig(config_yml) => match config::parse_config(&config_yml) {
+            Ok(mut conf) => match config::validate_config(&mut conf, None) {
+                Ok(()) => {
+                    apply_eq_gains(shared_data_inst, &mut conf);
+                    match shared_data_inst
+                        .command_sender
+                        .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
+                    {
+                        Ok(()) => Some(WsReply::SetConfig {
+                            result: WsResult::Ok,
+                        }),
+                        Err(TrySendError::Full(_)) => {
+                            error!("Error sending new config, too many requests");
+                            Some(WsReply::SetConfig {
+                                result: WsResult::Error,
+                            })
+                        }
+                        Err(TrySendError::Disconnected(_)) => {
+                            error!("Error sending new config, channel was disconnected");
+                            Some(WsReply::SetConfig {
+                                result: WsResult::Error,
+                            })
                         }
                     }
-                    Err(error) => {
-                        error!("Error validating config: {}", error);
-                        Some(WsReply::SetConfig {
-                            result: WsResult::Error,
-                        })
-                    }
-                },
+                }
                 Err(error) => {
-                    error!("Error parsing yaml: {}", error);
+                    error!("Error validating config: {}", error);
                     Some(WsReply::SetConfig {
                         result: WsResult::Error,
                     })
                 }
+            },
+            Err(error) => {
+                error!("Error parsing yaml: {}", error);
+                Some(WsReply::SetConfig {
+                    result: WsResult::Error,
+                })
             }
-        }
+        },
         WsCommand::SetConfigJson(config_json) => {
-            match serde_json::from_str::<config::Configuration>(&config_json) {
+            match serde_json::from_str::<serde_yaml::Value>(&config_json)
+                .map_err(|err| err.into())
+                .and_then(|value| config::config_from_value(value, &config_json))
+            {
                 Ok(mut conf) => match config::validate_config(&mut conf, None) {
                     Ok(()) => {
+                        apply_eq_gains(shared_data_inst, &mut conf);
                         match shared_data_inst
                             .command_sender
                             .try_send(ControllerMessage::ConfigChanged(Box::new(conf)))
@@ -1246,21 +1419,19 @@ fn handle_command(
                 }
             }
         }
-        WsCommand::ReadConfig(config_yml) => {
-            match serde_yaml::from_str::<config::Configuration>(&config_yml) {
-                Ok(conf) => Some(WsReply::ReadConfig {
-                    result: WsResult::Ok,
-                    value: serde_yaml::to_string(&conf).unwrap(),
-                }),
-                Err(error) => {
-                    error!("Error reading config: {}", error);
-                    Some(WsReply::ReadConfig {
-                        result: WsResult::Error,
-                        value: error.to_string(),
-                    })
-                }
+        WsCommand::ReadConfig(config_yml) => match config::parse_config(&config_yml) {
+            Ok(conf) => Some(WsReply::ReadConfig {
+                result: WsResult::Ok,
+                value: serde_yaml::to_string(&conf).unwrap(),
+            }),
+            Err(error) => {
+                error!("Error reading config: {}", error);
+                Some(WsReply::ReadConfig {
+                    result: WsResult::Error,
+                    value: error.to_string(),
+                })
             }
-        }
+        },
         WsCommand::ReadConfigFile(path) => match config::load_config(&path) {
             Ok(conf) => Some(WsReply::ReadConfigFile {
                 result: WsResult::Ok,
@@ -1275,25 +1446,23 @@ fn handle_command(
             }
         },
         WsCommand::ValidateConfig(config_yml) => {
-            match serde_yaml::from_str::<config::Configuration>(&config_yml) {
-                Ok(mut conf) => match config::validate_config(&mut conf, None) {
-                    Ok(()) => Some(WsReply::ValidateConfig {
-                        result: WsResult::Ok,
-                        value: serde_yaml::to_string(&conf).unwrap(),
-                    }),
-                    Err(error) => {
-                        error!("Config error: {}", error);
-                        Some(WsReply::ValidateConfig {
-                            result: WsResult::Error,
-                            value: error.to_string(),
-                        })
-                    }
-                },
-                Err(error) => {
-                    error!("Config error: {}", error);
+            match diagnostics::check_config(&config_yml, None) {
+                Ok(conf) => Some(WsReply::ValidateConfig {
+                    result: WsResult::Ok,
+                    value: serde_yaml::to_string(&conf).unwrap(),
+                    problems: Vec::new(),
+                }),
+                Err(problems) => {
+                    let value = problems
+                        .iter()
+                        .map(|problem| problem.to_string())
+                        .collect::<Vec<String>>()
+                        .join("\n");
+                    error!("Config error: {}", value);
                     Some(WsReply::ValidateConfig {
                         result: WsResult::Error,
-                        value: error.to_string(),
+                        value,
+                        problems,
                     })
                 }
             }
@@ -1370,6 +1539,185 @@ fn handle_command(
                 value: load,
             })
         }
+        WsCommand::GetProtectionStatus => Some(WsReply::GetProtectionStatus {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.protection_status(),
+        }),
+        WsCommand::GetStereoWidth(name) => {
+            match shared_data_inst.processing_params.stereo_width(&name) {
+                Some(width) => Some(WsReply::GetStereoWidth {
+                    result: WsResult::Ok,
+                    value: width,
+                }),
+                None => Some(WsReply::GetStereoWidth {
+                    result: WsResult::Error,
+                    value: 0.0,
+                }),
+            }
+        }
+        WsCommand::SetStereoWidth(name, width) => {
+            if !(0.0..=midside::MAX_WIDTH as f32).contains(&width) {
+                warn!("Invalid stereo width: {}", width);
+                return Some(WsReply::SetStereoWidth {
+                    result: WsResult::Error,
+                });
+            }
+            let result = if shared_data_inst
+                .processing_params
+                .set_stereo_width(&name, width)
+            {
+                WsResult::Ok
+            } else {
+                warn!("No mid/side processor named '{}'", name);
+                WsResult::Error
+            };
+            Some(WsReply::SetStereoWidth { result })
+        }
+        WsCommand::GetBass => Some(WsReply::GetBass {
+            result: WsResult::Ok,
+            value: shared_data_inst
+                .processing_params
+                .tone_control(ToneControl::Bass),
+        }),
+        WsCommand::SetBass(value) => {
+            let result = match set_tone_control(shared_data_inst, ToneControl::Bass, value) {
+                Some(_) => WsResult::Ok,
+                None => WsResult::Error,
+            };
+            Some(WsReply::SetBass { result })
+        }
+        WsCommand::AdjustBass(value) => {
+            let (result, value) = adjust_tone_control(shared_data_inst, ToneControl::Bass, value);
+            Some(WsReply::AdjustBass { result, value })
+        }
+        WsCommand::GetTreble => Some(WsReply::GetTreble {
+            result: WsResult::Ok,
+            value: shared_data_inst
+                .processing_params
+                .tone_control(ToneControl::Treble),
+        }),
+        WsCommand::SetTreble(value) => {
+            let result = match set_tone_control(shared_data_inst, ToneControl::Treble, value) {
+                Some(_) => WsResult::Ok,
+                None => WsResult::Error,
+            };
+            Some(WsReply::SetTreble { result })
+        }
+        WsCommand::AdjustTreble(value) => {
+            let (result, value) = adjust_tone_control(shared_data_inst, ToneControl::Treble, value);
+            Some(WsReply::AdjustTreble { result, value })
+        }
+        WsCommand::GetBalance => Some(WsReply::GetBalance {
+            result: WsResult::Ok,
+            value: shared_data_inst
+                .processing_params
+                .tone_control(ToneControl::Balance),
+        }),
+        WsCommand::SetBalance(value) => {
+            let result = match set_tone_control(shared_data_inst, ToneControl::Balance, value) {
+                Some(_) => WsResult::Ok,
+                None => WsResult::Error,
+            };
+            Some(WsReply::SetBalance { result })
+        }
+        WsCommand::AdjustBalance(value) => {
+            let (result, value) =
+                adjust_tone_control(shared_data_inst, ToneControl::Balance, value);
+            Some(WsReply::AdjustBalance { result, value })
+        }
+        WsCommand::GetEqBands(filter) => match get_eq_gains(shared_data_inst, &filter) {
+            Some(gains) => Some(WsReply::GetEqBands {
+                result: WsResult::Ok,
+                value: gains,
+            }),
+            None => Some(WsReply::GetEqBands {
+                result: WsResult::Error,
+                value: Vec::new(),
+            }),
+        },
+        WsCommand::GetEqBand { filter, band } => {
+            match get_eq_gains(shared_data_inst, &filter).and_then(|gains| gains.get(band).copied())
+            {
+                Some(gain) => Some(WsReply::GetEqBand {
+                    result: WsResult::Ok,
+                    value: gain,
+                }),
+                None => Some(WsReply::GetEqBand {
+                    result: WsResult::Error,
+                    value: 0.0,
+                }),
+            }
+        }
+        WsCommand::SetEqBand { filter, band, gain } => {
+            let result = set_eq_band(shared_data_inst, &filter, band, gain);
+            Some(WsReply::SetEqBand { result })
+        }
+        WsCommand::GetStepBypass { index, name } => {
+            match shared_data_inst
+                .processing_params
+                .step_bypass(index, name.as_deref())
+            {
+                Some(bypassed) => Some(WsReply::GetStepBypass {
+                    result: WsResult::Ok,
+                    value: bypassed,
+                }),
+                None => {
+                    warn!(
+                        "No pipeline step matching index {:?}, name {:?}",
+                        index, name
+                    );
+                    Some(WsReply::GetStepBypass {
+                        result: WsResult::Error,
+                        value: false,
+                    })
+                }
+            }
+        }
+        WsCommand::SetStepBypass {
+            index,
+            name,
+            bypassed,
+        } => {
+            let result = match shared_data_inst.processing_params.set_step_bypass(
+                index,
+                name.as_deref(),
+                bypassed,
+            ) {
+                Ok(()) => WsResult::Ok,
+                Err(err) => {
+                    warn!(
+                        "Unable to set bypass of pipeline step with index {:?}, name {:?}: {}",
+                        index, name, err
+                    );
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SetStepBypass { result })
+        }
+        WsCommand::GetVariants => Some(WsReply::GetVariants {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.variants(),
+        }),
+        WsCommand::GetVariant => Some(WsReply::GetVariant {
+            result: WsResult::Ok,
+            value: shared_data_inst.processing_params.selected_variant().0,
+        }),
+        WsCommand::SelectVariant {
+            name,
+            loudness_match,
+        } => {
+            let result = match shared_data_inst
+                .processing_params
+                .select_variant(&name, loudness_match)
+            {
+                Ok(()) => WsResult::Ok,
+                Err(err) => {
+                    warn!("Unable to select pipeline variant: {}", err);
+                    WsResult::Error
+                }
+            };
+            Some(WsReply::SelectVariant { result })
+        }
         WsCommand::None => None,
     }
 }
@@ -1387,6 +1735,148 @@ fn clamped_volume(vol: f32) -> f32 {
     new_vol
 }
 
+fn tone_control_limit(control: ToneControl) -> f32 {
+    match control {
+        ToneControl::Bass | ToneControl::Treble => tonecontrols::MAX_TONE_GAIN,
+        ToneControl::Balance => tonecontrols::MAX_BALANCE,
+    }
+}
+
+// Set a tone control, returns the new value or None if the value is not a valid number.
+fn set_tone_control(
+    shared_data_inst: &SharedData,
+    control: ToneControl,
+    value: f32,
+) -> Option<f32> {
+    if !value.is_finite() {
+        warn!("Invalid {:?} value: {}", control, value);
+        return None;
+    }
+    let limit = tone_control_limit(control);
+    let clamped = value.clamp(-limit, limit);
+    if clamped != value {
+        warn!("Clamped {:?} at {} dB", control, clamped);
+    }
+    shared_data_inst
+        .processing_params
+        .set_tone_control(control, clamped);
+    shared_data_inst
+        .unsaved_state_change
+        .store(true, Ordering::Relaxed);
+    shared_data_inst
+        .state_change_notify
+        .try_send(())
+        .unwrap_or(());
+    Some(clamped)
+}
+
+fn adjust_tone_control(
+    shared_data_inst: &SharedData,
+    control: ToneControl,
+    value: ValueWithOptionalLimits,
+) -> (WsResult, f32) {
+    let current = shared_data_inst.processing_params.tone_control(control);
+    let limit = tone_control_limit(control);
+    let (change, min, max) = match value {
+        ValueWithOptionalLimits::Plain(change) => (change, -limit, limit),
+        ValueWithOptionalLimits::Limited(change, min, max) => (change, min, max),
+    };
+    if min.is_nan() || max.is_nan() || max < min {
+        return (WsResult::Error, current);
+    }
+    let new_value = (current + change).clamp(min, max);
+    match set_tone_control(shared_data_inst, control, new_value) {
+        Some(value) => (WsResult::Ok, value),
+        None => (WsResult::Error, current),
+    }
+}
+
+// Get the band gains of a graphic equalizer in the active config.
+fn get_eq_gains(shared_data_inst: &SharedData, filter: &str) -> Option<Vec<f32>> {
+    let active_config = shared_data_inst.active_config.lock();
+    let gains = active_config
+        .as_ref()
+        .and_then(|conf| config::graphic_eq_gains(conf, filter))
+        .cloned();
+    if gains.is_none() {
+        warn!("No graphic equalizer named '{}'", filter);
+    }
+    gains
+}
+
+// Apply the stored graphic equalizer gains to a new config.
+// Stored gains that are dropped because the config was edited are also removed from the statefile.
+fn apply_eq_gains(shared_data_inst: &SharedData, conf: &mut config::Configuration) {
+    let processing_params = &shared_data_inst.processing_params;
+    let kept = config::apply_eq_gains(conf, &processing_params.eq_gains());
+    if processing_params.set_all_eq_gains(kept) {
+        shared_data_inst
+            .unsaved_state_change
+            .store(true, Ordering::Relaxed);
+        shared_data_inst
+            .state_change_notify
+            .try_send(())
+            .unwrap_or(());
+    }
+}
+
+// Change the gain of one band of a graphic equalizer.
+// The change is applied to the active config, and only the parameters of this filter are updated.
+fn set_eq_band(shared_data_inst: &SharedData, filter: &str, band: usize, gain: f32) -> WsResult {
+    if !gain.is_finite() {
+        warn!("Invalid band gain: {}", gain);
+        return WsResult::Error;
+    }
+    let limit = biquadcombo::MAX_GRAPHIC_EQ_GAIN;
+    let clamped = gain.clamp(-limit, limit);
+    if clamped != gain {
+        warn!("Clamped band gain at {} dB", clamped);
+    }
+    let mut active_config = shared_data_inst.active_config.lock();
+    let mut new_config = match active_config.as_ref() {
+        Some(conf) => conf.clone(),
+        None => {
+            warn!("There is no active config");
+            return WsResult::Error;
+        }
+    };
+    let gains = match config::graphic_eq_gains_mut(&mut new_config, filter) {
+        Some(gains) if band < gains.len() => gains,
+        Some(_) => {
+            warn!("Graphic equalizer '{}' has no band {}", filter, band);
+            return WsResult::Error;
+        }
+        None => {
+            warn!("No graphic equalizer named '{}'", filter);
+            return WsResult::Error;
+        }
+    };
+    let config_gains = gains.clone();
+    gains[band] = clamped;
+    let gains = gains.clone();
+    config::set_source_eq_gains(&mut new_config, filter, &gains);
+    let message = ControllerMessage::FilterParametersChanged(
+        Box::new(new_config.clone()),
+        vec![filter.to_string()],
+    );
+    if let Err(err) = shared_data_inst.command_sender.try_send(message) {
+        error!("Error sending filter parameter change message: {}", err);
+        return WsResult::Error;
+    }
+    *active_config = Some(new_config);
+    shared_data_inst
+        .processing_params
+        .set_eq_gains(filter, &gains, &config_gains);
+    shared_data_inst
+        .unsaved_state_change
+        .store(true, Ordering::Relaxed);
+    shared_data_inst
+        .state_change_notify
+        .try_send(())
+        .unwrap_or(());
+    WsResult::Ok
+}
+
 // Workaround to safely subtract from an Instant on all operating systems
 fn get_subtracted_instant(seconds: f32) -> Instant {
     let now = Instant::now();
@@ -1606,14 +2096,68 @@ fn capture_signal_rms(shared_data: &SharedData) -> Vec<f32> {
 
 #[cfg(test)]
 mod tests {
+    use crate::config;
+    use crate::graph;
     use crate::socketserver::{parse_command, WsCommand};
+    use crate::ProcessingParameters;
     use tungstenite::Message;
 
+    fn eq_config(gains: &str) -> config::Configuration {
+        let yaml = format!(
+            "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  eq:
+    type: BiquadCombo
+    parameters:
+      type: GraphicEqualizer
+      gains: [{gains}]
+"
+        );
+        config::parse_config(&yaml).unwrap()
+    }
+
+    #[test]
+    fn reload_with_edited_gains_wins() {
+        let params = ProcessingParameters::default();
+        params.set_eq_gains("eq", &[3.0, -3.0], &[0.0, 0.0]);
+        params.set_eq_gains("eq", &[2.0, -3.0], &[3.0, -3.0]);
+
+        // Reloading the unchanged config keeps the gains that were set at runtime.
+        let mut conf = eq_config("0.0, 0.0");
+        let kept = config::apply_eq_gains(&mut conf, &params.eq_gains());
+        assert!(!params.set_all_eq_gains(kept));
+        assert_eq!(config::graphic_eq_gains(&conf, "eq").unwrap(), &[2.0, -3.0]);
+
+        // Reloading with edited gains uses the config, and drops the stored gains.
+        let mut conf = eq_config("1.0, 1.0");
+        let kept = config::apply_eq_gains(&mut conf, &params.eq_gains());
+        assert!(params.set_all_eq_gains(kept));
+        assert_eq!(config::graphic_eq_gains(&conf, "eq").unwrap(), &[1.0, 1.0]);
+        assert!(params.eq_gains().is_empty());
+    }
+
     #[test]
     fn parse_commands() {
         let cmd = Message::text("\"Reload\"");
         let res = parse_command(cmd).unwrap();
         assert_eq!(res, WsCommand::Reload);
+        let cmd = Message::text("{\"GetPipelineGraph\": \"dot\"}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::GetPipelineGraph(graph::GraphFormat::Dot));
+        let cmd = Message::text("\"GetConfigRaw\"");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::GetConfigRaw);
         let cmd = Message::text("asdfasdf");
         let res = parse_command(cmd);
         assert!(res.is_err());
@@ -1623,5 +2167,38 @@ mod tests {
         let cmd = Message::text("{\"SetConfigFilePath\": \"somefile\"}");
         let res = parse_command(cmd).unwrap();
         assert_eq!(res, WsCommand::SetConfigFilePath("somefile".to_string()));
+        let cmd =
+            Message::text("{\"SetEqBand\": {\"filter\": \"eq\", \"band\": 2, \"gain\": -1.5}}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::SetEqBand {
+                filter: "eq".to_string(),
+                band: 2,
+                gain: -1.5
+            }
+        );
+        let cmd = Message::text("{\"SetStepBypass\": {\"name\": \"roomeq\", \"bypassed\": true}}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::SetStepBypass {
+                index: None,
+                name: Some("roomeq".to_string()),
+                bypassed: true
+            }
+        );
+        let cmd = Message::text("{\"SelectVariant\": {\"name\": \"roomcorr_v1\"}}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(
+            res,
+            WsCommand::SelectVariant {
+                name: "roomcorr_v1".to_string(),
+                loudness_match: false
+            }
+        );
+        let cmd = Message::text("{\"SetBass\": 3.5}");
+        let res = parse_command(cmd).unwrap();
+        assert_eq!(res, WsCommand::SetBass(3.5));
     }
 }
@@ -1,13 +1,16 @@
 //use crate::config::Configuration;
 use parking_lot::Mutex;
 use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
 use std::fs::File;
 use std::io::BufReader;
 use std::io::Read;
 use std::sync::atomic::{AtomicBool, Ordering};
 use std::sync::Arc;
 
+use crate::config::StoredEqGains;
 use crate::ProcessingParameters;
+use crate::ToneControl;
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
 #[serde(deny_unknown_fields)]
@@ -15,6 +18,14 @@ pub struct State {
     pub config_path: Option<String>,
     pub mute: [bool; 5],
     pub volume: [f32; 5],
+    #[serde(default)]
+    pub bass: f32,
+    #[serde(default)]
+    pub treble: f32,
+    #[serde(default)]
+    pub balance: f32,
+    #[serde(default)]
+    pub eq_gains: HashMap<String, StoredEqGains>,
 }
 
 pub fn load_state(filename: &str) -> Option<State> {
@@ -50,16 +61,25 @@ pub fn save_state(
     params: &ProcessingParameters,
     unsaved_changes: &Arc<AtomicBool>,
 ) {
-    let state = State {
-        config_path: config_path.lock().as_ref().map(|s| s.to_string()),
-        volume: params.volumes(),
-        mute: params.mutes(),
-    };
+    let state = current_state(config_path.lock().as_ref().map(|s| s.to_string()), params);
     if save_state_to_file(filename, &state) {
         unsaved_changes.store(false, Ordering::Relaxed);
     }
 }
 
+/// Get the state to save from the processing parameters.
+pub fn current_state(config_path: Option<String>, params: &ProcessingParameters) -> State {
+    State {
+        config_path,
+        volume: params.volumes(),
+        mute: params.mutes(),
+        bass: params.tone_control(ToneControl::Bass),
+        treble: params.tone_control(ToneControl::Treble),
+        balance: params.tone_control(ToneControl::Balance),
+        eq_gains: params.eq_gains(),
+    }
+}
+
 pub fn save_state_to_file(filename: &str, state: &State) -> bool {
     debug!("Saving state to {}", filename);
     match std::fs::OpenOptions::new()
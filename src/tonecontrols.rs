@@ -0,0 +1,309 @@
+use crate::biquad;
+use crate::config;
+use crate::filters::Filter;
+use std::sync::Arc;
+
+use crate::PrcFmt;
+use crate::ProcessingParameters;
+use crate::Res;
+use crate::ToneControl;
+
+/// Largest bass and treble boost or cut in dB.
+pub const MAX_TONE_GAIN: f32 = 20.0;
+/// Largest balance in dB.
+pub const MAX_BALANCE: f32 = 50.0;
+
+// Number of samples between updates of the filter coefficients while ramping.
+const SUBBLOCK: usize = 16;
+
+// A value that moves towards a target in equal steps.
+#[derive(Clone, Debug)]
+struct Ramp {
+    current: PrcFmt,
+    target: PrcFmt,
+    step: PrcFmt,
+}
+
+impl Ramp {
+    fn new(value: PrcFmt) -> Self {
+        Ramp {
+            current: value,
+            target: value,
+            step: 0.0,
+        }
+    }
+
+    fn set_target(&mut self, target: PrcFmt, nbr_steps: PrcFmt) {
+        if target != self.target {
+            self.target = target;
+            self.step = (target - self.current).abs() / nbr_steps.max(1.0);
+        }
+    }
+
+    /// Take one step towards the target, returns true if the value changed.
+    fn advance(&mut self) -> bool {
+        if self.current == self.target {
+            return false;
+        }
+        if (self.target - self.current).abs() <= self.step {
+            self.current = self.target;
+        } else if self.target > self.current {
+            self.current += self.step;
+        } else {
+            self.current -= self.step;
+        }
+        true
+    }
+}
+
+/// Bass and treble shelving filters and balance, that follow the tone controls in the processing parameters.
+pub struct ToneControls {
+    pub name: String,
+    samplerate: usize,
+    conf: config::ToneControlsParameters,
+    processing_params: Arc<ProcessingParameters>,
+    ramp_steps: PrcFmt,
+    bass: Ramp,
+    treble: Ramp,
+    balance: Ramp,
+    balance_gain: PrcFmt,
+    bass_shelf: biquad::Biquad,
+    treble_shelf: biquad::Biquad,
+}
+
+fn bass_config(freq: PrcFmt, gain: PrcFmt) -> config::BiquadParameters {
+    config::BiquadParameters::Lowshelf(config::ShelfSteepness::Slope {
+        freq,
+        slope: 6.0,
+        gain,
+    })
+}
+
+fn treble_config(freq: PrcFmt, gain: PrcFmt) -> config::BiquadParameters {
+    config::BiquadParameters::Highshelf(config::ShelfSteepness::Slope {
+        freq,
+        slope: 6.0,
+        gain,
+    })
+}
+
+/// Get the gain in dB for one side, a positive balance attenuates the left side.
+fn balance_to_gain(balance: PrcFmt, side: Option<config::BalanceSide>) -> PrcFmt {
+    let gain_db = match side {
+        Some(config::BalanceSide::Left) => -balance.max(0.0),
+        Some(config::BalanceSide::Right) => balance.min(0.0),
+        None => 0.0,
+    };
+    (10.0 as PrcFmt).powf(gain_db / 20.0)
+}
+
+impl ToneControls {
+    pub fn from_config(
+        name: &str,
+        samplerate: usize,
+        conf: config::ToneControlsParameters,
+        processing_params: Arc<ProcessingParameters>,
+    ) -> Self {
+        let bass = processing_params.tone_control(ToneControl::Bass) as PrcFmt;
+        let treble = processing_params.tone_control(ToneControl::Treble) as PrcFmt;
+        let balance = processing_params.tone_control(ToneControl::Balance) as PrcFmt;
+        let bass_coeffs = biquad::BiquadCoefficients::from_config(
+            samplerate,
+            bass_config(conf.bass_freq(), bass),
+        );
+        let treble_coeffs = biquad::BiquadCoefficients::from_config(
+            samplerate,
+            treble_config(conf.treble_freq(), treble),
+        );
+        let ramp_steps = ramp_steps(samplerate, &conf);
+        ToneControls {
+            name: name.to_string(),
+            samplerate,
+            balance_gain: balance_to_gain(balance, conf.balance_side),
+            conf,
+            processing_params,
+            ramp_steps,
+            bass: Ramp::new(bass),
+            treble: Ramp::new(treble),
+            balance: Ramp::new(balance),
+            bass_shelf: biquad::Biquad::new("bass", samplerate, bass_coeffs),
+            treble_shelf: biquad::Biquad::new("treble", samplerate, treble_coeffs),
+        }
+    }
+
+    // Read the targets from the shared parameters, and update the filters one step.
+    fn update_controls(&mut self) {
+        self.bass.set_target(
+            self.processing_params.tone_control(ToneControl::Bass) as PrcFmt,
+            self.ramp_steps,
+        );
+        self.treble.set_target(
+            self.processing_params.tone_control(ToneControl::Treble) as PrcFmt,
+            self.ramp_steps,
+        );
+        self.balance.set_target(
+            self.processing_params.tone_control(ToneControl::Balance) as PrcFmt,
+            self.ramp_steps,
+        );
+        if self.bass.advance() {
+            self.bass_shelf.update_parameters(config::Filter::Biquad {
+                description: None,
+                parameters: bass_config(self.conf.bass_freq(), self.bass.current),
+            });
+        }
+        if self.treble.advance() {
+            self.treble_shelf.update_parameters(config::Filter::Biquad {
+                description: None,
+                parameters: treble_config(self.conf.treble_freq(), self.treble.current),
+            });
+        }
+        if self.balance.advance() {
+            self.balance_gain = balance_to_gain(self.balance.current, self.conf.balance_side);
+        }
+    }
+}
+
+fn ramp_steps(samplerate: usize, conf: &config::ToneControlsParameters) -> PrcFmt {
+    (conf.ramp_time() as PrcFmt / 1000.0 * samplerate as PrcFmt / SUBBLOCK as PrcFmt).round()
+}
+
+impl Filter for ToneControls {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn process_waveform(&mut self, waveform: &mut [PrcFmt]) -> Res<()> {
+        for block in waveform.chunks_mut(SUBBLOCK) {
+            self.update_controls();
+            self.bass_shelf.process_waveform(block)?;
+            self.treble_shelf.process_waveform(block)?;
+            if self.balance_gain != 1.0 {
+                for value in block.iter_mut() {
+                    *value *= self.balance_gain;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, conf: config::Filter) {
+        if let config::Filter::ToneControls {
+            parameters: conf, ..
+        } = conf
+        {
+            self.ramp_steps = ramp_steps(self.samplerate, &conf);
+            self.bass_shelf.update_parameters(config::Filter::Biquad {
+                description: None,
+                parameters: bass_config(conf.bass_freq(), self.bass.current),
+            });
+            self.treble_shelf.update_parameters(config::Filter::Biquad {
+                description: None,
+                parameters: treble_config(conf.treble_freq(), self.treble.current),
+            });
+            self.balance_gain = balance_to_gain(self.balance.current, conf.balance_side);
+            self.conf = conf;
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+}
+
+/// Validate a ToneControls config.
+pub fn validate_config(samplerate: usize, conf: &config::ToneControlsParameters) -> Res<()> {
+    let maxfreq = samplerate as PrcFmt / 2.0;
+    for (parameter, freq) in [
+        ("bass_freq", conf.bass_freq()),
+        ("treble_freq", conf.treble_freq()),
+    ] {
+        if freq <= 0.0 || freq >= maxfreq {
+            let msg = format!("Shelf frequency must be > 0 and < samplerate/2, got {freq}");
+            return Err(config::ConfigError::for_parameter(parameter, &msg).into());
+        }
+    }
+    if conf.ramp_time() < 0.0 {
+        return Err(config::ConfigError::for_parameter(
+            "ramp_time",
+            "Ramp time cannot be negative",
+        )
+        .into());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::{BalanceSide, ToneControlsParameters};
+    use crate::filters::Filter;
+    use crate::tonecontrols::{validate_config, ToneControls};
+    use crate::PrcFmt;
+    use crate::{ProcessingParameters, ToneControl};
+    use std::sync::Arc;
+
+    fn make_conf(balance_side: Option<BalanceSide>) -> ToneControlsParameters {
+        ToneControlsParameters {
+            bass_freq: None,
+            treble_freq: None,
+            balance_side,
+            ramp_time: Some(10.0),
+        }
+    }
+
+    // Peak level in dB of a sine after the ramp has finished.
+    fn sine_level(filter: &mut ToneControls, freq: PrcFmt) -> PrcFmt {
+        let fs = 48000.0;
+        let mut peak: PrcFmt = 0.0;
+        for chunk in 0..10 {
+            let mut wave: Vec<PrcFmt> = (0..4800)
+                .map(|n| {
+                    let t = (chunk * 4800 + n) as PrcFmt / fs;
+                    (2.0 * std::f64::consts::PI as PrcFmt * freq * t).sin()
+                })
+                .collect();
+            filter.process_waveform(&mut wave).unwrap();
+            if chunk > 5 {
+                peak = wave.iter().fold(peak, |acc, val| acc.max(val.abs()));
+            }
+        }
+        20.0 * peak.log10()
+    }
+
+    #[test]
+    fn follows_bass_and_treble() {
+        let params = Arc::new(ProcessingParameters::default());
+        let mut filter = ToneControls::from_config("test", 48000, make_conf(None), params.clone());
+        assert!(sine_level(&mut filter, 1000.0).abs() < 0.1);
+        params.set_tone_control(ToneControl::Bass, 6.0);
+        params.set_tone_control(ToneControl::Treble, -6.0);
+        assert!((sine_level(&mut filter, 20.0) - 6.0).abs() < 0.5);
+        assert!((sine_level(&mut filter, 20000.0) + 6.0).abs() < 0.5);
+    }
+
+    #[test]
+    fn balance() {
+        let params = Arc::new(ProcessingParameters::default());
+        params.set_tone_control(ToneControl::Balance, 10.0);
+        let mut left = ToneControls::from_config(
+            "left",
+            48000,
+            make_conf(Some(BalanceSide::Left)),
+            params.clone(),
+        );
+        let mut right = ToneControls::from_config(
+            "right",
+            48000,
+            make_conf(Some(BalanceSide::Right)),
+            params.clone(),
+        );
+        assert!((sine_level(&mut left, 1000.0) + 10.0).abs() < 0.1);
+        assert!(sine_level(&mut right, 1000.0).abs() < 0.1);
+    }
+
+    #[test]
+    fn check_config() {
+        let mut conf = make_conf(None);
+        assert!(validate_config(48000, &conf).is_ok());
+        conf.treble_freq = Some(30000.0);
+        assert!(validate_config(48000, &conf).is_err());
+    }
+}
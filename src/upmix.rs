@@ -0,0 +1,398 @@
+use crate::audiodevice::AudioChunk;
+use crate::basicfilters::Delay;
+use crate::biquad;
+use crate::config;
+use crate::filters::{Filter, Processor};
+use crate::PrcFmt;
+use crate::Res;
+
+// Time constant in seconds for the signal analysis of the adaptive mode.
+const STEERING_TIME: PrcFmt = 0.05;
+// Output channels, the back channels are only used for 7.1.
+const LEFT: usize = 0;
+const RIGHT: usize = 1;
+const CENTER: usize = 2;
+const LFE: usize = 3;
+
+/// Upmix a stereo signal to 5.1 or 7.1.
+///
+/// The signal is split in mid (L+R)/2 and side (L-R)/2.
+/// A part of the mid signal is moved from the front left and right to the center channel,
+/// and the delayed side signal is sent with opposite polarity to the surround channels.
+/// In passive mode, the amounts are fixed, and the front left and right are passed through unchanged,
+/// so that signals panned to one side do not leak into the other channels.
+/// In adaptive mode, they are steered by the correlation
+/// between left and right, so that correlated content is kept in front and the center,
+/// while uncorrelated ambience is sent to the surrounds. Hard panned signals stay in front.
+pub struct Upmix {
+    pub name: String,
+    layout: config::UpmixLayout,
+    mode: config::UpmixMode,
+    center_width: PrcFmt,
+    steering_coeff: PrcFmt,
+    power_left: PrcFmt,
+    power_right: PrcFmt,
+    cross_power: PrcFmt,
+    surround_delay: PrcFmt,
+    side_delay: Delay,
+    back_delay: Delay,
+    lfe_filter: Option<biquad::Biquad>,
+    samplerate: usize,
+    mid: Vec<PrcFmt>,
+    side: Vec<PrcFmt>,
+}
+
+fn build_delays(name: &str, samplerate: usize, delay_ms: PrcFmt) -> (Delay, Delay) {
+    let samples = delay_ms / 1000.0 * samplerate as PrcFmt;
+    (
+        Delay::new(name, samplerate, samples, true),
+        Delay::new(name, samplerate, 2.0 * samples, true),
+    )
+}
+
+fn lfe_config(cutoff: PrcFmt) -> config::BiquadParameters {
+    config::BiquadParameters::Lowpass {
+        freq: cutoff,
+        q: 0.5f64.sqrt() as PrcFmt,
+    }
+}
+
+fn build_lfe_filter(samplerate: usize, cutoff: Option<PrcFmt>) -> Option<biquad::Biquad> {
+    cutoff.map(|freq| {
+        let coeffs = biquad::BiquadCoefficients::from_config(samplerate, lfe_config(freq));
+        biquad::Biquad::new("lfe", samplerate, coeffs)
+    })
+}
+
+impl Upmix {
+    /// Creates an Upmix from a config struct
+    pub fn from_config(
+        name: &str,
+        config: config::UpmixParameters,
+        samplerate: usize,
+        chunksize: usize,
+    ) -> Self {
+        let (side_delay, back_delay) = build_delays(name, samplerate, config.surround_delay());
+        debug!(
+            "Creating upmix '{}', layout: {:?}, mode: {:?}, center width: {}, surround delay: {}, lfe cutoff: {:?}",
+            name,
+            config.layout,
+            config.mode(),
+            config.center_width(),
+            config.surround_delay(),
+            config.lfe_cutoff
+        );
+        Upmix {
+            name: name.to_string(),
+            layout: config.layout,
+            mode: config.mode(),
+            center_width: config.center_width(),
+            steering_coeff: (-1.0 / (samplerate as PrcFmt * STEERING_TIME)).exp(),
+            power_left: 0.0,
+            power_right: 0.0,
+            cross_power: 0.0,
+            surround_delay: config.surround_delay(),
+            side_delay,
+            back_delay,
+            lfe_filter: build_lfe_filter(samplerate, config.lfe_cutoff),
+            samplerate,
+            mid: Vec::with_capacity(chunksize),
+            side: Vec::with_capacity(chunksize),
+        }
+    }
+
+    /// Update the signal analysis with a new pair of samples, and return the steering values.
+    /// The first is the amount of mid signal to move to the center,
+    /// and the second is the amount of side signal to send to the surrounds.
+    fn steering(&mut self, left: PrcFmt, right: PrcFmt) -> (PrcFmt, PrcFmt) {
+        let coeff = self.steering_coeff;
+        self.power_left = coeff * self.power_left + (1.0 - coeff) * left * left;
+        self.power_right = coeff * self.power_right + (1.0 - coeff) * right * right;
+        self.cross_power = coeff * self.cross_power + (1.0 - coeff) * left * right;
+        let total = self.power_left + self.power_right;
+        if total < 1.0e-12 {
+            return (0.0, 0.0);
+        }
+        // Balance between the channels, 1 for equal levels and 0 for a hard panned signal.
+        let balance = 2.0 * (self.power_left * self.power_right).sqrt() / total;
+        // Normalized correlation, 1 for identical signals, 0 for uncorrelated signals
+        // and -1 for signals in opposite phase.
+        let correlation =
+            self.cross_power / (self.power_left * self.power_right).sqrt().max(1.0e-12);
+        // Only content with similar levels in both channels is steered.
+        // Correlated content goes to the center, and decorrelated ambience to the surrounds,
+        // while a hard panned signal stays in its front channel.
+        (
+            (balance * correlation).clamp(0.0, 1.0),
+            (balance * (1.0 - correlation.max(0.0))).clamp(0.0, 1.0),
+        )
+    }
+}
+
+impl Processor for Upmix {
+    fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Apply an Upmix to an AudioChunk, the chunk gets the channels of the new layout.
+    fn process_chunk(&mut self, input: &mut AudioChunk) -> Res<()> {
+        let frames = input.frames;
+        for waveform in input.waveforms.iter_mut().take(2) {
+            // Unused channels may be empty
+            waveform.resize(frames, 0.0);
+        }
+        let center_amount = 1.0 - self.center_width;
+        self.mid.clear();
+        self.side.clear();
+        let mut waveforms = vec![vec![0.0; frames]; self.layout.channels()];
+        let inputs = input.waveforms[LEFT]
+            .iter()
+            .zip(input.waveforms[RIGHT].iter());
+        for (n, (&left, &right)) in inputs.enumerate() {
+            let mid = 0.5 * (left + right);
+            let surround = match self.mode {
+                config::UpmixMode::Passive => {
+                    waveforms[LEFT][n] = left;
+                    waveforms[RIGHT][n] = right;
+                    waveforms[CENTER][n] = center_amount * mid;
+                    1.0
+                }
+                config::UpmixMode::Adaptive => {
+                    let (center, surround) = self.steering(left, right);
+                    let center_mid = center * center_amount * mid;
+                    waveforms[LEFT][n] = left - center_mid;
+                    waveforms[RIGHT][n] = right - center_mid;
+                    waveforms[CENTER][n] = center_mid;
+                    surround
+                }
+            };
+            self.mid.push(mid);
+            self.side.push(surround * 0.5 * (left - right));
+        }
+        if let Some(lfe_filter) = &mut self.lfe_filter {
+            lfe_filter.process_waveform(&mut self.mid)?;
+            waveforms[LFE].copy_from_slice(&self.mid);
+        }
+        match self.layout {
+            config::UpmixLayout::Surround51 => {
+                self.side_delay.process_waveform(&mut self.side)?;
+                for (n, value) in self.side.iter().enumerate() {
+                    waveforms[4][n] = *value;
+                    waveforms[5][n] = -*value;
+                }
+            }
+            config::UpmixLayout::Surround71 => {
+                // Split the surround signal between the side and back channels.
+                let gain = 0.5f64.sqrt() as PrcFmt;
+                for value in self.side.iter_mut() {
+                    *value *= gain;
+                }
+                self.mid.clear();
+                self.mid.extend_from_slice(&self.side);
+                self.back_delay.process_waveform(&mut self.mid)?;
+                self.side_delay.process_waveform(&mut self.side)?;
+                for (n, (back, side)) in self.mid.iter().zip(self.side.iter()).enumerate() {
+                    waveforms[4][n] = *back;
+                    waveforms[5][n] = -*back;
+                    waveforms[6][n] = *side;
+                    waveforms[7][n] = -*side;
+                }
+            }
+        }
+        input.channels = waveforms.len();
+        input.waveforms = waveforms;
+        Ok(())
+    }
+
+    fn update_parameters(&mut self, config: config::Processor) {
+        if let config::Processor::Upmix {
+            parameters: config, ..
+        } = config
+        {
+            self.mode = config.mode();
+            self.center_width = config.center_width();
+            if config.surround_delay() != self.surround_delay {
+                let (side_delay, back_delay) =
+                    build_delays(&self.name, self.samplerate, config.surround_delay());
+                self.side_delay = side_delay;
+                self.back_delay = back_delay;
+                self.surround_delay = config.surround_delay();
+            }
+            match (&mut self.lfe_filter, config.lfe_cutoff) {
+                (Some(lfe_filter), Some(cutoff)) => {
+                    lfe_filter.update_parameters(config::Filter::Biquad {
+                        description: None,
+                        parameters: lfe_config(cutoff),
+                    });
+                }
+                (_, cutoff) => {
+                    self.lfe_filter = build_lfe_filter(self.samplerate, cutoff);
+                }
+            }
+            debug!(
+                "Updated upmix '{}', mode: {:?}, center width: {}, surround delay: {}, lfe cutoff: {:?}",
+                self.name,
+                config.mode(),
+                config.center_width(),
+                config.surround_delay(),
+                config.lfe_cutoff
+            );
+        } else {
+            // This should never happen unless there is a bug somewhere else
+            panic!("Invalid config change!");
+        }
+    }
+
+    fn channels_out(&self) -> Option<usize> {
+        Some(self.layout.channels())
+    }
+}
+
+/// Validate the upmix config, to give a helpful message intead of a panic.
+pub fn validate_upmix(samplerate: usize, config: &config::UpmixParameters) -> Res<()> {
+    if config.channels != 2 {
+        let msg = format!("Upmix needs 2 input channels, found {}.", config.channels);
+        return Err(config::ConfigError::new(&msg).into());
+    }
+    if !(0.0..=1.0).contains(&config.center_width()) {
+        let msg = "Center width must be in the range 0 to 1.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if config.surround_delay() < 0.0 {
+        let msg = "Surround delay cannot be negative.";
+        return Err(config::ConfigError::new(msg).into());
+    }
+    if let Some(cutoff) = config.lfe_cutoff {
+        biquad::validate_config(samplerate, &lfe_config(cutoff))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audiodevice::AudioChunk;
+    use crate::config::{UpmixLayout, UpmixMode, UpmixParameters};
+    use crate::filters::Processor;
+    use crate::upmix::{validate_upmix, Upmix};
+    use crate::PrcFmt;
+
+    fn make_params(layout: UpmixLayout, mode: UpmixMode) -> UpmixParameters {
+        UpmixParameters {
+            channels: 2,
+            layout,
+            mode: Some(mode),
+            center_width: Some(0.0),
+            surround_delay: Some(1.0),
+            lfe_cutoff: Some(120.0),
+        }
+    }
+
+    fn process(upmix: &mut Upmix, left: PrcFmt, right: PrcFmt, chunks: usize) -> AudioChunk {
+        let mut chunk = AudioChunk::new(vec![vec![], vec![]], 1.0, -1.0, 0, 0);
+        for _ in 0..chunks {
+            chunk = AudioChunk::new(
+                vec![vec![left; 1000], vec![right; 1000]],
+                1.0,
+                -1.0,
+                1000,
+                1000,
+            );
+            upmix.process_chunk(&mut chunk).unwrap();
+        }
+        chunk
+    }
+
+    #[test]
+    fn passive_51() {
+        let mut upmix = Upmix::from_config(
+            "test",
+            make_params(UpmixLayout::Surround51, UpmixMode::Passive),
+            48000,
+            1000,
+        );
+        let chunk = process(&mut upmix, 0.5, 0.3, 5);
+        assert_eq!(chunk.channels, 6);
+        let last: Vec<PrcFmt> = chunk.waveforms.iter().map(|w| w[999]).collect();
+        // Mid is 0.4 and side is 0.1, the fronts are unchanged.
+        assert!((last[0] - 0.5).abs() < 1.0e-9);
+        assert!((last[1] - 0.3).abs() < 1.0e-9);
+        assert!((last[2] - 0.4).abs() < 1.0e-9);
+        assert!((last[3] - 0.4).abs() < 1.0e-3);
+        assert!((last[4] - 0.1).abs() < 1.0e-9);
+        assert!((last[5] + 0.1).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn passive_hard_panned() {
+        let mut upmix = Upmix::from_config(
+            "test",
+            make_params(UpmixLayout::Surround51, UpmixMode::Passive),
+            48000,
+            1000,
+        );
+        // A hard panned signal does not leak into the opposite front channel.
+        let chunk = process(&mut upmix, 1.0, 0.0, 5);
+        let last: Vec<PrcFmt> = chunk.waveforms.iter().map(|w| w[999]).collect();
+        assert!((last[0] - 1.0).abs() < 1.0e-9);
+        assert!(last[1].abs() < 1.0e-9);
+        assert!((last[2] - 0.5).abs() < 1.0e-9);
+    }
+
+    #[test]
+    fn adaptive_71() {
+        let mut upmix = Upmix::from_config(
+            "test",
+            make_params(UpmixLayout::Surround71, UpmixMode::Adaptive),
+            48000,
+            1000,
+        );
+        // Centered mono goes to the center only.
+        let chunk = process(&mut upmix, 0.5, 0.5, 20);
+        assert_eq!(chunk.channels, 8);
+        let last: Vec<PrcFmt> = chunk.waveforms.iter().map(|w| w[999]).collect();
+        assert!(last[0].abs() < 1.0e-3);
+        assert!((last[2] - 0.5).abs() < 1.0e-3);
+        assert!(last[6].abs() < 1.0e-9);
+        // A hard panned signal stays in front.
+        let mut upmix = Upmix::from_config(
+            "test",
+            make_params(UpmixLayout::Surround71, UpmixMode::Adaptive),
+            48000,
+            1000,
+        );
+        let chunk = process(&mut upmix, 0.5, 0.0, 20);
+        let last: Vec<PrcFmt> = chunk.waveforms.iter().map(|w| w[999]).collect();
+        assert!((last[0] - 0.5).abs() < 1.0e-3);
+        assert!(last[2].abs() < 1.0e-3);
+        // and is not sent to the surrounds.
+        for value in last[4..8].iter() {
+            assert!(value.abs() < 1.0e-3);
+        }
+        // Content in opposite phase goes to the surrounds only.
+        let mut upmix = Upmix::from_config(
+            "test",
+            make_params(UpmixLayout::Surround71, UpmixMode::Adaptive),
+            48000,
+            1000,
+        );
+        let chunk = process(&mut upmix, 0.5, -0.5, 20);
+        let last: Vec<PrcFmt> = chunk.waveforms.iter().map(|w| w[999]).collect();
+        let surround = 0.5 * 0.5f64.sqrt() as PrcFmt;
+        assert!(last[2].abs() < 1.0e-3);
+        assert!((last[4] - surround).abs() < 1.0e-3);
+        assert!((last[5] + surround).abs() < 1.0e-3);
+        assert!((last[6] - surround).abs() < 1.0e-3);
+        assert!((last[7] + surround).abs() < 1.0e-3);
+    }
+
+    #[test]
+    fn check_config() {
+        let mut conf = make_params(UpmixLayout::Surround51, UpmixMode::Passive);
+        assert!(validate_upmix(48000, &conf).is_ok());
+        conf.channels = 3;
+        assert!(validate_upmix(48000, &conf).is_err());
+        let mut conf = make_params(UpmixLayout::Surround51, UpmixMode::Passive);
+        conf.center_width = Some(1.5);
+        assert!(validate_upmix(48000, &conf).is_err());
+    }
+}
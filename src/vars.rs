@@ -0,0 +1,426 @@
+use crate::config::ConfigError;
+use crate::Res;
+use serde_yaml::{Mapping, Number, Value};
+use std::collections::{HashMap, HashSet};
+
+/// Evaluate the variables in the `vars` section of a config,
+/// and substitute them in all the other strings of the config.
+/// The evaluated values replace the definitions in the `vars` section.
+pub fn resolve_vars(config: &mut Value) -> Res<()> {
+    let key = Value::String("vars".to_string());
+    let definitions = match config.as_mapping_mut().and_then(|m| m.remove(&key)) {
+        Some(Value::Mapping(definitions)) => definitions,
+        Some(Value::Null) | None => return Ok(()),
+        Some(_) => return Err(ConfigError::new("The vars section must be a mapping").into()),
+    };
+    let mut names = Vec::with_capacity(definitions.len());
+    let mut pending = HashMap::new();
+    for (name, definition) in definitions {
+        let name = match name {
+            Value::String(name) => name,
+            _ => return Err(ConfigError::new("Variable names must be strings").into()),
+        };
+        names.push(name.clone());
+        pending.insert(name, definition);
+    }
+    let mut evaluator = Evaluator {
+        pending,
+        vars: HashMap::new(),
+        in_progress: HashSet::new(),
+    };
+    for name in names.iter() {
+        evaluator.evaluate_var(name)?;
+    }
+    evaluator.substitute(config)?;
+    let mut evaluated = Mapping::new();
+    for name in names {
+        let value = evaluator.vars[&name].clone();
+        evaluated.insert(Value::String(name), value);
+    }
+    if let Some(mapping) = config.as_mapping_mut() {
+        mapping.insert(key, Value::Mapping(evaluated));
+    }
+    Ok(())
+}
+
+/// Substitute evaluated variables in all strings of a value, including mapping keys.
+pub fn substitute_vars(value: &mut Value, vars: &HashMap<String, Value>) -> Res<()> {
+    let mut evaluator = Evaluator {
+        pending: HashMap::new(),
+        vars: vars.clone(),
+        in_progress: HashSet::new(),
+    };
+    evaluator.substitute(value)
+}
+
+struct Evaluator {
+    pending: HashMap<String, Value>,
+    vars: HashMap<String, Value>,
+    in_progress: HashSet<String>,
+}
+
+impl Evaluator {
+    fn evaluate_var(&mut self, name: &str) -> Res<Value> {
+        if let Some(value) = self.vars.get(name) {
+            return Ok(value.clone());
+        }
+        let definition = match self.pending.get(name) {
+            Some(definition) => definition.clone(),
+            None => {
+                let msg = format!("Use of undefined variable '{name}'");
+                return Err(ConfigError::new(&msg).into());
+            }
+        };
+        if !self.in_progress.insert(name.to_string()) {
+            let msg = format!("Variable '{name}' is defined in terms of itself");
+            return Err(ConfigError::new(&msg).into());
+        }
+        let value = match definition {
+            Value::String(text) => self.evaluate_string(&text)?,
+            Value::Number(_) | Value::Bool(_) => definition,
+            _ => {
+                let msg = format!("Variable '{name}' must be a number, a string or a boolean");
+                return Err(ConfigError::new(&msg).into());
+            }
+        };
+        self.in_progress.remove(name);
+        self.vars.insert(name.to_string(), value.clone());
+        Ok(value)
+    }
+
+    // Look up a reference, either a variable or an environment variable written as `env:NAME`.
+    fn lookup(&mut self, reference: &str) -> Res<Value> {
+        if let Some(env_name) = reference.strip_prefix("env:") {
+            return match std::env::var(env_name) {
+                Ok(value) => Ok(Value::String(value)),
+                Err(_) => {
+                    let msg = format!("Environment variable '{env_name}' is not set");
+                    Err(ConfigError::new(&msg).into())
+                }
+            };
+        }
+        self.evaluate_var(reference)
+    }
+
+    // Replace the references and expressions in a string. A string that is a single
+    // variable reference takes the value of the variable, and a string that is a single
+    // expression written as `$(( ... ))` takes the value of the expression.
+    // Other strings are kept as strings, with the references and expressions replaced.
+    fn evaluate_string(&mut self, text: &str) -> Res<Value> {
+        if !has_references(text) {
+            return Ok(Value::String(text.to_string()));
+        }
+        let trimmed = text.trim();
+        if let Some(reference) = trimmed
+            .strip_prefix("${")
+            .and_then(|rest| rest.strip_suffix('}'))
+            .filter(|r| !r.contains('}') && !r.starts_with("env:"))
+        {
+            return self.lookup(reference.trim());
+        }
+        if let Some(expression) = trimmed
+            .strip_prefix("$((")
+            .filter(|rest| expression_end(rest).map(|end| end + 2) == Some(rest.len()))
+        {
+            let value = self.evaluate_arithmetic(&expression[..expression.len() - 2], text)?;
+            return number_value(value, text);
+        }
+        Ok(Value::String(self.replace_references(text)?))
+    }
+
+    // Evaluate the inside of a `$(( ... ))` expression, after replacing its references.
+    fn evaluate_arithmetic(&mut self, expression: &str, text: &str) -> Res<f64> {
+        let replaced = self.replace_references(expression)?;
+        match evaluate_expression(&replaced) {
+            Some(value) => Ok(value),
+            None => {
+                let msg = format!("Invalid arithmetic expression in '{text}'");
+                Err(ConfigError::new(&msg).into())
+            }
+        }
+    }
+
+    fn replace_references(&mut self, text: &str) -> Res<String> {
+        let mut result = String::with_capacity(text.len());
+        let mut rest = text;
+        while let Some(start) = rest.find('$') {
+            result.push_str(&rest[..start]);
+            let after = &rest[start + 1..];
+            if let Some(expression) = after.strip_prefix("((") {
+                let end = match expression_end(expression) {
+                    Some(end) => end,
+                    None => {
+                        let msg = format!("Unterminated expression in '{text}'");
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                };
+                let value = self.evaluate_arithmetic(&expression[..end], text)?;
+                if let Value::Number(value) = number_value(value, text)? {
+                    result.push_str(&value.to_string());
+                }
+                rest = &expression[end + 2..];
+            } else if let Some(reference) = after.strip_prefix('{') {
+                let end = match reference.find('}') {
+                    Some(end) => end,
+                    None => {
+                        let msg = format!("Unterminated variable reference in '{text}'");
+                        return Err(ConfigError::new(&msg).into());
+                    }
+                };
+                let value = self.lookup(reference[..end].trim())?;
+                match value {
+                    Value::String(value) => result.push_str(&value),
+                    Value::Number(value) => result.push_str(&value.to_string()),
+                    Value::Bool(value) => result.push_str(&value.to_string()),
+                    _ => {}
+                }
+                rest = &reference[end + 1..];
+            } else {
+                result.push('$');
+                rest = after;
+            }
+        }
+        result.push_str(rest);
+        Ok(result)
+    }
+
+    // Substitute the variables in all strings of a value.
+    fn substitute(&mut self, value: &mut Value) -> Res<()> {
+        match value {
+            Value::String(text) if has_references(text) => {
+                *value = self.evaluate_string(text)?;
+            }
+            Value::Sequence(values) => {
+                for value in values.iter_mut() {
+                    self.substitute(value)?;
+                }
+            }
+            Value::Mapping(mapping) => {
+                let mut substituted = Mapping::with_capacity(mapping.len());
+                for (key, mut value) in std::mem::take(mapping) {
+                    let key = match key {
+                        Value::String(key) if key.contains("${") => {
+                            Value::String(self.replace_references(&key)?)
+                        }
+                        key => key,
+                    };
+                    self.substitute(&mut value)?;
+                    substituted.insert(key, value);
+                }
+                *mapping = substituted;
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+}
+
+/// Check if a string contains any variable references or expressions.
+pub fn has_references(text: &str) -> bool {
+    text.contains("${") || text.contains("$((")
+}
+
+// Find the end of an expression that follows an opening `$((`.
+// Returns the position of the closing `))`.
+fn expression_end(text: &str) -> Option<usize> {
+    let mut depth = 2;
+    for (position, c) in text.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => {
+                depth -= 1;
+                if depth == 0 {
+                    return text[..position].ends_with(')').then(|| position - 1);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+// Store integer results as integers, so that they can also be used for integer parameters.
+fn number_value(value: f64, text: &str) -> Res<Value> {
+    if !value.is_finite() {
+        let msg = format!("The expression '{text}' does not evaluate to a finite number");
+        return Err(ConfigError::new(&msg).into());
+    }
+    if value.fract() == 0.0 && value.abs() < 9.0e15 {
+        Ok(Value::Number(Number::from(value as i64)))
+    } else {
+        Ok(Value::Number(Number::from(value)))
+    }
+}
+
+/// Evaluate a simple arithmetic expression with `+`, `-`, `*`, `/` and parentheses.
+/// Returns None if the text is not a valid expression.
+pub fn evaluate_expression(text: &str) -> Option<f64> {
+    let mut parser = ExpressionParser {
+        chars: text.chars().filter(|c| !c.is_whitespace()).collect(),
+        position: 0,
+    };
+    let value = parser.expression()?;
+    if parser.position == parser.chars.len() {
+        Some(value)
+    } else {
+        None
+    }
+}
+
+struct ExpressionParser {
+    chars: Vec<char>,
+    position: usize,
+}
+
+impl ExpressionParser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.position).copied()
+    }
+
+    fn expression(&mut self) -> Option<f64> {
+        let mut value = self.term()?;
+        while let Some(op) = self.peek().filter(|c| *c == '+' || *c == '-') {
+            self.position += 1;
+            let rhs = self.term()?;
+            value = if op == '+' { value + rhs } else { value - rhs };
+        }
+        Some(value)
+    }
+
+    fn term(&mut self) -> Option<f64> {
+        let mut value = self.factor()?;
+        while let Some(op) = self.peek().filter(|c| *c == '*' || *c == '/') {
+            self.position += 1;
+            let rhs = self.factor()?;
+            value = if op == '*' { value * rhs } else { value / rhs };
+        }
+        Some(value)
+    }
+
+    fn factor(&mut self) -> Option<f64> {
+        match self.peek()? {
+            '-' => {
+                self.position += 1;
+                Some(-self.factor()?)
+            }
+            '+' => {
+                self.position += 1;
+                self.factor()
+            }
+            '(' => {
+                self.position += 1;
+                let value = self.expression()?;
+                if self.peek()? != ')' {
+                    return None;
+                }
+                self.position += 1;
+                Some(value)
+            }
+            _ => self.number(),
+        }
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        let start = self.position;
+        while let Some(c) = self.peek() {
+            let is_exponent_sign = (c == '-' || c == '+')
+                && self.position > start
+                && matches!(self.chars[self.position - 1], 'e' | 'E');
+            if c.is_ascii_digit() || c == '.' || c == 'e' || c == 'E' || is_exponent_sign {
+                self.position += 1;
+            } else {
+                break;
+            }
+        }
+        let text: String = self.chars[start..self.position].iter().collect();
+        text.parse().ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config;
+    use crate::vars::{evaluate_expression, resolve_vars};
+    use serde_yaml::Value;
+
+    #[test]
+    fn arithmetic() {
+        assert_eq!(evaluate_expression("2 + 3 * 4"), Some(14.0));
+        assert_eq!(evaluate_expression("(2 + 3) * -4"), Some(-20.0));
+        assert_eq!(evaluate_expression("1.5e3 / 3"), Some(500.0));
+        assert_eq!(evaluate_expression("2 +"), None);
+        assert_eq!(evaluate_expression("left-right"), None);
+    }
+
+    #[test]
+    fn substitute_vars() {
+        std::env::set_var("CAMILLA_TEST_ROOM", "livingroom");
+        let yaml = "
+vars:
+  xover: 2000
+  distance: 1.715
+  delay: $(( ${distance} / 343 * 1000 ))
+  room: ${env:CAMILLA_TEST_ROOM}
+  left: 1
+  right: 2
+filters:
+  lowpass_${room}:
+    freq: $((${xover} * (1 + 0.1)))
+    delay: ${delay}
+    filename: fir_${room}_${xover}.wav
+    label: ${left}-${right}
+    taps: taps_$(( ${xover} / 1000 ))k
+";
+        let mut config: Value = serde_yaml::from_str(yaml).unwrap();
+        resolve_vars(&mut config).unwrap();
+        let filter = &config["filters"]["lowpass_livingroom"];
+        assert!((filter["freq"].as_f64().unwrap() - 2200.0).abs() < 1.0e-9);
+        assert!((filter["delay"].as_f64().unwrap() - 5.0).abs() < 1.0e-9);
+        assert_eq!(filter["filename"].as_str(), Some("fir_livingroom_2000.wav"));
+        assert_eq!(filter["label"].as_str(), Some("1-2"));
+        assert_eq!(filter["taps"].as_str(), Some("taps_2k"));
+        assert_eq!(config["vars"]["room"].as_str(), Some("livingroom"));
+
+        let mut config: Value = serde_yaml::from_str("vars:\n  a: ${b}\n  b: ${a}\n").unwrap();
+        assert!(resolve_vars(&mut config).is_err());
+        let mut config: Value = serde_yaml::from_str("vars:\n  a: $(( 2 + x ))\n").unwrap();
+        assert!(resolve_vars(&mut config).is_err());
+    }
+
+    #[test]
+    fn source_is_not_compared() {
+        let template = "
+devices:
+  samplerate: 48000
+  chunksize: 1024
+  capture:
+    type: Stdin
+    channels: 2
+    format: S16LE
+  playback:
+    type: Stdout
+    channels: 2
+    format: S16LE
+filters:
+  eq:
+    type: BiquadCombo
+    parameters:
+      type: GraphicEqualizer
+      gains: [GAINS]
+";
+        let written = template.replace("GAINS", "0.0, 2.5");
+        let reformatted = format!(
+            "# Same values written differently\n{}",
+            template.replace("GAINS", "0, 2.50")
+        );
+        let mut conf = config::parse_config(&written).unwrap();
+        assert_eq!(conf, config::parse_config(&reformatted).unwrap());
+
+        config::set_source_eq_gains(&mut conf, "eq", &[1.0, -1.0]);
+        let mut updated = config::parse_config(conf.source.0.as_ref().unwrap()).unwrap();
+        assert_eq!(
+            config::graphic_eq_gains_mut(&mut updated, "eq").unwrap(),
+            &vec![1.0, -1.0]
+        );
+    }
+}